@@ -0,0 +1,86 @@
+//! Benchmarks for the work done before the picker can draw: parsing
+//! `tailscale status --json` and filtering the node list.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde_json::{json, Map, Value};
+use ssh_tailscale::{config::Config, index::NodeIndex, tailscale::parse_tailscale_status};
+use std::hint::black_box;
+
+/// Tailnet sizes to measure, from a home lab to a large fleet
+const TAILNET_SIZES: [usize; 3] = [10, 100, 1000];
+
+/// A `tailscale status --json` output with `peers` peers, a mix of owned and tagged machines
+fn status_json(peers: usize) -> String {
+    let peer_map: Map<String, Value> = (0..peers)
+        .map(|i| {
+            let tags = if i % 3 == 0 { json!(["tag:server", "tag:web"]) } else { Value::Null };
+            let peer = json!({
+                "HostName": format!("node-{}-{}", ["web", "db", "cache", "worker"][i % 4], i),
+                "DNSName": format!("node-{}.tail1234.ts.net.", i),
+                "OS": if i % 5 == 0 { "windows" } else { "linux" },
+                "UserID": 1000 + (i % 7),
+                "TailscaleIPs": [format!("100.64.{}.{}", i / 250, i % 250 + 1), format!("fd7a:115c:a1e0::{:x}", i + 1)],
+                "Tags": tags,
+                "Online": i % 4 != 0,
+                "Active": i % 8 == 1,
+                "Relay": "fra",
+                "CurAddr": if i % 2 == 0 { "1.2.3.4:41641" } else { "" },
+                "LastSeen": "2026-10-15T10:00:00Z",
+            });
+            (format!("nodekey:{:x}", i), peer)
+        })
+        .collect();
+    let users: Map<String, Value> = (0..7)
+        .map(|i| (format!("{}", 1000 + i), json!({ "LoginName": format!("user{}@example.com", i) })))
+        .collect();
+
+    json!({
+        "Version": "1.76.1-t1234abcd-gdeadbeef",
+        "BackendState": "Running",
+        "Self": {
+            "HostName": "laptop",
+            "DNSName": "laptop.tail1234.ts.net.",
+            "OS": "linux",
+            "UserID": 1000,
+            "TailscaleIPs": ["100.100.0.1"],
+            "Online": true,
+        },
+        "CurrentTailnet": { "MagicDNSEnabled": true },
+        "Peer": peer_map,
+        "User": users,
+    })
+    .to_string()
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_tailscale_status");
+    for peers in TAILNET_SIZES {
+        let output = status_json(peers);
+        group.bench_with_input(BenchmarkId::from_parameter(peers), &output, |b, output| {
+            b.iter(|| parse_tailscale_status(black_box(output)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn filter(c: &mut Criterion) {
+    let config = Config::default();
+    let mut group = c.benchmark_group("filter");
+    for peers in TAILNET_SIZES {
+        let nodes = parse_tailscale_status(&status_json(peers)).unwrap().nodes;
+        group.bench_with_input(BenchmarkId::new("index", peers), &nodes, |b, nodes| {
+            b.iter(|| NodeIndex::new(black_box(nodes), &config))
+        });
+
+        let index = NodeIndex::new(&nodes, &config);
+        for query in ["w", "worker-9", "tag:serv"] {
+            group.bench_with_input(BenchmarkId::new(query, peers), &index, |b, index| {
+                b.iter(|| index.matches(black_box(query)))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, filter);
+criterion_main!(benches);
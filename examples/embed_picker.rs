@@ -0,0 +1,44 @@
+//! A minimal ratatui application that asks for a Tailscale node with the
+//! embedded picker and prints the one chosen.
+
+use anyhow::Result;
+use crossterm::{
+    event,
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{backend::CrosstermBackend, Terminal};
+use ssh_tailscale::{
+    config::load_config,
+    picker::{NodePicker, PickerEvent},
+    tailscale::get_tailscale_status,
+};
+use std::io;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = load_config()?;
+    let status = get_tailscale_status(&config).await?;
+    let mut picker = NodePicker::from_status(status).config(config).build()?;
+
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+
+    let outcome = loop {
+        terminal.draw(|frame| picker.render(frame, frame.size()))?;
+        match picker.handle_event(&event::read()?) {
+            PickerEvent::Pending => {}
+            outcome => break outcome,
+        }
+    };
+
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen)?;
+
+    match outcome {
+        PickerEvent::Selected(node) => println!("{} ({})", node.name, node.ip),
+        _ => println!("No node chosen"),
+    }
+    Ok(())
+}
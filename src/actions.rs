@@ -0,0 +1,277 @@
+use crate::{
+    address,
+    config::{ActionTarget, Config, CustomAction, Service, Transport},
+    keys::{KeyBinding, KeyMap, ACTION_MENU_ACTIONS},
+    platform::{open_command, shell_command},
+    plugins::{self, Plugin},
+    ssh::{transport_for, SshCommand},
+    tailscale::TailscaleNode,
+};
+use anyhow::{anyhow, Result};
+use std::process::Command;
+
+/// An entry of the action menu, with its key parsed
+#[derive(Debug, Clone)]
+pub struct MenuAction {
+    pub name: String,
+    pub key: Option<KeyBinding>,
+    pub kind: ActionKind,
+}
+
+/// What a menu entry runs
+#[derive(Debug, Clone)]
+pub enum ActionKind {
+    /// A command from the config's `actions`
+    Custom(CustomAction),
+    /// An `ssh-tailscale-*` executable found on PATH
+    Plugin(Plugin),
+    /// Open one of the node's services in the browser
+    OpenService(Service),
+    /// Forward one of the node's services to a local port until the pane closes
+    ForwardService(Service),
+    /// Open a URL in the browser
+    OpenUrl(String),
+}
+
+/// The menu entries: the config's `actions`, then the plugins on PATH. Fails if
+/// an action's key is invalid, used twice, or already moves around or closes the menu.
+pub fn menu_actions(actions: &[CustomAction], plugins: Vec<Plugin>, keys: &KeyMap) -> Result<Vec<MenuAction>> {
+    let mut menu: Vec<MenuAction> = Vec::new();
+    for action in actions {
+        let key = action
+            .key
+            .as_deref()
+            .map(KeyBinding::parse)
+            .transpose()
+            .map_err(|err| anyhow!("Invalid key for action '{}': {}", action.name, err))?;
+
+        if let Some(key) = &key {
+            if let Some(bound) = keys.bound_to(key, ACTION_MENU_ACTIONS) {
+                return Err(anyhow!(
+                    "{} is bound to both action '{}' and `{}` in `keys`",
+                    key.label(),
+                    action.name,
+                    bound.name()
+                ));
+            }
+            if let Some(other) = menu.iter().find(|other| other.key.as_ref() == Some(key)) {
+                return Err(anyhow!(
+                    "{} is bound to both action '{}' and action '{}'",
+                    key.label(),
+                    other.name,
+                    action.name
+                ));
+            }
+        }
+
+        menu.push(MenuAction {
+            name: action.name.clone(),
+            key,
+            kind: ActionKind::Custom(action.clone()),
+        });
+    }
+
+    menu.extend(plugins.into_iter().map(|plugin| MenuAction {
+        name: plugin.name.clone(),
+        key: None,
+        kind: ActionKind::Plugin(plugin),
+    }));
+    Ok(menu)
+}
+
+/// The entries for the node's services, by name: "open" for web services and
+/// "forward" for all of them. A service's key opens it, or forwards it when
+/// it can't be opened. Forwarding needs sshd, so it isn't offered over `tailscale ssh`.
+pub fn service_actions(config: &Config, node: &TailscaleNode) -> Vec<MenuAction> {
+    let can_forward = transport_for(config, node) == Transport::Ssh;
+    let mut menu = Vec::new();
+    for (name, service) in &config.node_settings(&node.name).services {
+        // Keys are checked when the picker starts
+        let key = service.key.as_deref().and_then(|key| KeyBinding::parse(key).ok());
+        let opens = service.scheme.url_scheme().is_some();
+        if opens {
+            menu.push(MenuAction {
+                name: format!("open {}", name),
+                key,
+                kind: ActionKind::OpenService(service.clone()),
+            });
+        }
+        if can_forward {
+            menu.push(MenuAction {
+                name: format!("forward {}", name),
+                key: if opens { None } else { key },
+                kind: ActionKind::ForwardService(service.clone()),
+            });
+        }
+    }
+    menu
+}
+
+/// What the browse key opens for a node: its web service if it has exactly
+/// one, or else the site Tailscale Serve and Funnel publish at its MagicDNS
+/// name. `None` when it has several web services to choose from, or no web
+/// services and no MagicDNS name.
+pub fn browse_action(config: &Config, node: &TailscaleNode) -> Option<MenuAction> {
+    let services = config.node_settings(&node.name).services;
+    let mut web = services.into_iter().filter(|(_, service)| service.scheme.url_scheme().is_some());
+    match (web.next(), web.next()) {
+        (Some((name, service)), None) => Some(MenuAction {
+            name: format!("open {}", name),
+            key: None,
+            kind: ActionKind::OpenService(service),
+        }),
+        (None, _) if !node.dns_name.is_empty() => Some(MenuAction {
+            name: format!("open {}", node.dns_name),
+            key: None,
+            kind: ActionKind::OpenUrl(format!("https://{}", node.dns_name)),
+        }),
+        _ => None,
+    }
+}
+
+/// Fail if a service's key is invalid, moves around or closes the menu, or is
+/// already taken by an action or another service of the same node
+pub fn check_service_keys(config: &Config, actions: &[MenuAction], keys: &KeyMap) -> Result<()> {
+    for (node, settings) in &config.nodes {
+        let mut taken: Vec<(KeyBinding, String)> = Vec::new();
+        for (name, service) in &settings.services {
+            let Some(key) = &service.key else {
+                continue;
+            };
+            let key = KeyBinding::parse(key)
+                .map_err(|err| anyhow!("Invalid key for service '{}' of {}: {}", name, node, err))?;
+            if let Some(bound) = keys.bound_to(&key, ACTION_MENU_ACTIONS) {
+                return Err(anyhow!(
+                    "{} is bound to both service '{}' of {} and `{}` in `keys`",
+                    key.label(),
+                    name,
+                    node,
+                    bound.name()
+                ));
+            }
+            if let Some(action) = actions.iter().find(|action| action.key.as_ref() == Some(&key)) {
+                return Err(anyhow!(
+                    "{} is bound to both service '{}' of {} and action '{}'",
+                    key.label(),
+                    name,
+                    node,
+                    action.name
+                ));
+            }
+            if let Some((_, other)) = taken.iter().find(|(other_key, _)| *other_key == key) {
+                return Err(anyhow!(
+                    "{} is bound to both services '{}' and '{}' of {}",
+                    key.label(),
+                    other,
+                    name,
+                    node
+                ));
+            }
+            taken.push((key, name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// The address a service is reached at, like `http://web-1.tailnet.ts.net:8096`
+pub fn service_url(config: &Config, node: &TailscaleNode, service: &Service) -> Option<String> {
+    let scheme = service.scheme.url_scheme()?;
+    let host = address::destination(config, node).host;
+    let host = if host.contains(':') { format!("[{}]", host) } else { host };
+    Some(format!("{}://{}:{}", scheme, host, service.port))
+}
+
+/// The local port a service is forwarded to
+pub fn forward_port(service: &Service) -> u16 {
+    service.local_port.unwrap_or(service.port)
+}
+
+/// Fill in a command template for a node. Unknown `{...}` words are left as
+/// they are, so braces meant for the shell survive.
+pub fn expand(template: &str, node: &TailscaleNode, username: &str) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let value = match &rest[1..end] {
+                "node" => node.name.as_str(),
+                "dns" => node.dns_name.as_str(),
+                "ip" => node.ip.as_str(),
+                "user" => username,
+                "os" => node.os.as_str(),
+                _ => return None,
+            };
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                expanded.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                expanded.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// The command that runs a menu entry against a node, and the text for its
+/// stdin. Remote commands never prompt for a password, since the terminal
+/// belongs to the picker.
+pub fn command_for(entry: &MenuAction, config: &Config, node: &TailscaleNode) -> (Command, Option<String>) {
+    match &entry.kind {
+        ActionKind::Custom(action) => (custom_command(action, config, node), None),
+        ActionKind::Plugin(plugin) => (
+            plugins::command_for(plugin, config, node),
+            Some(plugins::node_json(config, node)),
+        ),
+        ActionKind::OpenService(service) => {
+            let url = service_url(config, node, service).unwrap_or_default();
+            (open_command(&url), None)
+        }
+        ActionKind::ForwardService(service) => (forward_command(config, node, service), None),
+        ActionKind::OpenUrl(url) => (open_command(url), None),
+    }
+}
+
+/// The ssh that forwards a service to its local port until it is stopped
+pub fn forward_command(config: &Config, node: &TailscaleNode, service: &Service) -> Command {
+    let (username, _) = config.resolve_username(node);
+    SshCommand::for_node(config, &username, node)
+        .option("BatchMode", "yes")
+        .option("ExitOnForwardFailure", "yes")
+        .local_forward(forward_port(service), service.port)
+        .command()
+}
+
+/// The command for an action from the config
+fn custom_command(action: &CustomAction, config: &Config, node: &TailscaleNode) -> Command {
+    let (username, _) = config.resolve_username(node);
+    let command_line = expand(&action.command, node, &username);
+    match action.run {
+        ActionTarget::Remote => SshCommand::for_node(config, &username, node)
+            .option("BatchMode", "yes")
+            .remote_command(vec![command_line])
+            .command(),
+        ActionTarget::Local => {
+            let mut command = shell_command(&command_line);
+            set_node_env(&mut command, node, &username);
+            command
+        }
+    }
+}
+
+/// Describe a node to a local command through `SSH_TAILSCALE_*` variables
+pub fn set_node_env(command: &mut Command, node: &TailscaleNode, username: &str) {
+    command
+        .env("SSH_TAILSCALE_NODE", &node.name)
+        .env("SSH_TAILSCALE_IP", &node.ip)
+        .env("SSH_TAILSCALE_DNS", &node.dns_name)
+        .env("SSH_TAILSCALE_USER", username)
+        .env("SSH_TAILSCALE_OS", &node.os);
+}
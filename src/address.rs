@@ -0,0 +1,168 @@
+use crate::{
+    config::{AddressKind, Config, HopAddress, JumpHop},
+    platform::tailscale_program,
+    subprocess,
+    tailscale::TailscaleNode,
+};
+use std::{
+    collections::HashMap,
+    net::ToSocketAddrs,
+    process::Command,
+    sync::Mutex,
+};
+
+/// Tailscale IPs of jump hosts by node name, looked up once per run
+static HOP_IPS: Mutex<Option<HashMap<String, Option<String>>>> = Mutex::new(None);
+
+/// Lookup results by host name, so the picker can redraw without hitting the resolver
+static RESOLVED: Mutex<Option<HashMap<String, bool>>> = Mutex::new(None);
+
+/// The host a session connects to, and why it isn't the preferred one
+pub struct Destination {
+    /// Host name or IP address passed to ssh
+    pub host: String,
+    /// Which of the node's addresses `host` is
+    pub kind: AddressKind,
+    /// Why the node's IP is used although its DNS name was asked for
+    pub fallback_reason: Option<String>,
+}
+
+/// Pick the host to connect to: the address that worked last time if one was
+/// remembered, else the node's MagicDNS name when configured and usable,
+/// otherwise its Tailscale IP
+pub fn destination(config: &Config, node: &TailscaleNode) -> Destination {
+    if let Some(kind) = config.node_settings(&node.name).preferred_address
+        && let Some(host) = address_of(node, kind)
+    {
+        return Destination {
+            host,
+            kind,
+            fallback_reason: None,
+        };
+    }
+
+    let by_ip = |reason: Option<String>| Destination {
+        host: node.ip.clone(),
+        kind: AddressKind::Ipv4,
+        fallback_reason: reason,
+    };
+
+    if !config.ssh.use_magic_dns {
+        return by_ip(None);
+    }
+    if node.dns_name.is_empty() {
+        return by_ip(Some("MagicDNS is off for this tailnet".to_string()));
+    }
+    // MagicDNS can be on for the tailnet while this machine ignores it, e.g.
+    // with `--accept-dns=false` or a resolver that bypasses 100.100.100.100
+    if !resolves_locally(&node.dns_name) {
+        return by_ip(Some(format!("{} doesn't resolve on this machine", node.dns_name)));
+    }
+
+    Destination {
+        host: node.dns_name.clone(),
+        kind: AddressKind::Dns,
+        fallback_reason: None,
+    }
+}
+
+/// Every address worth trying for a node, in order: the `destination` first,
+/// then the DNS name (when MagicDNS is configured), IPv4 and IPv6
+pub fn candidates(config: &Config, node: &TailscaleNode) -> Vec<Destination> {
+    let mut candidates = vec![destination(config, node)];
+    let kinds: &[AddressKind] = if config.ssh.use_magic_dns {
+        &[AddressKind::Dns, AddressKind::Ipv4, AddressKind::Ipv6]
+    } else {
+        &[AddressKind::Ipv4, AddressKind::Ipv6]
+    };
+
+    for &kind in kinds {
+        if let Some(host) = address_of(node, kind)
+            && !candidates.iter().any(|candidate| candidate.host == host)
+        {
+            candidates.push(Destination {
+                host,
+                kind,
+                fallback_reason: None,
+            });
+        }
+    }
+    candidates
+}
+
+/// One of the node's addresses, if it has it; DNS names only when they resolve here
+fn address_of(node: &TailscaleNode, kind: AddressKind) -> Option<String> {
+    let host = match kind {
+        AddressKind::Dns if resolves_locally(&node.dns_name) => &node.dns_name,
+        AddressKind::Dns => return None,
+        AddressKind::Ipv4 => &node.ip,
+        AddressKind::Ipv6 => &node.ipv6,
+    };
+    (!host.is_empty()).then(|| host.clone())
+}
+
+/// Whether the system resolver can look up a host name, checked once per run
+fn resolves_locally(host: &str) -> bool {
+    if host.is_empty() {
+        return false;
+    }
+    let mut resolved = RESOLVED.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *resolved
+        .get_or_insert_with(HashMap::new)
+        .entry(host.to_string())
+        .or_insert_with(|| {
+            (host, 22)
+                .to_socket_addrs()
+                .map(|mut addresses| addresses.next().is_some())
+                .unwrap_or(false)
+        })
+}
+
+/// The `ProxyJump` value for a node's configured hops, if it has any
+pub fn proxy_jump(config: &Config, node: &TailscaleNode) -> Option<String> {
+    let hops = config.node_settings(&node.name).jump;
+    if hops.is_empty() {
+        return None;
+    }
+
+    let hops: Vec<String> = hops
+        .iter()
+        .map(|hop| {
+            let preference = hop.address().unwrap_or(config.ssh.jump_address);
+            let address = hop_address(config, node, hop, preference);
+            match hop.user() {
+                Some(user) => format!("{}@{}", user, address),
+                None => address,
+            }
+        })
+        .collect();
+    Some(hops.join(","))
+}
+
+/// Address of one hop. Names are qualified with the tailnet's MagicDNS suffix,
+/// taken from the destination's own name; without MagicDNS, or when an IP
+/// can't be found, the hop is passed on as written.
+fn hop_address(config: &Config, node: &TailscaleNode, hop: &JumpHop, preference: HopAddress) -> String {
+    let host = hop.host();
+    match preference {
+        HopAddress::Dns => match node.dns_name.split_once('.') {
+            Some((_, suffix)) if !host.contains('.') => format!("{}.{}", host, suffix),
+            _ => host.to_string(),
+        },
+        HopAddress::Ip => hop_ip(config, host).unwrap_or_else(|| host.to_string()),
+    }
+}
+
+/// The Tailscale IPv4 address of a node, from `tailscale ip -4`
+fn hop_ip(config: &Config, host: &str) -> Option<String> {
+    let mut ips = HOP_IPS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    ips.get_or_insert_with(HashMap::new)
+        .entry(host.to_string())
+        .or_insert_with(|| {
+            let program = tailscale_program(config.tailscale_path.as_deref()).ok()?;
+            let output = subprocess::output(Command::new(program).args(["ip", "-4", host])).ok()?;
+            let ip = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            (output.status.success() && !ip.is_empty()).then_some(ip)
+        })
+        .clone()
+}
@@ -0,0 +1,126 @@
+use crate::{
+    config::{Config, SshClient, Transport},
+    identities::identity_for,
+    platform::expand_home,
+    ssh::transport_for,
+    subprocess,
+    tailscale::TailscaleNode,
+};
+use anyhow::Result;
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use std::{
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// What `ssh-add -L` says about the agent
+enum AgentState {
+    /// Running, with these public keys loaded
+    Loaded(Vec<String>),
+    /// Running, but holding no keys
+    Empty,
+    /// No agent to talk to
+    Missing,
+}
+
+fn agent_state() -> AgentState {
+    let Ok(output) = subprocess::output(Command::new("ssh-add").arg("-L")) else {
+        return AgentState::Missing;
+    };
+    // ssh-add exits with 1 for an empty agent and 2 when it can't reach one
+    match output.status.code() {
+        Some(0) => AgentState::Loaded(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(key_part)
+                .collect(),
+        ),
+        Some(1) => AgentState::Empty,
+        _ => AgentState::Missing,
+    }
+}
+
+/// The public keys the agent holds, or `None` when no agent is running
+pub fn loaded_keys() -> Option<Vec<String>> {
+    match agent_state() {
+        AgentState::Loaded(loaded) => Some(loaded),
+        AgentState::Empty => Some(Vec::new()),
+        AgentState::Missing => None,
+    }
+}
+
+/// The `type base64` part of a public key line
+fn key_part(line: &str) -> Option<String> {
+    let mut fields = line.split_whitespace();
+    Some(format!("{} {}", fields.next()?, fields.next()?))
+}
+
+/// The private key file `ssh-add` should load for the node: its configured
+/// identity when that is a private key, else `ssh.agent_key`. `None` means
+/// ssh-add's default keys.
+pub fn key_to_add(config: &Config, node: &TailscaleNode) -> Option<PathBuf> {
+    identity_for(config, node)
+        .map(|(identity, _)| identity)
+        .filter(|identity| !identity.ends_with(".pub"))
+        .or_else(|| config.ssh.agent_key.clone())
+        .and_then(|key| expand_home(&key).ok())
+}
+
+/// Whether the agent already holds the key in `path`, judged by its `.pub`;
+/// a key without one counts as loaded when the agent has any key at all
+pub fn holds_key(loaded: &[String], path: &Path) -> bool {
+    let public_path = PathBuf::from(format!("{}.pub", path.display()));
+    match fs::read_to_string(public_path).ok().and_then(|text| text.lines().next().and_then(key_part)) {
+        Some(public_key) => loaded.contains(&public_key),
+        None => !loaded.is_empty(),
+    }
+}
+
+/// With `ssh.check_agent` on, make sure the agent holds a key before ssh runs,
+/// offering to run `ssh-add` when it doesn't, so a key-only node doesn't end
+/// in a password prompt. Only asks on a terminal; never stops the connection.
+pub fn ensure_loaded(config: &Config, node: &TailscaleNode) -> Result<()> {
+    if !config.ssh.check_agent
+        || config.ssh.client == SshClient::Plink
+        || transport_for(config, node) == Transport::Tailscale
+        || !io::stdin().is_terminal()
+    {
+        return Ok(());
+    }
+
+    let key = key_to_add(config, node);
+    let prompt = match (agent_state(), &key) {
+        (AgentState::Missing, _) => {
+            println!("No ssh-agent is running, so ssh may ask for key passphrases or a password.");
+            println!("Start one in your shell with `eval \"$(ssh-agent -s)\"` and run ssh-add.");
+            return Ok(());
+        }
+        (AgentState::Loaded(loaded), Some(key)) if !holds_key(&loaded, key) => {
+            format!("The ssh-agent doesn't hold {}. Run ssh-add for it?", key.display())
+        }
+        (AgentState::Loaded(_), _) => return Ok(()),
+        (AgentState::Empty, Some(key)) => format!("The ssh-agent holds no keys. Run ssh-add {}?", key.display()),
+        (AgentState::Empty, None) => "The ssh-agent holds no keys. Run ssh-add?".to_string(),
+    };
+
+    if !Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .default(true)
+        .interact()?
+    {
+        return Ok(());
+    }
+    // ssh-add asks for the passphrase on the terminal
+    let mut ssh_add = Command::new("ssh-add");
+    if let Some(key) = &key {
+        ssh_add.arg(key);
+    }
+    match subprocess::status(&mut ssh_add) {
+        Ok(status) if status.success() => {}
+        Ok(status) => println!("Warning: ssh-add exited with {}; connecting anyway", status),
+        Err(err) => println!("Warning: couldn't run ssh-add: {}; connecting anyway", err),
+    }
+    Ok(())
+}
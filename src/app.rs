@@ -0,0 +1,815 @@
+use crate::{
+    actions::{service_actions, MenuAction},
+    changes::TailnetChanges,
+    config::{Config, NodeSettings, SortKey},
+    cooldowns::Cooldowns,
+    index::NodeIndex,
+    netcheck::NetcheckReport,
+    selector::{group_contains, is_hidden, member_matches},
+    keys::{Action, FooterHint, KeyMap},
+    sort::{active_sort, sort},
+    ssh::ForwardingToggles,
+    sweep::PortState,
+    tailscale::{TailnetHealth, TailscaleNode},
+    theme::Theme,
+    tunnels::Tunnels,
+    usernames::{scopes_for, UsernameScope},
+};
+use anyhow::{anyhow, Result};
+use crossterm::event::{KeyCode, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::layout::Rect;
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+use tokio::task::AbortHandle;
+
+/// What keyboard input currently drives in the terminal UI
+pub enum Mode {
+    /// Typing filters the node list
+    Browse,
+    /// Typing edits a one-off command to run on the selected node
+    CommandPrompt { input: String },
+    /// Typing the path of a file to send to the selected node with Taildrop
+    SendPrompt { input: String },
+    /// Showing the output of a one-off command
+    CommandOutput(CommandOutput),
+    /// Editing default usernames for the scopes that apply to a node
+    UsernameSettings(Box<UsernameEditor>),
+    /// Editing the settings the config keeps for the selected node itself
+    EditNode(Box<NodeEditor>),
+    /// Showing every key binding and where settings come from
+    Help(HelpScreen),
+    /// Choosing a custom action to run on the selected node. `confirm` is an
+    /// action chosen once for a node that is cooling down, which runs if chosen again.
+    ActionMenu { cursor: usize, confirm: Option<usize> },
+    /// Choosing which group of nodes to list; the first entry is all of them
+    Groups { cursor: usize },
+    /// Showing the service forwards kept open in the background
+    Tunnels { cursor: usize },
+    /// Showing how the tailnet changed since the last run
+    Changes { scroll: usize },
+    /// Showing what `tailscale netcheck` says about this machine's network
+    Diagnostics(Diagnostics),
+    /// Choosing or composing the order of the list
+    Sorts(Box<SortEditor>),
+}
+
+/// One entry of the group list
+pub struct GroupEntry {
+    /// The group's name, or `None` for every node
+    pub name: Option<String>,
+    pub nodes: usize,
+    pub online: usize,
+}
+
+/// State of the diagnostics screen
+pub struct Diagnostics {
+    /// The netcheck report, or why there is none; `None` while it runs
+    pub report: Option<Result<NetcheckReport, String>>,
+    /// First visible line
+    pub scroll: usize,
+}
+
+/// State of the help overlay, with the details that are looked up when it opens
+pub struct HelpScreen {
+    /// Where the config file is read from, or why there is none
+    pub config_path: String,
+    /// The tailscale CLI in use, or why none was found
+    pub tailscale_program: String,
+    /// First visible line
+    pub scroll: usize,
+}
+
+/// State of the default username settings popup
+pub struct UsernameEditor {
+    /// Node whose username precedence is being previewed
+    pub node: TailscaleNode,
+    /// Scopes that apply to the node, most specific first
+    pub scopes: Vec<UsernameScope>,
+    /// Highlighted scope
+    pub cursor: usize,
+    /// Text being typed for the highlighted scope, while editing
+    pub input: Option<String>,
+    /// Result of the last save, shown at the bottom of the popup
+    pub message: Option<String>,
+    /// The node as a refresh now describes it, when it changed in the middle of an
+    /// edit; the edit waits until the user decides which version it applies to
+    pub conflict: Option<TailscaleNode>,
+}
+
+impl UsernameEditor {
+    /// Open the editor for a node
+    pub fn new(node: TailscaleNode) -> Self {
+        Self {
+            scopes: scopes_for(&node),
+            node,
+            cursor: 0,
+            input: None,
+            message: None,
+            conflict: None,
+        }
+    }
+
+    /// Switch to a fresher record of the same node, staying on the same kind of
+    /// scope. Returns false if the highlighted scope no longer applies to it.
+    pub fn retarget(&mut self, node: TailscaleNode) -> bool {
+        let scopes = scopes_for(&node);
+        let position = match self.selected_scope() {
+            UsernameScope::Node(_) => Some(0),
+            scope => scopes.iter().position(|other| other == scope),
+        };
+        self.cursor = position.unwrap_or(0);
+        self.scopes = scopes;
+        self.node = node;
+        position.is_some()
+    }
+
+    /// The highlighted scope
+    pub fn selected_scope(&self) -> &UsernameScope {
+        &self.scopes[self.cursor]
+    }
+}
+
+/// A node setting that can be changed from the node editor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeField {
+    Username,
+    Alias,
+    Port,
+    Notes,
+}
+
+impl NodeField {
+    /// Every field, in the order the editor lists them
+    pub const ALL: [NodeField; 4] = [NodeField::Username, NodeField::Alias, NodeField::Port, NodeField::Notes];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            NodeField::Username => "Username",
+            NodeField::Alias => "Alias",
+            NodeField::Port => "Port",
+            NodeField::Notes => "Notes",
+        }
+    }
+
+    /// The field's value in the config for a node, empty if it isn't set
+    pub fn value(self, config: &Config, name: &str) -> String {
+        let settings = config.node_settings(name);
+        match self {
+            NodeField::Username => settings.username.unwrap_or_default(),
+            NodeField::Alias => settings.alias.unwrap_or_default(),
+            NodeField::Port => settings.port.map(|port| port.to_string()).unwrap_or_default(),
+            NodeField::Notes => settings.notes,
+        }
+    }
+
+    /// Set the field for a node, or clear it with empty text. Nodes left with
+    /// nothing set are dropped from the config.
+    pub fn set(self, config: &mut Config, name: &str, text: &str) -> Result<()> {
+        let text = text.trim();
+        let port = match self {
+            NodeField::Port if !text.is_empty() => Some(
+                text.parse()
+                    .ok()
+                    .filter(|&port: &u16| port != 0)
+                    .ok_or_else(|| anyhow!("The port must be a number from 1 to 65535"))?,
+            ),
+            _ => None,
+        };
+        if self == NodeField::Alias
+            && let Some((other, _)) = config.nodes.iter().find(|(other, settings)| {
+                *other != name && settings.alias.as_deref().is_some_and(|alias| alias.eq_ignore_ascii_case(text))
+            })
+        {
+            return Err(anyhow!("{} already has the alias '{}'", other, text));
+        }
+
+        let non_empty = (!text.is_empty()).then(|| text.to_string());
+        let settings = config.nodes.entry(name.to_string()).or_default();
+        match self {
+            NodeField::Username => settings.username = non_empty,
+            NodeField::Alias => settings.alias = non_empty,
+            NodeField::Port => settings.port = port,
+            NodeField::Notes => settings.notes = text.to_string(),
+        }
+        if *settings == NodeSettings::default() {
+            config.nodes.remove(name);
+        }
+        Ok(())
+    }
+}
+
+/// State of the node editor popup
+pub struct NodeEditor {
+    /// Node whose settings are edited
+    pub node: TailscaleNode,
+    /// Highlighted field
+    pub cursor: usize,
+    /// Text being typed for the highlighted field, while editing
+    pub input: Option<String>,
+    /// Result of the last save, shown at the bottom of the popup
+    pub message: Option<String>,
+}
+
+impl NodeEditor {
+    pub fn new(node: TailscaleNode) -> Self {
+        Self {
+            node,
+            cursor: 0,
+            input: None,
+            message: None,
+        }
+    }
+
+    /// The highlighted field
+    pub fn selected_field(&self) -> NodeField {
+        NodeField::ALL[self.cursor]
+    }
+}
+
+/// State of the sort popup
+#[derive(Default)]
+pub struct SortEditor {
+    /// Highlighted entry; the first is Tailscale's own order, then the named sorts
+    pub cursor: usize,
+    /// The sort being composed, while editing one
+    pub draft: Option<SortDraft>,
+    /// Result of the last change, shown at the bottom of the popup
+    pub message: Option<String>,
+}
+
+/// A sort being composed in the sort popup
+pub struct SortDraft {
+    /// The name it is saved under, or `None` for a new sort
+    pub original: Option<String>,
+    /// Name typed for saving, while `naming`
+    pub name: String,
+    pub keys: Vec<SortKey>,
+    /// Highlighted key; one past the last is the row that adds a key
+    pub cursor: usize,
+    /// Whether the name to save under is being typed
+    pub naming: bool,
+}
+
+/// Output of a one-off remote command shown in the results pane
+pub struct CommandOutput {
+    /// Node the command ran on
+    pub node: String,
+    /// The command line as typed
+    pub command: String,
+    /// Combined stdout and stderr lines, as they arrive
+    pub lines: Vec<String>,
+    /// Exit status description once the command has finished
+    pub status: Option<String>,
+    /// First visible line in the results pane
+    pub scroll: usize,
+    /// Keep the newest lines in view as they arrive, until scrolled up
+    pub follow: bool,
+    /// The background task running the command, stopped when the pane goes away
+    pub task: Option<AbortHandle>,
+}
+
+impl CommandOutput {
+    /// Scroll the results pane by `delta` lines, staying within the output.
+    /// Scrolling up stops following new output.
+    pub fn scroll_by(&mut self, delta: isize) {
+        let max_scroll = self.lines.len().saturating_sub(1);
+        self.scroll = self.scroll.saturating_add_signed(delta).min(max_scroll);
+        if delta < 0 {
+            self.follow = false;
+        }
+    }
+}
+
+impl Drop for CommandOutput {
+    /// Closing the pane, or replacing it, stops a command that is still running
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// Rows moved by PageUp/PageDown before the list or pane has been drawn
+const DEFAULT_PAGE_SIZE: usize = 10;
+
+/// Longest gap between two clicks on the same node that counts as a double click
+const DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+
+/// Where the node list was last drawn, so mouse positions can be mapped to rows
+pub struct ListView {
+    /// Screen area the rows were drawn in
+    pub area: Rect,
+    /// Display position of the first visible row
+    pub offset: usize,
+}
+
+/// One row of the node list
+#[derive(Debug, Clone, PartialEq)]
+pub enum Row {
+    /// A node, by position in `App::nodes`
+    Node(usize),
+    /// The nodes of a `ui.collapse` entry, as one row until it is expanded
+    Folded(Fold),
+}
+
+/// Nodes folded into one row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fold {
+    /// The `ui.collapse` entry they match
+    pub pattern: String,
+    /// Their positions in `App::nodes`, in list order
+    pub nodes: Vec<usize>,
+    pub online: usize,
+}
+
+/// App state for the terminal UI
+pub struct App {
+    /// All available nodes
+    pub nodes: Vec<TailscaleNode>,
+    /// Lookup keys for the nodes, which the filter matches against
+    pub index: NodeIndex,
+    /// Rows of the list: the nodes the filter, group and `ui.hide` leave, in
+    /// order, with those of unexpanded `ui.collapse` entries folded together
+    pub rows: Vec<Row>,
+    /// `ui.collapse` entries whose nodes are listed one by one
+    pub expanded: BTreeSet<String>,
+    /// Current search filter text
+    pub filter: String,
+    /// Group the list is narrowed to, or `None` for every node
+    pub group: Option<String>,
+    /// Whether the nodes `ui.hide` leaves out are listed anyway, for now
+    pub show_hidden: bool,
+    /// Currently selected node index in filtered list
+    pub selection: usize,
+    /// What keyboard input is currently driving
+    pub mode: Mode,
+    /// Loaded configuration, used to compose ssh invocations for actions
+    pub config: Config,
+    /// State of this machine's Tailscale client, shown in the header
+    pub health: TailnetHealth,
+    /// Whether the nodes are still the ones saved by an earlier run, until the first refresh
+    pub cached: bool,
+    /// Whether the detail pane with the selected node's effective settings is shown
+    pub show_details: bool,
+    /// Keys bound to each action
+    pub keys: KeyMap,
+    /// Key hints shown in the footer while browsing
+    pub footer_hints: Vec<FooterHint>,
+    /// Custom actions from the config, in menu order
+    pub actions: Vec<MenuAction>,
+    /// Service forwards started by this run
+    pub tunnels: Tunnels,
+    /// Nodes recently disturbed by actions, from every run on this machine
+    pub cooldowns: Cooldowns,
+    /// Whether each online node's ssh port answered, by hostname. Each node is
+    /// probed once per run, when it is first seen online.
+    pub ports: HashMap<String, PortState>,
+    /// How the tailnet changed since the node list saved by the last run, once
+    /// a fresh list has replaced it
+    pub changes: Option<TailnetChanges>,
+    /// Forwarding turned on or off for the session the picker starts
+    pub forwarding: ForwardingToggles,
+    /// Styles for everything drawn
+    pub theme: Theme,
+    /// The node list as last drawn, if it is on screen
+    pub list_view: Option<ListView>,
+    /// Lines of text the output pane or help showed when last drawn
+    pub pane_height: Option<usize>,
+    /// Display position of the first row shown, kept between frames so the list
+    /// only scrolls when the selection would leave the view
+    pub list_offset: usize,
+    /// Scroll the selection to the middle of the list on the next draw, after a jump
+    pub center_selection: bool,
+    /// When and on which node the last mouse click landed, to detect double clicks
+    pub last_click: Option<(Instant, usize)>,
+}
+
+impl App {
+    /// Create a new App with the provided nodes
+    pub fn new(nodes: Vec<TailscaleNode>, config: Config) -> Self {
+        let rows = (0..nodes.len()).map(Row::Node).collect();
+        let index = NodeIndex::new(&nodes, &config);
+        Self {
+            nodes,
+            index,
+            rows,
+            expanded: BTreeSet::new(),
+            filter: String::new(),
+            group: None,
+            show_hidden: false,
+            selection: 0,
+            mode: Mode::Browse,
+            config,
+            health: TailnetHealth::default(),
+            cached: false,
+            show_details: false,
+            keys: KeyMap::default(),
+            footer_hints: Vec::new(),
+            actions: Vec::new(),
+            tunnels: Tunnels::default(),
+            cooldowns: Cooldowns::default(),
+            ports: HashMap::new(),
+            changes: None,
+            forwarding: ForwardingToggles::default(),
+            theme: Theme::default(),
+            list_view: None,
+            pane_height: None,
+            list_offset: 0,
+            center_selection: false,
+            last_click: None,
+        }
+    }
+
+    /// Apply the current filter to the nodes list, keeping the same node
+    /// selected while it is still listed
+    pub fn apply_filter(&mut self) {
+        let selected = self.selected_identity();
+        self.rebuild_rows(selected.as_ref());
+    }
+
+    /// Look nodes up by their current aliases after the config changed, and filter again
+    pub fn reindex(&mut self) {
+        self.index = NodeIndex::new(&self.nodes, &self.config);
+        self.apply_filter();
+    }
+
+    /// The node the selection is on, or the first node of the selected folded
+    /// row, to find it again once the rows change
+    fn selected_identity(&self) -> Option<TailscaleNode> {
+        let position = match self.rows.get(self.selection)? {
+            &Row::Node(position) => position,
+            Row::Folded(fold) => fold.nodes[0],
+        };
+        self.nodes.get(position).cloned()
+    }
+
+    /// Filter, sort and fold the nodes into rows again, then put the selection
+    /// back on `selected` by its Tailscale ID, so renames and reordering don't
+    /// move it. When that node is no longer listed, the first row is selected.
+    fn rebuild_rows(&mut self, selected: Option<&TailscaleNode>) {
+        // Names, aliases or `tag:` prefixes, the same way node arguments match on the command line
+        let mut positions = self.index.matches(&self.filter);
+        if let Some(group) = &self.group {
+            positions.retain(|&position| group_contains(&self.config, group, &self.nodes[position]));
+        }
+        if !self.show_hidden {
+            positions.retain(|&position| !is_hidden(&self.config, &self.nodes[position]));
+        }
+        if let Some(keys) = active_sort(&self.config) {
+            sort(&self.config, keys, &self.nodes, &mut positions);
+        }
+        self.rows = self.fold(positions);
+
+        self.selection = selected
+            .and_then(|selected| self.row_of(|node| node.same_node(selected)))
+            .unwrap_or(0);
+    }
+
+    /// Turn positions into rows, folding the nodes of each unexpanded
+    /// `ui.collapse` entry into one row where the first of them would be.
+    /// A search is for particular nodes, so nothing is folded while there is one.
+    fn fold(&self, positions: Vec<usize>) -> Vec<Row> {
+        let patterns: Vec<&String> = self
+            .config
+            .ui
+            .collapse
+            .iter()
+            .filter(|pattern| !self.expanded.contains(*pattern))
+            .collect();
+        if patterns.is_empty() || !self.filter.is_empty() {
+            return positions.into_iter().map(Row::Node).collect();
+        }
+
+        let mut rows = Vec::with_capacity(positions.len());
+        let mut fold_rows: HashMap<&String, usize> = HashMap::new();
+        for position in positions {
+            let node = &self.nodes[position];
+            let Some(pattern) = patterns.iter().find(|pattern| member_matches(pattern, node)) else {
+                rows.push(Row::Node(position));
+                continue;
+            };
+            let row = *fold_rows.entry(pattern).or_insert_with(|| {
+                rows.push(Row::Folded(Fold {
+                    pattern: pattern.to_string(),
+                    nodes: Vec::new(),
+                    online: 0,
+                }));
+                rows.len() - 1
+            });
+            if let Row::Folded(fold) = &mut rows[row] {
+                fold.nodes.push(position);
+                fold.online += usize::from(node.is_online());
+            }
+        }
+
+        // A single node has nothing to fold it with
+        for row in &mut rows {
+            if let Row::Folded(fold) = row
+                && fold.nodes.len() == 1
+            {
+                *row = Row::Node(fold.nodes[0]);
+            }
+        }
+        rows
+    }
+
+    /// Expand the selected folded row, or fold the nodes of the expanded entry
+    /// the selected node belongs to again. The selection stays on the same nodes.
+    pub fn toggle_fold(&mut self) {
+        match self.rows.get(self.selection) {
+            Some(Row::Folded(fold)) => {
+                self.expanded.insert(fold.pattern.clone());
+                self.apply_filter();
+            }
+            Some(&Row::Node(position)) => {
+                let node = &self.nodes[position];
+                let Some(pattern) = self
+                    .expanded
+                    .iter()
+                    .find(|pattern| self.config.ui.collapse.contains(pattern) && member_matches(pattern, node))
+                    .cloned()
+                else {
+                    return;
+                };
+                self.expanded.remove(&pattern);
+                self.apply_filter();
+            }
+            None => {}
+        }
+    }
+
+    /// Expand the selected row if it is a folded one; used where choosing it
+    /// would otherwise connect. Returns whether it was.
+    pub fn unfold_selected(&mut self) -> bool {
+        if matches!(self.rows.get(self.selection), Some(Row::Folded(_))) {
+            self.toggle_fold();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Move selection up (visually) - IMPORTANT: When rendering bottom-to-top, 
+    /// moving "up" visually means INCREASING the index in the array
+    pub fn move_selection_up(&mut self) {
+        if !self.rows.is_empty() && self.selection + 1 < self.rows.len() {
+            self.selection += 1;
+        }
+    }
+
+    /// Move selection down (visually) - IMPORTANT: When rendering bottom-to-top,
+    /// moving "down" visually means DECREASING the index in the array
+    pub fn move_selection_down(&mut self) {
+        if !self.rows.is_empty() && self.selection > 0 {
+            self.selection -= 1;
+        }
+    }
+
+    /// Move selection up (visually) a full page, leaving it in the middle of the view
+    pub fn move_page_up(&mut self) {
+        if self.rows.is_empty() {
+            return;
+        }
+
+        self.selection = (self.selection + self.page_size()).min(self.rows.len() - 1);
+        self.center_selection = true;
+    }
+
+    /// Move selection down (visually) a full page, leaving it in the middle of the view
+    pub fn move_page_down(&mut self) {
+        if self.rows.is_empty() {
+            return;
+        }
+
+        self.selection = self.selection.saturating_sub(self.page_size());
+        self.center_selection = true;
+    }
+
+    /// Rows PageUp/PageDown move in the list: `ui.page_size`, or else as many as
+    /// the list showed when last drawn
+    fn page_size(&self) -> usize {
+        self.config.ui.page_size.unwrap_or_else(|| {
+            self.list_view
+                .as_ref()
+                .map_or(DEFAULT_PAGE_SIZE, |view| usize::from(view.area.height))
+        })
+        .max(1)
+    }
+
+    /// Lines PageUp/PageDown scroll the output pane and help: `ui.page_size`, or
+    /// else as many as the pane showed when last drawn
+    pub fn pane_page_size(&self) -> usize {
+        self.config.ui.page_size.or(self.pane_height).unwrap_or(DEFAULT_PAGE_SIZE).max(1)
+    }
+
+    /// Move to the first item in the list
+    pub fn move_to_start(&mut self) {
+        if !self.rows.is_empty() {
+            self.selection = 0;
+        }
+    }
+
+    /// Move to the last item in the list
+    pub fn move_to_end(&mut self) {
+        if !self.rows.is_empty() {
+            self.selection = self.rows.len() - 1;
+        }
+    }
+
+    /// Replace the node list with a fresh one, keeping the same node selected if it still exists,
+    /// even under a new name
+    pub fn replace_nodes(&mut self, nodes: Vec<TailscaleNode>) {
+        let selected = self.selected_identity();
+        self.index = NodeIndex::new(&nodes, &self.config);
+        self.nodes = nodes;
+        self.rebuild_rows(selected.as_ref());
+        self.follow_edited_node();
+    }
+
+    /// Keep the username editor on its node when a refresh renames it or changes
+    /// its address, tags or OS. With an edit in progress the user is asked which
+    /// version the edit is for, so typed text never lands on a stale name.
+    fn follow_edited_node(&mut self) {
+        // The node editor only needs the current name, which its settings are kept under
+        if let Mode::EditNode(editor) = &mut self.mode
+            && let Some(fresh) = self.nodes.iter().find(|node| node.same_node(&editor.node))
+        {
+            editor.node = fresh.clone();
+        }
+        let Mode::UsernameSettings(editor) = &mut self.mode else {
+            return;
+        };
+        let Some(fresh) = self.nodes.iter().find(|node| node.same_node(&editor.node)) else {
+            return;
+        };
+        let changes = fresh.identity_changes(&editor.node);
+        if changes.is_empty() {
+            return;
+        }
+
+        if editor.input.is_some() {
+            editor.conflict = Some(fresh.clone());
+        } else {
+            editor.retarget(fresh.clone());
+            editor.message = Some(format!("Updated from Tailscale: {}", changes.join("; ")));
+        }
+    }
+
+    /// Select a node by hostname, or the folded row it is in, if the filter shows it
+    pub fn select_node(&mut self, name: &str) -> bool {
+        match self.row_of(|node| node.name == name) {
+            Some(row) => {
+                self.selection = row;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The row of the first listed node `matches` accepts, or of the folded row it is in
+    fn row_of(&self, matches: impl Fn(&TailscaleNode) -> bool) -> Option<usize> {
+        let is_match = |&position: &usize| matches(&self.nodes[position]);
+        self.rows.iter().position(|row| match row {
+            Row::Node(position) => is_match(position),
+            Row::Folded(fold) => fold.nodes.iter().any(is_match),
+        })
+    }
+
+    /// The group list: every node, then each configured group, with how many
+    /// nodes it has and how many are online
+    pub fn group_entries(&self) -> Vec<GroupEntry> {
+        let entry = |name: Option<&String>| {
+            let members: Vec<&TailscaleNode> = self
+                .nodes
+                .iter()
+                .filter(|node| name.is_none_or(|group| group_contains(&self.config, group, node)))
+                .collect();
+            GroupEntry {
+                name: name.cloned(),
+                nodes: members.len(),
+                online: members.iter().filter(|node| node.is_online()).count(),
+            }
+        };
+        std::iter::once(entry(None))
+            .chain(self.config.groups.keys().map(|group| entry(Some(group))))
+            .collect()
+    }
+
+    /// List only the members of a group, or every node for `None`
+    pub fn set_group(&mut self, group: Option<String>) {
+        self.group = group;
+        self.apply_filter();
+    }
+
+    /// How many nodes `ui.hide` leaves out of the list, while they are hidden
+    pub fn hidden_count(&self) -> usize {
+        if self.show_hidden {
+            return 0;
+        }
+        self.nodes.iter().filter(|node| is_hidden(&self.config, node)).count()
+    }
+
+    /// Order the list by a named sort, or Tailscale's order for `None`, keeping the same node selected
+    pub fn set_sort(&mut self, name: Option<String>) {
+        self.config.ui.sort = name;
+        self.apply_filter();
+    }
+
+    /// Handle a browsing key that isn't bound to anything beyond the list
+    /// itself: move the selection, show details, or edit the filter
+    pub fn navigate(&mut self, action: Option<Action>, key: &KeyEvent) {
+        match action {
+            // Navigation in visual direction
+            Some(Action::Up) => self.move_selection_up(),
+            Some(Action::Down) => self.move_selection_down(),
+            Some(Action::PageUp) => self.move_page_up(),
+            Some(Action::PageDown) => self.move_page_down(),
+            Some(Action::Top) => self.move_to_start(),
+            Some(Action::Bottom) => self.move_to_end(),
+            // Show or hide the detail pane
+            Some(Action::ToggleDetails) => self.show_details = !self.show_details,
+            // List the nodes `ui.hide` leaves out, or leave them out again, keeping the selection
+            Some(Action::ShowHidden) => {
+                self.show_hidden = !self.show_hidden;
+                self.apply_filter();
+            }
+            Some(Action::Fold) => self.toggle_fold(),
+            Some(Action::ClearFilter) => {
+                self.filter.clear();
+                self.apply_filter();
+            }
+            Some(_) => {}
+            // Any other key edits the filter text
+            None => match key.code {
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                    self.apply_filter();
+                }
+                KeyCode::Char(c) => {
+                    self.filter.push(c);
+                    self.apply_filter();
+                }
+                _ => {}
+            },
+        }
+    }
+
+    /// Handle the mouse over the list: the wheel moves the selection and a click
+    /// selects the node under it. Returns whether it was the second click of a
+    /// double click on that node.
+    pub fn mouse(&mut self, mouse: &MouseEvent) -> bool {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => self.move_selection_up(),
+            MouseEventKind::ScrollDown => self.move_selection_down(),
+            MouseEventKind::Down(MouseButton::Left) => {
+                let Some(index) = self.node_at(mouse.column, mouse.row) else {
+                    return false;
+                };
+                self.selection = index;
+
+                let now = Instant::now();
+                let double_click = self
+                    .last_click
+                    .is_some_and(|(at, clicked)| clicked == index && now.duration_since(at) <= DOUBLE_CLICK_INTERVAL);
+                self.last_click = if double_click { None } else { Some((now, index)) };
+                return double_click;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// The selection index of the row drawn at a screen position, if any.
+    /// Rows are drawn in reverse, so the top row is the last one.
+    pub fn node_at(&self, column: u16, row: u16) -> Option<usize> {
+        let view = self.list_view.as_ref()?;
+        let area = view.area;
+        if column < area.x || column >= area.right() || row < area.y || row >= area.bottom() {
+            return None;
+        }
+
+        let display_index = view.offset + usize::from(row - area.y);
+        (display_index < self.rows.len()).then(|| self.rows.len() - 1 - display_index)
+    }
+
+    /// Get the currently selected node, if available; `None` on a folded row
+    pub fn get_selected_node(&self) -> Option<&TailscaleNode> {
+        match self.rows.get(self.selection)? {
+            &Row::Node(position) => Some(&self.nodes[position]),
+            Row::Folded(_) => None,
+        }
+    }
+
+    /// The action menu for the selected node: the custom actions and plugins,
+    /// then the node's own services
+    pub fn menu_entries(&self) -> Vec<MenuAction> {
+        let mut entries = self.actions.clone();
+        if let Some(node) = self.get_selected_node() {
+            entries.extend(service_actions(&self.config, node));
+        }
+        entries
+    }
+}
+
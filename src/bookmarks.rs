@@ -0,0 +1,127 @@
+use crate::config::{Config, NodeSettings};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Version of the bookmark file format written by this build
+pub const BOOKMARK_FORMAT_VERSION: u32 = 1;
+
+/// A shareable set of curated node bookmarks, meant to be committed to a team repo.
+/// Only hand-curated data is included; usernames and history stay local.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BookmarkFile {
+    /// Format version, bumped on incompatible changes
+    pub version: u32,
+    /// Bookmarked nodes, keyed by node hostname
+    #[serde(default)]
+    pub nodes: BTreeMap<String, Bookmark>,
+    /// Named groups of nodes
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+}
+
+/// The curated fields of a single node
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct Bookmark {
+    /// Short alternative name for the node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// Free-text notes about the node
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub notes: String,
+    /// Whether the node is marked as protected
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub protected: bool,
+}
+
+impl Bookmark {
+    /// Extract the curated fields from a node's settings
+    fn from_settings(settings: &NodeSettings) -> Self {
+        Self {
+            alias: settings.alias.clone(),
+            notes: settings.notes.clone(),
+            protected: settings.protected,
+        }
+    }
+
+    /// Whether this bookmark carries no information worth sharing
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl BookmarkFile {
+    /// Build a bookmark file from the curated parts of the config
+    pub fn from_config(config: &Config) -> Self {
+        let nodes = config
+            .nodes
+            .iter()
+            .map(|(name, settings)| (name.clone(), Bookmark::from_settings(settings)))
+            .filter(|(_, bookmark)| !bookmark.is_empty())
+            .collect();
+
+        Self {
+            version: BOOKMARK_FORMAT_VERSION,
+            nodes,
+            groups: config.groups.clone(),
+        }
+    }
+
+    /// Merge these bookmarks into the config; imported values win over local ones
+    pub fn merge_into(self, config: &mut Config) {
+        for (name, bookmark) in self.nodes {
+            let settings = config.nodes.entry(name).or_default();
+            settings.alias = bookmark.alias;
+            settings.notes = bookmark.notes;
+            settings.protected = bookmark.protected;
+        }
+        config.groups.extend(self.groups);
+    }
+}
+
+/// Write the config's bookmarks to a file, or to stdout when no path is given
+pub fn export_bookmarks(config: &Config, path: Option<&Path>) -> Result<()> {
+    let bookmarks = BookmarkFile::from_config(config);
+    let contents = serde_json::to_string_pretty(&bookmarks)?;
+
+    match path {
+        Some(path) => {
+            fs::write(path, contents + "\n")
+                .with_context(|| format!("Failed to write bookmarks to {}", path.display()))?;
+            println!(
+                "Exported {} nodes and {} groups to {}",
+                bookmarks.nodes.len(),
+                bookmarks.groups.len(),
+                path.display()
+            );
+        }
+        None => println!("{}", contents),
+    }
+
+    Ok(())
+}
+
+/// Read a bookmark file and merge it into the config
+pub fn import_bookmarks(config: &mut Config, path: &Path) -> Result<()> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read bookmarks from {}", path.display()))?;
+    let bookmarks: BookmarkFile = serde_json::from_str(&contents)
+        .with_context(|| format!("{} is not a valid bookmark file", path.display()))?;
+
+    if bookmarks.version > BOOKMARK_FORMAT_VERSION {
+        return Err(anyhow!(
+            "{} uses bookmark format version {}, but this version of ssh-tailscale only understands up to {}",
+            path.display(),
+            bookmarks.version,
+            BOOKMARK_FORMAT_VERSION
+        ));
+    }
+
+    let node_count = bookmarks.nodes.len();
+    let group_count = bookmarks.groups.len();
+    bookmarks.merge_into(config);
+    println!("Imported {} nodes and {} groups from {}", node_count, group_count, path.display());
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use crate::{config::BootstrapConfig, platform::shell_command, ssh::SshCommand, subprocess, tailscale::TailscaleNode};
+use anyhow::{anyhow, Context, Result};
+use std::process::Stdio;
+
+/// Run the configured bootstrap against a node that has never been connected to
+pub fn run_bootstrap(bootstrap: &BootstrapConfig, ssh: &SshCommand, node: &TailscaleNode) -> Result<()> {
+    if let Some(local_command) = &bootstrap.local_command {
+        println!("Bootstrapping {}: {}", node.name, local_command);
+        let mut command = shell_command(local_command);
+        command
+            .env("SSH_TAILSCALE_NODE", &node.name)
+            .env("SSH_TAILSCALE_IP", &node.ip)
+            .env("SSH_TAILSCALE_USER", ssh.user())
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        let status = subprocess::status(&mut command).context("Failed to run local bootstrap command")?;
+        if !status.success() {
+            return Err(anyhow!("Local bootstrap command failed with {}", status));
+        }
+    }
+
+    if let Some(remote_command) = &bootstrap.remote_command {
+        println!("Bootstrapping {} remotely: {}", node.name, remote_command);
+        let status = ssh
+            .clone()
+            .remote_command(vec![remote_command.clone()])
+            .run_interactive()?;
+        if !status.success() {
+            return Err(anyhow!("Remote bootstrap command failed with {}", status));
+        }
+    }
+
+    Ok(())
+}
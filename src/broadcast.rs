@@ -0,0 +1,335 @@
+use crate::{
+    config::Config,
+    history::{format_iso_utc, unix_now},
+    hooks, humanize,
+    presence::load_presence,
+    secrets,
+    secure::{create_private_dir, write_private},
+    selector::{is_left_out_self, Selector},
+    ssh::{limit_reached_message, SshCommand},
+    subprocess::Subprocess,
+    tailscale::TailscaleNode,
+};
+use anyhow::{anyhow, Context, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::Serialize;
+use std::{
+    io,
+    path::Path,
+    process::{ExitStatus, Stdio},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    sync::mpsc,
+};
+
+/// How long to keep reading a node's output once its command is stopped at the session limit
+const DRAIN_AFTER_KILL: Duration = Duration::from_secs(1);
+
+/// Safety filters applied to the matched nodes before anything runs
+#[derive(Default)]
+pub struct BroadcastGates {
+    /// Nodes matching any of these are skipped
+    pub exclude: Vec<Selector>,
+    /// Only run on nodes seen online for at least this long
+    pub min_online: Option<Duration>,
+}
+
+/// Pick the nodes a broadcast will run on, printing why any matched node was held back
+pub fn select_targets<'a>(
+    nodes: &'a [TailscaleNode],
+    config: &Config,
+    selector: &Selector,
+    gates: &BroadcastGates,
+) -> Result<Vec<&'a TailscaleNode>> {
+    let presence = load_presence()?;
+    let mut targets = Vec::new();
+
+    for node in nodes.iter().filter(|node| selector.matches(node, config)) {
+        if is_left_out_self(config, node) {
+            println!("Skipping {}: this machine", node.name);
+        } else if !node.is_online() {
+            println!("Skipping {}: offline", node.name);
+        } else if node.platform().is_mobile() {
+            println!("Skipping {}: {} has no SSH server", node.name, node.os);
+        } else if gates.exclude.iter().any(|exclude| exclude.matches(node, config)) {
+            println!("Skipping {}: excluded", node.name);
+        } else if let Some(min_online) = gates.min_online
+            && presence.online_for(&node.name).is_none_or(|online| online < min_online)
+        {
+            println!(
+                "Skipping {}: not seen online for {} yet",
+                node.name,
+                humanize::duration(min_online)
+            );
+        } else {
+            targets.push(node);
+        }
+    }
+
+    Ok(targets)
+}
+
+/// One line of a node's output, with when it arrived
+struct OutputLine {
+    at: u64,
+    stderr: bool,
+    text: String,
+}
+
+/// How a command went on one node
+struct NodeRun {
+    name: String,
+    username: String,
+    started: u64,
+    finished: u64,
+    /// The exit status, or why the command didn't run
+    status: std::result::Result<ExitStatus, String>,
+    lines: Vec<OutputLine>,
+}
+
+/// What `summary.json` in the output directory records about a broadcast
+#[derive(Serialize)]
+struct Summary<'a> {
+    command: &'a [String],
+    started: String,
+    finished: String,
+    succeeded: usize,
+    failed: usize,
+    nodes: Vec<NodeSummary>,
+}
+
+/// A node's entry in `summary.json`
+#[derive(Serialize)]
+struct NodeSummary {
+    node: String,
+    user: String,
+    started: String,
+    finished: String,
+    /// `None` when the command didn't run or was killed by a signal
+    exit_code: Option<i32>,
+    /// Why the command didn't run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The node's log file, relative to the output directory
+    log: String,
+}
+
+/// Run a command on every target in parallel, printing each node's output as it
+/// finishes and a summary at the end. With an output directory, each node's
+/// output also goes to `<node>.log` there, with a timestamp per line, and the
+/// results to `summary.json`.
+pub async fn broadcast(
+    config: &Config,
+    targets: &[&TailscaleNode],
+    command: &[String],
+    output_dir: Option<&Path>,
+) -> Result<()> {
+    if targets.is_empty() {
+        return Err(anyhow!("No nodes left to run on"));
+    }
+    // Find out about an unusable directory before anything runs
+    if let Some(dir) = output_dir {
+        create_private_dir(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let usernames = targets
+        .iter()
+        .map(|node| secrets::connect_username(config, node))
+        .collect::<Result<Vec<_>>>()?;
+
+    let started = unix_now();
+    // The hooks run one at a time, before anything runs, so their output and prompts stay readable
+    let hooked: Vec<_> = targets
+        .iter()
+        .zip(usernames)
+        .map(|(node, username)| {
+            let hook = hooks::run_before_connect(config, node, &username).map_err(|err| format!("{:#}", err));
+            (node, username, hook)
+        })
+        .collect();
+    let mut runs: FuturesUnordered<_> = hooked
+        .into_iter()
+        .map(|(node, username, hook)| {
+            // Nobody can answer a password prompt for a dozen nodes at once
+            let ssh = SshCommand::for_node(config, &username, node)
+                .option("BatchMode", "yes")
+                .remote_command(command.to_vec());
+            let name = node.name.clone();
+            async move {
+                let started = unix_now();
+                let (status, lines) = match hook {
+                    Err(err) => (Err(err), Vec::new()),
+                    Ok(()) => match run_captured(&ssh).await {
+                        Ok((status, lines)) => (Ok(status), lines),
+                        Err(err) => (Err(format!("failed to start ssh: {}", err)), Vec::new()),
+                    },
+                };
+                NodeRun { name, username, started, finished: unix_now(), status, lines }
+            }
+        })
+        .collect();
+
+    let mut results = Vec::new();
+    while let Some(run) = runs.next().await {
+        match &run.status {
+            Ok(status) => {
+                println!("== {} ({}) ==", run.name, status);
+                for line in &run.lines {
+                    if line.stderr {
+                        eprintln!("{}", line.text);
+                    } else {
+                        println!("{}", line.text);
+                    }
+                }
+            }
+            Err(err) => println!("== {} ({}) ==", run.name, err),
+        }
+        if let Some(dir) = output_dir {
+            write_log(dir, command, &run)?;
+        }
+        results.push(run);
+    }
+
+    let mut failed: Vec<&str> = results
+        .iter()
+        .filter(|run| !run.status.as_ref().is_ok_and(ExitStatus::success))
+        .map(|run| run.name.as_str())
+        .collect();
+    println!(
+        "Ran on {} nodes: {} succeeded, {} failed",
+        targets.len(),
+        targets.len() - failed.len(),
+        failed.len()
+    );
+    if let Some(dir) = output_dir {
+        write_summary(dir, command, started, &results, failed.len())?;
+        println!("Logs and summary.json are in {}", dir.display());
+    }
+    if !failed.is_empty() {
+        failed.sort();
+        return Err(anyhow!("Failed on: {}", failed.join(", ")));
+    }
+
+    Ok(())
+}
+
+/// Run ssh, collecting its stdout and stderr lines in the order they arrive,
+/// and stopping it at the node's session limit
+async fn run_captured(ssh: &SshCommand) -> io::Result<(ExitStatus, Vec<OutputLine>)> {
+    let command = ssh.command();
+    let subprocess = Subprocess::start(&command);
+    let mut child = tokio::process::Command::from(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .inspect_err(|err| subprocess.finish(Err(err)))?;
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(forward_lines(stdout, false, sender.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(forward_lines(stderr, true, sender));
+    }
+
+    // The channel closes once both streams have ended
+    let mut lines = Vec::new();
+    let collect = async {
+        while let Some(line) = receiver.recv().await {
+            lines.push(line);
+        }
+    };
+    let limit = ssh.time_limit().map(|(max, _)| max);
+    let reached = match limit {
+        Some(max) => tokio::time::timeout(max, collect).await.is_err(),
+        None => {
+            collect.await;
+            false
+        }
+    };
+    if let Some(max) = limit.filter(|_| reached) {
+        let _ = child.start_kill();
+        // Whatever ssh started may still hold the streams open
+        let _ = tokio::time::timeout(DRAIN_AFTER_KILL, async {
+            while let Some(line) = receiver.recv().await {
+                lines.push(line);
+            }
+        })
+        .await;
+        lines.push(OutputLine { at: unix_now(), stderr: true, text: limit_reached_message(max) });
+    }
+    let status = child.wait().await;
+    subprocess.finish(status.as_ref());
+    Ok((status?, lines))
+}
+
+/// Send each line of a stream, stamped with the time it arrived, until the stream ends
+async fn forward_lines(stream: impl AsyncRead + Unpin, stderr: bool, sender: mpsc::UnboundedSender<OutputLine>) {
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    while matches!(reader.read_until(b'\n', &mut line).await, Ok(read) if read > 0) {
+        let text = String::from_utf8_lossy(&line).trim_end_matches(['\n', '\r']).to_string();
+        let _ = sender.send(OutputLine { at: unix_now(), stderr, text });
+        line.clear();
+    }
+}
+
+/// The log file name for a node, which stays inside the output directory whatever the name
+fn log_name(node: &str) -> String {
+    format!("{}.log", node.replace(['/', '\\'], "_"))
+}
+
+/// Write a node's output to its log, each line stamped and marked `out` or `err`
+fn write_log(dir: &Path, command: &[String], run: &NodeRun) -> Result<()> {
+    let mut log = format!(
+        "# node: {}\n# user: {}\n# command: {}\n# started: {}\n",
+        run.name,
+        run.username,
+        command.join(" "),
+        format_iso_utc(run.started)
+    );
+    for line in &run.lines {
+        let stream = if line.stderr { "err" } else { "out" };
+        log.push_str(&format!("{} {} {}\n", format_iso_utc(line.at), stream, line.text));
+    }
+    let outcome = match &run.status {
+        Ok(status) => status.to_string(),
+        Err(err) => err.clone(),
+    };
+    log.push_str(&format!("# finished: {}, {}\n", format_iso_utc(run.finished), outcome));
+
+    let path = dir.join(log_name(&run.name));
+    write_private(&path, log.as_bytes()).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Write `summary.json`, with the nodes sorted by name
+fn write_summary(dir: &Path, command: &[String], started: u64, results: &[NodeRun], failed: usize) -> Result<()> {
+    let mut nodes: Vec<NodeSummary> = results
+        .iter()
+        .map(|run| NodeSummary {
+            node: run.name.clone(),
+            user: run.username.clone(),
+            started: format_iso_utc(run.started),
+            finished: format_iso_utc(run.finished),
+            exit_code: run.status.as_ref().ok().and_then(ExitStatus::code),
+            error: run.status.as_ref().err().cloned(),
+            log: log_name(&run.name),
+        })
+        .collect();
+    nodes.sort_by(|a, b| a.node.cmp(&b.node));
+
+    let summary = Summary {
+        command,
+        started: format_iso_utc(started),
+        finished: format_iso_utc(unix_now()),
+        succeeded: results.len() - failed,
+        failed,
+        nodes,
+    };
+    let path = dir.join("summary.json");
+    write_private(&path, serde_json::to_string_pretty(&summary)?.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,73 @@
+use crate::tailscale::TailscaleNode;
+use std::time::Instant;
+
+/// How the tailnet changed between the node list saved by the last run and
+/// the first fresh one, by hostname
+#[derive(Debug, Clone)]
+pub struct TailnetChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub came_online: Vec<String>,
+    pub went_offline: Vec<String>,
+    /// When the fresh list came in
+    pub found: Instant,
+}
+
+impl TailnetChanges {
+    /// Compare two node lists. A renamed node is matched by its ID and listed
+    /// under its new name.
+    pub fn between(before: &[TailscaleNode], after: &[TailscaleNode]) -> Self {
+        let mut changes = TailnetChanges {
+            added: Vec::new(),
+            removed: Vec::new(),
+            came_online: Vec::new(),
+            went_offline: Vec::new(),
+            found: Instant::now(),
+        };
+        for node in after {
+            match before.iter().find(|earlier| earlier.same_node(node)) {
+                None => changes.added.push(node.name.clone()),
+                Some(earlier) if !earlier.is_online() && node.is_online() => changes.came_online.push(node.name.clone()),
+                Some(earlier) if earlier.is_online() && !node.is_online() => {
+                    changes.went_offline.push(node.name.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        changes.removed = before
+            .iter()
+            .filter(|earlier| !after.iter().any(|node| node.same_node(earlier)))
+            .map(|earlier| earlier.name.clone())
+            .collect();
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections().iter().all(|(_, names)| names.is_empty())
+    }
+
+    /// Whether anything left or dropped off the tailnet, which is worth a warning
+    pub fn has_losses(&self) -> bool {
+        !self.removed.is_empty() || !self.went_offline.is_empty()
+    }
+
+    /// Each kind of change with its nodes, in the order they are shown
+    pub fn sections(&self) -> [(&'static str, &[String]); 4] {
+        [
+            ("went offline", &self.went_offline),
+            ("removed", &self.removed),
+            ("added", &self.added),
+            ("came online", &self.came_online),
+        ]
+    }
+
+    /// The counts of each kind of change, like `2 went offline, 1 added`
+    pub fn summary(&self) -> String {
+        self.sections()
+            .iter()
+            .filter(|(_, names)| !names.is_empty())
+            .map(|(label, names)| format!("{} {}", names.len(), label))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
@@ -0,0 +1,789 @@
+use crate::{
+    broadcast::BroadcastGates,
+    completion::Shell,
+    model::SchemaKind,
+    plugins::{self, Plugin},
+    selector::Selector,
+    config::X11Forwarding,
+    ssh::{CopyDirection, ForwardingToggles},
+    ssh_uri::SshUri,
+    table::{ColorChoice, ListColumn},
+};
+use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser, ValueEnum};
+use std::{ffi::OsString, fmt::Display, path::PathBuf, time::Duration};
+
+/// Parsed command line: global options plus the command to run
+pub struct Cli {
+    /// The command to run
+    pub command: Command,
+    /// Options that apply to every command
+    pub options: Options,
+}
+
+/// Options shared by the commands, given before or after the command
+#[derive(Clone, Default)]
+pub struct Options {
+    /// Retry failed connections automatically instead of asking first
+    pub retry: bool,
+    /// Reconnect to the last used node and username without showing the picker
+    pub last: bool,
+    /// With `last`, still ask for the username
+    pub pick_user: bool,
+    /// Don't read or write any config, history or other state
+    pub ephemeral: bool,
+    /// Reconnect interactive sessions whose connection drops
+    pub reconnect: bool,
+    /// Go back to the picker after each session instead of exiting
+    pub loop_picker: bool,
+    /// Pick nodes from a numbered text menu instead of the terminal UI
+    pub plain: bool,
+    /// Start with the default keys, theme and layout, and no hooks, actions or plugins
+    pub safe_mode: bool,
+    /// Print the ssh command a session would run instead of running it
+    pub dry_run: bool,
+    /// Print how long each step of connecting took, and log to the rotating
+    /// log file: 1 for `-v`, 2 or more for `-vv` to include debug detail
+    pub verbosity: u8,
+    /// Append a span for every program run to the debug log
+    pub debug_log: bool,
+    /// How listings are printed
+    pub emit: Emit,
+    /// Whether `--list` colors its table
+    pub color: ColorChoice,
+    /// Columns `--list` prints, if not the default ones
+    pub columns: Option<Vec<ListColumn>>,
+    /// Read the nodes from this file, or stdin for `-`, instead of `tailscale status`
+    pub nodes_from: Option<PathBuf>,
+    /// Use `config.<name>.json` instead of `config.json`
+    pub profile: Option<String>,
+    /// Log in as this user instead of asking or using the configured one
+    pub user: Option<String>,
+    /// Connect to this port instead of the configured one, from an `ssh://` URI
+    pub port: Option<u16>,
+    /// Agent or X11 forwarding or a terminal asked for with `-A`, `-X`, `-Y` or `-t`
+    pub forwarding: ForwardingToggles,
+    /// `--tmux` or `--no-tmux`: attach to the node's tmux session or not, whatever the config says
+    pub tmux: Option<bool>,
+    /// Command run before the login shell, from a saved destination
+    pub startup: Option<String>,
+}
+
+/// How `--list`, `logs` and `history` print what they list
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Emit {
+    /// Lines for people to read
+    #[default]
+    Text,
+    /// The versioned JSON of the `model` types
+    Json,
+}
+
+/// What the user asked the program to do
+pub enum Command {
+    /// Pick a node interactively and connect to it (the default)
+    Connect,
+    /// Print the nodes instead of showing the picker
+    List,
+    /// Connect to the node a hostname, alias or query names, without the picker
+    ConnectTo { node: String },
+    /// Run a single command on a node and exit with its status
+    Run { node: String, command: Vec<String> },
+    /// Copy a file or directory between this machine and a node with scp
+    Copy {
+        node: String,
+        direction: CopyDirection,
+        local: PathBuf,
+        /// Path on the node; empty for the home directory
+        remote_path: String,
+        recursive: bool,
+    },
+    /// Run a command on every node matching a selector, in parallel
+    Broadcast {
+        selector: Selector,
+        gates: BroadcastGates,
+        /// Where to write a log per node and a summary, if anywhere
+        output_dir: Option<PathBuf>,
+        command: Vec<String>,
+    },
+    /// Copy a local file to every node matching a selector, in parallel
+    Push {
+        file: PathBuf,
+        selector: Selector,
+        /// Where the file goes on each node; empty for the home directory
+        remote_path: String,
+    },
+    /// Pick the key a node or group connects with
+    Identity { target: String },
+    /// Check step by step why a node can't be reached, and suggest a fix
+    Troubleshoot { node: String },
+    /// Wake an offline node with Wake-on-LAN, wait for it and connect
+    Wake { node: String },
+    /// Run sessions to several nodes side by side in split panes
+    Panes { nodes: Vec<String> },
+    /// Send a file with Taildrop to a node, or one picked interactively
+    Send { file: PathBuf, node: Option<String> },
+    /// Move files received with Taildrop into a directory
+    Receive { dir: PathBuf, wait: bool },
+    /// Drop tailnet host keys whose address moved to another node
+    PruneKnownHosts,
+    /// Add the host keys of every node matching a selector, after confirmation
+    ScanKnownHosts { selector: Selector, assume_yes: bool },
+    /// Encrypt the config file and history with a passphrase
+    EncryptConfig,
+    /// Store the config file in plain text again, for editing
+    DecryptConfig,
+    /// Print where the config file is
+    ConfigPath,
+    /// Print the config as it is loaded, with every default filled in
+    ConfigShow,
+    /// Write a `Host` block for each node, for ssh and other tools to use without ssh-tailscale
+    ExportSshConfig { path: Option<PathBuf> },
+    /// Push the configured dotfiles to a node
+    SyncDotfiles { node: String },
+    /// Write the curated bookmarks to a file, or stdout
+    ExportBookmarks { path: Option<PathBuf> },
+    /// Merge a bookmark file into the local config
+    ImportBookmarks { path: PathBuf },
+    /// List recorded sessions, or search them
+    Logs { node: Option<String>, grep: Option<String> },
+    /// Play back a recorded session
+    Replay { path: PathBuf },
+    /// Print past connections, optionally to one node
+    History { node: Option<String> },
+    /// Print the JSON schema of a listing
+    Schema { kind: SchemaKind },
+    /// Print the node names, aliases and tags starting with a word, for shell completion
+    Complete { word: String },
+    /// Print the completion script for a shell
+    Completions { shell: Shell },
+    /// Run an `ssh-tailscale-<name>` executable from PATH with the remaining arguments
+    Plugin { plugin: Plugin, args: Vec<String> },
+}
+
+/// The command line as clap reads it, before it is checked and turned into a `Cli`.
+/// Options go before or after the command, like `ssh-tailscale list --json`.
+#[derive(Parser)]
+#[command(
+    name = "ssh-tailscale",
+    about = "Pick a node on your tailnet and connect to it with SSH",
+    after_help = AFTER_HELP,
+    // `help` could be a node's name
+    disable_help_subcommand = true
+)]
+struct CommandLine {
+    /// Print the nodes instead of showing the picker
+    #[arg(long, global = true)]
+    list: bool,
+    /// Print --list, logs and history as text (the default) or json
+    #[arg(long, value_enum, value_name = "FORMAT", hide_possible_values = true, global = true)]
+    emit: Option<Emit>,
+    /// Same as --emit json
+    #[arg(long, conflicts_with = "emit", global = true)]
+    json: bool,
+    /// Columns --list prints, like name,ip,status; also alias, ipv6, dns, user, owner, os, tags and seen
+    #[arg(long, value_name = "LIST", global = true)]
+    columns: Option<String>,
+    /// Color --list output: auto (the default; off with NO_COLOR), always or never
+    #[arg(long, value_name = "WHEN", value_parser = ColorChoice::parse, global = true)]
+    color: Option<ColorChoice>,
+    /// Read the nodes from FILE, or stdin for -, as --list --emit json or CSV, instead of asking Tailscale
+    #[arg(long, value_name = "FILE", global = true)]
+    nodes_from: Option<PathBuf>,
+    /// Don't read or write any config, history or other state
+    #[arg(long, global = true)]
+    ephemeral: bool,
+    /// Use the config file config.NAME.json instead of config.json
+    #[arg(long, value_name = "NAME", global = true)]
+    profile: Option<String>,
+    /// Log in as USER instead of asking, for connecting, run, cp, wake and panes
+    #[arg(short, long, conflicts_with = "pick_user", global = true)]
+    user: Option<String>,
+    /// Forward the ssh agent, like ssh -A
+    #[arg(short = 'A', global = true)]
+    agent: bool,
+    /// Forward X11 untrusted, like ssh -X
+    #[arg(short = 'X', conflicts_with = "trusted_x11", global = true)]
+    x11: bool,
+    /// Forward X11 trusted, like ssh -Y
+    #[arg(short = 'Y', global = true)]
+    trusted_x11: bool,
+    /// Allocate a terminal, even for `run`, like ssh -t
+    #[arg(short = 't', global = true)]
+    tty: bool,
+    /// Attach to the node's tmux session (ssh.tmux_session, or main), creating it if needed, instead of opening a plain shell
+    #[arg(long, conflicts_with = "no_tmux", global = true)]
+    tmux: bool,
+    /// Open a plain shell even if the config names a tmux session
+    #[arg(long, global = true)]
+    no_tmux: bool,
+    /// Ignore custom keys, themes, layout, hooks, actions and plugins, and don't save the config, to recover from a broken setup
+    #[arg(long, global = true)]
+    safe_mode: bool,
+    /// Reconnect to the last node and username, skipping the picker
+    #[arg(long, global = true)]
+    last: bool,
+    /// With --last, still ask for the username
+    #[arg(long, requires = "last", global = true)]
+    pick_user: bool,
+    /// Retry refused or timed out connections with backoff
+    #[arg(long, global = true)]
+    retry: bool,
+    /// Reconnect when the network drops an interactive session
+    #[arg(long, global = true)]
+    reconnect: bool,
+    /// Go back to the picker after each session, until it is quit
+    #[arg(long = "loop", conflicts_with = "last", global = true)]
+    loop_picker: bool,
+    /// Pick nodes from a numbered text menu instead of the full-screen picker, for serial consoles and dumb terminals (the default when $TERM is unset or dumb)
+    #[arg(long, conflicts_with = "last", global = true)]
+    plain: bool,
+    /// Print the ssh command a session or `run` would use, without running it
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Print how long each step took: status fetch, checks, ssh handshake, and log program runs and ssh commands to ssh-tailscale.log in the data directory; -vv also logs tailscale's output and parse decisions
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Log every program run (argv, duration, exit status) to debug.log in the data directory
+    #[arg(long, global = true)]
+    debug_log: bool,
+    #[command(subcommand)]
+    command: Option<Subcommand>,
+}
+
+/// Printed after the commands and options in `--help`
+const AFTER_HELP: &str = "\
+Without a command, the picker opens. Name a node instead, as `ssh-tailscale <NODE>` or \
+`ssh-tailscale ssh://[USER@]NODE[:PORT]`, to connect to it by hostname, alias or IP without \
+the picker. `ssh-tailscale <PLUGIN> [ARGS...]` runs the ssh-tailscale-<PLUGIN> executable found on PATH.
+
+Selectors are hostname globs like 'web-*', 'tag:<name>' for an ACL tag, or 'group:<name>' for a configured group.";
+
+/// The commands as clap reads them
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    /// Pick a node interactively and connect via SSH, or connect to NODE, skipping the picker
+    Connect {
+        /// Hostname, alias or IP, or an ssh://[USER@]NODE[:PORT] URI
+        node: Option<String>,
+    },
+    /// Print the nodes instead of showing the picker, like --list
+    List,
+    /// Run a command on a node without an interactive shell
+    Run {
+        node: String,
+        /// The command, after an optional `--`. Options for ssh-tailscale go before it;
+        /// from the command on, everything is the command's.
+        #[arg(required = true, trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+    /// Copy a file, or a directory with -r, to or from a node; write the node's side as NODE:PATH, like `cp notes.txt web-1:/tmp/`
+    Cp {
+        /// Copy a directory and everything in it
+        #[arg(short, long)]
+        recursive: bool,
+        source: String,
+        dest: String,
+    },
+    /// Run a command on all matching online nodes in parallel, optionally logging each node's output to DIR
+    Broadcast {
+        selector: String,
+        /// Skip the nodes matching this selector too
+        #[arg(long, value_name = "SELECTOR")]
+        exclude: Vec<String>,
+        /// Only run on nodes seen online for at least this many minutes
+        #[arg(long, value_name = "MINUTES")]
+        min_online: Option<u64>,
+        /// Write each node's output to <node>.log there, and the results to summary.json
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+        /// The command, after `--`
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Copy a file to all matching online nodes in parallel
+    Push {
+        file: PathBuf,
+        selector: String,
+        /// Where the file goes on each node, the home directory if left out
+        remote_path: Option<String>,
+    },
+    /// Send a file to a node with Taildrop, picking the node without NODE
+    Send { file: PathBuf, node: Option<String> },
+    /// Move files received with Taildrop into DIR (the current directory)
+    Receive {
+        /// Wait for a file to arrive if none has yet
+        #[arg(long)]
+        wait: bool,
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Pick the only key offered to a node or group, from ~/.ssh and the agent
+    Identity {
+        #[arg(value_name = "NODE|group:NAME")]
+        target: String,
+    },
+    /// Check step by step why ssh can't get through to a node
+    Troubleshoot { node: String },
+    /// Wake an offline node through its `wake.via` node, then connect
+    Wake { node: String },
+    /// Run sessions to several nodes side by side in split panes, opening one per NODE or picking the first one
+    Panes { nodes: Vec<String> },
+    /// Drop or add tailnet host keys in ~/.ssh/known_hosts
+    #[command(subcommand)]
+    KnownHosts(KnownHostsCommand),
+    /// Encrypt, decrypt, locate or print the config
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Write a Host block per node for ~/.ssh/config, to FILE or stdout
+    ExportSshConfig { file: Option<PathBuf> },
+    /// Push your configured dotfiles to a node
+    SyncDotfiles { node: String },
+    /// Export aliases, groups, notes and protected flags
+    ExportBookmarks { file: Option<PathBuf> },
+    /// Merge a bookmark file into your config
+    ImportBookmarks { file: PathBuf },
+    /// List recorded sessions, or print lines containing TEXT
+    Logs {
+        node: Option<String>,
+        #[arg(long, value_name = "TEXT")]
+        grep: Option<String>,
+    },
+    /// Play back a recorded session
+    Replay { file: PathBuf },
+    /// List past connections, newest last
+    History { node: Option<String> },
+    /// Print the JSON schema of nodes, sessions or history for --emit json
+    Schema {
+        #[arg(value_name = "LISTING", value_parser = SchemaKind::parse)]
+        kind: SchemaKind,
+    },
+    /// Print the node names, aliases and tags starting with WORD, for the completion scripts
+    #[command(hide = true)]
+    Complete {
+        #[arg(default_value = "", allow_hyphen_values = true)]
+        word: String,
+    },
+    /// Print the completion script for bash, zsh or fish
+    Completions {
+        #[arg(value_parser = Shell::parse)]
+        shell: Shell,
+    },
+    /// A node to connect to, or a plugin and its arguments
+    #[command(external_subcommand)]
+    Other(Vec<String>),
+}
+
+/// `known-hosts` commands
+#[derive(clap::Subcommand)]
+enum KnownHostsCommand {
+    /// Drop host keys whose Tailscale address now belongs to another node
+    Prune,
+    /// Fetch matching nodes' host keys with ssh-keyscan and add them
+    Scan {
+        selector: String,
+        /// Add the keys without asking
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+/// `config` commands
+#[derive(clap::Subcommand)]
+enum ConfigCommand {
+    /// Encrypt the config file and history with a passphrase
+    Encrypt,
+    /// Store the config file in plain text again, e.g. to edit it
+    Decrypt,
+    /// Print where the config file is
+    Path,
+    /// Print the config as loaded, with every default filled in
+    Show,
+}
+
+/// Parse the process arguments, or print help or what is wrong with them and exit
+pub fn parse_args() -> Cli {
+    parse(std::env::args_os()).unwrap_or_else(|err| err.exit())
+}
+
+/// Parse a command line, starting with the program name
+fn parse<I, T>(args: I) -> Result<Cli, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let line = CommandLine::try_parse_from(args)?;
+    let mut options = Options {
+        retry: line.retry,
+        last: line.last,
+        pick_user: line.pick_user,
+        ephemeral: line.ephemeral,
+        reconnect: line.reconnect,
+        loop_picker: line.loop_picker,
+        plain: line.plain,
+        safe_mode: line.safe_mode,
+        dry_run: line.dry_run,
+        verbosity: line.verbose,
+        debug_log: line.debug_log,
+        emit: if line.json { Emit::Json } else { line.emit.unwrap_or_default() },
+        color: line.color.unwrap_or_default(),
+        columns: line
+            .columns
+            .as_deref()
+            .map(ListColumn::parse_list)
+            .transpose()
+            .map_err(|err| invalid(ErrorKind::ValueValidation, err))?,
+        nodes_from: line.nodes_from,
+        profile: line.profile,
+        user: line.user,
+        port: None,
+        forwarding: ForwardingToggles {
+            agent: line.agent.then_some(true),
+            x11: match (line.x11, line.trusted_x11) {
+                (_, true) => Some(X11Forwarding::Trusted),
+                (true, _) => Some(X11Forwarding::Untrusted),
+                _ => None,
+            },
+            tty: line.tty.then_some(true),
+        },
+        tmux: match (line.tmux, line.no_tmux) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+        startup: None,
+    };
+
+    let command = match line.command {
+        None | Some(Subcommand::Connect { node: None }) => Command::Connect,
+        Some(Subcommand::Connect { node: Some(node) }) => Command::ConnectTo { node },
+        Some(Subcommand::List) => Command::List,
+        Some(Subcommand::Run { node, command }) => Command::Run { node, command },
+        Some(Subcommand::Cp { recursive, source, dest }) => {
+            let (direction, node, remote_path, local) = match (split_remote(&source), split_remote(&dest)) {
+                (None, Some((node, path))) => (CopyDirection::Upload, node, path, source),
+                (Some((node, path)), None) => (CopyDirection::Download, node, path, dest),
+                (Some(_), Some(_)) => {
+                    return Err(invalid(ErrorKind::ValueValidation, "cp copies between this machine and one node, not two nodes"));
+                }
+                (None, None) => {
+                    return Err(invalid(ErrorKind::ValueValidation, "cp requires the node's side written as NODE:PATH"));
+                }
+            };
+            Command::Copy {
+                node,
+                direction,
+                local: PathBuf::from(local),
+                remote_path,
+                recursive,
+            }
+        }
+        Some(Subcommand::Broadcast { selector, exclude, min_online, output_dir, command }) => Command::Broadcast {
+            selector: Selector::parse(&selector),
+            gates: BroadcastGates {
+                exclude: exclude.iter().map(|exclude| Selector::parse(exclude)).collect(),
+                min_online: min_online.map(|minutes| Duration::from_secs(minutes * 60)),
+            },
+            output_dir,
+            command,
+        },
+        Some(Subcommand::Push { file, selector, remote_path }) => Command::Push {
+            file,
+            selector: Selector::parse(&selector),
+            remote_path: remote_path.unwrap_or_default(),
+        },
+        Some(Subcommand::Send { file, node }) => Command::Send { file, node },
+        Some(Subcommand::Receive { wait, dir }) => Command::Receive { dir, wait },
+        Some(Subcommand::Identity { target }) => Command::Identity { target },
+        Some(Subcommand::Troubleshoot { node }) => Command::Troubleshoot { node },
+        Some(Subcommand::Wake { node }) => Command::Wake { node },
+        Some(Subcommand::Panes { nodes }) => Command::Panes { nodes },
+        Some(Subcommand::KnownHosts(KnownHostsCommand::Prune)) => Command::PruneKnownHosts,
+        Some(Subcommand::KnownHosts(KnownHostsCommand::Scan { selector, yes })) => Command::ScanKnownHosts {
+            selector: Selector::parse(&selector),
+            assume_yes: yes,
+        },
+        Some(Subcommand::Config(ConfigCommand::Encrypt)) => Command::EncryptConfig,
+        Some(Subcommand::Config(ConfigCommand::Decrypt)) => Command::DecryptConfig,
+        Some(Subcommand::Config(ConfigCommand::Path)) => Command::ConfigPath,
+        Some(Subcommand::Config(ConfigCommand::Show)) => Command::ConfigShow,
+        Some(Subcommand::ExportSshConfig { file }) => Command::ExportSshConfig { path: file },
+        Some(Subcommand::SyncDotfiles { node }) => Command::SyncDotfiles { node },
+        Some(Subcommand::ExportBookmarks { file }) => Command::ExportBookmarks { path: file },
+        Some(Subcommand::ImportBookmarks { file }) => Command::ImportBookmarks { path: file },
+        Some(Subcommand::Logs { node, grep }) => Command::Logs { node, grep },
+        Some(Subcommand::Replay { file }) => Command::Replay { path: file },
+        Some(Subcommand::History { node }) => Command::History { node },
+        Some(Subcommand::Schema { kind }) => Command::Schema { kind },
+        Some(Subcommand::Complete { word }) => Command::Complete { word },
+        Some(Subcommand::Completions { shell }) => Command::Completions { shell },
+        // A plugin gets every remaining argument; anything else is a node
+        Some(Subcommand::Other(mut args)) => {
+            let name = args.remove(0);
+            match plugins::find(&name).filter(|_| !options.safe_mode) {
+                Some(plugin) => Command::Plugin { plugin, args },
+                None => match args.first() {
+                    Some(extra) => {
+                        return Err(invalid(ErrorKind::UnknownArgument, format!("Unexpected argument '{}'", extra)));
+                    }
+                    None => Command::ConnectTo { node: name },
+                },
+            }
+        }
+    };
+
+    // An `ssh://user@node:port` URI names the login and port along with the node
+    let command = match command {
+        Command::ConnectTo { node } => match SshUri::parse(&node).map_err(|err| invalid(ErrorKind::ValueValidation, err))? {
+            Some(uri) => {
+                if let Some(user) = uri.user {
+                    if let Some(given) = options.user.as_ref().filter(|given| **given != user) {
+                        return Err(invalid(
+                            ErrorKind::ArgumentConflict,
+                            format!("--user {} disagrees with the user {} in {}", given, user, node),
+                        ));
+                    }
+                    options.user = Some(user);
+                }
+                options.port = uri.port;
+                Command::ConnectTo { node: uri.host }
+            }
+            None => Command::ConnectTo { node },
+        },
+        command => command,
+    };
+
+    let command = match command {
+        Command::Connect if line.list => Command::List,
+        command => command,
+    };
+    check_applies(&command, &options, line.list).map_err(|message| invalid(ErrorKind::ArgumentConflict, message))?;
+
+    Ok(Cli { command, options })
+}
+
+/// Check the options given apply to the command. clap checks how options go
+/// together, but they are shared by every command, so which command they may
+/// go with is checked here.
+fn check_applies(command: &Command, options: &Options, list: bool) -> Result<(), &'static str> {
+    let connects = matches!(command, Command::Connect | Command::ConnectTo { .. });
+    if list && !matches!(command, Command::List) {
+        return Err("--list can't be combined with a command");
+    }
+    if options.columns.is_some() && !matches!(command, Command::List) {
+        return Err("--columns only applies to --list");
+    }
+    if options.emit == Emit::Json && !matches!(command, Command::List | Command::Logs { grep: None, .. } | Command::History { .. }) {
+        return Err("--emit json only applies to --list, logs and history");
+    }
+    if options.last && matches!(command, Command::ConnectTo { .. }) {
+        return Err("--last reconnects to the last node, so it can't be combined with a node name");
+    }
+    if options.loop_picker && !matches!(command, Command::Connect) {
+        return Err("--loop only applies to picking a node in the picker");
+    }
+    if options.plain && !matches!(command, Command::Connect | Command::Send { node: None, .. }) {
+        return Err("--plain only applies to picking a node, for connecting and send");
+    }
+    if options.user.is_some()
+        && !(connects || matches!(command, Command::Run { .. } | Command::Copy { .. } | Command::Wake { .. } | Command::Panes { .. }))
+    {
+        return Err("--user only applies to connecting, run, cp, wake and panes");
+    }
+    let forwarding = options.forwarding;
+    if (forwarding.agent.is_some() || forwarding.x11.is_some() || forwarding.tty.is_some())
+        && !(connects || matches!(command, Command::Run { .. } | Command::Wake { .. }))
+    {
+        return Err("-A, -X, -Y and -t only apply to connecting, run and wake");
+    }
+    if options.tmux.is_some() && !(connects || matches!(command, Command::Wake { .. })) {
+        return Err("--tmux and --no-tmux only apply to connecting and wake");
+    }
+    Ok(())
+}
+
+/// An error about the command line, shown the way clap shows its own
+fn invalid(kind: ErrorKind, message: impl Display) -> clap::Error {
+    CommandLine::command().error(kind, message)
+}
+
+/// Split a `NODE:PATH` argument of `cp` into the node and the path. A single
+/// letter before the colon is a Windows drive, and a slash before it makes a local path.
+fn split_remote(arg: &str) -> Option<(String, String)> {
+    let (node, path) = arg.split_once(':')?;
+    if node.chars().count() < 2 || node.contains(['/', '\\']) {
+        return None;
+    }
+    Some((node.to_string(), path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<Cli, clap::Error> {
+        parse(std::iter::once("ssh-tailscale").chain(line.split_whitespace()))
+    }
+
+    fn error_kind(line: &str) -> ErrorKind {
+        match parse_line(line) {
+            Ok(_) => panic!("`{}` parsed", line),
+            Err(err) => err.kind(),
+        }
+    }
+
+    #[test]
+    fn the_definition_is_consistent() {
+        CommandLine::command().debug_assert();
+    }
+
+    #[test]
+    fn no_command_opens_the_picker() {
+        assert!(matches!(parse_line("").unwrap().command, Command::Connect));
+        assert!(matches!(parse_line("--list").unwrap().command, Command::List));
+    }
+
+    #[test]
+    fn a_bare_name_is_a_node() {
+        let cli = parse_line("-u root web-1").unwrap();
+        assert!(matches!(cli.command, Command::ConnectTo { node } if node == "web-1"));
+        assert_eq!(cli.options.user.as_deref(), Some("root"));
+        assert_eq!(error_kind("web-1 extra"), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn an_ssh_uri_sets_the_user_and_port() {
+        let cli = parse_line("ssh://deploy@web-1:2222").unwrap();
+        assert!(matches!(cli.command, Command::ConnectTo { node } if node == "web-1"));
+        assert_eq!(cli.options.user.as_deref(), Some("deploy"));
+        assert_eq!(cli.options.port, Some(2222));
+        assert!(parse_line("-u deploy ssh://deploy@web-1").is_ok());
+        assert_eq!(error_kind("-u root ssh://deploy@web-1"), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn run_takes_the_rest_as_the_command() {
+        for line in ["run web-1 -- ls -la /", "run web-1 ls -la /"] {
+            match parse_line(line).unwrap().command {
+                Command::Run { node, command } => {
+                    assert_eq!(node, "web-1");
+                    assert_eq!(command, ["ls", "-la", "/"]);
+                }
+                _ => panic!("`{}` isn't run", line),
+            }
+        }
+        assert_eq!(error_kind("run web-1"), ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn run_options_go_before_the_command() {
+        for line in ["run web-1 -u root -- id -u", "run web-1 -u root id -u", "-u root run web-1 id -u"] {
+            let cli = parse_line(line).unwrap();
+            assert_eq!(cli.options.user.as_deref(), Some("root"), "{}", line);
+            match cli.command {
+                Command::Run { command, .. } => assert_eq!(command, ["id", "-u"], "{}", line),
+                _ => panic!("`{}` isn't run", line),
+            }
+        }
+        match parse_line("run web-1 -- -- x").unwrap().command {
+            Command::Run { command, .. } => assert_eq!(command, ["--", "x"]),
+            _ => panic!("not run"),
+        }
+        // A command starting with a dash would look like an option for ssh-tailscale
+        assert_eq!(error_kind("run web-1 -la"), ErrorKind::UnknownArgument);
+    }
+
+    #[test]
+    fn broadcast_needs_the_command_after_a_separator() {
+        match parse_line("broadcast web-* --exclude web-9 --min-online 10 -- uptime").unwrap().command {
+            Command::Broadcast { gates, command, .. } => {
+                assert_eq!(gates.exclude.len(), 1);
+                assert_eq!(gates.min_online, Some(Duration::from_secs(600)));
+                assert_eq!(command, ["uptime"]);
+            }
+            _ => panic!("not broadcast"),
+        }
+        assert!(parse_line("broadcast web-* uptime").is_err());
+    }
+
+    #[test]
+    fn cp_finds_the_node_side() {
+        match parse_line("cp -r web-1:/var/log logs").unwrap().command {
+            Command::Copy { node, direction, local, remote_path, recursive } => {
+                assert_eq!(node, "web-1");
+                assert_eq!(direction, CopyDirection::Download);
+                assert_eq!(local, PathBuf::from("logs"));
+                assert_eq!(remote_path, "/var/log");
+                assert!(recursive);
+            }
+            _ => panic!("not cp"),
+        }
+        assert!(matches!(
+            parse_line(r"cp C:\notes.txt web-1:").unwrap().command,
+            Command::Copy { direction: CopyDirection::Upload, .. }
+        ));
+        assert_eq!(error_kind("cp a b"), ErrorKind::ValueValidation);
+        assert_eq!(error_kind("cp web-1:a web-2:b"), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn verbosity_counts() {
+        assert_eq!(parse_line("-vv").unwrap().options.verbosity, 2);
+        assert_eq!(parse_line("-v --verbose").unwrap().options.verbosity, 2);
+    }
+
+    #[test]
+    fn conflicting_options_are_refused() {
+        assert_eq!(error_kind("--pick-user"), ErrorKind::MissingRequiredArgument);
+        assert_eq!(error_kind("--last --pick-user -u root"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("--last --loop"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("--last --plain"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("-X -Y"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("--tmux --no-tmux"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("--json --emit text"), ErrorKind::ArgumentConflict);
+        assert!(parse_line("--last --pick-user").is_ok());
+    }
+
+    #[test]
+    fn options_only_go_with_their_commands() {
+        assert_eq!(error_kind("--list history"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("--columns name history"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("--json logs --grep x"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("--last web-1"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("--loop wake web-1"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("--plain send notes.txt web-1"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("-u root history"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("-A cp a web-1:"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("--tmux run web-1 -- id"), ErrorKind::ArgumentConflict);
+        assert!(parse_line("--columns name,ip list").is_ok());
+        assert!(parse_line("--json logs").is_ok());
+        assert!(parse_line("--plain send notes.txt").is_ok());
+        assert!(parse_line("-u root -t run web-1 -- id").is_ok());
+        assert!(parse_line("--no-tmux wake web-1").is_ok());
+    }
+
+    #[test]
+    fn options_also_go_after_the_command() {
+        let cli = parse_line("list --json --columns name,ip").unwrap();
+        assert!(matches!(cli.command, Command::List));
+        assert_eq!(cli.options.emit, Emit::Json);
+        let cli = parse_line("connect web-1 -u root -A --profile work").unwrap();
+        assert!(matches!(cli.command, Command::ConnectTo { node } if node == "web-1"));
+        assert_eq!(cli.options.user.as_deref(), Some("root"));
+        assert_eq!(cli.options.forwarding.agent, Some(true));
+        assert_eq!(cli.options.profile.as_deref(), Some("work"));
+        assert_eq!(parse_line("history web-1 --emit json -vv").unwrap().options.verbosity, 2);
+        assert!(parse_line("cp -r web-1:/var/log logs -u root").is_ok());
+        assert_eq!(error_kind("history -u root"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("logs --grep x --json"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("wake web-1 --loop"), ErrorKind::ArgumentConflict);
+        assert_eq!(error_kind("list --json --emit text"), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn bad_values_are_refused() {
+        assert_eq!(error_kind("--color sometimes"), ErrorKind::ValueValidation);
+        assert_eq!(error_kind("--columns name,nope"), ErrorKind::ValueValidation);
+        assert_eq!(error_kind("--emit yaml"), ErrorKind::InvalidValue);
+        assert_eq!(error_kind("completions powershell"), ErrorKind::ValueValidation);
+    }
+}
@@ -0,0 +1,13 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::io::{self, Write};
+
+/// Put text on the clipboard with the OSC 52 escape sequence, which the
+/// terminal handles itself, so it also works over ssh and without a display
+/// server. Terminals that don't support it, or have it turned off, ignore it.
+pub fn copy(text: &str) -> Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use crate::{config::Config, index::NodeIndex, tailscale::get_tailscale_nodes};
+use anyhow::{anyhow, Result};
+
+/// Shells a completion script can be printed for
+#[derive(Clone, Copy, Debug)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    /// Parse a shell name as typed on the command line
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            other => Err(anyhow!("No completions for '{}'; use bash, zsh or fish", other)),
+        }
+    }
+
+    /// The completion script, which asks `ssh-tailscale complete` for node names
+    /// so they match exactly what the commands accept
+    pub fn script(self) -> &'static str {
+        match self {
+            Shell::Bash => BASH_SCRIPT,
+            Shell::Zsh => ZSH_SCRIPT,
+            Shell::Fish => FISH_SCRIPT,
+        }
+    }
+}
+
+const BASH_SCRIPT: &str = r#"_ssh_tailscale() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    local prev="${COMP_WORDS[COMP_CWORD-1]}"
+    case "$prev" in
+        connect|run|wake|panes|sync-dotfiles|logs|broadcast|--exclude)
+            COMPREPLY=($(ssh-tailscale complete "$cur" 2>/dev/null))
+            ;;
+        *)
+            COMPREPLY=($(compgen -W "connect list run cp broadcast sync-dotfiles export-bookmarks import-bookmarks export-ssh-config push send receive identity troubleshoot wake panes known-hosts config logs replay history schema completions" -- "$cur")
+                       $(ssh-tailscale complete "$cur" 2>/dev/null))
+            ;;
+    esac
+}
+complete -F _ssh_tailscale ssh-tailscale"#;
+
+const ZSH_SCRIPT: &str = r#"#compdef ssh-tailscale
+_ssh_tailscale() {
+    case "${words[CURRENT-1]}" in
+        connect|run|wake|panes|sync-dotfiles|logs|broadcast|--exclude)
+            compadd -- ${(f)"$(ssh-tailscale complete "${words[CURRENT]}" 2>/dev/null)"}
+            ;;
+        *)
+            compadd connect list run cp broadcast sync-dotfiles export-bookmarks import-bookmarks export-ssh-config push send receive identity troubleshoot wake panes known-hosts config logs replay history schema completions
+            compadd -- ${(f)"$(ssh-tailscale complete "${words[CURRENT]}" 2>/dev/null)"}
+            ;;
+    esac
+}
+compdef _ssh_tailscale ssh-tailscale"#;
+
+const FISH_SCRIPT: &str = r#"complete -c ssh-tailscale -f
+complete -c ssh-tailscale -n __fish_use_subcommand -a "connect list run cp broadcast sync-dotfiles export-bookmarks import-bookmarks export-ssh-config push send receive identity troubleshoot wake panes known-hosts config logs replay history schema completions"
+complete -c ssh-tailscale -n __fish_use_subcommand -a "(ssh-tailscale complete (commandline -ct) 2>/dev/null)"
+complete -c ssh-tailscale -n "__fish_seen_subcommand_from connect run wake panes sync-dotfiles logs broadcast" -a "(ssh-tailscale complete (commandline -ct) 2>/dev/null)""#;
+
+/// Print the node names, aliases and tags starting with `word`, one per line
+pub async fn print_completions(config: &Config, word: &str) -> Result<()> {
+    let nodes = get_tailscale_nodes(config).await?;
+    for completion in NodeIndex::new(&nodes, config).completions(word) {
+        println!("{}", completion);
+    }
+    Ok(())
+}
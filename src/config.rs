@@ -0,0 +1,1127 @@
+use crate::secure::{create_private_dir, encrypted_path, is_encrypted, read_sensitive, write_sensitive_as};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+/// Configuration for the SSH Tailscale app, stored between sessions
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// Default username to use for SSH connections
+    pub default_username: String,
+    /// Last selected node name for auto-selection next time
+    pub last_selected_node: String,
+    /// Tailscale's ID for the last selected node, to find it again after a rename
+    pub last_selected_id: String,
+    /// Record interactive sessions to typescripts under the data directory
+    pub record_sessions: bool,
+    /// Write a manifest of how each interactive session was started, next to its recording
+    pub session_manifests: bool,
+    /// Open the picker again when a session started from it ends, instead of exiting
+    pub return_to_picker: bool,
+    /// Encrypt the connection history with a passphrase, since it maps out your infrastructure
+    pub encrypt_history: bool,
+    /// Encrypt this config file too, along with the history; see `config encrypt`
+    pub encrypt_config: bool,
+    /// Location of the tailscale CLI, when it isn't on PATH or in a usual install location
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tailscale_path: Option<String>,
+    /// Default usernames for groups of nodes
+    pub usernames: UsernameDefaults,
+    /// Per-node settings, keyed by node hostname
+    pub nodes: BTreeMap<String, NodeSettings>,
+    /// Named groups of nodes, each listing hostnames, hostname globs or tags
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Named orders for the picker's list, each a list of keys tried in turn; see `ui.sort`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub sorts: BTreeMap<String, Vec<SortKey>>,
+    /// Keys to connect with, for groups of nodes
+    pub identities: IdentityDefaults,
+    /// Keepalive and session length limits for nodes with certain tags
+    pub session_limits: SessionLimitDefaults,
+    /// Terminal tints for sessions to nodes with certain tags, like red for `tag:prod`
+    pub session_colors: SessionColorDefaults,
+    /// Secret manager commands that provide usernames and key passphrases for groups
+    pub secrets: SecretDefaults,
+    /// Setup to run the first time a node is connected to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bootstrap: Option<BootstrapConfig>,
+    /// Local commands run before connecting and after sessions end
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<HooksConfig>,
+    /// Options added to every ssh invocation
+    pub ssh: SshSettings,
+    /// Backoff used when retrying failed connections
+    pub retry: RetryConfig,
+    /// Reconnecting interactive sessions whose connection drops
+    pub reconnect: ReconnectConfig,
+    /// How many sessions may start a second with `--loop` and in `panes`
+    pub launches: LaunchConfig,
+    /// How often the picker refreshes the node list in the background
+    pub polling: PollingConfig,
+    /// Dotfiles pushed to nodes by the sync action
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dotfiles: Option<DotfilesConfig>,
+    /// Layout of the picker
+    pub ui: UiSettings,
+    /// Colors of the picker
+    pub theme: ThemeConfig,
+    /// Keys for picker actions, by action name, replacing that action's defaults
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, Vec<String>>,
+    /// Commands of your own, run on the selected node from the action menu
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<CustomAction>,
+}
+
+/// A command from the config, offered in the picker's action menu
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CustomAction {
+    /// Name shown in the menu
+    pub name: String,
+    /// Key that runs the action while the menu is open, like `d` or `ctrl+r`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Command line, with `{node}`, `{dns}`, `{ip}`, `{user}` and `{os}` replaced by the node's values
+    pub command: String,
+    /// Where the command runs
+    #[serde(default)]
+    pub run: ActionTarget,
+    /// For disruptive commands like a reboot: mark the node as recently disturbed
+    /// for this long, and ask before running one on a node that still is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown_minutes: Option<u64>,
+}
+
+/// Where a custom action's command runs
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionTarget {
+    /// On the node, over ssh without a terminal
+    #[default]
+    Remote,
+    /// On this machine, through the platform shell
+    Local,
+}
+
+/// Where dotfiles come from and how they are installed on a node
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct DotfilesConfig {
+    /// Local directory whose contents are copied with rsync; `~` expands to the home directory
+    pub directory: Option<String>,
+    /// Destination directory on the node, relative to the remote home directory
+    pub remote_directory: String,
+    /// Command run on the node after copying, e.g. `chezmoi init --apply <repo>`
+    pub script: Option<String>,
+}
+
+/// Layout of the picker
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct UiSettings {
+    /// Key hints listed in the footer while browsing: action names from `keys`, or
+    /// `navigate`. Unset means the built-in list, which grows along with the actions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub footer_hints: Option<Vec<String>>,
+    /// Show the footer; when hidden, the filter line only appears while there is filter text
+    pub show_footer: bool,
+    /// When to collapse the header and footer to one line each
+    pub compact: CompactMode,
+    /// Open the picker on the list of groups, when there are any, instead of every node
+    pub start_in_groups: bool,
+    /// Show times as UTC dates rather than how long ago, in the picker and `history`
+    pub absolute_times: bool,
+    /// Show a line at the bottom with this machine, the tailnet, the exit node and health warnings
+    pub show_status_bar: bool,
+    /// The entry of `sorts` the list is ordered by; unset keeps Tailscale's order
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    /// Nodes left out of the list: device types (`mobile`, `ios`, `android`,
+    /// `mullvad`), tags like `tag:kiosk`, or hostname globs
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hide: Vec<String>,
+    /// List this machine along with the other nodes; it is left out otherwise,
+    /// as connecting to it is rarely what's wanted
+    pub show_self: bool,
+    /// Tags or hostname globs, like `ci-runner-*`, whose nodes are folded into
+    /// one row per entry until it is expanded
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub collapse: Vec<String>,
+    /// Lines PageUp and PageDown move in the list and panes; unset means one screenful
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_size: Option<usize>,
+    /// How often the picker redraws on its own, for countdowns and times, in milliseconds
+    pub tick_ms: u64,
+}
+
+impl Default for UiSettings {
+    fn default() -> Self {
+        Self {
+            footer_hints: None,
+            show_footer: true,
+            compact: CompactMode::default(),
+            start_in_groups: false,
+            absolute_times: false,
+            show_status_bar: true,
+            sort: None,
+            hide: Vec::new(),
+            show_self: false,
+            collapse: Vec::new(),
+            page_size: None,
+            tick_ms: 250,
+        }
+    }
+}
+
+/// When the picker uses its compact layout
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompactMode {
+    /// Only on terminals too short for the full layout
+    #[default]
+    Auto,
+    /// Always
+    Always,
+    /// Never
+    Never,
+}
+
+/// Colors of the picker: a built-in preset, optionally with some colors replaced
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// Palette to start from
+    pub preset: ThemePreset,
+    /// Which colors the terminal can show
+    pub colors: ColorMode,
+    /// Background of the selected row, as a color name like `blue` or `#rrggbb`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<String>,
+    /// Color of active nodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active: Option<String>,
+    /// Color of idle and offline nodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inactive: Option<String>,
+    /// Color of the header title
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+    /// Whether the header title is bold
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header_bold: Option<bool>,
+}
+
+/// Built-in color palettes
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemePreset {
+    /// For dark terminal backgrounds
+    #[default]
+    Dark,
+    /// For light terminal backgrounds
+    Light,
+    /// Solarized dark
+    Solarized,
+}
+
+/// Which colors the picker uses
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    /// Any color, including 24-bit ones
+    #[default]
+    Truecolor,
+    /// Only the 16 basic ANSI colors, approximating any others
+    Ansi16,
+    /// No colors; the selection is shown in reverse video
+    None,
+}
+
+/// Keepalive and connection multiplexing options for ssh
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SshSettings {
+    /// Which ssh client to run
+    pub client: SshClient,
+    /// Ways to connect in order of preference; the first one a node supports is used
+    pub transports: Vec<Transport>,
+    /// Connect to nodes by MagicDNS name instead of IP, when the name resolves
+    pub use_magic_dns: bool,
+    /// Reachability check run before a session starts
+    pub preflight: PreflightCheck,
+    /// Warn before a session when the node is only reached through a DERP relay,
+    /// and offer to wait for a direct connection
+    pub relay_warning: bool,
+    /// Probe port 22 of every online node when the picker starts, since a node
+    /// can stay online in Tailscale long after its sshd has died
+    pub health_sweep: bool,
+    /// Most nodes the health sweep probes at once
+    pub health_sweep_parallelism: usize,
+    /// How intermediate `jump` hops are addressed, unless a hop says otherwise
+    pub jump_address: HopAddress,
+    /// Seconds between keepalive probes (`ServerAliveInterval`); `None` leaves ssh's default
+    pub server_alive_interval: Option<u32>,
+    /// Unanswered keepalives before disconnecting (`ServerAliveCountMax`)
+    pub server_alive_count_max: Option<u32>,
+    /// Share one connection per node between sessions (`ControlMaster=auto`)
+    pub control_master: bool,
+    /// Socket path for shared connections (`ControlPath`)
+    pub control_path: String,
+    /// How long a shared connection stays open after the last session (`ControlPersist`)
+    pub control_persist: String,
+    /// Shell that runs remote commands, which decides how their arguments are quoted
+    pub remote_shell: RemoteShell,
+    /// Separate known_hosts file for tailnet nodes (`UserKnownHostsFile`); `None` uses ssh's own
+    pub known_hosts: Option<String>,
+    /// Before connecting, offer to run `ssh-add` when the agent has no keys
+    pub check_agent: bool,
+    /// Key `ssh-add` loads when a node has no identity of its own; `None` loads ssh-add's defaults
+    pub agent_key: Option<String>,
+    /// Forward the ssh agent to nodes (`-A`)
+    pub forward_agent: bool,
+    /// Forward X11 to nodes (`-X` or `-Y`)
+    pub forward_x11: X11Forwarding,
+    /// Allocate a terminal even for remote commands (`-t`)
+    pub force_tty: bool,
+    /// tmux session interactive sessions attach to on the node, created if it
+    /// doesn't exist (`tmux new -A -s <name>`); `None` opens a plain shell
+    pub tmux_session: Option<String>,
+}
+
+impl Default for SshSettings {
+    fn default() -> Self {
+        Self {
+            client: SshClient::default(),
+            transports: vec![Transport::Ssh],
+            use_magic_dns: false,
+            preflight: PreflightCheck::default(),
+            relay_warning: true,
+            health_sweep: true,
+            health_sweep_parallelism: 32,
+            jump_address: HopAddress::default(),
+            // Keeps idle sessions alive across DERP relays and NAT timeouts
+            server_alive_interval: Some(30),
+            server_alive_count_max: Some(3),
+            control_master: false,
+            control_path: "~/.ssh/ssh-tailscale-%C".to_string(),
+            control_persist: "10m".to_string(),
+            remote_shell: RemoteShell::default(),
+            known_hosts: None,
+            check_agent: false,
+            agent_key: None,
+            forward_agent: false,
+            forward_x11: X11Forwarding::default(),
+            force_tty: false,
+            tmux_session: None,
+        }
+    }
+}
+
+/// The ssh client program used for sessions and remote commands
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SshClient {
+    /// OpenSSH's `ssh`, including the one bundled with Windows
+    #[default]
+    OpenSsh,
+    /// PuTTY's `plink`, for Windows setups built around PuTTY and Pageant
+    Plink,
+}
+
+impl SshClient {
+    /// Short description of the client for display
+    pub fn label(&self) -> &'static str {
+        match self {
+            SshClient::OpenSsh => "OpenSSH (ssh)",
+            SshClient::Plink => "PuTTY (plink)",
+        }
+    }
+}
+
+/// How a session reaches a node
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// `tailscale ssh`, for nodes running Tailscale SSH
+    Tailscale,
+    /// The configured ssh client
+    #[default]
+    Ssh,
+}
+
+/// The shell on a node that runs remote commands
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteShell {
+    /// `cmd` on Windows nodes, since it is the default shell of OpenSSH there, and `posix` elsewhere
+    #[default]
+    Auto,
+    /// `sh`, `bash`, `zsh` and the like
+    Posix,
+    /// Windows PowerShell or PowerShell 7 set as the OpenSSH default shell
+    PowerShell,
+    /// The Windows command prompt
+    Cmd,
+}
+
+impl RemoteShell {
+    /// Short name of the shell for display
+    pub fn label(&self) -> &'static str {
+        match self {
+            RemoteShell::Auto => "auto",
+            RemoteShell::Posix => "POSIX shell",
+            RemoteShell::PowerShell => "PowerShell",
+            RemoteShell::Cmd => "cmd",
+        }
+    }
+}
+
+/// Whether and how X11 is forwarded to a node
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum X11Forwarding {
+    #[default]
+    Off,
+    /// `-X`, with the node's X11 clients limited by the X security extension
+    Untrusted,
+    /// `-Y`, with full access to the local display
+    Trusted,
+}
+
+impl X11Forwarding {
+    /// Short description for display
+    pub fn label(&self) -> &'static str {
+        match self {
+            X11Forwarding::Off => "off",
+            X11Forwarding::Untrusted => "untrusted (-X)",
+            X11Forwarding::Trusted => "trusted (-Y)",
+        }
+    }
+
+    /// The setting after this one, for cycling through them with a key
+    pub fn next(self) -> Self {
+        match self {
+            X11Forwarding::Off => X11Forwarding::Untrusted,
+            X11Forwarding::Untrusted => X11Forwarding::Trusted,
+            X11Forwarding::Trusted => X11Forwarding::Off,
+        }
+    }
+}
+
+/// How to check that a node is reachable before connecting
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PreflightCheck {
+    /// Connect straight away
+    #[default]
+    Off,
+    /// Open a TCP connection to port 22, pinging the node if that fails to explain why
+    Tcp,
+    /// Send one `tailscale ping`
+    Ping,
+}
+
+/// Backoff settings for retrying connections to unreachable nodes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Number of retries after the first failed attempt
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled on each further attempt
+    pub initial_delay_secs: u64,
+    /// Upper bound for the delay between attempts
+    pub max_delay_secs: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay_secs: 2,
+            max_delay_secs: 30,
+        }
+    }
+}
+
+/// Reconnecting an interactive session after the network drops it, like autossh
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// Reconnect without `--reconnect` being passed
+    pub enabled: bool,
+    /// Most reconnects in one run before giving up
+    pub max_attempts: u32,
+    /// How long to wait for the node to answer again before each reconnect
+    pub wait_secs: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_attempts: 10,
+            wait_secs: 300,
+        }
+    }
+}
+
+/// A cap on starting sessions while ssh-tailscale stays open between them
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LaunchConfig {
+    /// Most sessions started in any one second; 0 for no cap
+    pub max_per_second: u32,
+}
+
+impl Default for LaunchConfig {
+    fn default() -> Self {
+        Self { max_per_second: 2 }
+    }
+}
+
+/// Intervals of the picker's background work. Unset ones scale with the size of
+/// the tailnet and how long `tailscale status` takes.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct PollingConfig {
+    /// Seconds between `tailscale status` refreshes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_secs: Option<u64>,
+    /// Seconds between updating presence history, known_hosts and the status
+    /// cache from a refresh
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enrich_secs: Option<u64>,
+}
+
+/// Limits on sessions to sensitive nodes. Unset fields fall back to the
+/// `ssh` section, or to no limit.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct SessionLimit {
+    /// Seconds between keepalive probes, overriding `ssh.server_alive_interval`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_alive_interval: Option<u32>,
+    /// Unanswered keepalives before disconnecting, overriding `ssh.server_alive_count_max`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_alive_count_max: Option<u32>,
+    /// Longest a session may last before ssh-tailscale ends it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_session_minutes: Option<u64>,
+    /// How long before the end to warn; 5 minutes if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warn_minutes: Option<u64>,
+}
+
+impl SessionLimit {
+    /// The stricter of two limits, field by field
+    pub fn strictest(self, other: &SessionLimit) -> SessionLimit {
+        fn min<T: Ord + Copy>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+        SessionLimit {
+            server_alive_interval: min(self.server_alive_interval, other.server_alive_interval),
+            server_alive_count_max: min(self.server_alive_count_max, other.server_alive_count_max),
+            max_session_minutes: min(self.max_session_minutes, other.max_session_minutes),
+            // A longer warning is the more cautious one
+            warn_minutes: match (self.warn_minutes, other.warn_minutes) {
+                (Some(a), Some(b)) => Some(a.max(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+}
+
+/// Session limits shared by several nodes; a node's own `session_limit` adds to them
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct SessionLimitDefaults {
+    /// Limits keyed by ACL tag, like `tag:prod`
+    pub tags: BTreeMap<String, SessionLimit>,
+}
+
+/// How the terminal is marked while a session to a node is open
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct SessionColor {
+    /// Background color set with OSC 11, like `#3a0000` or `darkred`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    /// Label put in front of the window title and shown before connecting, like `PROD`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marker: Option<String>,
+}
+
+/// Session colors shared by several nodes; a node's own `session_color` wins
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct SessionColorDefaults {
+    /// Colors keyed by ACL tag, like `tag:prod`
+    pub tags: BTreeMap<String, SessionColor>,
+}
+
+/// Default usernames shared by several nodes. Per-node usernames live in
+/// `NodeSettings` and the global default in `default_username`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct UsernameDefaults {
+    /// Usernames keyed by ACL tag, like `tag:server`
+    pub tags: BTreeMap<String, String>,
+    /// Usernames keyed by lowercase operating system, like `linux` or `windows`
+    pub os: BTreeMap<String, String>,
+    /// Connect as the node owner's Tailscale login without asking, when that is the username that would be used
+    pub trust_suggested: bool,
+}
+
+/// Secret manager commands, keyed by group name
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct SecretDefaults {
+    pub groups: BTreeMap<String, SecretSource>,
+}
+
+/// Shell commands that print a secret on their first line of output, like
+/// `op read op://Infra/prod-ssh/username`
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct SecretSource {
+    /// Prints the username to connect with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Prints the passphrase of the key to load into the ssh-agent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<String>,
+}
+
+/// Keys assigned to groups of nodes; a node's own `identity` comes first
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct IdentityDefaults {
+    /// Key files keyed by group name
+    pub groups: BTreeMap<String, String>,
+}
+
+/// Commands that prepare a node the first time it is connected to
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct BootstrapConfig {
+    /// Command run locally through the shell, e.g. an rsync of dotfiles to the node
+    pub local_command: Option<String>,
+    /// Command run on the node over ssh, e.g. installing a tmux config
+    pub remote_command: Option<String>,
+}
+
+/// Local commands run around every session, with the node in `SSH_TAILSCALE_*` variables
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Run before connecting, e.g. `op signin`; the connection is called off if it fails
+    pub before_connect: Option<String>,
+    /// Run once the session ends, however it ended, e.g. to send a notification
+    pub after_session: Option<String>,
+}
+
+impl Config {
+    /// Settings for a node, or defaults if it has none
+    pub fn node_settings(&self, name: &str) -> NodeSettings {
+        self.nodes.get(name).cloned().unwrap_or_default()
+    }
+
+    /// The short name given to a node, if any
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        self.nodes.get(name).and_then(|settings| settings.alias.as_deref())
+    }
+
+    /// The config as `--safe-mode` uses it: default keys, theme and layout,
+    /// and no hooks, bootstrap or custom actions
+    fn without_customizations(self) -> Self {
+        Self {
+            keys: BTreeMap::new(),
+            theme: ThemeConfig::default(),
+            ui: UiSettings::default(),
+            hooks: None,
+            bootstrap: None,
+            actions: Vec::new(),
+            ..self
+        }
+    }
+}
+
+/// Settings attached to a single node
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct NodeSettings {
+    /// Username to connect with, overriding all other defaults
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Short alternative name for the node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// Free-text notes about the node
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub notes: String,
+    /// Port the node's ssh server listens on, when it isn't 22
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Whether the node is marked as protected (e.g. production)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub protected: bool,
+    /// Never run the bootstrap on this node
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub skip_bootstrap: bool,
+    /// Nodes to hop through on the way, in order (`ProxyJump`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub jump: Vec<JumpHop>,
+    /// Address that reached the node after the usual one failed, tried first from then on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_address: Option<AddressKind>,
+    /// Shell that runs remote commands on this node, overriding `ssh.remote_shell`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_shell: Option<RemoteShell>,
+    /// Local variables to pass to the node (`SendEnv`); the server must accept them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub send_env: Vec<String>,
+    /// Variables to set on the node (`SetEnv`); the server must accept them
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub set_env: BTreeMap<String, String>,
+    /// Variables set for the local ssh process, like a `TERM` the node has terminfo for
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Forward the ssh agent to this node, overriding `ssh.forward_agent`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward_agent: Option<bool>,
+    /// Forward X11 to this node, overriding `ssh.forward_x11`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward_x11: Option<X11Forwarding>,
+    /// Allocate a terminal for remote commands on this node, overriding `ssh.force_tty`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force_tty: Option<bool>,
+    /// tmux session to attach to on this node, overriding `ssh.tmux_session`; empty for none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tmux_session: Option<String>,
+    /// Other ways to log in to this node, offered after choosing it
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub destinations: Vec<SavedDestination>,
+    /// The only key to offer this node (`IdentityFile` with `IdentitiesOnly`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// Keepalive and session length limits for this node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_limit: Option<SessionLimit>,
+    /// How the terminal is marked during sessions to this node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_color: Option<SessionColor>,
+    /// Services the node runs, offered in the action menu, keyed by name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub services: BTreeMap<String, Service>,
+    /// How to wake the node with Wake-on-LAN when it is offline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wake: Option<WakeOnLan>,
+}
+
+/// A saved way to log in to a node: another user, port or startup command
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct SavedDestination {
+    /// Name shown in the list, instead of `user@node`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// User to log in as; asked for as usual if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Port to connect to, instead of the configured one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Command run before the login shell, like `cd /srv/app`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+/// Waking a node by sending a magic packet from another node on its LAN
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WakeOnLan {
+    /// MAC address of the node's network card, like `aa:bb:cc:dd:ee:ff`
+    pub mac: String,
+    /// The node that sends the packet: a hostname or alias on the same LAN
+    pub via: String,
+    /// Command run on `via` instead of the built-in sender, with `{mac}` filled in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// How long to wait for the node to come online, in seconds (default 180)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+/// A service on a node, like a web UI, that the action menu can open or forward
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Service {
+    /// Port the service listens on, on the node
+    pub port: u16,
+    /// How it is spoken to, which decides whether it can be opened in a browser
+    #[serde(default)]
+    pub scheme: ServiceScheme,
+    /// Local port to forward it to; the same as `port` if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_port: Option<u16>,
+    /// Key in the action menu that opens the service, or forwards it if it isn't a web service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Forward the service as soon as the picker starts, if the node is online
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub autostart: bool,
+}
+
+/// How a service is spoken to
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceScheme {
+    #[default]
+    Http,
+    Https,
+    /// Anything else, like a database; it can be forwarded but not opened
+    Tcp,
+}
+
+impl ServiceScheme {
+    /// The URL scheme for opening it in a browser, if it can be
+    pub fn url_scheme(self) -> Option<&'static str> {
+        match self {
+            ServiceScheme::Http => Some("http"),
+            ServiceScheme::Https => Some("https"),
+            ServiceScheme::Tcp => None,
+        }
+    }
+}
+
+/// One key of a named sort
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub field: SortField,
+    /// Largest, latest or last in the alphabet first
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub descending: bool,
+}
+
+/// What the picker's list can be ordered by
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortField {
+    /// Hostname
+    #[default]
+    Name,
+    /// Alias, or the hostname for nodes without one
+    Alias,
+    /// Direct connections, then relayed ones, then idle and offline nodes
+    Status,
+    /// The first of the node's tags, alphabetically; untagged nodes come last
+    Tag,
+    Os,
+    /// The login of the node's owner
+    Owner,
+    /// The username the node would be connected with
+    User,
+    Ip,
+    /// When the node was last online; online nodes are the latest
+    Seen,
+    /// Bytes sent and received since this machine's client started
+    Traffic,
+}
+
+impl SortField {
+    /// Every field, in the order the sort editor cycles through them
+    pub const ALL: [SortField; 10] = [
+        SortField::Name,
+        SortField::Alias,
+        SortField::Status,
+        SortField::Tag,
+        SortField::Os,
+        SortField::Owner,
+        SortField::User,
+        SortField::Ip,
+        SortField::Seen,
+        SortField::Traffic,
+    ];
+
+    /// Name used in the config
+    pub fn name(self) -> &'static str {
+        match self {
+            SortField::Name => "name",
+            SortField::Alias => "alias",
+            SortField::Status => "status",
+            SortField::Tag => "tag",
+            SortField::Os => "os",
+            SortField::Owner => "owner",
+            SortField::User => "user",
+            SortField::Ip => "ip",
+            SortField::Seen => "seen",
+            SortField::Traffic => "traffic",
+        }
+    }
+
+    /// The field `step` places further along `ALL`, wrapping around
+    pub fn cycle(self, step: isize) -> SortField {
+        let position = Self::ALL.iter().position(|&field| field == self).unwrap_or_default();
+        Self::ALL[(position as isize + step).rem_euclid(Self::ALL.len() as isize) as usize]
+    }
+}
+
+/// One hop of a `ProxyJump` chain: a node name, or a node with its own settings
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum JumpHop {
+    /// Just the node name, like `"bastion"`
+    Name(String),
+    /// A node with a username or address preference for this hop
+    Detailed {
+        host: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        address: Option<HopAddress>,
+    },
+}
+
+impl JumpHop {
+    /// Node name of the hop
+    pub fn host(&self) -> &str {
+        match self {
+            JumpHop::Name(host) | JumpHop::Detailed { host, .. } => host,
+        }
+    }
+
+    /// Username for the hop, if one is set; otherwise ssh picks it
+    pub fn user(&self) -> Option<&str> {
+        match self {
+            JumpHop::Name(_) => None,
+            JumpHop::Detailed { user, .. } => user.as_deref(),
+        }
+    }
+
+    /// Address preference for this hop, overriding `ssh.jump_address`
+    pub fn address(&self) -> Option<HopAddress> {
+        match self {
+            JumpHop::Name(_) => None,
+            JumpHop::Detailed { address, .. } => *address,
+        }
+    }
+}
+
+/// How a jump host is addressed
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HopAddress {
+    /// The MagicDNS name, which host certificates on bastions are usually issued for
+    #[default]
+    Dns,
+    /// The Tailscale IP
+    Ip,
+}
+
+/// Which of a node's addresses a connection uses
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressKind {
+    /// The MagicDNS name
+    Dns,
+    /// The Tailscale IPv4 address
+    Ipv4,
+    /// The Tailscale IPv6 address
+    Ipv6,
+}
+
+impl AddressKind {
+    /// Name shown to the user
+    pub fn label(self) -> &'static str {
+        match self {
+            AddressKind::Dns => "MagicDNS name",
+            AddressKind::Ipv4 => "IPv4 address",
+            AddressKind::Ipv6 => "IPv6 address",
+        }
+    }
+}
+
+/// Environment variable naming a directory to keep config and data in,
+/// instead of the usual locations under the home directory
+pub const STATE_DIR_ENV: &str = "SSH_TAILSCALE_STATE_DIR";
+
+/// Set by `--ephemeral`: behave as if there were nowhere to keep state
+static EPHEMERAL: AtomicBool = AtomicBool::new(false);
+
+/// Stop reading and writing config and data files for the rest of the run
+pub fn set_ephemeral() {
+    EPHEMERAL.store(true, Ordering::Relaxed);
+}
+
+/// Whether state is kept in memory only, because of `--ephemeral`
+pub fn is_ephemeral() -> bool {
+    EPHEMERAL.load(Ordering::Relaxed)
+}
+
+/// Set by `--safe-mode`: leave out the parts of the config that can make the
+/// picker unusable, and don't save the config
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// The `--profile` of the run, which picks the config file
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Read and write the config of a named profile instead of the default one.
+/// History and other state are shared between profiles.
+pub fn set_profile(name: &str) -> Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(anyhow!("Profile names may only use letters, digits, '-' and '_', not '{}'", name));
+    }
+    let _ = PROFILE.set(name.to_string());
+    Ok(())
+}
+
+/// Start with the default keys, theme and layout, and no hooks, actions or plugins
+pub fn set_safe_mode() {
+    SAFE_MODE.store(true, Ordering::Relaxed);
+}
+
+/// Whether the run was started with `--safe-mode`
+pub fn is_safe_mode() -> bool {
+    SAFE_MODE.load(Ordering::Relaxed)
+}
+
+/// Get the configuration directory path, or `None` if there is nowhere to keep
+/// state and the tool runs in memory only
+pub fn get_config_dir() -> Result<Option<PathBuf>> {
+    state_dir(&[".config", "ssh-tailscale"])
+}
+
+/// Get the data directory path, where history and other state is kept
+pub fn get_data_dir() -> Result<Option<PathBuf>> {
+    state_dir(&[".local", "share", "ssh-tailscale"])
+}
+
+/// Resolve and create a state directory: the override from the environment if
+/// set, else the given path under the home directory. Containers often run
+/// without a home directory, in which case nothing is kept.
+fn state_dir(under_home: &[&str]) -> Result<Option<PathBuf>> {
+    if is_ephemeral() {
+        return Ok(None);
+    }
+
+    let dir = match std::env::var_os(STATE_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => match dirs::home_dir() {
+            Some(home_dir) => under_home.iter().fold(home_dir, |dir, part| dir.join(part)),
+            None => return Ok(None),
+        },
+    };
+
+    // Create the directory if it doesn't exist
+    if !dir.exists() {
+        create_private_dir(&dir)
+            .with_context(|| format!("Failed to create state directory {}", dir.display()))?;
+    }
+
+    Ok(Some(dir))
+}
+
+/// Get the configuration file path: `config.json`, or `config.<name>.json` for a `--profile`
+pub fn get_config_path() -> Result<Option<PathBuf>> {
+    let file = match PROFILE.get() {
+        Some(profile) => format!("config.{}.json", profile),
+        None => "config.json".to_string(),
+    };
+    Ok(get_config_dir()?.map(|dir| dir.join(file)))
+}
+
+/// Load configuration from the config file
+pub fn load_config() -> Result<Config> {
+    let Some(config_path) = get_config_path()? else {
+        return Ok(Config::default());
+    };
+    load_config_from(&config_path)
+}
+
+/// Load configuration from a config file at `config_path`
+fn load_config_from(config_path: &Path) -> Result<Config> {
+    // Return default config if file doesn't exist
+    let Some(config_str) = read_sensitive(config_path)? else {
+        return Ok(Config::default());
+    };
+    let config = match parse_config(config_path, &config_str) {
+        Ok(config) => config,
+        // Safe mode saves nothing, so it can start from the defaults without losing the file
+        Err(err) if is_safe_mode() => {
+            eprintln!("Warning: {:#}; safe mode starts with the default config", err);
+            Config::default()
+        }
+        Err(err) => return Err(err),
+    };
+
+    // Turning `encrypt_config` on by hand encrypts the file right away
+    if config.encrypt_config && !is_encrypted(config_path) && !is_safe_mode() {
+        save_config_to(config_path, &config)?;
+        eprintln!("Encrypted {}", encrypted_path(config_path).display());
+    }
+    if is_safe_mode() {
+        return Ok(config.without_customizations());
+    }
+    Ok(config)
+}
+
+/// Save configuration to the config file
+pub fn save_config(config: &Config) -> Result<()> {
+    // What safe mode leaves out would be lost from the file
+    if is_safe_mode() {
+        return Ok(());
+    }
+    let Some(config_path) = get_config_path()? else {
+        return Ok(());
+    };
+    save_config_to(&config_path, config)
+}
+
+/// Save configuration to a config file at `config_path`
+fn save_config_to(config_path: &Path, config: &Config) -> Result<()> {
+    // A hand-edited file that doesn't parse would be replaced by what was loaded instead
+    if let Some(existing) = read_sensitive(config_path)? {
+        parse_config(config_path, &existing).map_err(|err| anyhow!("{:#}; fix it before settings can be saved", err))?;
+    }
+    let config_str = serde_json::to_string_pretty(config)?;
+    write_sensitive_as(config_path, &config_str, config.encrypt_config)?;
+    Ok(())
+}
+
+/// Parse the config file, naming the file and the line and column of any mistake
+fn parse_config(path: &Path, text: &str) -> Result<Config> {
+    serde_json::from_str(text).map_err(|err| anyhow!("{} is not a valid config file: {}", path.display(), err))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn invalid_config_is_reported_and_never_overwritten() {
+        let dir = std::env::temp_dir().join(format!("ssh-tailscale-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        let broken = "{\n  \"record_sessions\": \"yes\"\n}\n";
+        fs::write(&path, broken).unwrap();
+
+        let err = load_config_from(&path).unwrap_err().to_string();
+        assert!(err.contains("config.json"), "{}", err);
+        assert!(err.contains("line 2 column"), "{}", err);
+        assert!(save_config_to(&path, &Config::default()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), broken);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
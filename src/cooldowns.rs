@@ -0,0 +1,78 @@
+use crate::{config::get_data_dir, history::unix_now, secure::write_private};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::PathBuf, time::Duration};
+
+/// Nodes recently disturbed by a custom action with `cooldown_minutes`, like a
+/// reboot. Kept in the data directory rather than in memory, so every
+/// ssh-tailscale running on this machine sees them.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Cooldowns {
+    /// The latest cooldown of each node, by hostname
+    pub nodes: BTreeMap<String, Cooldown>,
+}
+
+/// One node's cooldown
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cooldown {
+    /// Name of the action that disturbed the node
+    pub action: String,
+    /// Unix timestamps of when the action ran and when the cooldown ends
+    pub started: u64,
+    pub until: u64,
+}
+
+impl Cooldowns {
+    /// The node's cooldown, unless it has run out
+    pub fn active(&self, node: &str) -> Option<&Cooldown> {
+        self.nodes.get(node).filter(|cooldown| cooldown.until > unix_now())
+    }
+}
+
+impl Cooldown {
+    /// Time left before the node counts as settled again
+    pub fn remaining(&self) -> Duration {
+        Duration::from_secs(self.until.saturating_sub(unix_now()))
+    }
+}
+
+/// Get the cooldowns file path, if there is anywhere to keep it
+fn get_cooldowns_path() -> Result<Option<PathBuf>> {
+    Ok(get_data_dir()?.map(|dir| dir.join("cooldowns.json")))
+}
+
+/// Load the cooldowns every run has started, without those that ran out
+pub fn load_cooldowns() -> Result<Cooldowns> {
+    let Some(cooldowns_path) = get_cooldowns_path()? else {
+        return Ok(Cooldowns::default());
+    };
+
+    let mut cooldowns: Cooldowns = if cooldowns_path.exists() {
+        serde_json::from_str(&fs::read_to_string(cooldowns_path)?).unwrap_or_default()
+    } else {
+        Cooldowns::default()
+    };
+    let now = unix_now();
+    cooldowns.nodes.retain(|_, cooldown| cooldown.until > now);
+    Ok(cooldowns)
+}
+
+/// Mark a node as disturbed by an action for the next `minutes`
+pub fn start_cooldown(node: &str, action: &str, minutes: u64) -> Result<()> {
+    let Some(cooldowns_path) = get_cooldowns_path()? else {
+        return Ok(());
+    };
+    let mut cooldowns = load_cooldowns()?;
+    let now = unix_now();
+    cooldowns.nodes.insert(
+        node.to_string(),
+        Cooldown {
+            action: action.to_string(),
+            started: now,
+            until: now + minutes * 60,
+        },
+    );
+    write_private(&cooldowns_path, serde_json::to_string_pretty(&cooldowns)?.as_bytes())?;
+    Ok(())
+}
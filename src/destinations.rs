@@ -0,0 +1,45 @@
+use crate::{
+    config::{Config, SavedDestination},
+    tailscale::TailscaleNode,
+};
+use anyhow::Result;
+use dialoguer::{theme::ColorfulTheme, Select};
+use std::io::{self, IsTerminal};
+
+/// How a saved destination is listed: its name, or `user@node:port` and the
+/// startup command
+pub fn label(destination: &SavedDestination, node: &TailscaleNode) -> String {
+    if let Some(name) = &destination.name {
+        return name.clone();
+    }
+    let mut label = match &destination.user {
+        Some(user) => format!("{}@{}", user, node.name),
+        None => node.name.clone(),
+    };
+    if let Some(port) = destination.port {
+        label.push_str(&format!(":{}", port));
+    }
+    if let Some(command) = &destination.command {
+        label.push_str(&format!(" + {}", command));
+    }
+    label
+}
+
+/// Ask which of the node's saved destinations to log in with, or `None` for
+/// the usual login. Nothing is asked when the node has none, or without a
+/// terminal to ask on.
+pub fn choose(config: &Config, node: &TailscaleNode) -> Result<Option<SavedDestination>> {
+    let mut destinations = config.node_settings(&node.name).destinations;
+    if destinations.is_empty() || !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+
+    let mut items: Vec<String> = destinations.iter().map(|destination| label(destination, node)).collect();
+    items.push("Usual login".to_string());
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Log in to {} as", node.name))
+        .items(&items)
+        .default(0)
+        .interact()?;
+    Ok((choice < destinations.len()).then(|| destinations.swap_remove(choice)))
+}
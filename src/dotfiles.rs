@@ -0,0 +1,36 @@
+use crate::{config::DotfilesConfig, platform::expand_home, ssh::SshCommand};
+use anyhow::{anyhow, Result};
+use std::process::Command;
+
+/// Build the commands that sync dotfiles to a node: an rsync of the configured
+/// directory over the same ssh settings as a session, then the optional script
+pub fn sync_commands(dotfiles: &DotfilesConfig, ssh: &SshCommand) -> Result<Vec<Command>> {
+    let mut commands = Vec::new();
+
+    if let Some(directory) = &dotfiles.directory {
+        let local_dir = expand_home(directory)?;
+        if !local_dir.is_dir() {
+            return Err(anyhow!("Dotfiles directory {} does not exist", local_dir.display()));
+        }
+
+        let mut rsync = Command::new("rsync");
+        rsync
+            .arg("-az")
+            .arg("-e")
+            .arg(ssh.transport_command())
+            // The trailing slash copies the directory's contents rather than the directory
+            .arg(format!("{}/", local_dir.display()))
+            .arg(format!("{}:{}", ssh.copy_destination(), dotfiles.remote_directory));
+        commands.push(rsync);
+    }
+
+    if let Some(script) = &dotfiles.script {
+        commands.push(ssh.clone().remote_command(vec![script.clone()]).command());
+    }
+
+    if commands.is_empty() {
+        return Err(anyhow!("The `dotfiles` config needs a `directory`, a `script`, or both"));
+    }
+
+    Ok(commands)
+}
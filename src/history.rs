@@ -0,0 +1,130 @@
+use crate::{
+    config::get_data_dir,
+    secure::{read_sensitive, write_sensitive},
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many connections are remembered before the oldest are dropped
+const MAX_HISTORY_ENTRIES: usize = 1000;
+
+/// Record of past SSH connections, oldest first
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+}
+
+/// A single SSH connection made through the tool
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    /// Hostname of the node connected to
+    pub node: String,
+    /// Username used for the connection
+    pub user: String,
+    /// When the connection was started, in seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl History {
+    /// Whether any connection to the node has been recorded
+    pub fn has_connected_to(&self, node: &str) -> bool {
+        self.entries.iter().any(|entry| entry.node == node)
+    }
+
+    /// Record a new connection, dropping the oldest entries past the limit
+    pub fn record(&mut self, node: &str, user: &str) {
+        self.entries.push(HistoryEntry {
+            node: node.to_string(),
+            user: user.to_string(),
+            timestamp: unix_now(),
+        });
+
+        if self.entries.len() > MAX_HISTORY_ENTRIES {
+            let excess = self.entries.len() - MAX_HISTORY_ENTRIES;
+            self.entries.drain(..excess);
+        }
+    }
+}
+
+/// The UTC calendar date and time of day of a Unix timestamp: year, month,
+/// day, hour, minute and second
+pub fn utc_parts(unix_seconds: u64) -> (i64, i64, i64, u64, u64, u64) {
+    let days = (unix_seconds / 86_400) as i64;
+    let seconds_of_day = unix_seconds % 86_400;
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, seconds_of_day / 3600, seconds_of_day / 60 % 60, seconds_of_day % 60)
+}
+
+/// The Unix timestamp of a UTC calendar date and time of day, the reverse of `utc_parts`
+pub fn unix_from_utc(year: i64, month: i64, day: i64, hour: u64, minute: u64, second: u64) -> Option<u64> {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146_097 + day_of_era - 719_468).ok()?;
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+/// A Unix timestamp written like `2024-05-01 13:45 UTC`
+pub fn format_utc(unix_seconds: u64) -> String {
+    let (year, month, day, hour, minute, _) = utc_parts(unix_seconds);
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, hour, minute)
+}
+
+/// A Unix timestamp in ISO 8601 form, like `2024-05-01T13:45:07Z`
+pub fn format_iso_utc(unix_seconds: u64) -> String {
+    let (year, month, day, hour, minute, second) = utc_parts(unix_seconds);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+/// Current time in seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Get the history file path, if there is anywhere to keep it
+fn get_history_path() -> Result<Option<PathBuf>> {
+    Ok(get_data_dir()?.map(|dir| dir.join("history.json")))
+}
+
+/// Load connection history, starting fresh if there is none or it can't be read
+pub fn load_history() -> Result<History> {
+    let Some(history_path) = get_history_path()? else {
+        return Ok(History::default());
+    };
+
+    match read_sensitive(&history_path)? {
+        Some(history_str) => Ok(serde_json::from_str(&history_str).unwrap_or_default()),
+        None => Ok(History::default()),
+    }
+}
+
+/// Save connection history to the history file
+pub fn save_history(history: &History) -> Result<()> {
+    let Some(history_path) = get_history_path()? else {
+        return Ok(());
+    };
+    let history_str = serde_json::to_string_pretty(history)?;
+    write_sensitive(&history_path, &history_str)?;
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use crate::{actions::set_node_env, config::Config, platform::shell_command, subprocess, tailscale::TailscaleNode};
+use anyhow::{anyhow, Context, Result};
+use std::process::{Command, ExitStatus, Stdio};
+
+/// Run the `before_connect` hook, if there is one. A failing hook calls the connection off.
+pub fn run_before_connect(config: &Config, node: &TailscaleNode, username: &str) -> Result<()> {
+    let Some(mut command) = before_connect_command(config, node, username) else {
+        return Ok(());
+    };
+
+    command.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
+    let status = subprocess::status(&mut command).context("Failed to run the before_connect hook")?;
+    if !status.success() {
+        return Err(anyhow!("Not connecting to {}: the before_connect hook failed with {}", node.name, status));
+    }
+    Ok(())
+}
+
+/// Run the `before_connect` hook without a terminal, for when the screen is
+/// someone else's: its output is dropped, except the last line it wrote to
+/// stderr, which goes in the error if it fails.
+pub fn check_before_connect(config: &Config, node: &TailscaleNode, username: &str) -> Result<()> {
+    let Some(mut command) = before_connect_command(config, node, username) else {
+        return Ok(());
+    };
+
+    command.stdin(Stdio::null());
+    let output = subprocess::output(&mut command).context("Failed to run the before_connect hook")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = match stderr.lines().rev().find(|line| !line.trim().is_empty()) {
+            Some(line) => format!(": {}", line.trim()),
+            None => String::new(),
+        };
+        return Err(anyhow!(
+            "Not connecting to {}: the before_connect hook failed with {}{}",
+            node.name,
+            output.status,
+            reason
+        ));
+    }
+    Ok(())
+}
+
+/// The `before_connect` hook's command, with the node's details in its environment
+fn before_connect_command(config: &Config, node: &TailscaleNode, username: &str) -> Option<Command> {
+    let command_line = config.hooks.as_ref().and_then(|hooks| hooks.before_connect.as_deref())?;
+    let mut command = shell_command(command_line);
+    set_node_env(&mut command, node, username);
+    Some(command)
+}
+
+/// Run the `after_session` hook, if there is one, with how the session ended
+/// in `SSH_TAILSCALE_EXIT_STATUS`. Failures are only reported, since the session is over anyway.
+pub fn run_after_session(config: &Config, node: &TailscaleNode, username: &str, session: Option<ExitStatus>) {
+    let Some(command_line) = config.hooks.as_ref().and_then(|hooks| hooks.after_session.as_deref()) else {
+        return;
+    };
+
+    let mut command = shell_command(command_line);
+    set_node_env(&mut command, node, username);
+    // Sessions that never started, or were ended by a signal, have no exit code
+    let exit_status = session.and_then(|status| status.code()).map(|code| code.to_string()).unwrap_or_default();
+    command.env("SSH_TAILSCALE_EXIT_STATUS", exit_status);
+    command.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
+    match subprocess::status(&mut command) {
+        Ok(status) if !status.success() => println!("Warning: the after_session hook failed with {}", status),
+        Ok(_) => {}
+        Err(err) => println!("Warning: failed to run the after_session hook: {}", err),
+    }
+}
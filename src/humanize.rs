@@ -0,0 +1,85 @@
+use crate::history::{format_utc, unix_now};
+use std::{env, time::Duration};
+
+/// Languages that write `1,5` rather than `1.5`
+const DECIMAL_COMMA_LANGUAGES: &[&str] = &[
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "lt", "lv", "nb", "nl", "nn",
+    "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+];
+
+/// Units of `bytes`, each 1024 times the one before
+const BYTE_UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+/// A Unix timestamp as how long ago it was, or as a UTC date when `absolute` is set
+pub fn timestamp(unix_seconds: u64, absolute: bool) -> String {
+    if absolute {
+        format_utc(unix_seconds)
+    } else {
+        time_ago(unix_seconds, unix_now())
+    }
+}
+
+/// How long before `now` a Unix timestamp was: `just now`, `3 min ago`,
+/// `yesterday`, and the date once it is more than a month back
+pub fn time_ago(unix_seconds: u64, now: u64) -> String {
+    let secs = now.saturating_sub(unix_seconds);
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", secs / 60),
+        3600..86_400 => format!("{} h ago", secs / 3600),
+        86_400..172_800 => "yesterday".to_string(),
+        172_800..2_592_000 => format!("{} days ago", secs / 86_400),
+        _ => format_utc(unix_seconds)[..10].to_string(),
+    }
+}
+
+/// A duration in its two largest units: `45 s`, `3 min`, `2 h 5 min`, `3 days 4 h`
+pub fn duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (minutes, hours, days) = (secs / 60, secs / 3600, secs / 86_400);
+    let with_rest = |whole: String, rest: u64, unit: &str| {
+        if rest == 0 { whole } else { format!("{} {} {}", whole, rest, unit) }
+    };
+    if secs < 60 {
+        format!("{} s", secs)
+    } else if hours == 0 {
+        format!("{} min", minutes)
+    } else if days == 0 {
+        with_rest(format!("{} h", hours), minutes % 60, "min")
+    } else {
+        let whole = if days == 1 { "1 day".to_string() } else { format!("{} days", days) };
+        with_rest(whole, hours % 24, "h")
+    }
+}
+
+/// A byte count in binary units with one decimal, like `1.2 GiB`, using the
+/// locale's decimal separator
+pub fn bytes(count: u64) -> String {
+    let mut value = count as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < BYTE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        return format!("{} B", count);
+    }
+    let number = format!("{:.1}", value).replace('.', &decimal_separator().to_string());
+    format!("{} {}", number, BYTE_UNITS[unit])
+}
+
+/// The decimal separator of the locale in `LC_ALL`, `LC_NUMERIC` or `LANG`,
+/// whichever is set first
+fn decimal_separator() -> char {
+    let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+        .into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    let language = locale.split(['_', '.', '@', '-']).next().unwrap_or_default().to_lowercase();
+    if DECIMAL_COMMA_LANGUAGES.contains(&language.as_str()) {
+        ','
+    } else {
+        '.'
+    }
+}
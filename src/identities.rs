@@ -0,0 +1,286 @@
+use crate::{
+    config::{get_data_dir, save_config, Config},
+    platform::expand_home,
+    secure::create_private_dir,
+    selector::group_contains,
+    subprocess::{self, Subprocess},
+    tailscale::TailscaleNode,
+};
+use anyhow::{anyhow, Context, Result};
+use dialoguer::{theme::ColorfulTheme, Select};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// A key that can be offered to nodes
+#[derive(Debug, Clone)]
+pub struct Identity {
+    /// The file to pass as `IdentityFile`: the private key next to a `.pub`, or
+    /// the public key alone, which makes ssh use the matching agent key
+    pub path: Option<PathBuf>,
+    /// The `type base64` public key, which identifies the key across files and the agent
+    pub public_key: String,
+    /// The key's comment, usually `user@host` or the file it was loaded from
+    pub comment: String,
+    /// `ssh-keygen -l` style summary, like `256 SHA256:... (ED25519)`
+    pub fingerprint: String,
+    /// Whether the ssh-agent holds the key
+    pub in_agent: bool,
+}
+
+impl Identity {
+    /// How the key is shown in the picker
+    fn label(&self) -> String {
+        let place = match (&self.path, self.in_agent) {
+            (Some(path), true) => format!("{} (in agent)", path.display()),
+            (Some(path), false) => path.display().to_string(),
+            (None, _) => format!("agent: {}", self.comment),
+        };
+        format!("{}  {}", place, self.fingerprint)
+    }
+}
+
+/// The key a node connects with, and where that was set: the node itself, or
+/// the first group (alphabetically) that both contains it and has a key
+pub fn identity_for(config: &Config, node: &TailscaleNode) -> Option<(String, String)> {
+    if let Some(identity) = &config.node_settings(&node.name).identity {
+        return Some((identity.clone(), "config node identity".to_string()));
+    }
+    config
+        .identities
+        .groups
+        .iter()
+        .find(|(group, _)| group_contains(config, group, node))
+        .map(|(group, identity)| (identity.clone(), format!("config identities, group {}", group)))
+}
+
+/// The `type base64` part of a public key line
+fn key_part(line: &str) -> Option<String> {
+    let mut fields = line.split_whitespace();
+    Some(format!("{} {}", fields.next()?, fields.next()?))
+}
+
+/// Everything after the key in a public key line
+fn comment_part(line: &str) -> String {
+    line.split_whitespace().skip(2).collect::<Vec<_>>().join(" ")
+}
+
+/// Keys in `~/.ssh` (found by their `.pub` files) and in the ssh-agent, each once
+pub fn discover() -> Vec<Identity> {
+    let mut identities = Vec::new();
+
+    if let Some(ssh_dir) = dirs::home_dir().map(|home| home.join(".ssh"))
+        && let Ok(entries) = fs::read_dir(&ssh_dir)
+    {
+        let mut paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "pub"))
+            .collect();
+        paths.sort();
+        for public_path in paths {
+            let Some(line) = fs::read_to_string(&public_path).ok().and_then(|text| text.lines().next().map(str::to_string))
+            else {
+                continue;
+            };
+            let Some(public_key) = key_part(&line) else {
+                continue;
+            };
+            // The private key is what ssh is usually pointed at; the public one works with an agent
+            let private_path = public_path.with_extension("");
+            let path = if private_path.is_file() { private_path } else { public_path.clone() };
+            identities.push(Identity {
+                path: Some(path),
+                public_key,
+                comment: comment_part(&line),
+                fingerprint: fingerprint_of(&public_path),
+                in_agent: false,
+            });
+        }
+    }
+
+    for line in agent_keys() {
+        let Some(public_key) = key_part(&line) else {
+            continue;
+        };
+        match identities.iter_mut().find(|identity| identity.public_key == public_key) {
+            Some(identity) => identity.in_agent = true,
+            None => identities.push(Identity {
+                path: None,
+                fingerprint: fingerprint_of_line(&line),
+                comment: comment_part(&line),
+                public_key,
+                in_agent: true,
+            }),
+        }
+    }
+
+    identities
+}
+
+/// Public keys held by the ssh-agent, one line each; none without an agent
+fn agent_keys() -> Vec<String> {
+    subprocess::output(Command::new("ssh-add").arg("-L"))
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// `ssh-keygen -l` of a key file, without the comment
+fn fingerprint_of(path: &Path) -> String {
+    subprocess::output(Command::new("ssh-keygen").arg("-lf").arg(path))
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| summarize_fingerprint(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or_default()
+}
+
+/// `ssh-keygen -l` of a public key that only exists in the agent
+fn fingerprint_of_line(line: &str) -> String {
+    use std::io::Write;
+    let mut command = Command::new("ssh-keygen");
+    command
+        .args(["-l", "-f", "-"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null());
+    let subprocess = Subprocess::start(&command);
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            subprocess.finish(Err(&err));
+            return String::new();
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "{}", line);
+    }
+    let output = child.wait_with_output();
+    subprocess.finish(output.as_ref().map(|output| &output.status));
+    output
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| summarize_fingerprint(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or_default()
+}
+
+/// Keep the bits, hash and key type of `256 SHA256:abc comment (ED25519)`
+fn summarize_fingerprint(text: &str) -> String {
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    match (fields.first(), fields.get(1), fields.last()) {
+        (Some(bits), Some(hash), Some(kind)) if fields.len() >= 3 => format!("{} {} {}", bits, hash, kind),
+        _ => text.trim().to_string(),
+    }
+}
+
+/// A file for a key that only exists in the agent, so ssh can be told to use
+/// it: with `IdentitiesOnly`, an `IdentityFile` holding just the public key
+/// makes ssh ask the agent for the matching private key
+fn save_agent_key(identity: &Identity) -> Result<PathBuf> {
+    let dir = get_data_dir()?
+        .ok_or_else(|| anyhow!("Agent keys need somewhere to keep their public key, which --ephemeral doesn't allow"))?
+        .join("identities");
+    create_private_dir(&dir)?;
+    let name: String = identity
+        .fingerprint
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("agent-key")
+        .trim_start_matches("SHA256:")
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .take(16)
+        .collect();
+    let path = dir.join(format!("{}.pub", name));
+    fs::write(&path, format!("{} {}\n", identity.public_key, identity.comment))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// The public key of a configured key file, read from its `.pub`
+fn public_key_of(identity: &str) -> Option<String> {
+    let path = expand_home(identity).ok()?;
+    let public_path = if path.extension().is_some_and(|extension| extension == "pub") {
+        path
+    } else {
+        PathBuf::from(format!("{}.pub", path.display()))
+    };
+    key_part(fs::read_to_string(public_path).ok()?.lines().next()?)
+}
+
+/// A path with the home directory written as `~`, which ssh expands itself
+fn tilde_path(path: &Path) -> String {
+    match dirs::home_dir().and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// What `choose` assigns a key to
+pub enum IdentityTarget {
+    Node(String),
+    Group(String),
+}
+
+/// Let the user pick one of the discovered keys for a node or group, or go back
+/// to letting ssh choose, and save the choice to the config
+pub fn choose(config: &mut Config, target: IdentityTarget) -> Result<()> {
+    let identities = discover();
+    let current = match &target {
+        IdentityTarget::Node(name) => config.node_settings(name).identity,
+        IdentityTarget::Group(group) => config.identities.groups.get(group).cloned(),
+    };
+    let name = match &target {
+        IdentityTarget::Node(name) => name.clone(),
+        IdentityTarget::Group(group) => format!("group:{}", group),
+    };
+
+    let mut items = vec!["Let ssh choose (every key it has, in its own order)".to_string()];
+    items.extend(identities.iter().map(Identity::label));
+    let current_key = current.as_deref().and_then(public_key_of);
+    let selected = identities
+        .iter()
+        .position(|identity| Some(&identity.public_key) == current_key.as_ref())
+        .map_or(0, |index| index + 1);
+    if identities.is_empty() {
+        println!("No keys found in ~/.ssh or the ssh-agent");
+    }
+
+    let choice = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Key for {}", name))
+        .items(&items)
+        .default(selected)
+        .interact()?;
+    let identity = match choice {
+        0 => None,
+        index => {
+            let identity = &identities[index - 1];
+            let path = match &identity.path {
+                Some(path) => path.clone(),
+                None => save_agent_key(identity)?,
+            };
+            Some(tilde_path(&path))
+        }
+    };
+
+    match target {
+        IdentityTarget::Node(node) => config.nodes.entry(node).or_default().identity = identity.clone(),
+        IdentityTarget::Group(group) => match &identity {
+            Some(identity) => {
+                config.identities.groups.insert(group, identity.clone());
+            }
+            None => {
+                config.identities.groups.remove(&group);
+            }
+        },
+    }
+    save_config(config)?;
+    match identity {
+        Some(identity) => println!("{} now connects with {} only", name, identity),
+        None => println!("{} now lets ssh choose the key", name),
+    }
+    Ok(())
+}
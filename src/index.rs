@@ -0,0 +1,333 @@
+use crate::{config::Config, tailscale::TailscaleNode};
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Lowercased lookup keys for a list of nodes, shared by the picker filter,
+/// node arguments on the command line and shell completion so they all agree
+/// on what a piece of text matches
+#[derive(Clone, Default)]
+pub struct NodeIndex {
+    /// Hostname of each node as shown, in node order
+    names: Vec<String>,
+    /// Searchable fields of each node, in node order
+    entries: Vec<Entry>,
+    /// Lowercased aliases from the config, to the node they name
+    aliases: BTreeMap<String, usize>,
+    /// Lowercased ACL tags, like `tag:server`, to the nodes that have them
+    tags: BTreeMap<String, Vec<usize>>,
+}
+
+/// The lowercased fields of one node that queries match against
+#[derive(Clone)]
+struct Entry {
+    name: String,
+    alias: Option<String>,
+    /// IPv4 and IPv6 address, the latter empty if there is none
+    ips: [String; 2],
+    /// MagicDNS name, empty without MagicDNS
+    dns: String,
+    os: String,
+    /// The owner's login shown in the list
+    user: String,
+    /// Connection status, like `active; direct` or `offline`
+    status: String,
+    tags: Vec<String>,
+    /// Notes from the config
+    notes: String,
+}
+
+/// One space-separated part of a query
+enum Term<'a> {
+    /// `ip:` and the start of an address
+    Ip(&'a str),
+    /// `os:` and part of the operating system
+    Os(&'a str),
+    /// `user:` and part of the owner's login
+    User(&'a str),
+    /// `tag:` and the start of a tag, matched with the prefix so `tag:web` finds `tag:web-prod`
+    Tag(&'a str),
+    /// `note:` and part of the node's notes
+    Note(&'a str),
+    /// Anything else, matched against every field
+    Any(&'a str),
+}
+
+impl<'a> Term<'a> {
+    /// Parse a lowercased query word
+    fn parse(word: &'a str) -> Self {
+        if let Some(ip) = word.strip_prefix("ip:") {
+            Term::Ip(ip)
+        } else if let Some(os) = word.strip_prefix("os:") {
+            Term::Os(os)
+        } else if let Some(user) = word.strip_prefix("user:") {
+            Term::User(user)
+        } else if let Some(note) = word.strip_prefix("note:") {
+            Term::Note(note)
+        } else if word.starts_with("tag:") {
+            Term::Tag(word)
+        } else {
+            Term::Any(word)
+        }
+    }
+
+    /// Whether a node's fields satisfy the term
+    fn matches(&self, entry: &Entry) -> bool {
+        match *self {
+            Term::Ip(ip) => entry.ips.iter().any(|address| !address.is_empty() && address.starts_with(ip)),
+            Term::Os(os) => entry.os.contains(os),
+            Term::User(user) => entry.user.contains(user),
+            Term::Tag(tag) => entry.tags.iter().any(|node_tag| node_tag.starts_with(tag)),
+            Term::Note(note) => entry.notes.contains(note),
+            Term::Any(text) => {
+                entry.name.contains(text)
+                    || entry.alias.as_ref().is_some_and(|alias| alias.contains(text))
+                    || entry.ips.iter().any(|address| address.contains(text))
+                    || entry.dns.contains(text)
+                    || entry.os.contains(text)
+                    || entry.user.contains(text)
+                    || entry.status.contains(text)
+                    || entry.tags.iter().any(|tag| tag.contains(text))
+                    || entry.notes.contains(text)
+            }
+        }
+    }
+}
+
+impl NodeIndex {
+    /// Index the nodes, along with the aliases the config gives them
+    pub fn new(nodes: &[TailscaleNode], config: &Config) -> Self {
+        let mut index = Self::default();
+
+        for (position, node) in nodes.iter().enumerate() {
+            let alias = config.alias(&node.name).map(str::to_lowercase);
+            if let Some(alias) = &alias {
+                index.aliases.insert(alias.clone(), position);
+            }
+            let tags: Vec<String> = node.tags.iter().map(|tag| tag.to_lowercase()).collect();
+            for tag in &tags {
+                index.tags.entry(tag.clone()).or_default().push(position);
+            }
+
+            index.names.push(node.name.clone());
+            index.entries.push(Entry {
+                name: node.name.to_lowercase(),
+                alias,
+                ips: [node.ip.to_lowercase(), node.ipv6.to_lowercase()],
+                dns: node.dns_name.to_lowercase(),
+                os: node.os.to_lowercase(),
+                user: node.suggested_user.to_lowercase(),
+                status: node.status.to_lowercase(),
+                tags,
+                notes: config.nodes.get(&node.name).map(|settings| settings.notes.to_lowercase()).unwrap_or_default(),
+            });
+        }
+
+        index
+    }
+
+    /// Positions of the nodes a query matches, in node order. Every space-separated
+    /// term has to match: `ip:`, `os:`, `user:` and `note:` look at just that field,
+    /// `tag:` matches the start of a tag, and other words match any field shown
+    /// for the node, including its notes. Case is ignored and an empty query matches everything.
+    pub fn matches(&self, query: &str) -> Vec<usize> {
+        let query = query.to_lowercase();
+        let terms: Vec<Term> = query.split_whitespace().map(Term::parse).collect();
+        (0..self.entries.len())
+            .filter(|&position| terms.iter().all(|term| term.matches(&self.entries[position])))
+            .collect()
+    }
+
+    /// The node a query names exactly: its hostname, alias or IP address
+    pub fn exact(&self, query: &str) -> Option<usize> {
+        let lower_query = query.to_lowercase();
+        self.entries
+            .iter()
+            .position(|entry| entry.name == lower_query)
+            .or_else(|| self.aliases.get(&lower_query).copied())
+            .or_else(|| self.entries.iter().position(|entry| entry.ips.contains(&lower_query)))
+            .or_else(|| {
+                let dns = lower_query.trim_end_matches('.');
+                self.entries.iter().position(|entry| !entry.dns.is_empty() && entry.dns == dns)
+            })
+    }
+
+    /// The single node a command line argument refers to. Exact names win, even
+    /// if they are also part of other names, and a query matching several nodes
+    /// still picks one if only its hostname or alias contains the text.
+    pub fn resolve(&self, query: &str) -> Result<usize> {
+        if let Some(position) = self.exact(query) {
+            return Ok(position);
+        }
+
+        let matches = self.matches(query);
+        let lower_query = query.to_lowercase();
+        let by_name: Vec<usize> = matches
+            .iter()
+            .copied()
+            .filter(|&position| {
+                let entry = &self.entries[position];
+                entry.name.contains(&lower_query) || entry.alias.as_ref().is_some_and(|alias| alias.contains(&lower_query))
+            })
+            .collect();
+
+        match (matches.as_slice(), by_name.as_slice()) {
+            ([], _) => Err(anyhow!("No Tailscale node matches '{}'", query)),
+            ([position], _) | (_, [position]) => Ok(*position),
+            (positions, _) => Err(anyhow!(
+                "'{}' matches several nodes: {}",
+                query,
+                positions
+                    .iter()
+                    .map(|&position| self.names[position].as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    /// Hostnames, aliases and tags starting with a partly typed word, for shell completion
+    pub fn completions(&self, word: &str) -> Vec<String> {
+        let word = word.to_lowercase();
+        let words: BTreeSet<&str> = self
+            .names
+            .iter()
+            .zip(&self.entries)
+            .filter(|(_, entry)| entry.name.starts_with(&word))
+            .map(|(name, _)| name.as_str())
+            .chain(self.aliases.keys().map(String::as_str).filter(|alias| alias.starts_with(&word)))
+            .chain(self.tags.keys().map(String::as_str).filter(|tag| tag.starts_with(&word)))
+            .collect();
+        words.into_iter().map(str::to_string).collect()
+    }
+}
+
+/// Find the node a command line argument refers to
+pub fn find_node<'a>(nodes: &'a [TailscaleNode], config: &Config, query: &str) -> Result<&'a TailscaleNode> {
+    let position = NodeIndex::new(nodes, config).resolve(query)?;
+    Ok(&nodes[position])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NodeSettings;
+
+    fn node(name: &str, ip: &str, os: &str, user: &str, tags: &[&str]) -> TailscaleNode {
+        TailscaleNode {
+            id: String::new(),
+            name: name.to_string(),
+            ip: ip.to_string(),
+            ipv6: String::new(),
+            dns_name: format!("{}.tail1234.ts.net", name.to_lowercase()),
+            suggested_user: user.to_string(),
+            os: os.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            status: "active; direct".to_string(),
+            tailscale_ssh: false,
+            last_seen: None,
+            rx_bytes: 0,
+            tx_bytes: 0,
+            mullvad: false,
+            is_self: false,
+        }
+    }
+
+    fn index() -> NodeIndex {
+        let nodes = [
+            node("web-1", "100.64.0.1", "linux", "piotr", &["tag:web-prod"]),
+            node("web-10", "100.64.0.10", "linux", "", &["tag:web"]),
+            node("Gaming-PC", "100.64.1.5", "windows", "jane", &[]),
+            node("db", "100.64.2.1", "freebsd", "", &["tag:db"]),
+        ];
+        let mut config = Config::default();
+        config.nodes.insert(
+            "db".to_string(),
+            NodeSettings {
+                alias: Some("Postgres".to_string()),
+                notes: "Backups at 03:00".to_string(),
+                ..NodeSettings::default()
+            },
+        );
+        NodeIndex::new(&nodes, &config)
+    }
+
+    #[test]
+    fn each_term_looks_at_its_field() {
+        let index = index();
+        let cases: &[(&str, &[usize])] = &[
+            ("ip:100.64.0.1", &[0, 1]),
+            ("ip:0.1", &[]),
+            ("os:win", &[2]),
+            ("OS:LINUX", &[0, 1]),
+            ("user:pio", &[0]),
+            ("user:linux", &[]),
+            ("tag:web", &[0, 1]),
+            ("tag:web-", &[0]),
+            ("tag:prod", &[]),
+            ("note:backups", &[3]),
+            ("note:web", &[]),
+            ("postgres", &[3]),
+            ("jane", &[2]),
+            ("direct", &[0, 1, 2, 3]),
+            ("", &[0, 1, 2, 3]),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(index.matches(query), *expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn plain_words_match_the_dns_name() {
+        let index = index();
+        assert_eq!(index.matches("gaming-pc.tail1234"), [2]);
+        assert_eq!(index.matches("ts.net"), [0, 1, 2, 3]);
+        assert_eq!(index.resolve("db.tail").unwrap(), 3);
+    }
+
+    #[test]
+    fn every_term_has_to_match() {
+        let index = index();
+        assert_eq!(index.matches("os:linux user:piotr"), [0]);
+        assert_eq!(index.matches("web tag:web"), [0, 1]);
+        assert_eq!(index.matches("web windows"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn exact_takes_a_name_alias_ip_or_dns_name() {
+        let index = index();
+        assert_eq!(index.exact("WEB-1"), Some(0));
+        assert_eq!(index.exact("gaming-pc"), Some(2));
+        assert_eq!(index.exact("postgres"), Some(3));
+        assert_eq!(index.exact("100.64.0.10"), Some(1));
+        assert_eq!(index.exact("db.tail1234.ts.net."), Some(3));
+        assert_eq!(index.exact("web"), None);
+    }
+
+    #[test]
+    fn resolve_prefers_names_and_reports_ambiguity() {
+        let index = index();
+        // web-1 is also part of web-10, but names it exactly
+        assert_eq!(index.resolve("web-1").unwrap(), 0);
+        assert_eq!(index.resolve("os:windows").unwrap(), 2);
+        assert_eq!(index.resolve("user:piotr").unwrap(), 0);
+        assert_eq!(index.resolve("gres").unwrap(), 3);
+        // Every address contains "10", but only one name does
+        assert_eq!(index.resolve("10").unwrap(), 1);
+
+        let err = index.resolve("web").unwrap_err().to_string();
+        assert!(err.contains("matches several nodes: web-1, web-10"), "{}", err);
+        let err = index.resolve("mainframe").unwrap_err().to_string();
+        assert!(err.contains("No Tailscale node matches 'mainframe'"), "{}", err);
+    }
+
+    #[test]
+    fn completions_offer_names_aliases_and_tags() {
+        let index = index();
+        assert_eq!(index.completions("web"), ["web-1", "web-10"]);
+        assert_eq!(index.completions("G"), ["Gaming-PC"]);
+        assert_eq!(index.completions("post"), ["postgres"]);
+        assert_eq!(index.completions("tag:w"), ["tag:web", "tag:web-prod"]);
+        assert!(index.completions("x").is_empty());
+        assert_eq!(index.completions("").len(), 4 + 1 + 3);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{
+    model::{Listing, Node},
+    tailscale::TailscaleNode,
+};
+use anyhow::{anyhow, Context, Result};
+use std::{fs, io::Read, path::Path};
+
+/// Read the nodes for `--nodes-from`, from a file or `-` for stdin. The text is
+/// either the JSON of `--list --emit json` (or just its `items` array), or CSV
+/// with a header row naming the same fields.
+pub fn read_nodes(source: &Path) -> Result<Vec<TailscaleNode>> {
+    let text = if source == Path::new("-") {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .context("Failed to read the node list from stdin")?;
+        text
+    } else {
+        fs::read_to_string(source).with_context(|| format!("Failed to read {}", source.display()))?
+    };
+
+    let nodes = if text.trim_start().starts_with(['{', '[']) {
+        parse_json(&text)?
+    } else {
+        parse_csv(&text)?
+    };
+    if nodes.is_empty() {
+        return Err(anyhow!("The node list from {} is empty", source.display()));
+    }
+    Ok(nodes)
+}
+
+/// A `Listing` of nodes, or a bare array of them
+fn parse_json(text: &str) -> Result<Vec<TailscaleNode>> {
+    let nodes: Vec<Node> = if text.trim_start().starts_with('[') {
+        serde_json::from_str(text).context("The node list isn't an array of --emit json nodes")?
+    } else {
+        serde_json::from_str::<Listing<Node>>(text)
+            .context("The node list isn't the output of --list --emit json")?
+            .items
+    };
+    Ok(nodes.into_iter().map(from_model).collect())
+}
+
+/// A node from the JSON model. `user` and `alias` come from the config, so
+/// they are worked out again rather than taken from the list.
+fn from_model(node: Node) -> TailscaleNode {
+    let status = if node.online || node.status.starts_with("offline") {
+        node.status
+    } else {
+        "offline".to_string()
+    };
+    TailscaleNode {
+        id: String::new(),
+        name: node.name,
+        ip: node.ip,
+        ipv6: node.ipv6.unwrap_or_default(),
+        dns_name: node.dns_name.unwrap_or_default(),
+        suggested_user: node.owner.unwrap_or_default(),
+        os: node.os,
+        tags: node.tags,
+        status,
+        tailscale_ssh: node.tailscale_ssh,
+        last_seen: node.last_seen,
+        rx_bytes: 0,
+        tx_bytes: 0,
+        mullvad: false,
+        is_self: false,
+    }
+}
+
+/// CSV with a header row. `name` and `ip` are required; the other columns are
+/// the JSON fields, and unknown ones are ignored.
+fn parse_csv(text: &str) -> Result<Vec<TailscaleNode>> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<String> = split_csv_line(lines.next().unwrap_or_default())
+        .into_iter()
+        .map(|column| column.trim().to_lowercase())
+        .collect();
+    let column = |name: &str| header.iter().position(|column| column == name);
+    let (Some(name_column), Some(ip_column)) = (column("name"), column("ip")) else {
+        return Err(anyhow!("The node list needs a header row with at least `name` and `ip` columns"));
+    };
+
+    let mut nodes = Vec::new();
+    for (row, line) in lines.enumerate() {
+        let fields = split_csv_line(line);
+        let field = |index: Option<usize>| {
+            index
+                .and_then(|index| fields.get(index))
+                .map(|field| field.trim().to_string())
+                .unwrap_or_default()
+        };
+        let flag = |name: &str| matches!(field(column(name)).to_lowercase().as_str(), "true" | "yes" | "1");
+
+        let name = field(Some(name_column));
+        let ip = field(Some(ip_column));
+        if name.is_empty() || ip.is_empty() {
+            return Err(anyhow!("Row {} of the node list has no name or ip", row + 1));
+        }
+        let status = match (field(column("status")), column("online")) {
+            (status, _) if !status.is_empty() => status,
+            (_, Some(_)) if !flag("online") => "offline".to_string(),
+            _ => "idle".to_string(),
+        };
+        nodes.push(TailscaleNode {
+            id: String::new(),
+            name,
+            ip,
+            ipv6: field(column("ipv6")),
+            dns_name: field(column("dns_name")),
+            suggested_user: field(column("owner")),
+            os: field(column("os")),
+            // Tags may be separated by commas (in a quoted field), semicolons or spaces
+            tags: field(column("tags"))
+                .split([',', ';', ' '])
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect(),
+            status,
+            tailscale_ssh: flag("tailscale_ssh"),
+            last_seen: field(column("last_seen")).parse().ok(),
+            rx_bytes: 0,
+            tx_bytes: 0,
+            mullvad: false,
+            is_self: false,
+        });
+    }
+    Ok(nodes)
+}
+
+/// Split one CSV line into fields, with `"..."` quoting and `""` for a quote
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_columns_are_found_by_header() {
+        let nodes = parse_csv("OS, IP ,Name,extra\nlinux,100.64.0.1,web-1,x\r\nwindows,100.64.0.2,gaming-pc,y\n").unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].name, "web-1");
+        assert_eq!(nodes[0].ip, "100.64.0.1");
+        assert_eq!(nodes[0].os, "linux");
+        assert_eq!(nodes[1].name, "gaming-pc");
+        assert_eq!(nodes[1].os, "windows");
+    }
+
+    #[test]
+    fn csv_fields_can_be_quoted() {
+        let nodes = parse_csv(
+            "name,ip,tags,status\n\"web, the first\",100.64.0.1,\"tag:web,tag:prod\",\"active; relay \"\"fra\"\"\"\n",
+        )
+        .unwrap();
+        assert_eq!(nodes[0].name, "web, the first");
+        assert_eq!(nodes[0].tags, ["tag:web", "tag:prod"]);
+        assert_eq!(nodes[0].status, "active; relay \"fra\"");
+    }
+
+    #[test]
+    fn missing_csv_columns_get_defaults() {
+        let nodes = parse_csv("name,ip\nweb-1,100.64.0.1\n\n").unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].os, "");
+        assert!(nodes[0].tags.is_empty());
+        assert_eq!(nodes[0].status, "idle");
+        assert_eq!(nodes[0].last_seen, None);
+
+        let nodes = parse_csv("name,ip,online,tailscale_ssh,last_seen\na,100.64.0.1,no,yes,1700000000\nb,100.64.0.2,true,,\n").unwrap();
+        assert_eq!(nodes[0].status, "offline");
+        assert!(nodes[0].tailscale_ssh);
+        assert_eq!(nodes[0].last_seen, Some(1_700_000_000));
+        assert_eq!(nodes[1].status, "idle");
+        assert!(!nodes[1].tailscale_ssh);
+    }
+
+    #[test]
+    fn csv_needs_name_and_ip() {
+        for text in ["", "name,os\nweb-1,linux\n", "host,address\nweb-1,100.64.0.1\n"] {
+            let err = parse_csv(text).unwrap_err().to_string();
+            assert!(err.contains("`name` and `ip` columns"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn bad_csv_rows_are_reported_by_number() {
+        let err = parse_csv("name,ip\nweb-1,100.64.0.1\nweb-2\n").unwrap_err().to_string();
+        assert!(err.contains("Row 2"), "{}", err);
+        let err = parse_csv("name,ip\n,100.64.0.1\n").unwrap_err().to_string();
+        assert!(err.contains("Row 1"), "{}", err);
+    }
+}
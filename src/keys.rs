@@ -0,0 +1,630 @@
+use anyhow::{anyhow, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::BTreeMap;
+
+/// Something a key can be bound to in the terminal UI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Connect to the selected node, or edit the highlighted entry in a popup
+    Select,
+    /// Move the selection or scroll position up
+    Up,
+    /// Move the selection or scroll position down
+    Down,
+    /// Move up by a page
+    PageUp,
+    /// Move down by a page
+    PageDown,
+    /// Jump to the first entry
+    Top,
+    /// Jump to the last entry
+    Bottom,
+    /// Clear the filter text
+    ClearFilter,
+    /// Close the current pane or popup
+    Back,
+    /// Exit without connecting, from anywhere
+    Quit,
+    /// Open the one-off command prompt
+    RunCommand,
+    /// Edit default usernames for the selected node
+    EditUsernames,
+    /// Edit the selected node's username, alias, port and notes
+    EditNode,
+    /// Sync dotfiles to the selected node
+    SyncDotfiles,
+    /// Show or hide the detail pane
+    ToggleDetails,
+    /// Show every key binding and where settings come from
+    Help,
+    /// Open the menu of custom actions for the selected node
+    Actions,
+    /// Choose a group of nodes to list, or all of them
+    Groups,
+    /// Show the service forwards kept open in the background
+    Tunnels,
+    /// Choose or compose the order of the list
+    Sort,
+    /// List the nodes `ui.hide` leaves out, or leave them out again
+    ShowHidden,
+    /// Expand a row of nodes folded by `ui.collapse`, or fold them again
+    Fold,
+    /// Open the selected node's web UI in the browser
+    Browse,
+    /// Send a file to the selected node with Taildrop
+    SendFile,
+    /// List the nodes added, removed, or gone on- or offline since the last run
+    Changes,
+    /// Show the ssh command that connecting to the selected node would run
+    ShowCommand,
+    /// Copy the `ssh://` URI of the selected node to the clipboard
+    CopyUri,
+    /// Turn agent forwarding (`-A`) on or off for the session
+    ToggleAgent,
+    /// Cycle X11 forwarding through off, `-X` and `-Y` for the session
+    ToggleX11,
+    /// Turn a forced terminal (`-t`) on or off for the session
+    ToggleTty,
+    /// Run `tailscale netcheck` and show what it found
+    Diagnostics,
+    /// Pick a node and open a session to it in a new pane
+    NewPane,
+    /// Move the focus to the next pane
+    NextPane,
+    /// End the focused pane's session and close it
+    ClosePane,
+}
+
+impl Action {
+    /// Every action, in the order they are listed in help
+    pub const ALL: [Action; 34] = [
+        Action::Select,
+        Action::Up,
+        Action::Down,
+        Action::PageUp,
+        Action::PageDown,
+        Action::Top,
+        Action::Bottom,
+        Action::ClearFilter,
+        Action::Back,
+        Action::Quit,
+        Action::RunCommand,
+        Action::EditUsernames,
+        Action::EditNode,
+        Action::SyncDotfiles,
+        Action::ToggleDetails,
+        Action::Help,
+        Action::Actions,
+        Action::Groups,
+        Action::Tunnels,
+        Action::Sort,
+        Action::ShowHidden,
+        Action::Fold,
+        Action::Browse,
+        Action::SendFile,
+        Action::Changes,
+        Action::ShowCommand,
+        Action::CopyUri,
+        Action::ToggleAgent,
+        Action::ToggleX11,
+        Action::ToggleTty,
+        Action::Diagnostics,
+        Action::NewPane,
+        Action::NextPane,
+        Action::ClosePane,
+    ];
+
+    /// Name used for the action in the `keys` config table
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Select => "select",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::PageUp => "page_up",
+            Action::PageDown => "page_down",
+            Action::Top => "top",
+            Action::Bottom => "bottom",
+            Action::ClearFilter => "clear_filter",
+            Action::Back => "back",
+            Action::Quit => "quit",
+            Action::RunCommand => "run_command",
+            Action::EditUsernames => "edit_usernames",
+            Action::EditNode => "edit_node",
+            Action::SyncDotfiles => "sync_dotfiles",
+            Action::ToggleDetails => "toggle_details",
+            Action::Help => "help",
+            Action::Actions => "actions",
+            Action::Groups => "groups",
+            Action::Tunnels => "tunnels",
+            Action::Sort => "sort",
+            Action::ShowHidden => "show_hidden",
+            Action::Fold => "fold",
+            Action::Browse => "browse",
+            Action::SendFile => "send_file",
+            Action::Changes => "changes",
+            Action::ShowCommand => "show_command",
+            Action::CopyUri => "copy_uri",
+            Action::ToggleAgent => "toggle_agent",
+            Action::ToggleX11 => "toggle_x11",
+            Action::ToggleTty => "toggle_tty",
+            Action::Diagnostics => "diagnostics",
+            Action::NewPane => "new_pane",
+            Action::NextPane => "next_pane",
+            Action::ClosePane => "close_pane",
+        }
+    }
+
+    /// Keys bound to the action unless the config says otherwise
+    fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Action::Select => &["enter"],
+            Action::Up => &["up", "k"],
+            Action::Down => &["down", "j"],
+            Action::PageUp => &["pageup"],
+            Action::PageDown => &["pagedown"],
+            Action::Top => &["home"],
+            Action::Bottom => &["end"],
+            Action::ClearFilter => &["esc"],
+            Action::Back => &["esc", "q"],
+            Action::Quit => &["ctrl+c", "ctrl+q"],
+            Action::RunCommand => &["!"],
+            Action::EditUsernames => &["ctrl+u"],
+            Action::EditNode => &["alt+e"],
+            Action::SyncDotfiles => &["ctrl+s"],
+            Action::ToggleDetails => &["tab"],
+            Action::Help => &["?"],
+            Action::Actions => &["ctrl+a"],
+            Action::Groups => &["ctrl+g"],
+            Action::Tunnels => &["ctrl+t"],
+            Action::Sort => &["ctrl+o"],
+            Action::ShowHidden => &["ctrl+e"],
+            Action::Fold => &["ctrl+f"],
+            Action::Browse => &["ctrl+b"],
+            Action::SendFile => &["ctrl+d"],
+            Action::Changes => &["ctrl+l"],
+            Action::ShowCommand => &["ctrl+y"],
+            Action::CopyUri => &["ctrl+k"],
+            Action::ToggleAgent => &["alt+a"],
+            Action::ToggleX11 => &["alt+x"],
+            Action::ToggleTty => &["alt+t"],
+            Action::Diagnostics => &["alt+d"],
+            Action::NewPane => &["alt+n"],
+            Action::NextPane => &["alt+o"],
+            Action::ClosePane => &["alt+w"],
+        }
+    }
+
+    /// What the action does, as shown in footer hints
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Select => "Connect",
+            Action::Up => "Up",
+            Action::Down => "Down",
+            Action::PageUp => "Page up",
+            Action::PageDown => "Page down",
+            Action::Top => "Top",
+            Action::Bottom => "Bottom",
+            Action::ClearFilter => "Clear filter",
+            Action::Back => "Back",
+            Action::Quit => "Exit",
+            Action::RunCommand => "Run command",
+            Action::EditUsernames => "Usernames",
+            Action::EditNode => "Edit node",
+            Action::SyncDotfiles => "Sync dotfiles",
+            Action::ToggleDetails => "Details",
+            Action::Help => "Help",
+            Action::Actions => "Actions",
+            Action::Groups => "Groups",
+            Action::Tunnels => "Tunnels",
+            Action::Sort => "Sort",
+            Action::ShowHidden => "Show hidden",
+            Action::Fold => "Fold",
+            Action::Browse => "Browser",
+            Action::SendFile => "Taildrop",
+            Action::Changes => "Changes",
+            Action::ShowCommand => "SSH command",
+            Action::CopyUri => "Copy URI",
+            Action::ToggleAgent => "Agent forwarding",
+            Action::ToggleX11 => "X11 forwarding",
+            Action::ToggleTty => "Force TTY",
+            Action::Diagnostics => "Diagnostics",
+            Action::NewPane => "New pane",
+            Action::NextPane => "Next pane",
+            Action::ClosePane => "Close pane",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+/// Actions available while browsing the node list
+pub const BROWSE_ACTIONS: &[Action] = &[
+    Action::Select,
+    Action::RunCommand,
+    Action::EditUsernames,
+    Action::EditNode,
+    Action::SyncDotfiles,
+    Action::ToggleDetails,
+    Action::Help,
+    Action::Actions,
+    Action::Browse,
+    Action::SendFile,
+    Action::ShowCommand,
+    Action::CopyUri,
+    Action::ToggleAgent,
+    Action::ToggleX11,
+    Action::ToggleTty,
+    Action::Diagnostics,
+    Action::Groups,
+    Action::Tunnels,
+    Action::Changes,
+    Action::Sort,
+    Action::ShowHidden,
+    Action::Fold,
+    Action::Up,
+    Action::Down,
+    Action::PageUp,
+    Action::PageDown,
+    Action::Top,
+    Action::Bottom,
+    Action::ClearFilter,
+];
+
+/// Actions available in the command output pane
+pub const OUTPUT_ACTIONS: &[Action] = &[
+    Action::Back,
+    Action::Up,
+    Action::Down,
+    Action::PageUp,
+    Action::PageDown,
+    Action::Top,
+    Action::Bottom,
+];
+
+/// Actions available in the help overlay
+pub const HELP_ACTIONS: &[Action] = &[Action::Back, Action::Help, Action::Up, Action::Down, Action::PageUp, Action::PageDown];
+
+/// Actions available in the username settings and node editor popups, outside of typing a value
+pub const USERNAME_ACTIONS: &[Action] = &[Action::Back, Action::Select, Action::Up, Action::Down];
+
+/// Actions available in the group list
+pub const GROUP_ACTIONS: &[Action] = &[
+    Action::Back,
+    Action::Select,
+    Action::Groups,
+    Action::Up,
+    Action::Down,
+    Action::Top,
+    Action::Bottom,
+];
+
+/// Actions available in the tunnel list
+pub const TUNNEL_ACTIONS: &[Action] = &[Action::Back, Action::Select, Action::Tunnels, Action::Up, Action::Down];
+
+/// Actions available in the list of changes since the last run
+pub const CHANGES_ACTIONS: &[Action] = &[Action::Back, Action::Changes, Action::Up, Action::Down];
+
+/// Actions available on the diagnostics screen
+pub const DIAGNOSTICS_ACTIONS: &[Action] = &[Action::Back, Action::Diagnostics, Action::Select, Action::Up, Action::Down];
+
+/// Actions of the split-pane sessions; every other key goes to the focused session
+pub const PANE_ACTIONS: &[Action] = &[Action::NewPane, Action::NextPane, Action::ClosePane];
+
+/// Actions available in the sort popup, outside of typing a name
+pub const SORT_ACTIONS: &[Action] = &[
+    Action::Back,
+    Action::Select,
+    Action::Sort,
+    Action::Up,
+    Action::Down,
+    Action::Top,
+    Action::Bottom,
+];
+
+/// Actions an embedded `NodePicker` handles, a subset of the browsing ones
+pub const PICKER_ACTIONS: &[Action] = &[
+    Action::Select,
+    Action::ToggleDetails,
+    Action::ShowHidden,
+    Action::Fold,
+    Action::Up,
+    Action::Down,
+    Action::PageUp,
+    Action::PageDown,
+    Action::Top,
+    Action::Bottom,
+    Action::ClearFilter,
+];
+
+/// Actions available in the custom action menu, besides the custom actions' own keys
+pub const ACTION_MENU_ACTIONS: &[Action] = &[Action::Back, Action::Select, Action::Up, Action::Down];
+
+/// One entry of the key hints in the footer while browsing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FooterHint {
+    /// An action and its key
+    Action(Action),
+    /// The up and down keys together
+    Navigate,
+}
+
+/// Footer hints shown unless the config lists its own
+const DEFAULT_FOOTER_HINTS: &[&str] = &["select", "run_command", "toggle_details", "clear_filter", "navigate", "help", "quit"];
+
+/// Parse the `ui.footer_hints` config list of action names (and `navigate`),
+/// or the default hints if there is none
+pub fn parse_footer_hints(names: Option<&[String]>) -> Result<Vec<FooterHint>> {
+    let names: Vec<&str> = match names {
+        Some(names) => names.iter().map(String::as_str).collect(),
+        None => DEFAULT_FOOTER_HINTS.to_vec(),
+    };
+    names
+        .into_iter()
+        .map(|name| match name {
+            "navigate" => Ok(FooterHint::Navigate),
+            _ => Action::from_name(name)
+                .map(FooterHint::Action)
+                .ok_or_else(|| anyhow!("Unknown hint '{}' in `ui.footer_hints`; use an action name from `keys` or `navigate`", name)),
+        })
+        .collect()
+}
+
+/// A key together with the modifiers that must be held
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyBinding {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    /// Parse a key like `ctrl+n`, `alt+enter`, `pagedown`, `K` or `!`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let invalid = || anyhow!("'{}' is not a key; use names like `ctrl+n`, `enter`, `pagedown` or a single character", spec);
+
+        let mut parts: Vec<&str> = spec.split('+').collect();
+        // A trailing `+` is the plus key itself, as in `ctrl++`
+        if spec.ends_with("++") || spec == "+" {
+            parts.truncate(parts.len() - 2);
+            parts.push("+");
+        }
+        let key = parts.pop().filter(|key| !key.is_empty()).ok_or_else(invalid)?;
+
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in parts {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(invalid()),
+            };
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match key.to_lowercase().as_str() {
+                "enter" | "return" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" | "ins" => KeyCode::Insert,
+                "space" => KeyCode::Char(' '),
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" | "pgup" => KeyCode::PageUp,
+                "pagedown" | "pgdn" => KeyCode::PageDown,
+                name => match name.strip_prefix('f').and_then(|number| number.parse().ok()) {
+                    Some(number @ 1..=24) => KeyCode::F(number),
+                    _ => return Err(invalid()),
+                },
+            },
+        };
+
+        Ok(Self::normalized(code, modifiers))
+    }
+
+    /// Whether a key event is this key
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        Self::from_event(key) == *self
+    }
+
+    /// The binding a key event matches
+    fn from_event(key: &KeyEvent) -> Self {
+        Self::normalized(key.code, key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT))
+    }
+
+    /// Bring equivalent spellings together. Shift is already part of a
+    /// character (`K`, `!`), and terminals report Ctrl+letter in either case.
+    fn normalized(code: KeyCode, mut modifiers: KeyModifiers) -> Self {
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            // Terminals report Shift+Tab as its own key
+            KeyCode::Tab | KeyCode::BackTab if modifiers.contains(KeyModifiers::SHIFT) || code == KeyCode::BackTab => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::BackTab
+            }
+            code => code,
+        };
+        let code = match code {
+            KeyCode::Char(c) if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                KeyCode::Char(c.to_ascii_lowercase())
+            }
+            code => code,
+        };
+        Self { code, modifiers }
+    }
+
+    /// How the key is written in help text, like `Ctrl+U` or `PgDn`
+    pub fn label(&self) -> String {
+        let key = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(c) if self.modifiers.is_empty() => c.to_string(),
+            KeyCode::Char(c) => c.to_ascii_uppercase().to_string(),
+            KeyCode::Enter => "Enter".to_string(),
+            KeyCode::Esc => "Esc".to_string(),
+            KeyCode::Tab => "Tab".to_string(),
+            KeyCode::BackTab => "Shift+Tab".to_string(),
+            KeyCode::Backspace => "Backspace".to_string(),
+            KeyCode::Delete => "Del".to_string(),
+            KeyCode::Insert => "Ins".to_string(),
+            KeyCode::Up => "↑".to_string(),
+            KeyCode::Down => "↓".to_string(),
+            KeyCode::Left => "←".to_string(),
+            KeyCode::Right => "→".to_string(),
+            KeyCode::Home => "Home".to_string(),
+            KeyCode::End => "End".to_string(),
+            KeyCode::PageUp => "PgUp".to_string(),
+            KeyCode::PageDown => "PgDn".to_string(),
+            KeyCode::F(number) => format!("F{}", number),
+            other => format!("{:?}", other),
+        };
+
+        let mut label = String::new();
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            label.push_str("Ctrl+");
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            label.push_str("Alt+");
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            label.push_str("Shift+");
+        }
+        label + &key
+    }
+}
+
+/// Keys bound to each action: the defaults, with any actions listed in the
+/// config's `keys` table rebound
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    /// Bindings in the order of `Action::ALL`
+    bindings: Vec<(Action, Vec<KeyBinding>)>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        let bindings = Action::ALL
+            .into_iter()
+            .map(|action| {
+                let keys = action.default_keys().iter().filter_map(|spec| KeyBinding::parse(spec).ok());
+                (action, keys.collect())
+            })
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl KeyMap {
+    /// Build the key map from the config's `keys` table, which maps action
+    /// names to the keys that replace their defaults
+    pub fn from_config(keys: &BTreeMap<String, Vec<String>>) -> Result<Self> {
+        let mut map = Self::default();
+        for (name, specs) in keys {
+            let action = Action::from_name(name).ok_or_else(|| {
+                let names: Vec<&str> = Action::ALL.iter().map(|action| action.name()).collect();
+                anyhow!("Unknown action '{}' in `keys`; expected one of: {}", name, names.join(", "))
+            })?;
+            let bindings = specs
+                .iter()
+                .map(|spec| KeyBinding::parse(spec))
+                .collect::<Result<Vec<_>>>()
+                .map_err(|err| anyhow!("Invalid key for `{}` in `keys`: {}", name, err))?;
+            map.bindings_mut(action).clone_from(&bindings);
+        }
+
+        for context in [
+            BROWSE_ACTIONS,
+            OUTPUT_ACTIONS,
+            HELP_ACTIONS,
+            USERNAME_ACTIONS,
+            ACTION_MENU_ACTIONS,
+            GROUP_ACTIONS,
+            TUNNEL_ACTIONS,
+            CHANGES_ACTIONS,
+            DIAGNOSTICS_ACTIONS,
+            PANE_ACTIONS,
+            SORT_ACTIONS,
+        ] {
+            map.check_conflicts(context)?;
+        }
+        Ok(map)
+    }
+
+    /// The action among `actions` that a key is bound to, if any. Quit is
+    /// checked first since it applies everywhere.
+    pub fn action(&self, key: &KeyEvent, actions: &[Action]) -> Option<Action> {
+        self.bound_to(&KeyBinding::from_event(key), actions)
+    }
+
+    /// The action among `actions` that a binding is already taken by, if any
+    pub fn bound_to(&self, binding: &KeyBinding, actions: &[Action]) -> Option<Action> {
+        std::iter::once(&Action::Quit)
+            .chain(actions)
+            .copied()
+            .find(|&action| self.bindings(action).contains(binding))
+    }
+
+    /// Help label for an action's first key, or `-` if it is unbound
+    pub fn label(&self, action: Action) -> String {
+        self.bindings(action)
+            .first()
+            .map(KeyBinding::label)
+            .unwrap_or_else(|| "-".to_string())
+    }
+
+    /// Labels of all keys bound to an action, like `↑, k`, or `-` if there are none
+    pub fn labels(&self, action: Action) -> String {
+        let labels: Vec<String> = self.bindings(action).iter().map(KeyBinding::label).collect();
+        if labels.is_empty() { "-".to_string() } else { labels.join(", ") }
+    }
+
+    /// A footer hint with the keys currently bound, like `Enter: Connect`
+    pub fn hint(&self, hint: FooterHint) -> String {
+        match hint {
+            FooterHint::Action(action) => format!("{}: {}", self.label(action), action.description()),
+            FooterHint::Navigate => format!("{}/{}: Navigate", self.label(Action::Up), self.label(Action::Down)),
+        }
+    }
+
+    fn bindings(&self, action: Action) -> &[KeyBinding] {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == action)
+            .map(|(_, keys)| keys.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn bindings_mut(&mut self, action: Action) -> &mut Vec<KeyBinding> {
+        let index = Action::ALL.iter().position(|&bound| bound == action).unwrap_or_default();
+        &mut self.bindings[index].1
+    }
+
+    /// Fail if one key would do two things in the same place
+    fn check_conflicts(&self, context: &[Action]) -> Result<()> {
+        let actions: Vec<Action> = std::iter::once(Action::Quit).chain(context.iter().copied()).collect();
+        for (index, &first) in actions.iter().enumerate() {
+            for &second in &actions[index + 1..] {
+                if let Some(key) = self.bindings(first).iter().find(|key| self.bindings(second).contains(key)) {
+                    return Err(anyhow!(
+                        "{} is bound to both `{}` and `{}` in `keys`",
+                        key.label(),
+                        first.name(),
+                        second.name()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
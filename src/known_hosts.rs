@@ -0,0 +1,367 @@
+use crate::{
+    config::{get_data_dir, Config},
+    platform::expand_home,
+    secure::write_private,
+    ssh::SshCommand,
+    subprocess::{self, Subprocess},
+    tailscale::TailscaleNode,
+};
+use anyhow::{anyhow, Context, Result};
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::io::AsyncWriteExt;
+
+/// How long `ssh-keyscan` waits for each node
+const KEYSCAN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A host key removed because its address now belongs to another node
+pub struct Removal {
+    pub host: String,
+    /// The node that has the address now
+    pub node: String,
+}
+
+/// Which node (by Tailscale ID) each host in the known_hosts file belonged to
+/// when it was last seen, kept next to the presence file
+type Owners = BTreeMap<String, String>;
+
+/// The dedicated known_hosts file, if one is configured
+pub fn known_hosts_path(config: &Config) -> Result<Option<PathBuf>> {
+    config.ssh.known_hosts.as_deref().map(expand_home).transpose()
+}
+
+fn get_owners_path() -> Result<Option<PathBuf>> {
+    Ok(get_data_dir()?.map(|dir| dir.join("known_hosts_owners.json")))
+}
+
+fn load_owners() -> Result<Owners> {
+    match get_owners_path()? {
+        Some(path) if path.exists() => Ok(serde_json::from_str(&fs::read_to_string(path)?).unwrap_or_default()),
+        _ => Ok(Owners::default()),
+    }
+}
+
+fn save_owners(owners: &Owners) -> Result<()> {
+    if let Some(path) = get_owners_path()? {
+        write_private(&path, serde_json::to_string_pretty(owners)?.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// What identifies a node across renames and address changes; older
+/// Tailscale versions don't report an ID, so the name stands in for it
+fn identity(node: &TailscaleNode) -> &str {
+    if node.id.is_empty() { &node.name } else { &node.id }
+}
+
+/// The node each tailnet address (IPs and MagicDNS name) currently belongs to
+fn current_owners(nodes: &[TailscaleNode]) -> HashMap<String, &TailscaleNode> {
+    let mut owners = HashMap::new();
+    for node in nodes {
+        let dns_name = node.dns_name.trim_end_matches('.');
+        for address in [node.ip.as_str(), node.ipv6.as_str(), dns_name] {
+            if !address.is_empty() {
+                owners.insert(address.to_string(), node);
+            }
+        }
+    }
+    owners
+}
+
+/// The hosts a known_hosts line is for, without `[...]:port` brackets; none
+/// for comments, blank lines, markers and hashed names
+fn line_hosts(line: &str) -> Vec<String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with('@') {
+        return Vec::new();
+    }
+    let Some(field) = line.split_whitespace().next() else {
+        return Vec::new();
+    };
+    field
+        .split(',')
+        .filter(|host| !host.starts_with('|'))
+        .map(|host| match host.strip_prefix('[').and_then(|rest| rest.split_once("]:")) {
+            Some((host, _port)) => host.to_string(),
+            None => host.to_string(),
+        })
+        .collect()
+}
+
+/// Drop host keys whose address has been handed to a different node since
+/// the key was recorded, so ssh doesn't refuse to connect to the new owner
+/// with a changed-key warning. Hosts seen for the first time are recorded as
+/// belonging to their current node. Does nothing without `ssh.known_hosts`.
+pub fn prune(config: &Config, nodes: &[TailscaleNode]) -> Result<Vec<Removal>> {
+    let Some(path) = known_hosts_path(config)? else {
+        return Ok(Vec::new());
+    };
+    let recorded = load_owners()?;
+    let (removals, owners) = prune_file(&path, nodes, &recorded)?;
+    if owners != recorded {
+        save_owners(&owners)?;
+    }
+    Ok(removals)
+}
+
+/// Drop the host keys in a known_hosts file whose host belonged to another
+/// node when it was `recorded`, returning them and the owners to record now
+fn prune_file(path: &Path, nodes: &[TailscaleNode], recorded: &Owners) -> Result<(Vec<Removal>, Owners)> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), recorded.clone())),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let current = current_owners(nodes);
+    let mut owners = Owners::new();
+    let mut removals = Vec::new();
+    let mut kept = Vec::new();
+
+    for line in contents.lines() {
+        let hosts = line_hosts(line);
+        let stale = hosts.iter().find_map(|host| {
+            let node = current.get(host)?;
+            let previous = recorded.get(host)?;
+            (previous != identity(node)).then(|| Removal {
+                host: host.clone(),
+                node: node.name.clone(),
+            })
+        });
+        if let Some(removal) = stale {
+            removals.push(removal);
+            continue;
+        }
+        for host in hosts {
+            // Addresses no node has right now keep their last owner
+            let owner = current.get(&host).map(|node| identity(node)).or(recorded.get(&host).map(String::as_str));
+            if let Some(owner) = owner {
+                owners.insert(host, owner.to_string());
+            }
+        }
+        kept.push(line);
+    }
+
+    if !removals.is_empty() {
+        let mut contents = kept.join("\n");
+        contents.push('\n');
+        fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok((removals, owners))
+}
+
+/// Fetch the host keys of `targets` with `ssh-keyscan`, show their
+/// fingerprints, and add the ones not already known after asking
+pub async fn scan(config: &Config, targets: &[&TailscaleNode], assume_yes: bool) -> Result<()> {
+    let path = known_hosts_path(config)?
+        .ok_or_else(|| anyhow!("Set ssh.known_hosts in the config to keep tailnet host keys in their own file"))?;
+    if targets.is_empty() {
+        return Err(anyhow!("No nodes left to scan"));
+    }
+    let existing = fs::read_to_string(&path).unwrap_or_default();
+
+    let mut scans: FuturesUnordered<_> = targets
+        .iter()
+        .map(|node| {
+            let (username, _) = config.resolve_username(node);
+            let host = SshCommand::for_node(config, &username, node).host().to_string();
+            async move {
+                let output = subprocess::output_async(
+                    tokio::process::Command::new("ssh-keyscan")
+                        .arg("-T")
+                        .arg(KEYSCAN_TIMEOUT.as_secs().to_string())
+                        .arg("--")
+                        .arg(&host)
+                        .stdin(Stdio::null()),
+                )
+                .await;
+                (*node, output)
+            }
+        })
+        .collect();
+
+    let mut new_keys: Vec<(&TailscaleNode, String)> = Vec::new();
+    while let Some((node, output)) = scans.next().await {
+        let output = output.context("Failed to run ssh-keyscan")?;
+        let keys: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line_hosts(line).is_empty())
+            .map(str::to_string)
+            .collect();
+        if keys.is_empty() {
+            println!("{}: no host keys received", node.name);
+            continue;
+        }
+        let fresh: Vec<String> = keys.into_iter().filter(|key| !existing.lines().any(|line| line == key)).collect();
+        if fresh.is_empty() {
+            println!("{}: all host keys already known", node.name);
+        }
+        new_keys.extend(fresh.into_iter().map(|key| (node, key)));
+    }
+    if new_keys.is_empty() {
+        return Ok(());
+    }
+
+    new_keys.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+    for (node, key) in &new_keys {
+        println!("{:<20} {}", node.name, fingerprint(key).await.unwrap_or_else(|| key.clone()));
+    }
+    if !assume_yes && !io::stdin().is_terminal() {
+        return Err(anyhow!("Not asking for confirmation without a terminal; pass --yes to add the keys"));
+    }
+    if !assume_yes
+        && !Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Add {} host keys to {}?", new_keys.len(), path.display()))
+            .default(false)
+            .interact()?
+    {
+        println!("Nothing added");
+        return Ok(());
+    }
+
+    let mut contents = existing;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    let mut owners = load_owners()?;
+    for (node, key) in &new_keys {
+        contents.push_str(key);
+        contents.push('\n');
+        for host in line_hosts(key) {
+            owners.insert(host, identity(node).to_string());
+        }
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    save_owners(&owners)?;
+    println!("Added {} host keys to {}", new_keys.len(), path.display());
+    Ok(())
+}
+
+/// A key's type and SHA256 fingerprint as `ssh-keygen -l` shows them
+async fn fingerprint(key: &str) -> Option<String> {
+    let mut command = tokio::process::Command::new("ssh-keygen");
+    command
+        .args(["-l", "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    let subprocess = Subprocess::start(command.as_std());
+    let mut child = command.spawn().ok()?;
+    let mut stdin = child.stdin.take()?;
+    stdin.write_all(format!("{}\n", key).as_bytes()).await.ok()?;
+    drop(stdin);
+    let output = child.wait_with_output().await;
+    subprocess.finish(output.as_ref().map(|output| &output.status));
+    let output = output.ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, name: &str, ip: &str) -> TailscaleNode {
+        TailscaleNode {
+            id: id.to_string(),
+            name: name.to_string(),
+            ip: ip.to_string(),
+            ipv6: String::new(),
+            dns_name: format!("{}.tail1234.ts.net.", name),
+            suggested_user: String::new(),
+            os: "linux".to_string(),
+            tags: Vec::new(),
+            status: "idle".to_string(),
+            tailscale_ssh: false,
+            last_seen: None,
+            rx_bytes: 0,
+            tx_bytes: 0,
+            mullvad: false,
+            is_self: false,
+        }
+    }
+
+    fn owners(pairs: &[(&str, &str)]) -> Owners {
+        pairs.iter().map(|(host, owner)| (host.to_string(), owner.to_string())).collect()
+    }
+
+    fn known_hosts_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ssh-tailscale-known-hosts-{}-{}", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    const KNOWN_HOSTS: &str = "\
+# tailnet hosts
+|1|c2FsdA==|aGFzaA== ssh-ed25519 AAAAhashed
+100.64.0.1,web-1.tail1234.ts.net ssh-ed25519 AAAAweb1
+[100.64.0.2]:2222 ssh-ed25519 AAAAolddb
+
+100.64.0.3 ssh-ed25519 AAAAgone
+@cert-authority *.tail1234.ts.net ssh-ed25519 AAAAca
+";
+
+    #[test]
+    fn only_keys_of_moved_addresses_are_dropped() {
+        let path = known_hosts_file("moved", KNOWN_HOSTS);
+        // db's address now belongs to db-2, and 100.64.0.3 to no node at all
+        let nodes = [node("n1", "web-1", "100.64.0.1"), node("n3", "db-2", "100.64.0.2")];
+        let recorded = owners(&[
+            ("100.64.0.1", "n1"),
+            ("web-1.tail1234.ts.net", "n1"),
+            ("100.64.0.2", "n2"),
+            ("100.64.0.3", "n4"),
+        ]);
+
+        let (removals, owners_now) = prune_file(&path, &nodes, &recorded).unwrap();
+        assert_eq!(removals.len(), 1);
+        assert_eq!(removals[0].host, "100.64.0.2");
+        assert_eq!(removals[0].node, "db-2");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            KNOWN_HOSTS.replace("[100.64.0.2]:2222 ssh-ed25519 AAAAolddb\n", "")
+        );
+        assert_eq!(
+            owners_now,
+            owners(&[("100.64.0.1", "n1"), ("web-1.tail1234.ts.net", "n1"), ("100.64.0.3", "n4")])
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn hosts_seen_for_the_first_time_are_recorded() {
+        let path = known_hosts_file("first", KNOWN_HOSTS);
+        let nodes = [node("n1", "web-1", "100.64.0.1"), node("n3", "db-2", "100.64.0.2")];
+
+        let (removals, owners_now) = prune_file(&path, &nodes, &Owners::new()).unwrap();
+        assert!(removals.is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), KNOWN_HOSTS);
+        assert_eq!(
+            owners_now,
+            owners(&[("100.64.0.1", "n1"), ("100.64.0.2", "n3"), ("web-1.tail1234.ts.net", "n1")])
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_missing_file_keeps_the_recorded_owners() {
+        let path = std::env::temp_dir().join(format!("ssh-tailscale-known-hosts-missing-{}", std::process::id()));
+        let recorded = owners(&[("100.64.0.1", "n1")]);
+        let (removals, owners_now) = prune_file(&path, &[node("n2", "web-2", "100.64.0.1")], &recorded).unwrap();
+        assert!(removals.is_empty());
+        assert_eq!(owners_now, recorded);
+    }
+}
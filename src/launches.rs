@@ -0,0 +1,142 @@
+use crate::config::LaunchConfig;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// The window `max_per_second` counts launches in
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Sessions waiting to start in the modes that stay open between them, `--loop`
+/// and `panes`, let through at most `launches.max_per_second` at a time so that
+/// pressing Enter over and over doesn't start a burst of sessions
+pub struct LaunchQueue<T> {
+    max_per_second: u32,
+    /// When the launches of the last second started, oldest first
+    started: VecDeque<Instant>,
+    queued: VecDeque<T>,
+}
+
+impl<T> LaunchQueue<T> {
+    pub fn new(config: &LaunchConfig) -> Self {
+        Self {
+            max_per_second: config.max_per_second,
+            started: VecDeque::new(),
+            queued: VecDeque::new(),
+        }
+    }
+
+    /// Queue a launch behind the ones already waiting
+    pub fn push(&mut self, launch: T) {
+        self.queued.push_back(launch);
+    }
+
+    /// The next queued launch if the cap lets it start now, counted as started
+    pub fn pop_ready(&mut self, now: Instant) -> Option<T> {
+        if self.wait(now)? > Duration::ZERO {
+            return None;
+        }
+        while self.started.front().is_some_and(|&started| now.duration_since(started) >= WINDOW) {
+            self.started.pop_front();
+        }
+        self.started.push_back(now);
+        self.queued.pop_front()
+    }
+
+    /// Wait for the next queued launch's turn and take it
+    pub async fn next(&mut self) -> Option<T> {
+        loop {
+            let wait = self.wait(Instant::now())?;
+            if wait.is_zero() {
+                return self.pop_ready(Instant::now());
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// How long until the next queued launch may start, or `None` with nothing queued
+    pub fn wait(&self, now: Instant) -> Option<Duration> {
+        self.queued.front()?;
+        let max = self.max_per_second as usize;
+        let recent: Vec<Instant> = self
+            .started
+            .iter()
+            .copied()
+            .filter(|&started| now.duration_since(started) < WINDOW)
+            .collect();
+        if max == 0 || recent.len() < max {
+            return Some(Duration::ZERO);
+        }
+        // The oldest launch that keeps the window full has to drop out of it
+        Some(WINDOW - now.duration_since(recent[recent.len() - max]))
+    }
+
+    /// The launches waiting, in the order they will start
+    pub fn queued(&self) -> impl Iterator<Item = &T> {
+        self.queued.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// The cap on launches a second, or 0 for none
+    pub fn max_per_second(&self) -> u32 {
+        self.max_per_second
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(max_per_second: u32) -> LaunchQueue<&'static str> {
+        LaunchQueue::new(&LaunchConfig { max_per_second })
+    }
+
+    #[test]
+    fn starts_up_to_the_cap_at_once_then_waits() {
+        let start = Instant::now();
+        let mut launches = queue(2);
+        assert_eq!(launches.wait(start), None);
+        for name in ["a", "b", "c", "d"] {
+            launches.push(name);
+        }
+
+        assert_eq!(launches.pop_ready(start), Some("a"));
+        assert_eq!(launches.pop_ready(start + Duration::from_millis(300)), Some("b"));
+        assert_eq!(launches.pop_ready(start + Duration::from_millis(400)), None);
+        assert_eq!(launches.wait(start + Duration::from_millis(400)), Some(Duration::from_millis(600)));
+        assert_eq!(launches.queued().copied().collect::<Vec<_>>(), ["c", "d"]);
+
+        assert_eq!(launches.pop_ready(start + Duration::from_secs(1)), Some("c"));
+        assert_eq!(launches.wait(start + Duration::from_secs(1)), Some(Duration::from_millis(300)));
+        assert_eq!(launches.pop_ready(start + Duration::from_millis(1300)), Some("d"));
+        assert!(launches.is_empty());
+        assert_eq!(launches.pop_ready(start + Duration::from_secs(5)), None);
+    }
+
+    #[test]
+    fn zero_means_no_cap() {
+        let now = Instant::now();
+        let mut launches = queue(0);
+        for name in ["a", "b", "c"] {
+            launches.push(name);
+        }
+        assert_eq!(launches.pop_ready(now), Some("a"));
+        assert_eq!(launches.pop_ready(now), Some("b"));
+        assert_eq!(launches.wait(now), Some(Duration::ZERO));
+        assert_eq!(launches.pop_ready(now), Some("c"));
+    }
+
+    #[test]
+    fn launches_drop_out_of_the_window_after_a_second() {
+        let start = Instant::now();
+        let mut launches = queue(1);
+        launches.push("a");
+        assert_eq!(launches.pop_ready(start), Some("a"));
+        launches.push("b");
+        assert_eq!(launches.wait(start + Duration::from_millis(999)), Some(Duration::from_millis(1)));
+        assert_eq!(launches.pop_ready(start + Duration::from_secs(3)), Some("b"));
+    }
+}
@@ -0,0 +1,67 @@
+//! Pick a Tailscale node in the terminal and connect to it over SSH. The
+//! binary is a thin layer over these modules, which are also what the
+//! benchmarks exercise. Other ratatui applications can embed the node list
+//! through `picker::NodePicker`.
+
+pub mod actions;
+pub mod address;
+pub mod agent;
+pub mod app;
+pub mod bookmarks;
+pub mod bootstrap;
+pub mod broadcast;
+pub mod changes;
+pub mod cli;
+pub mod clipboard;
+pub mod completion;
+pub mod config;
+pub mod cooldowns;
+pub mod destinations;
+pub mod dotfiles;
+pub mod history;
+pub mod hooks;
+pub mod humanize;
+pub mod identities;
+pub mod index;
+pub mod inventory;
+pub mod keys;
+pub mod known_hosts;
+pub mod launches;
+pub mod manifest;
+pub mod model;
+pub mod netcheck;
+pub mod panes;
+pub mod picker;
+pub mod plain;
+pub mod platform;
+pub mod plugins;
+pub mod polling;
+pub mod preflight;
+pub mod presence;
+pub mod push;
+pub mod reconnect;
+pub mod remote_shell;
+pub mod retry;
+pub mod secrets;
+pub mod secure;
+pub mod selector;
+pub mod session_log;
+pub mod sort;
+pub mod ssh;
+pub mod ssh_config;
+pub mod ssh_uri;
+pub mod subprocess;
+pub mod sweep;
+pub mod table;
+pub mod taildrop;
+pub mod tailscale;
+pub mod telemetry;
+pub mod theme;
+pub mod timeline;
+pub mod tint;
+pub mod troubleshoot;
+pub mod tui;
+pub mod tunnels;
+pub mod ui;
+pub mod usernames;
+pub mod wake;
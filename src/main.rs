@@ -1,556 +1,769 @@
-use anyhow::{anyhow, Context, Result};
-use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers},
-    execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-};
-use dialoguer::{theme::ColorfulTheme, Input};
-use ratatui::{
-    backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
-    text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
-    Terminal,
+use ssh_tailscale::{
+    address, agent, bookmarks, bootstrap, broadcast, cli, completion, config, destinations, dotfiles, history,
+    hooks, humanize, identities, index, inventory, known_hosts, launches, manifest, model, panes, plain,
+    plugins, preflight, push, reconnect, retry, secrets, secure, selector, subprocess, platform, session_log,
+    ssh, ssh_config, table, taildrop, tailscale, telemetry, timeline, tint, troubleshoot, tui, usernames, wake,
 };
-use regex::Regex;
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, Context, Result};
+use broadcast::BroadcastGates;
+use cli::{Command as CliCommand, Emit, Options};
+use config::{load_config, save_config, Config};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input};
+use identities::IdentityTarget;
 use std::{
-    fs,
-    io,
-    path::PathBuf,
-    process::{Command, Stdio},
-    thread,
-    time::{Duration, Instant},
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    time::Instant,
 };
+use history::{load_history, save_history};
+use ssh::{tmux_session_for, CopyDirection, ForwardingToggles, SshCommand};
+use index::find_node;
+use launches::LaunchQueue;
+use model::Listing;
+use tailscale::{get_tailscale_nodes, get_tailscale_status, TailscaleNode, TailscaleStatus};
+use tui::run_tui;
+use usernames::UsernameScope;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // ssh-add runs this program again to ask for a key passphrase
+    if let Some(code) = secrets::askpass_from_env() {
+        std::process::exit(code);
+    }
 
-/// Configuration for the SSH Tailscale app, stored between sessions
-#[derive(Serialize, Deserialize, Debug, Default)]
-struct Config {
-    /// Default username to use for SSH connections
-    default_username: String,
-    /// Last selected node name for auto-selection next time
-    last_selected_node: String,
-}
+    let cli = cli::parse_args();
 
-/// Represents a Tailscale node from the 'tailscale status' command
-struct TailscaleNode {
-    /// Hostname of the node
-    name: String,
-    /// IP address of the node
-    ip: String,
-    /// Suggested username from tailscale status, if available
-    suggested_user: String,
-    /// Connection status (active, offline, etc.)
-    status: String,
-}
+    // Keep working without a home directory, just without remembering anything
+    if cli.options.ephemeral {
+        config::set_ephemeral();
+    } else if config::get_config_dir()?.is_none() {
+        eprintln!(
+            "Warning: no home directory found, so settings and history won't be saved. Set {} to keep them somewhere else.",
+            config::STATE_DIR_ENV
+        );
+    }
 
-/// App state for the terminal UI
-struct App {
-    /// All available nodes
-    nodes: Vec<TailscaleNode>,
-    /// Indices of filtered nodes
-    filtered_nodes: Vec<usize>,
-    /// Current search filter text
-    filter: String,
-    /// Currently selected node index in filtered list
-    selection: usize,
-}
+    if let Some(profile) = &cli.options.profile {
+        config::set_profile(profile)?;
+    }
 
-impl App {
-    /// Create a new App with the provided nodes
-    fn new(nodes: Vec<TailscaleNode>) -> Self {
-        let filtered_nodes = (0..nodes.len()).collect();
-        Self {
-            nodes,
-            filtered_nodes,
-            filter: String::new(),
-            selection: 0,
-        }
+    if cli.options.safe_mode {
+        config::set_safe_mode();
+        eprintln!(
+            "Safe mode: using the default keys, theme and layout, without hooks, actions or plugins. \
+             The config won't be changed."
+        );
     }
 
-    /// Apply the current filter to the nodes list
-    fn apply_filter(&mut self) {
-        if self.filter.is_empty() {
-            // Show all nodes when no filter is applied
-            self.filtered_nodes = (0..self.nodes.len()).collect();
-        } else {
-            // Filter nodes based on case-insensitive name matching
-            let lower_filter = self.filter.to_lowercase();
-            self.filtered_nodes = (0..self.nodes.len())
-                .filter(|&i| self.nodes[i].name.to_lowercase().contains(&lower_filter))
-                .collect();
+    // State files map out your infrastructure, so make sure they stay private
+    let mut dirs: Vec<PathBuf> = [config::get_config_dir()?, config::get_data_dir()?].into_iter().flatten().collect();
+    // SSH_TAILSCALE_STATE_DIR puts both in the same place
+    dirs.dedup();
+    for dir in dirs {
+        for warning in secure::permission_warnings(&dir) {
+            eprintln!("Warning: {}", warning);
         }
+    }
 
-        // Adjust selection if necessary
-        if self.filtered_nodes.is_empty() {
-            self.selection = 0;
-        } else if self.selection >= self.filtered_nodes.len() {
-            self.selection = self.filtered_nodes.len() - 1;
-        }
+    if cli.options.verbosity > 0 {
+        timeline::enable();
     }
+    telemetry::init(cli.options.debug_log, cli.options.verbosity)?;
 
-    /// Move selection up (visually) - IMPORTANT: When rendering bottom-to-top, 
-    /// moving "up" visually means INCREASING the index in the array
-    fn move_selection_up(&mut self) {
-        if !self.filtered_nodes.is_empty() && self.selection + 1 < self.filtered_nodes.len() {
-            self.selection += 1;
+    if let Some(source) = &cli.options.nodes_from {
+        tailscale::use_node_list(inventory::read_nodes(source)?);
+        if source.as_os_str() == "-" && !io::stdin().is_terminal() {
+            // Piped nodes used up stdin; the picker and ssh need the terminal back
+            let _ = platform::reattach_stdin();
         }
     }
 
-    /// Move selection down (visually) - IMPORTANT: When rendering bottom-to-top,
-    /// moving "down" visually means DECREASING the index in the array
-    fn move_selection_down(&mut self) {
-        if !self.filtered_nodes.is_empty() && self.selection > 0 {
-            self.selection -= 1;
-        }
+    // Load configuration
+    let mut config = load_config()?;
+    if config.encrypt_history || config.encrypt_config {
+        secure::enable_encryption();
     }
 
-    /// Move selection up a full page
-    fn move_page_up(&mut self, page_size: usize) {
-        if self.filtered_nodes.is_empty() {
-            return;
+    let result = match cli.command {
+        CliCommand::Connect if cli.options.last => reconnect_last(config, &cli.options).await,
+        CliCommand::Connect => connect(config, &cli.options).await,
+        CliCommand::ConnectTo { node } => connect_to(config, &node, &cli.options).await,
+        CliCommand::List => list_nodes(&config, &cli.options).await,
+        CliCommand::History { node } => print_history(&config, node.as_deref(), cli.options.emit),
+        CliCommand::Schema { kind } => {
+            println!("{}", kind.schema()?);
+            Ok(())
         }
-
-        if self.selection >= page_size {
-            self.selection -= page_size;
-        } else {
-            self.selection = 0;
+        CliCommand::Run { node, command } => run_remote(&mut config, &node, command, &cli.options).await,
+        CliCommand::Copy { node, direction, local, remote_path, recursive } => {
+            copy_files(&config, &node, direction, &local, &remote_path, recursive, &cli.options).await
         }
-    }
-
-    /// Move selection down a full page
-    fn move_page_down(&mut self, page_size: usize) {
-        if self.filtered_nodes.is_empty() {
-            return;
+        CliCommand::Broadcast { selector, gates, output_dir, command } => {
+            let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+            let targets = broadcast::select_targets(&nodes, &config, &selector, &gates)?;
+            broadcast::broadcast(&config, &targets, &command, output_dir.as_deref()).await
+        }
+        CliCommand::Push { file, selector, remote_path } => {
+            let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+            let targets = broadcast::select_targets(&nodes, &config, &selector, &BroadcastGates::default())?;
+            push::push(&config, &targets, &file, &remote_path).await
+        }
+        CliCommand::Identity { target } => {
+            let target = match target.strip_prefix("group:") {
+                Some(group) if config.groups.contains_key(group) => IdentityTarget::Group(group.to_string()),
+                Some(group) => return Err(anyhow!("No group named '{}' in the config", group)),
+                None => {
+                    let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+                    IdentityTarget::Node(find_node(&nodes, &config, &target)?.name.clone())
+                }
+            };
+            identities::choose(&mut config, target)
         }
+        CliCommand::Troubleshoot { node } => {
+            let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+            let node = find_node(&nodes, &config, &node)?;
+            let (username, _) = config.resolve_username(node);
+            let ssh = SshCommand::for_node(&config, &username, node);
+            troubleshoot::run(&config, node, &username, ssh.host(), "").await
+        }
+        CliCommand::Wake { node } => wake_and_connect(config, &node, &cli.options).await,
+        CliCommand::Panes { nodes: queries } => {
+            let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+            let start = queries
+                .iter()
+                .map(|query| find_node(&nodes, &config, query).cloned())
+                .collect::<Result<Vec<_>>>()?;
+            panes::run(config, nodes, start, cli.options.user.clone()).await
+        }
+        CliCommand::Send { file, node } => send_file(config, &file, node.as_deref(), &cli.options).await,
+        CliCommand::Receive { dir, wait } => taildrop::receive(&config, &dir, wait).await,
+        CliCommand::PruneKnownHosts => {
+            if known_hosts::known_hosts_path(&config)?.is_none() {
+                return Err(anyhow!("Set ssh.known_hosts in the config to keep tailnet host keys in their own file"));
+            }
+            // Fetched directly, as get_tailscale_nodes would prune quietly first
+            let output = tailscale::fetch_tailscale_status(&config).await?;
+            let status = tailscale::parse_tailscale_status(&output)?;
+            let removals = known_hosts::prune(&config, &status.nodes)?;
+            for removal in &removals {
+                println!("Removed the host key of {}, which now belongs to {}", removal.host, removal.node);
+            }
+            if removals.is_empty() {
+                println!("No host keys to remove");
+            }
+            Ok(())
+        }
+        CliCommand::ScanKnownHosts { selector, assume_yes } => {
+            let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+            let targets = broadcast::select_targets(&nodes, &config, &selector, &BroadcastGates::default())?;
+            known_hosts::scan(&config, &targets, assume_yes).await
+        }
+        CliCommand::EncryptConfig | CliCommand::DecryptConfig if cli.options.ephemeral => {
+            Err(anyhow!("There is no config file to change with --ephemeral"))
+        }
+        CliCommand::EncryptConfig => {
+            // Read the history first, so a passphrase it is already encrypted with is the one used
+            let history = load_history()?;
+            config.encrypt_config = true;
+            save_config(&config)?;
+            secure::enable_encryption();
+            save_history(&history)?;
+            println!(
+                "The config file and history are now encrypted. Run `ssh-tailscale config decrypt` to edit the config."
+            );
+            Ok(())
+        }
+        CliCommand::DecryptConfig => {
+            config.encrypt_config = false;
+            save_config(&config)?;
+            match config::get_config_path()? {
+                Some(path) => println!("{} is in plain text again", path.display()),
+                None => println!("The config file is in plain text again"),
+            }
+            if config.encrypt_history {
+                println!("The history stays encrypted, as encrypt_history is on");
+            }
+            Ok(())
+        }
+        CliCommand::ConfigPath => {
+            match config::get_config_path()? {
+                Some(path) if secure::is_encrypted(&path) => println!("{}", secure::encrypted_path(&path).display()),
+                Some(path) => println!("{}", path.display()),
+                None => println!("(none; nothing is saved)"),
+            }
+            Ok(())
+        }
+        CliCommand::ConfigShow => {
+            println!("{}", serde_json::to_string_pretty(&config)?);
+            Ok(())
+        }
+        CliCommand::ExportSshConfig { path } => {
+            let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+            let text = ssh_config::render(&config, &nodes);
+            match path {
+                Some(path) => {
+                    secure::write_private(&path, text.as_bytes())?;
+                    println!("Wrote {}", path.display());
+                    Ok(())
+                }
+                None => {
+                    print!("{}", text);
+                    Ok(())
+                }
+            }
+        }
+        CliCommand::SyncDotfiles { node } => sync_dotfiles(&config, &node).await,
+        CliCommand::ExportBookmarks { path } => bookmarks::export_bookmarks(&config, path.as_deref()),
+        CliCommand::ImportBookmarks { .. } if cli.options.ephemeral => {
+            Err(anyhow!("import-bookmarks has nowhere to save the result with --ephemeral"))
+        }
+        CliCommand::ImportBookmarks { path } => {
+            bookmarks::import_bookmarks(&mut config, &path)?;
+            save_config(&config)
+        }
+        CliCommand::Logs { node, grep: Some(pattern) } => session_log::grep_logs(&pattern, node.as_deref()),
+        CliCommand::Logs { node, grep: None } => session_log::list_logs(node.as_deref(), cli.options.emit),
+        CliCommand::Replay { path } => session_log::replay_log(&path),
+        CliCommand::Complete { word } => completion::print_completions(&config, &word).await,
+        CliCommand::Completions { shell } => {
+            println!("{}", shell.script());
+            Ok(())
+        }
+        CliCommand::Plugin { plugin, args } => run_plugin(&plugin, &args),
+    };
 
-        if self.selection + page_size < self.filtered_nodes.len() {
-            self.selection += page_size;
-        } else {
-            self.selection = self.filtered_nodes.len() - 1;
+    // Shown whether connecting worked or not, since a failure is when it helps most
+    timeline::print();
+    telemetry::flush();
+    result
+}
+
+/// Pick a node in the terminal UI and open an SSH session to it
+async fn connect(mut config: Config, options: &Options) -> Result<()> {
+    // With `--loop` or `return_to_picker`, each session ends back here until the picker is left
+    let looping = (options.loop_picker || config.return_to_picker) && !options.dry_run;
+    let mut first_round = true;
+    let mut launches = LaunchQueue::new(&config.launches);
+    loop {
+        // Show the nodes saved by the last run straight away when there are any; the
+        // picker fetches a fresh list in the background. Otherwise wait for tailscale status.
+        // Coming back from a session, the nodes may have changed meanwhile, so ask again.
+        let started = Instant::now();
+        let status = match tailscale::load_cached_status().filter(|_| first_round) {
+            Some(status) => {
+                timeline::step("cached node list", started);
+                status
+            }
+            None => get_tailscale_status(&config).await.context("Failed to get Tailscale nodes")?,
+        };
+        first_round = false;
+
+        if status.nodes.is_empty() {
+            println!("No Tailscale nodes found. Make sure Tailscale is connected.");
+            return Ok(());
         }
-    }
 
-    /// Move to the first item in the list
-    fn move_to_start(&mut self) {
-        if !self.filtered_nodes.is_empty() {
-            self.selection = 0;
+        // Run the terminal UI to select a node
+        let started = Instant::now();
+        // Forwarding toggled in the picker applies to the session it starts
+        let mut forwarding = options.forwarding;
+        let selected_node = pick_node(status, &mut config, &mut forwarding, options).await?;
+        timeline::step_with_note("picker", started.elapsed(), Some("waiting for you".to_string()));
+        let session_options = Options { forwarding, ..options.clone() };
+
+        // Picking over and over, like pressing Enter while sessions fail at once, is spaced out
+        launches.push(selected_node);
+        if let Some(wait) = launches.wait(Instant::now()).filter(|wait| !wait.is_zero()) {
+            println!(
+                "Connecting in {:.1}s, as at most {} sessions start a second",
+                wait.as_secs_f64(),
+                launches.max_per_second()
+            );
+        }
+        while let Some(node) = launches.next().await {
+            let result = connect_picked(&mut config, node, &session_options).await;
+            if !looping {
+                return exit_like(result?);
+            }
+            // A session that failed to start shouldn't end the loop; show why and pick again
+            if let Err(err) = result {
+                eprintln!("Error: {:#}", err);
+            }
         }
     }
+}
 
-    /// Move to the last item in the list
-    fn move_to_end(&mut self) {
-        if !self.filtered_nodes.is_empty() {
-            self.selection = self.filtered_nodes.len() - 1;
-        }
+/// Connect to the node picked in the terminal UI, waking it first if needed
+async fn connect_picked(config: &mut Config, node: TailscaleNode, options: &Options) -> Result<ExitStatus> {
+    // The plain menu lists phones and tablets too, as `send` picks from it
+    check_connectable(&node)?;
+    let node = wake::offer(config, node).await?;
+
+    // Save the selected node for next time
+    remember_node(config, &node, options)?;
+
+    let (username, options) = login_for(config, &node, options)?;
+    open_session(config, &node, &username, &options).await
+}
+
+/// Let the user choose a node in the terminal UI, or from a numbered list
+/// where it can't be drawn
+async fn pick_node(
+    status: TailscaleStatus,
+    config: &mut Config,
+    forwarding: &mut ForwardingToggles,
+    options: &Options,
+) -> Result<TailscaleNode> {
+    if plain::wanted(options.plain) {
+        return plain::choose(config, &status.nodes);
     }
+    run_tui(status, config, forwarding).await
+}
 
-    /// Get the currently selected node, if available
-    fn get_selected_node(&self) -> Option<&TailscaleNode> {
-        if self.filtered_nodes.is_empty() {
-            None
-        } else {
-            Some(&self.nodes[self.filtered_nodes[self.selection]])
-        }
+/// Return if a session succeeded, or exit with its status code like ssh would,
+/// so scripts and shells see how the remote command or shell ended
+fn exit_like(status: ExitStatus) -> Result<()> {
+    if status.success() {
+        return Ok(());
     }
+    timeline::print();
+    telemetry::flush();
+    std::process::exit(status.code().unwrap_or(1));
 }
 
-fn main() -> Result<()> {
-    // Load configuration
-    let mut config = load_config()?;
-    
-    // Run tailscale status to get list of nodes
-    let nodes = get_tailscale_nodes().context("Failed to get Tailscale nodes")?;
-    
-    if nodes.is_empty() {
-        println!("No Tailscale nodes found. Make sure Tailscale is connected.");
+/// Note the node connected to, for the picker to start on next time. A dry
+/// run leaves the config as it is.
+fn remember_node(config: &mut Config, node: &TailscaleNode, options: &Options) -> Result<()> {
+    if options.dry_run {
         return Ok(());
     }
-    
-    // Run the terminal UI to select a node
-    let selected_node = run_tui(nodes, &config.last_selected_node)?;
-    
-    // Save the selected node for next time
-    config.last_selected_node = selected_node.name.clone();
-    save_config(&config)?;
-    
-    // Get the default username from config or fallback to "ubuntu"
-    let default_username = if !config.default_username.is_empty() {
-        config.default_username.clone()
+    config.last_selected_node = node.name.clone();
+    config.last_selected_id = node.id.clone();
+    save_config(config)
+}
+
+/// Connect to the node a command line argument names, without the picker
+async fn connect_to(mut config: Config, query: &str, options: &Options) -> Result<()> {
+    let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+    let node = find_node(&nodes, &config, query)?;
+    check_connectable(node)?;
+    let node = wake::offer(&config, node.clone()).await?;
+
+    remember_node(&mut config, &node, options)?;
+
+    let (username, options) = login_for(&mut config, &node, options)?;
+    let status = open_session(&mut config, &node, &username, &options).await?;
+    exit_like(status)
+}
+
+/// Wake a node with Wake-on-LAN without asking, then connect to it
+async fn wake_and_connect(mut config: Config, query: &str, options: &Options) -> Result<()> {
+    let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+    let node = find_node(&nodes, &config, query)?;
+    check_connectable(node)?;
+    let wake = config
+        .node_settings(&node.name)
+        .wake
+        .ok_or_else(|| anyhow!("{} has no `wake` settings in the config file", node.name))?;
+    let node = if node.is_online() {
+        println!("{} is already online", node.name);
+        node.clone()
     } else {
-        "ubuntu".to_string()
+        wake::wake_node(&config, node, &wake).await?
     };
-    
-    // Username prompt with the saved default
-    let username: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt(format!("Enter username for {}", selected_node.name))
-        .default(default_username)
-        .interact_text()?;
-    
-    // Save the username for next time if it changed
-    if username != config.default_username {
-        config.default_username = username.clone();
-        save_config(&config)?;
+
+    remember_node(&mut config, &node, options)?;
+
+    let (username, options) = login_for(&mut config, &node, options)?;
+    let status = open_session(&mut config, &node, &username, &options).await?;
+    exit_like(status)
+}
+
+/// Print the nodes of the tailnet as a table or as a `Listing` of `model::Node`
+async fn list_nodes(config: &Config, options: &Options) -> Result<()> {
+    let mut nodes = get_tailscale_nodes(config).await.context("Failed to get Tailscale nodes")?;
+    nodes.retain(|node| !selector::is_left_out_self(config, node));
+    if options.emit == Emit::Json {
+        return Listing::new(nodes.iter().map(|node| model::Node::new(config, node)).collect()).print();
     }
-    
-    // Connect via SSH
-    println!("Connecting to {}@{}...", username, selected_node.name);
-    
-    // Execute SSH command
-    let status = Command::new("ssh")
-        .arg(format!("{}@{}", username, selected_node.ip))
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()
-        .context("Failed to execute SSH command")?;
-    
-    if !status.success() {
-        println!("SSH connection ended with non-zero status: {}", status);
+
+    let columns = options.columns.as_deref().unwrap_or(table::DEFAULT_COLUMNS);
+    table::print_nodes(config, &nodes, columns, options.color);
+    Ok(())
+}
+
+/// Print past connections, oldest first, optionally only those to one node
+fn print_history(config: &Config, node: Option<&str>, emit: Emit) -> Result<()> {
+    let history = load_history()?;
+    let entries = history
+        .entries
+        .iter()
+        .filter(|entry| node.is_none_or(|node| entry.node == node));
+    if emit == Emit::Json {
+        return Listing::new(entries.map(model::HistoryEntry::from).collect()).print();
+    }
+
+    for entry in entries {
+        let when = humanize::timestamp(entry.timestamp, config.ui.absolute_times);
+        println!("{:<22}  {}@{}", when, entry.user, entry.node);
     }
-    
     Ok(())
 }
 
-/// Run the terminal UI for node selection
-fn run_tui(nodes: Vec<TailscaleNode>, last_selected_node: &str) -> Result<TailscaleNode> {
-    // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    
-    // Flush to ensure all terminal commands are processed
-    io::Write::flush(&mut stdout)?;
-    
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-    
-    // Additional terminal stabilization for Windows
-    terminal.clear()?;
+/// Reconnect to the node and username of the most recent session, without any UI
+async fn reconnect_last(mut config: Config, options: &Options) -> Result<()> {
+    let history = load_history()?;
+    let last = history
+        .entries
+        .last()
+        .ok_or_else(|| anyhow!("No previous connection to reconnect to"))?;
+
+    let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+    let node = nodes
+        .iter()
+        .find(|node| node.name == last.node)
+        .ok_or_else(|| anyhow!("{} is no longer in your tailnet", last.node))?;
+    check_connectable(node)?;
+
+    let username = match &options.user {
+        Some(user) => user.clone(),
+        None if options.pick_user => prompt_username(&mut config, node, options.dry_run)?,
+        None => last.user.clone(),
+    };
+    let status = open_session(&mut config, node, &username, options).await?;
+    exit_like(status)
+}
 
-    // Create app state with initial selection
-    let mut app = App::new(nodes);
-    
-    // Find and select the last used node if available
-    if !last_selected_node.is_empty() {
-        // Find the index of the last selected node
-        if let Some((index, _)) = app.nodes.iter().enumerate()
-            .find(|(_, node)| node.name == last_selected_node) {
-            // Only update if the node is found
-            app.selection = index;
-        }
+/// Fail for phones and tablets before asking anything, as there is nothing to connect to
+fn check_connectable(node: &TailscaleNode) -> Result<()> {
+    if node.platform().is_mobile() {
+        return Err(anyhow!("{} runs {}, which has no SSH server to connect to", node.name, node.os));
+    }
+    Ok(())
+}
+
+/// The username a session logs in with and the options it runs with: one of the
+/// node's saved destinations if one is picked, else the `--user` given on the
+/// command line, else the username the user picks
+fn login_for(config: &mut Config, node: &TailscaleNode, options: &Options) -> Result<(String, Options)> {
+    // `--user` and `ssh://` URIs already say how to log in
+    let destination = if options.user.is_none() && options.port.is_none() {
+        destinations::choose(config, node)?
+    } else {
+        None
+    };
+    let Some(destination) = destination else {
+        let username = match &options.user {
+            Some(user) => user.clone(),
+            None => prompt_username(config, node, options.dry_run)?,
+        };
+        return Ok((username, options.clone()));
+    };
+
+    let username = match destination.user {
+        Some(user) => user,
+        None => prompt_username(config, node, options.dry_run)?,
+    };
+    let options = Options {
+        port: destination.port,
+        startup: destination.command,
+        ..options.clone()
+    };
+    Ok((username, options))
+}
+
+/// Ask for the username to connect with, remembering it if it differs from the
+/// default, except on a dry run
+fn prompt_username(config: &mut Config, node: &TailscaleNode, dry_run: bool) -> Result<String> {
+    // A username kept in a secret manager isn't asked for or saved
+    if let Some(group) = secrets::username_group(config, node) {
+        let username = secrets::connect_username(config, node)?;
+        println!("Using {} from the secret manager of group {}", username, group);
+        return Ok(username);
+    }
+
+    // Get the default username from the most specific configured scope, or fallback to "ubuntu"
+    let (default_username, username_scope) = config.resolve_username(node);
+
+    // The owner's login can be trusted outright, unless something more specific is configured
+    if config.usernames.trust_suggested && matches!(username_scope, Some(UsernameScope::Suggested(_))) {
+        println!("Using {} from the Tailscale login of {}", default_username, node.name);
+        return Ok(default_username);
     }
     
-    // Draw the initial UI before starting event loop
-    terminal.draw(|f| ui(f, &mut app))?;
-    
-    // Add a delay to let the terminal settle on Windows and ensure first draw is complete
-    thread::sleep(Duration::from_millis(150));
+    // Username prompt with the saved default
+    let username: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Enter username for {}", node.name))
+        .default(default_username.clone())
+        .interact_text()?;
     
-    // Clear any pending events that might have been generated during terminal setup
-    // This is particularly important on Windows/MINGW where spurious events can occur
-    while crossterm::event::poll(Duration::from_millis(0))? {
-        let _ = event::read()?; // Discard any pending events
+    // Save the username for next time if it changed. Overriding a tag, OS or
+    // node default only affects this node; otherwise the global default follows.
+    let remember_at = match username_scope {
+        Some(UsernameScope::Global) | None => (username != config.default_username).then_some(UsernameScope::Global),
+        Some(_) => (username != default_username).then(|| UsernameScope::Node(node.name.clone())),
+    };
+    if let Some(scope) = remember_at
+        && !dry_run
+    {
+        config.set_username_at(&scope, Some(username.clone()));
+        save_config(config)?;
     }
-    
-    // Final result storage
-    let result;
 
-    // Main loop
-    {
-        let tick_rate = Duration::from_millis(250); // Increased tick rate for Windows
-        let mut last_tick = Instant::now();
-        
-        // This loop runs until a node is selected or the user exits
-        loop {
-            // Draw the UI (redraw for any changes)
-            terminal.draw(|f| ui(f, &mut app))?;
-
-            // Handle events with timeout - use a longer timeout on Windows
-            let timeout = tick_rate
-                .checked_sub(last_tick.elapsed())
-                .unwrap_or_else(|| Duration::from_secs(0));
-                
-            // Check for events with a minimum timeout to prevent busy waiting
-            let event_timeout = std::cmp::max(timeout, Duration::from_millis(100));
-            
-            if crossterm::event::poll(event_timeout)? {
-                match event::read()? {
-                    Event::Key(key) => {
-                        // Only process key press events, not key release events
-                        // This prevents double triggering on Windows/MINGW
-                        if key.kind == KeyEventKind::Press {
-                            match key.code {
-                                // Exit on Ctrl+C or Ctrl+Q
-                                KeyCode::Char('q') | KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                    result = Err(anyhow!("User cancelled"));
-                                    break;
-                                }
-                                // Select current node on Enter
-                                KeyCode::Enter => {
-                                    if let Some(node) = app.get_selected_node() {
-                                        // Make a copy of the selected node to return
-                                        let selected_node = TailscaleNode {
-                                            name: node.name.clone(),
-                                            ip: node.ip.clone(),
-                                            suggested_user: node.suggested_user.clone(),
-                                            status: node.status.clone(),
-                                        };
-                                        result = Ok(selected_node);
-                                        break;
-                                    }
-                                }
-                                // Navigation keys - correct visual direction
-                                KeyCode::Up => app.move_selection_up(), 
-                                KeyCode::Down => app.move_selection_down(),
-                                // Vim keys - match visual direction
-                                KeyCode::Char('k') => app.move_selection_up(),
-                                KeyCode::Char('j') => app.move_selection_down(),
-                                KeyCode::PageUp => app.move_page_up(10),
-                                KeyCode::PageDown => app.move_page_down(10),
-                                KeyCode::Home => app.move_to_start(),
-                                KeyCode::End => app.move_to_end(),
-                                // Filter text editing
-                                KeyCode::Backspace => {
-                                    app.filter.pop();
-                                    app.apply_filter();
-                                }
-                                KeyCode::Esc => {
-                                    app.filter.clear();
-                                    app.apply_filter();
-                                }
-                                KeyCode::Char(c) => {
-                                    app.filter.push(c);
-                                    app.apply_filter();
-                                }
-                                _ => {
-                                    // Ignore other key events
-                                }
-                            }
-                        }
-                    }
-                    // Ignore other event types (mouse, resize, etc.)
-                    _ => {}
-                }
-            }
+    Ok(username)
+}
 
-            // Refresh timer
-            if last_tick.elapsed() >= tick_rate {
-                last_tick = Instant::now();
-            }
-        }
+/// Bootstrap the node if needed, record the connection and run the SSH session,
+/// returning how it ended
+async fn open_session(
+    config: &mut Config,
+    node: &TailscaleNode,
+    username: &str,
+    options: &Options,
+) -> Result<ExitStatus> {
+    let mut ssh = SshCommand::for_node(config, username, node)
+        .toggled(&options.forwarding)
+        .attach_tmux(tmux_session_for(config, node, options.tmux))
+        .startup(options.startup.clone());
+    if let Some(port) = options.port {
+        ssh = ssh.on_port(port);
+    }
+    if options.dry_run {
+        println!("{}", ssh.shell_line());
+        return Ok(ExitStatus::default());
     }
 
-    // Restore terminal state
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    // Summarize where we're connecting, and make sure it's reachable before doing anything
+    println!("Connecting to {}@{} at {}...", username, node.name, ssh.host());
+    if let Some(reason) = ssh.fallback_reason() {
+        println!("Using the IP address because {}", reason);
+    }
+    preflight::check_reachable(config, node, &ssh).await?;
+    preflight::check_direct_path(config, node).await?;
+    secrets::load_key(config, node)?;
+    agent::ensure_loaded(config, node)?;
+    hooks::run_before_connect(config, node, username)?;
+
+    // Prepare nodes we have never connected to before, unless they opted out
+    let mut history = load_history()?;
+    if let Some(bootstrap) = &config.bootstrap
+        && !config.node_settings(&node.name).skip_bootstrap
+        && !history.has_connected_to(&node.name)
+        && let Err(err) = bootstrap::run_bootstrap(bootstrap, &ssh, node)
+    {
+        println!("Warning: {:#}", err);
+    }
 
-    // Return result or propagate error
-    result
+    // Remember the connection, which also marks the node as bootstrapped
+    history.record(&node.name, username);
+    save_history(&history)?;
+
+    // Record the session for later review when asked to
+    if config.record_sessions
+        && let Some(typescript) = session_log::new_typescript(&node.name)?
+    {
+        ssh = ssh.record_to(typescript);
+    }
+    let manifest = if config.session_manifests {
+        manifest::start(config, node, &ssh, ssh.typescript())?
+    } else {
+        None
+    };
+    
+    // Execute SSH command, with the terminal marked for the node while it runs
+    let tint = tint::apply(config, node, username);
+    let started = Instant::now();
+    let result = run_at_any_address(config, node, &ssh, options).await;
+    drop(tint);
+    if let Some(manifest) = manifest {
+        manifest.finish(result.as_ref().ok().copied())?;
+    }
+    hooks::run_after_session(config, node, username, result.as_ref().ok().copied());
+    let status = result?;
+
+    let ending = match status.code() {
+        Some(code) => format!("exit status {}", code),
+        None => "killed by a signal".to_string(),
+    };
+    println!(
+        "Session to {}@{} ended after {}, {}",
+        username,
+        node.name,
+        humanize::duration(started.elapsed()),
+        ending
+    );
+    Ok(status)
 }
 
-/// Render the UI using Ratatui
-fn ui(f: &mut ratatui::Frame, app: &mut App) {
-    let size = f.size();
-
-    // Create layout
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .margin(1)
-        .constraints(
-            [
-                Constraint::Length(3),    // Header
-                Constraint::Min(3),       // List
-                Constraint::Length(3),    // Footer/Search
-            ]
-            .as_ref(),
-        )
-        .split(size);
-
-    // Header with title and node count
-    let header_text = vec![
-        Line::from(vec![
-            Span::styled(
-                "Tailscale SSH - Select a Node",
-                Style::default().fg(Color::Green).add_modifier(Modifier::BOLD),
-            )
-        ]),
-        Line::from(vec![
-            Span::styled(
-                format!("Found {} nodes", app.nodes.len()),
-                Style::default().fg(Color::Gray),
-            )
-        ]),
-    ];
-    let header = Paragraph::new(header_text)
-        .block(Block::default().borders(Borders::BOTTOM));
-    f.render_widget(header, chunks[0]);
-
-    // List of nodes from bottom to top
-    if !app.filtered_nodes.is_empty() {
-        // Create list items in reverse order for bottom-up display
-        let mut items: Vec<ListItem> = Vec::new();
-        
-        for &idx in app.filtered_nodes.iter().rev() {
-            let node = &app.nodes[idx];
-            
-            // Color status based on online/offline
-            let status_style = if node.status.contains("active") {
-                Style::default().fg(Color::Green)
-            } else {
-                Style::default().fg(Color::Red)
-            };
-            
-            // Format node information with improved spacing
-            let content = Line::from(vec![
-                Span::raw(format!("{:<55}", node.name)),  // Increase padding even more for hostname
-                Span::raw(format!("{:<20}", node.ip)),    // Add more space for IP address
-                Span::styled(&node.status, status_style),
-            ]);
-            
-            items.push(ListItem::new(content));
-        }
-        
-        // Display the list with selection
-        let list = List::new(items)
-            .block(
-                Block::default()
-                    .borders(Borders::NONE)
-            )
-            .highlight_style(
-                Style::default()
-                    .bg(Color::DarkGray)
-                    .add_modifier(Modifier::BOLD)
-            )
-            .highlight_symbol("> ");
-        
-        // Since we reversed the items for display, we need to convert the selection index
-        let display_selection = app.filtered_nodes.len() - 1 - app.selection;
-        
-        // Use stateful list to track selection
-        let mut state = ratatui::widgets::ListState::default();
-        state.select(Some(display_selection));
-        
-        f.render_stateful_widget(list, chunks[1], &mut state);
-    } else if !app.filter.is_empty() {
-        // No results for filter
-        let no_results = Paragraph::new("No nodes match your filter")
-            .style(Style::default().fg(Color::Yellow));
-        f.render_widget(no_results, chunks[1]);
-    }
-
-    // Footer with search bar and help text
-    let search_text = format!("Search: {}", app.filter);
-    let search = Paragraph::new(search_text)
-        .style(Style::default())
-        .block(
-            Block::default()
-                .borders(Borders::TOP)
-                .title("Enter: Connect  Esc: Clear filter  ↑/↓: Navigate  Ctrl+C: Exit"),
-        );
-    f.render_widget(search, chunks[2]);
+/// Run a plugin as a subcommand, exiting with its status
+fn run_plugin(plugin: &plugins::Plugin, args: &[String]) -> Result<()> {
+    let status = plugins::run(plugin, args)?;
+    exit_like(status)
 }
 
-/// Get the configuration directory path
-fn get_config_dir() -> Result<PathBuf> {
-    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
-    let config_dir = home_dir.join(".config").join("ssh-tailscale");
-    
-    // Create the directory if it doesn't exist
-    if !config_dir.exists() {
-        fs::create_dir_all(&config_dir)?;
+/// Run a single command on a node, exiting with the remote command's status
+async fn run_remote(config: &mut Config, query: &str, command: Vec<String>, options: &Options) -> Result<()> {
+    let nodes = get_tailscale_nodes(config).await.context("Failed to get Tailscale nodes")?;
+    let node = find_node(&nodes, config, query)?;
+    check_connectable(node)?;
+    let username = match &options.user {
+        Some(user) => user.clone(),
+        None => secrets::connect_username(config, node)?,
+    };
+
+    let ssh = SshCommand::for_node(config, &username, node)
+        .toggled(&options.forwarding)
+        .remote_command(command);
+    if options.dry_run {
+        println!("{}", ssh.shell_line());
+        return Ok(());
     }
-    
-    Ok(config_dir)
+    preflight::check_reachable(config, node, &ssh).await?;
+    secrets::load_key(config, node)?;
+    agent::ensure_loaded(config, node)?;
+    hooks::run_before_connect(config, node, &username)?;
+    let result = run_at_any_address(config, node, &ssh, options).await;
+    hooks::run_after_session(config, node, &username, result.as_ref().ok().copied());
+
+    // Mirror the remote exit code so `run` composes in scripts
+    exit_like(result?)
 }
 
-/// Get the configuration file path
-fn get_config_path() -> Result<PathBuf> {
-    let config_dir = get_config_dir()?;
-    Ok(config_dir.join("config.json"))
-}
+/// Copy a file or directory between this machine and a node with scp, exiting
+/// with scp's status when it fails
+async fn copy_files(
+    config: &Config,
+    query: &str,
+    direction: CopyDirection,
+    local: &Path,
+    remote_path: &str,
+    recursive: bool,
+    options: &Options,
+) -> Result<()> {
+    let nodes = get_tailscale_nodes(config).await.context("Failed to get Tailscale nodes")?;
+    let node = find_node(&nodes, config, query)?;
+    check_connectable(node)?;
+    let username = match &options.user {
+        Some(user) => user.clone(),
+        None => secrets::connect_username(config, node)?,
+    };
 
-/// Load configuration from the config file
-fn load_config() -> Result<Config> {
-    let config_path = get_config_path()?;
-    
-    if config_path.exists() {
-        let config_str = fs::read_to_string(config_path)?;
-        Ok(serde_json::from_str(&config_str).unwrap_or_default())
-    } else {
-        // Return default config if file doesn't exist
-        Ok(Config::default())
+    let ssh = SshCommand::for_node(config, &username, node);
+    let mut scp = ssh.copy_command(direction, local, remote_path, recursive);
+    if options.dry_run {
+        println!("{}", ssh::shell_line(&scp));
+        return Ok(());
     }
+    preflight::check_reachable(config, node, &ssh).await?;
+    secrets::load_key(config, node)?;
+    agent::ensure_loaded(config, node)?;
+
+    let status = subprocess::status(&mut scp).context("Failed to execute scp")?;
+    exit_like(status)
 }
 
-/// Save configuration to the config file
-fn save_config(config: &Config) -> Result<()> {
-    let config_path = get_config_path()?;
-    let config_str = serde_json::to_string_pretty(config)?;
-    fs::write(config_path, config_str)?;
-    Ok(())
+/// Run a session, falling back to the node's other addresses when one can't
+/// reach it. An address that works after the usual one failed is tried first from then on.
+async fn run_at_any_address(
+    config: &mut Config,
+    node: &TailscaleNode,
+    ssh: &SshCommand,
+    options: &Options,
+) -> Result<ExitStatus> {
+    // OpenSSH only reports when authentication is done at this log level, which marks the end of the handshake
+    let ssh = if options.verbosity > 0 { ssh.clone().option("LogLevel", "VERBOSE") } else { ssh.clone() };
+    let candidates = address::candidates(config, node);
+    for (index, destination) in candidates.iter().enumerate() {
+        tracing::info!(
+            node = %node.name,
+            order = index + 1,
+            kind = destination.kind.label(),
+            host = %destination.host,
+            fallback = destination.fallback_reason.as_deref(),
+            "address candidate"
+        );
+    }
+    let sessions: Vec<SshCommand> = candidates
+        .iter()
+        .map(|destination| ssh.clone().at_destination(destination))
+        .collect();
+    let mut outcome = retry::run_with_retry(&sessions, &config.retry, options.retry).await?;
+    if (options.reconnect || config.reconnect.enabled) && !ssh.has_remote_command() {
+        outcome = reconnect::supervise(config, node, &sessions, outcome).await?;
+    }
+
+    // ssh keeps exit code 255 for its own errors, like a refused host key or login
+    let ssh_failed = outcome.reached.is_none() || outcome.status.code() == Some(255);
+    if ssh_failed
+        && io::stdin().is_terminal()
+        && Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("The connection failed. Run the troubleshooting checks?")
+            .default(true)
+            .interact()?
+    {
+        troubleshoot::run(config, node, ssh.user(), ssh.host(), &outcome.stderr_tail).await?;
+    }
+
+    if let Some(index) = outcome.reached
+        && index > 0
+    {
+        let reached = &candidates[index];
+        println!(
+            "Reached {} at its {} ({}); it will be tried first from now on",
+            node.name,
+            reached.kind.label(),
+            reached.host
+        );
+        config.nodes.entry(node.name.clone()).or_default().preferred_address = Some(reached.kind);
+        save_config(config)?;
+    }
+
+    Ok(outcome.status)
 }
 
-/// Parse the output of 'tailscale status' to get a list of nodes
-fn get_tailscale_nodes() -> Result<Vec<TailscaleNode>> {
-    // Run 'tailscale status' command
-    let output = Command::new("tailscale")
-        .arg("status")
-        .output()
-        .context("Failed to execute 'tailscale status'. Is tailscale installed and in your PATH?")?;
-    
-    if !output.status.success() {
-        let error = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow!(
-            "Tailscale status command failed: {}. Make sure Tailscale is connected.", 
-            error
-        ));
+/// Send a file with Taildrop to the node a query names, or to one chosen in the picker
+async fn send_file(mut config: Config, file: &Path, query: Option<&str>, options: &Options) -> Result<()> {
+    if !file.is_file() {
+        return Err(anyhow!("{} is not a file", file.display()));
     }
-    
-    let output_str = String::from_utf8_lossy(&output.stdout);
-    
-    // Parse the output to extract node information
-    let mut nodes = Vec::new();
-    
-    // Regular expression to match node entries
-    // The format is typically:
-    // 100.74.180.3    testnet-staging-load-balancer-1 piotr@       linux   offline
-    // [IP]            [HOSTNAME]                      [USERNAME@]  [OS]    [STATUS]
-    let re = Regex::new(r"^(\d+\.\d+\.\d+\.\d+)\s+(\S+)\s+(\S*)\s+(\S+)\s+(\S+)")?;
-    
-    for line in output_str.lines() {
-        if line.trim().is_empty() || line.contains("tagmap") || line.contains("subnet") {
-            continue;
+    let node = match query {
+        Some(query) => {
+            let nodes = get_tailscale_nodes(&config).await.context("Failed to get Tailscale nodes")?;
+            find_node(&nodes, &config, query)?.clone()
         }
-        
-        if let Some(captures) = re.captures(line) {
-            let ip = captures.get(1).map(|m| m.as_str().to_string()).unwrap_or_default();
-            let name = captures.get(2).map(|m| m.as_str().to_string()).unwrap_or_default();
-            let suggested_user = captures.get(3).map(|m| m.as_str().to_string()).unwrap_or_default();
-            let status = captures.get(5).map(|m| m.as_str().to_string()).unwrap_or_default();
-            
-            // Only add nodes with non-empty names and IPs
-            if !name.is_empty() && !ip.is_empty() {
-                nodes.push(TailscaleNode { 
-                    name, 
-                    ip, 
-                    suggested_user,
-                    status,
-                });
+        None => {
+            let status = get_tailscale_status(&config).await.context("Failed to get Tailscale nodes")?;
+            if status.nodes.is_empty() {
+                return Err(anyhow!("No Tailscale nodes found. Make sure Tailscale is connected."));
             }
+            pick_node(status, &mut config, &mut ForwardingToggles::default(), options).await?
+        }
+    };
+    taildrop::send_with_progress(&config, &node, file).await
+}
+
+/// Push the configured dotfiles to a node
+async fn sync_dotfiles(config: &Config, query: &str) -> Result<()> {
+    let dotfiles = config
+        .dotfiles
+        .as_ref()
+        .ok_or_else(|| anyhow!("Add a `dotfiles` section to the config file to use sync-dotfiles"))?;
+
+    let nodes = get_tailscale_nodes(config).await.context("Failed to get Tailscale nodes")?;
+    let node = find_node(&nodes, config, query)?;
+    let (username, _) = config.resolve_username(node);
+    let ssh = SshCommand::for_node(config, &username, node);
+
+    println!("Syncing dotfiles to {}...", node.name);
+    for mut command in dotfiles::sync_commands(dotfiles, &ssh)? {
+        let program = command.get_program().to_string_lossy().into_owned();
+        let status = subprocess::status(&mut command).with_context(|| format!("Failed to execute {}", program))?;
+        if !status.success() {
+            return Err(anyhow!("{} failed with {}", program, status));
         }
     }
-    
-    // If we couldn't parse any nodes with the regex, try printing the output for debugging
-    if nodes.is_empty() && !output_str.trim().is_empty() {
-        println!("Warning: Could not parse tailscale status output. Raw output:\n{}", output_str);
-    }
-    
-    Ok(nodes)
+    println!("Dotfiles synced to {}", node.name);
+
+    Ok(())
 }
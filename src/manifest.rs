@@ -0,0 +1,162 @@
+use crate::{
+    address,
+    config::{AddressKind, Config, NodeSettings, SshClient},
+    history::unix_now,
+    model::Node,
+    platform::{self, tailscale_program},
+    secure::write_sensitive,
+    session_log,
+    ssh::{configured_options, SshCommand},
+    subprocess,
+    tailscale::TailscaleNode,
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+
+/// What a session was started with, written next to its recording so it can be
+/// reconstructed or reported later. It is written when the session starts and
+/// again with how it ended.
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+    /// The node as `--list --emit json` shows it
+    pub node: Node,
+    /// Login user on the node
+    pub user: String,
+    /// The node's addresses, in the order they are tried
+    pub addresses: Vec<Address>,
+    /// The full command run for the session, first address included
+    pub argv: Vec<String>,
+    /// ssh options from the config, with where each was set
+    pub ssh_options: Vec<SshOption>,
+    /// The node's own settings from the config
+    pub settings: NodeSettings,
+    /// Versions of the programs involved
+    pub versions: Versions,
+    /// The typescript of the session, if it is recorded
+    pub recording: Option<PathBuf>,
+    /// Unix timestamps of the start and end of the session
+    pub started: u64,
+    pub ended: Option<u64>,
+    /// Exit code of the session, or `None` if it was killed by a signal or hasn't ended
+    pub exit_code: Option<i32>,
+}
+
+/// One of the node's addresses
+#[derive(Serialize, Debug)]
+pub struct Address {
+    pub kind: AddressKind,
+    pub host: String,
+}
+
+/// One `-o` option from the config
+#[derive(Serialize, Debug)]
+pub struct SshOption {
+    pub key: &'static str,
+    pub value: String,
+    pub source: &'static str,
+}
+
+/// Versions of ssh-tailscale, the Tailscale CLI and the ssh client, as each prints it
+#[derive(Serialize, Debug)]
+pub struct Versions {
+    pub ssh_tailscale: String,
+    pub tailscale: Option<String>,
+    pub ssh: Option<String>,
+    /// Operating system and architecture of this machine, like `linux x86_64`
+    pub os: String,
+}
+
+/// A manifest being kept for a running session
+pub struct SessionManifest {
+    path: PathBuf,
+    manifest: Manifest,
+}
+
+/// Write the manifest of a session about to start, or return `None` if there
+/// is nowhere to keep it
+pub fn start(
+    config: &Config,
+    node: &TailscaleNode,
+    ssh: &SshCommand,
+    typescript: Option<&Path>,
+) -> Result<Option<SessionManifest>> {
+    let Some(path) = session_log::new_manifest(&node.name, typescript)? else {
+        return Ok(None);
+    };
+    let command = ssh.command();
+    let argv = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let manifest = Manifest {
+        node: Node::new(config, node),
+        user: ssh.user().to_string(),
+        addresses: address::candidates(config, node)
+            .into_iter()
+            .map(|destination| Address {
+                kind: destination.kind,
+                host: destination.host,
+            })
+            .collect(),
+        argv,
+        ssh_options: configured_options(config, node)
+            .into_iter()
+            .map(|option| SshOption {
+                key: option.key,
+                value: option.value,
+                source: option.source,
+            })
+            .collect(),
+        settings: config.node_settings(&node.name),
+        versions: versions(config),
+        recording: typescript.map(Path::to_path_buf),
+        started: unix_now(),
+        ended: None,
+        exit_code: None,
+    };
+    let session = SessionManifest { path, manifest };
+    session.write()?;
+    Ok(Some(session))
+}
+
+impl SessionManifest {
+    /// Record how the session ended
+    pub fn finish(mut self, status: Option<ExitStatus>) -> Result<()> {
+        self.manifest.ended = Some(unix_now());
+        self.manifest.exit_code = status.and_then(|status| status.code());
+        self.write()
+    }
+
+    fn write(&self) -> Result<()> {
+        write_sensitive(&self.path, &serde_json::to_string_pretty(&self.manifest)?)
+    }
+}
+
+/// Ask each program for its version
+fn versions(config: &Config) -> Versions {
+    let ssh = match config.ssh.client {
+        SshClient::OpenSsh => platform::ssh_program(),
+        SshClient::Plink => platform::plink_program(),
+    };
+    Versions {
+        ssh_tailscale: env!("CARGO_PKG_VERSION").to_string(),
+        tailscale: tailscale_program(config.tailscale_path.as_deref())
+            .ok()
+            .and_then(|program| first_line(Command::new(program).arg("version"))),
+        ssh: first_line(Command::new(ssh).arg("-V")),
+        os: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+    }
+}
+
+/// The first line a program prints, on stdout or else stderr (where `ssh -V` prints)
+fn first_line(command: &mut Command) -> Option<String> {
+    let output = subprocess::output(command).ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let text = if stdout.trim().is_empty() { stderr } else { stdout };
+    text.lines().next().map(|line| line.trim().to_string())
+}
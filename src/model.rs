@@ -0,0 +1,173 @@
+use crate::{config::Config, history, session_log::timing_path, tailscale::TailscaleNode};
+use anyhow::{anyhow, Result};
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Version of the JSON that `--emit json` prints and plugins read. It goes up
+/// whenever a field is removed, renamed or changes meaning; new fields can be
+/// added without a bump, so readers should ignore fields they don't know.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Everything printed with `--emit json`: the schema version and the items listed
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct Listing<T> {
+    pub schema_version: u32,
+    pub items: Vec<T>,
+}
+
+impl<T: Serialize> Listing<T> {
+    pub fn new(items: Vec<T>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            items,
+        }
+    }
+
+    /// Print the listing as pretty JSON on stdout
+    pub fn print(&self) -> Result<()> {
+        println!("{}", serde_json::to_string_pretty(self)?);
+        Ok(())
+    }
+}
+
+/// A node of the tailnet, with the settings ssh-tailscale would connect with
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Node {
+    /// Hostname, which is how the node is named everywhere else
+    pub name: String,
+    /// Short name from the config
+    pub alias: Option<String>,
+    /// Tailscale IPv4 address
+    pub ip: String,
+    /// Tailscale IPv6 address
+    pub ipv6: Option<String>,
+    /// MagicDNS name, like `web-1.tail1234.ts.net`
+    pub dns_name: Option<String>,
+    /// Username ssh-tailscale would connect as
+    pub user: String,
+    /// Login of the node's owner; tagged nodes have none
+    pub owner: Option<String>,
+    /// Operating system as reported by Tailscale, like `linux` or `windows`
+    pub os: String,
+    /// ACL tags, like `tag:server`
+    pub tags: Vec<String>,
+    /// Connection status, like `active; direct`, `idle` or `offline`
+    pub status: String,
+    pub online: bool,
+    /// Whether the node runs Tailscale SSH
+    pub tailscale_ssh: bool,
+    /// When the node was last seen online, in seconds since the Unix epoch, if Tailscale says
+    pub last_seen: Option<u64>,
+    /// Whether this is the machine ssh-tailscale runs on; lists written before
+    /// it was added don't have it
+    #[serde(default)]
+    pub this_machine: bool,
+}
+
+impl Node {
+    pub fn new(config: &Config, node: &TailscaleNode) -> Self {
+        let non_empty = |text: &str| (!text.is_empty()).then(|| text.to_string());
+        let (user, _) = config.resolve_username(node);
+        Self {
+            name: node.name.clone(),
+            alias: config.alias(&node.name).map(str::to_string),
+            ip: node.ip.clone(),
+            ipv6: non_empty(&node.ipv6),
+            dns_name: non_empty(&node.dns_name),
+            user,
+            owner: non_empty(&node.suggested_user),
+            os: node.os.clone(),
+            tags: node.tags.clone(),
+            status: node.status.clone(),
+            online: node.is_online(),
+            tailscale_ssh: node.tailscale_ssh,
+            last_seen: node.last_seen,
+            this_machine: node.is_self,
+        }
+    }
+}
+
+/// A recorded terminal session
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Session {
+    /// Hostname of the node the session was on
+    pub node: String,
+    /// When the session started, in UTC, like `2024-05-01T13-45-00Z`
+    pub started: String,
+    /// The typescript file, which `replay` plays back
+    pub path: String,
+    /// Whether timing data was recorded, so the session can be replayed in real time
+    pub timing: bool,
+}
+
+impl Session {
+    /// Describe a typescript, which is kept as `<node>/<start time>.typescript`
+    pub fn from_typescript(path: &Path) -> Self {
+        let name_of = |path: Option<&Path>| {
+            path.and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        Self {
+            node: name_of(path.parent()),
+            started: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            path: path.display().to_string(),
+            timing: timing_path(path).is_file(),
+        }
+    }
+}
+
+/// A connection made through ssh-tailscale
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// Hostname of the node connected to
+    pub node: String,
+    /// Username connected as
+    pub user: String,
+    /// When the connection was made, in seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl From<&history::HistoryEntry> for HistoryEntry {
+    fn from(entry: &history::HistoryEntry) -> Self {
+        Self {
+            node: entry.node.clone(),
+            user: entry.user.clone(),
+            timestamp: entry.timestamp,
+        }
+    }
+}
+
+/// The listings a JSON schema can be printed for
+#[derive(Clone, Copy, Debug)]
+pub enum SchemaKind {
+    Nodes,
+    Sessions,
+    History,
+}
+
+impl SchemaKind {
+    /// Parse a listing name as typed on the command line
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "nodes" => Ok(SchemaKind::Nodes),
+            "sessions" => Ok(SchemaKind::Sessions),
+            "history" => Ok(SchemaKind::History),
+            other => Err(anyhow!("No schema for '{}'; use nodes, sessions or history", other)),
+        }
+    }
+
+    /// The JSON schema of the listing, pretty-printed
+    pub fn schema(self) -> Result<String> {
+        let schema = match self {
+            SchemaKind::Nodes => schema_for!(Listing<Node>),
+            SchemaKind::Sessions => schema_for!(Listing<Session>),
+            SchemaKind::History => schema_for!(Listing<HistoryEntry>),
+        };
+        Ok(serde_json::to_string_pretty(&schema)?)
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{config::Config, platform::tailscale_program, subprocess};
+use anyhow::{anyhow, Context, Result};
+
+/// What `tailscale netcheck` found out about this machine's network
+#[derive(Debug, Clone, Default)]
+pub struct NetcheckReport {
+    /// Each `* Key: value` line of the report, in order
+    pub fields: Vec<(String, String)>,
+    /// Round trip to each DERP region, nearest first as Tailscale lists them
+    pub derp_latency: Vec<DerpLatency>,
+}
+
+/// Round trip time to one DERP region
+#[derive(Debug, Clone)]
+pub struct DerpLatency {
+    /// Region code, like `fra`
+    pub region: String,
+    /// Like `12.3ms`
+    pub latency: String,
+    /// Region name, like `Frankfurt`
+    pub name: String,
+}
+
+/// One line of the summary: a label, what was found, and whether it is
+/// likely why connections are slow
+pub struct Finding {
+    pub label: &'static str,
+    pub value: String,
+    pub problem: bool,
+}
+
+/// Run `tailscale netcheck`, which takes a few seconds while it probes the DERP servers
+pub async fn run(config: &Config) -> Result<NetcheckReport> {
+    let program = tailscale_program(config.tailscale_path.as_deref())?;
+    let output = subprocess::output_async(tokio::process::Command::new(program).arg("netcheck"))
+        .await
+        .context("Failed to run tailscale netcheck")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("tailscale netcheck failed: {}", stderr.trim()));
+    }
+    Ok(parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse the text report, keeping fields it doesn't know so newer clients still show them
+pub fn parse(text: &str) -> NetcheckReport {
+    let mut report = NetcheckReport::default();
+    for line in text.lines().map(str::trim) {
+        if let Some(field) = line.strip_prefix("* ") {
+            let (key, value) = field.split_once(':').unwrap_or((field, ""));
+            report.fields.push((key.trim().to_string(), value.trim().to_string()));
+        } else if let Some(entry) = line.strip_prefix("- ")
+            && let Some((region, rest)) = entry.split_once(':')
+        {
+            let (latency, name) = rest.split_once('(').unwrap_or((rest, ""));
+            report.derp_latency.push(DerpLatency {
+                region: region.trim().to_string(),
+                latency: latency.trim().to_string(),
+                name: name.trim_end_matches(')').trim().to_string(),
+            });
+        }
+    }
+    report
+}
+
+impl NetcheckReport {
+    /// The value of a `* Key: value` line
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The parts of the report that explain slow or relayed connections
+    pub fn findings(&self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let mut push = |label, value: String, problem| findings.push(Finding { label, value, problem });
+
+        if let Some(nearest) = self.field("Nearest DERP") {
+            let region = self
+                .derp_latency
+                .iter()
+                .find(|derp| derp.name == nearest || derp.region == nearest);
+            let value = match region {
+                Some(derp) => format!("{} ({}, {})", nearest, derp.region, derp.latency),
+                None => nearest.to_string(),
+            };
+            push("Nearest DERP", value, false);
+        }
+        match self.field("UDP") {
+            Some("true") => push("UDP", "allowed".to_string(), false),
+            Some(_) => push(
+                "UDP",
+                "blocked, so every connection goes through a DERP relay".to_string(),
+                true,
+            ),
+            None => {}
+        }
+        match self.field("MappingVariesByDestIP") {
+            Some("false") => push("NAT", "easy, direct connections should work".to_string(), false),
+            Some("true") => push(
+                "NAT",
+                "hard (the mapping varies by destination), so direct connections often fail".to_string(),
+                true,
+            ),
+            _ => {}
+        }
+        if let Some(mapping) = self.field("PortMapping") {
+            let value = if mapping.is_empty() { "none".to_string() } else { mapping.to_string() };
+            push("Port mapping", value, false);
+        }
+        for key in ["IPv4", "IPv6"] {
+            if let Some(value) = self.field(key) {
+                push(key, value.to_string(), false);
+            }
+        }
+        if self.field("CaptivePortal") == Some("true") {
+            push("Captive portal", "detected; sign in to the network first".to_string(), true);
+        }
+        findings
+    }
+}
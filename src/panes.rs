@@ -0,0 +1,666 @@
+use crate::{
+    config::Config,
+    history::{load_history, save_history},
+    hooks,
+    keys::{Action, FooterHint, KeyMap, PANE_ACTIONS},
+    launches::LaunchQueue,
+    picker::{NodePicker, PickerEvent},
+    secrets,
+    ssh::{limit_reached_message, limit_warning_message, tmux_session_for, SshCommand},
+    tailscale::TailscaleNode,
+    theme::Theme,
+    tui::{hook_restore_terminal, restore_terminal, unhook_restore_terminal},
+};
+use anyhow::{anyhow, Result};
+use crossterm::{
+    event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{enable_raw_mode, EnterAlternateScreen},
+};
+use futures::StreamExt;
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use ratatui::{
+    backend::CrosstermBackend,
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
+    Frame, Terminal,
+};
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+/// The terminal type sessions are told about, which is what the vt100 parser understands
+const PANE_TERM: &str = "xterm-256color";
+
+/// Lines of output each pane keeps for its screen, which is all the parser needs
+const SCROLLBACK_LINES: usize = 0;
+
+/// How often the panes' session limits are checked
+const LIMIT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// One ssh session running in a pane, with the screen its output draws
+struct Pane {
+    id: u64,
+    /// `user@node`
+    title: String,
+    parser: vt100::Parser,
+    master: Box<dyn MasterPty + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Box<dyn Child + Send + Sync>,
+    opened: Instant,
+    /// The node's session limit and how long before it to warn
+    time_limit: Option<(Duration, Duration)>,
+    warned: bool,
+    /// How the session ended, once it has; the pane stays open so its last output can be read
+    ended: Option<String>,
+}
+
+impl Drop for Pane {
+    fn drop(&mut self) {
+        if self.ended.is_none() {
+            let _ = self.child.kill();
+        }
+    }
+}
+
+/// What the thread reading a pane's terminal reports
+enum PaneEvent {
+    Output(u64, Vec<u8>),
+    Closed(u64),
+}
+
+/// The panes, the focus and the picker for opening another one
+struct Workspace {
+    config: Config,
+    nodes: Vec<TailscaleNode>,
+    keys: KeyMap,
+    theme: Theme,
+    /// `--user`, which every pane logs in as instead of the configured username
+    user: Option<String>,
+    panes: Vec<Pane>,
+    focus: usize,
+    /// Shown over the panes while choosing the node for a new one
+    picker: Option<NodePicker>,
+    /// Nodes chosen for new panes, waiting for `launches.max_per_second` to let them start
+    launches: LaunchQueue<TailscaleNode>,
+    /// Why the last pane couldn't be opened
+    message: Option<String>,
+    next_id: u64,
+    tx: mpsc::UnboundedSender<PaneEvent>,
+}
+
+/// Run sessions to several nodes side by side in one terminal, starting with
+/// `start`, or with the picker when it is empty, until every pane is closed
+pub async fn run(config: Config, nodes: Vec<TailscaleNode>, start: Vec<TailscaleNode>, user: Option<String>) -> Result<()> {
+    // Check the keys and theme while errors can still be printed normally
+    let keys = KeyMap::from_config(&config.keys)?;
+    let theme = Theme::from_config(&config.theme)?;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let launches = LaunchQueue::new(&config.launches);
+    let mut workspace = Workspace {
+        config,
+        nodes,
+        keys,
+        theme,
+        user,
+        panes: Vec::new(),
+        focus: 0,
+        picker: None,
+        launches,
+        message: None,
+        next_id: 0,
+        tx,
+    };
+    workspace.open_picker()?;
+
+    let previous_hook = hook_restore_terminal();
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    terminal.clear()?;
+
+    if !start.is_empty() {
+        workspace.picker = None;
+    }
+    for node in start {
+        workspace.launches.push(node);
+    }
+    workspace.open_ready(terminal.size()?)?;
+
+    let result = async {
+        let mut events = EventStream::new();
+        let mut limit_check = tokio::time::interval(LIMIT_CHECK_INTERVAL);
+        loop {
+            workspace.fit(terminal.size()?);
+            terminal.draw(|f| workspace.render(f))?;
+
+            let launch_wait = workspace.launches.wait(Instant::now());
+            tokio::select! {
+                maybe_event = events.next() => {
+                    let event = match maybe_event {
+                        Some(event) => event?,
+                        None => return Err(anyhow!("Terminal event stream closed")),
+                    };
+                    if workspace.handle_event(event, terminal.size()?)? {
+                        return Ok(());
+                    }
+                }
+                Some(event) = rx.recv() => {
+                    // Take everything already written before drawing again
+                    workspace.pane_event(event);
+                    while let Ok(event) = rx.try_recv() {
+                        workspace.pane_event(event);
+                    }
+                }
+                _ = limit_check.tick() => workspace.enforce_limits(),
+                _ = tokio::time::sleep(launch_wait.unwrap_or_default()), if launch_wait.is_some() => {
+                    workspace.open_ready(terminal.size()?)?;
+                }
+            }
+        }
+    }
+    .await;
+
+    // End the sessions still running before giving the terminal back
+    workspace.panes.clear();
+    restore_terminal();
+    unhook_restore_terminal(previous_hook);
+    result
+}
+
+impl Workspace {
+    /// Show the picker over the panes, to choose the node for a new one
+    fn open_picker(&mut self) -> Result<()> {
+        let picker = NodePicker::builder(self.nodes.clone()).config(self.config.clone()).build()?;
+        self.picker = Some(picker);
+        Ok(())
+    }
+
+    /// Open panes for the queued nodes the launch cap lets start now. With none
+    /// open or waiting after that, it's back to the picker.
+    fn open_ready(&mut self, screen: Rect) -> Result<()> {
+        while let Some(node) = self.launches.pop_ready(Instant::now()) {
+            self.open_pane(&node, screen);
+        }
+        if self.panes.is_empty() && self.launches.is_empty() && self.picker.is_none() {
+            self.open_picker()?;
+        }
+        Ok(())
+    }
+
+    /// Start a session to a node in a new pane and focus it, or say why it couldn't start
+    fn open_pane(&mut self, node: &TailscaleNode, screen: Rect) {
+        let size = pane_rects(panes_area(screen), self.panes.len() + 1)
+            .last()
+            .map(|&rect| inner_size(rect))
+            .unwrap_or((24, 80));
+        match self.spawn(node, size) {
+            Ok(pane) => {
+                self.panes.push(pane);
+                self.focus = self.panes.len() - 1;
+                self.message = None;
+            }
+            Err(err) => self.message = Some(format!("{:#}", err)),
+        }
+    }
+
+    /// Run ssh to a node in a new pseudo-terminal, with a thread passing its output back
+    fn spawn(&mut self, node: &TailscaleNode, (rows, cols): (u16, u16)) -> Result<Pane> {
+        if node.platform().is_mobile() {
+            return Err(anyhow!("{} runs {}, which has no SSH server to connect to", node.name, node.os));
+        }
+        let username = match &self.user {
+            Some(user) => user.clone(),
+            None => secrets::connect_username(&self.config, node)?,
+        };
+        let ssh = SshCommand::for_node(&self.config, &username, node)
+            .attach_tmux(tmux_session_for(&self.config, node, None));
+        // The hook can't have the terminal while the panes are drawn on it
+        hooks::check_before_connect(&self.config, node, &username)?;
+
+        let command = ssh.command();
+        let mut builder = CommandBuilder::new(command.get_program());
+        builder.args(command.get_args());
+        for (name, value) in command.get_envs() {
+            if let Some(value) = value {
+                builder.env(name, value);
+            }
+        }
+        builder.env("TERM", PANE_TERM);
+        if let Ok(dir) = std::env::current_dir() {
+            builder.cwd(dir);
+        }
+
+        let pair = native_pty_system().openpty(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?;
+        let child = pair.slave.spawn_command(builder)?;
+        drop(pair.slave);
+        let reader = pair.master.try_clone_reader()?;
+        let writer = pair.master.take_writer()?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let tx = self.tx.clone();
+        std::thread::spawn(move || read_pane(id, reader, tx));
+
+        // Remember the connection like any other session
+        let mut history = load_history()?;
+        history.record(&node.name, &username);
+        save_history(&history)?;
+        tracing::info!(node = %node.name, user = %username, "pane opened");
+
+        Ok(Pane {
+            id,
+            title: format!("{}@{}", username, node.name),
+            parser: vt100::Parser::new(rows, cols, SCROLLBACK_LINES),
+            master: pair.master,
+            writer,
+            child,
+            opened: Instant::now(),
+            time_limit: ssh.time_limit(),
+            warned: false,
+            ended: None,
+        })
+    }
+
+    /// Handle a terminal event, returning whether the last pane is gone and the picker was left
+    fn handle_event(&mut self, event: Event, screen: Rect) -> Result<bool> {
+        if let Some(picker) = &mut self.picker {
+            match picker.handle_event(&event) {
+                PickerEvent::Pending => {}
+                PickerEvent::Selected(node) => {
+                    self.picker = None;
+                    self.launches.push(*node);
+                    self.open_ready(screen)?;
+                }
+                PickerEvent::Cancelled => {
+                    self.picker = None;
+                    return Ok(self.panes.is_empty() && self.launches.is_empty());
+                }
+            }
+            return Ok(false);
+        }
+
+        match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press => match self.keys.action(&key, PANE_ACTIONS) {
+                Some(Action::NewPane) => self.open_picker()?,
+                Some(Action::NextPane) if !self.panes.is_empty() => {
+                    self.focus = (self.focus + 1) % self.panes.len();
+                }
+                Some(Action::ClosePane) if !self.panes.is_empty() => {
+                    self.panes.remove(self.focus);
+                    self.focus = self.focus.min(self.panes.len().saturating_sub(1));
+                    if self.panes.is_empty() && self.launches.is_empty() {
+                        self.open_picker()?;
+                    }
+                }
+                // The quit keys are Ctrl+C for the remote shell here
+                _ => {
+                    if let Some(pane) = self.panes.get(self.focus) {
+                        let bytes = key_bytes(&key, pane.parser.screen().application_cursor());
+                        self.write_focused(&bytes);
+                    }
+                }
+            },
+            Event::Paste(text) => self.write_focused(text.as_bytes()),
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// Send input to the focused session, unless it has ended
+    fn write_focused(&mut self, bytes: &[u8]) {
+        if let Some(pane) = self.panes.get_mut(self.focus)
+            && pane.ended.is_none()
+        {
+            let _ = pane.writer.write_all(bytes);
+            let _ = pane.writer.flush();
+        }
+    }
+
+    /// Draw a session's output, or note how it ended
+    fn pane_event(&mut self, event: PaneEvent) {
+        match event {
+            PaneEvent::Output(id, bytes) => {
+                if let Some(pane) = self.panes.iter_mut().find(|pane| pane.id == id) {
+                    pane.parser.process(&bytes);
+                }
+            }
+            PaneEvent::Closed(id) => {
+                if let Some(pane) = self.panes.iter_mut().find(|pane| pane.id == id)
+                    && pane.ended.is_none()
+                {
+                    pane.ended = Some(match pane.child.wait() {
+                        Ok(status) if status.success() => "ended".to_string(),
+                        Ok(status) => format!("ended with exit status {}", status.exit_code()),
+                        Err(err) => format!("ended: {}", err),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Warn the sessions nearing their node's session limit, and end the ones past it
+    fn enforce_limits(&mut self) {
+        for pane in &mut self.panes {
+            let Some((max, warn)) = pane.time_limit else {
+                continue;
+            };
+            if pane.ended.is_some() {
+                continue;
+            }
+            let elapsed = pane.opened.elapsed();
+            if elapsed >= max {
+                pane.parser.process(format!("\r\n{}\r\n", limit_reached_message(max)).as_bytes());
+                let _ = pane.child.kill();
+                let _ = pane.child.wait();
+                pane.ended = Some("ended at the session limit".to_string());
+            } else if !pane.warned && elapsed + warn >= max {
+                pane.warned = true;
+                pane.parser.process(format!("\r\n{}\r\n", limit_warning_message(max, elapsed)).as_bytes());
+            }
+        }
+    }
+
+    /// Give every pane's terminal the size of its place on the screen
+    fn fit(&mut self, screen: Rect) {
+        let rects = pane_rects(panes_area(screen), self.panes.len());
+        for (pane, rect) in self.panes.iter_mut().zip(rects) {
+            let (rows, cols) = inner_size(rect);
+            if pane.parser.screen().size() != (rows, cols) {
+                pane.parser.set_size(rows, cols);
+                let _ = pane.master.resize(PtySize {
+                    rows,
+                    cols,
+                    pixel_width: 0,
+                    pixel_height: 0,
+                });
+            }
+        }
+    }
+
+    fn render(&mut self, f: &mut Frame) {
+        let screen = f.size();
+        let rects = pane_rects(panes_area(screen), self.panes.len());
+        for (index, (pane, &rect)) in self.panes.iter().zip(&rects).enumerate() {
+            let focused = index == self.focus && self.picker.is_none();
+            let mut title = vec![Span::styled(format!(" {} ", pane.title), self.theme.title)];
+            if let Some(ended) = &pane.ended {
+                title.push(Span::styled(format!("{} ", ended), self.theme.warning));
+            }
+            let block = Block::default()
+                .borders(Borders::ALL)
+                .border_style(if focused { self.theme.accent } else { self.theme.faint })
+                .title(Line::from(title));
+            let inner = block.inner(rect);
+            f.render_widget(block, rect);
+
+            let vt = pane.parser.screen();
+            draw_screen(f.buffer_mut(), inner, vt);
+            if focused && pane.ended.is_none() && !vt.hide_cursor() {
+                let (row, col) = vt.cursor_position();
+                f.set_cursor(inner.x + col.min(inner.width.saturating_sub(1)), inner.y + row.min(inner.height.saturating_sub(1)));
+            }
+        }
+
+        // Keys along the bottom, or why a pane couldn't be opened, or the panes waiting to open
+        let footer = Rect::new(screen.x, screen.bottom().saturating_sub(1), screen.width, 1.min(screen.height));
+        let queued: Vec<&str> = self.launches.queued().map(|node| node.name.as_str()).collect();
+        let line = match &self.message {
+            Some(message) => Line::from(Span::styled(message.as_str(), self.theme.warning)),
+            None if !queued.is_empty() => Line::from(Span::styled(
+                format!(
+                    "Waiting to open {} (at most {} new sessions a second)",
+                    queued.join(", "),
+                    self.launches.max_per_second()
+                ),
+                self.theme.warning,
+            )),
+            None => {
+                let hints: Vec<String> = PANE_ACTIONS
+                    .iter()
+                    .map(|&action| self.keys.hint(FooterHint::Action(action)))
+                    .collect();
+                Line::from(Span::styled(hints.join("  "), self.theme.muted))
+            }
+        };
+        f.render_widget(Paragraph::new(line), footer);
+
+        if let Some(picker) = &mut self.picker {
+            let area = centered(panes_area(screen));
+            let block = Block::default().borders(Borders::ALL).title(" Open a pane ");
+            let inner = block.inner(area);
+            f.render_widget(Clear, area);
+            f.render_widget(block, area);
+            picker.render(f, inner);
+        }
+    }
+}
+
+/// Pass everything a session writes to the event loop, until its terminal closes
+fn read_pane(id: u64, mut reader: Box<dyn Read + Send>, tx: mpsc::UnboundedSender<PaneEvent>) {
+    let mut buffer = [0; 8192];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => {
+                if tx.send(PaneEvent::Output(id, buffer[..read].to_vec())).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    let _ = tx.send(PaneEvent::Closed(id));
+}
+
+/// The screen above the footer line
+fn panes_area(screen: Rect) -> Rect {
+    Rect::new(screen.x, screen.y, screen.width, screen.height.saturating_sub(1))
+}
+
+/// Where each of `count` panes goes: a grid as close to square as fits, filled row by row
+fn pane_rects(area: Rect, count: usize) -> Vec<Rect> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let columns = (1..=count).find(|columns| columns * columns >= count).unwrap_or(count);
+    let rows = count.div_ceil(columns);
+    let mut rects = Vec::with_capacity(count);
+    for row in 0..rows {
+        // The last row spreads its panes over the whole width
+        let in_row = if row + 1 == rows { count - columns * row } else { columns };
+        let y = area.y + (area.height as usize * row / rows) as u16;
+        let bottom = area.y + (area.height as usize * (row + 1) / rows) as u16;
+        for column in 0..in_row {
+            let x = area.x + (area.width as usize * column / in_row) as u16;
+            let right = area.x + (area.width as usize * (column + 1) / in_row) as u16;
+            rects.push(Rect::new(x, y, right - x, bottom - y));
+        }
+    }
+    rects
+}
+
+/// Rows and columns inside a pane's border. The parser can't wrap a line
+/// on a screen of one row, so even a squeezed pane gets two.
+fn inner_size(rect: Rect) -> (u16, u16) {
+    (rect.height.saturating_sub(2).max(2), rect.width.saturating_sub(2).max(2))
+}
+
+/// The middle of the screen, for the picker
+fn centered(area: Rect) -> Rect {
+    let width = (area.width * 4 / 5).max(area.width.min(40));
+    let height = (area.height * 4 / 5).max(area.height.min(10));
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Copy a session's screen into the frame, cell by cell
+fn draw_screen(buf: &mut Buffer, area: Rect, screen: &vt100::Screen) {
+    for row in 0..area.height {
+        for col in 0..area.width {
+            let Some(cell) = screen.cell(row, col) else { continue };
+            // The cell after a wide character is covered by it
+            if cell.is_wide_continuation() {
+                continue;
+            }
+            let mut style = Style::default().fg(color(cell.fgcolor())).bg(color(cell.bgcolor()));
+            for (on, modifier) in [
+                (cell.bold(), Modifier::BOLD),
+                (cell.italic(), Modifier::ITALIC),
+                (cell.underline(), Modifier::UNDERLINED),
+                (cell.inverse(), Modifier::REVERSED),
+            ] {
+                if on {
+                    style = style.add_modifier(modifier);
+                }
+            }
+            let contents = cell.contents();
+            let symbol = if contents.is_empty() { " " } else { contents.as_str() };
+            buf.get_mut(area.x + col, area.y + row).set_symbol(symbol).set_style(style);
+        }
+    }
+}
+
+fn color(color: vt100::Color) -> Color {
+    match color {
+        vt100::Color::Default => Color::Reset,
+        vt100::Color::Idx(index) => Color::Indexed(index),
+        vt100::Color::Rgb(red, green, blue) => Color::Rgb(red, green, blue),
+    }
+}
+
+/// What a terminal sends for a key, with the cursor keys in application mode
+/// when the session asked for it
+fn key_bytes(key: &KeyEvent, application_cursor: bool) -> Vec<u8> {
+    let cursor = |letter: char| {
+        if application_cursor { format!("\x1bO{}", letter) } else { format!("\x1b[{}", letter) }.into_bytes()
+    };
+    let mut bytes = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => match c.to_ascii_lowercase() {
+            letter @ 'a'..='z' => vec![letter as u8 - b'a' + 1],
+            '@' | ' ' | '2' => vec![0],
+            '[' | '3' => vec![0x1b],
+            '\\' | '4' => vec![0x1c],
+            ']' | '5' => vec![0x1d],
+            '^' | '6' => vec![0x1e],
+            '_' | '-' | '7' => vec![0x1f],
+            other => other.to_string().into_bytes(),
+        },
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => cursor('A'),
+        KeyCode::Down => cursor('B'),
+        KeyCode::Right => cursor('C'),
+        KeyCode::Left => cursor('D'),
+        KeyCode::Home => cursor('H'),
+        KeyCode::End => cursor('F'),
+        KeyCode::Insert => b"\x1b[2~".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        KeyCode::PageUp => b"\x1b[5~".to_vec(),
+        KeyCode::PageDown => b"\x1b[6~".to_vec(),
+        KeyCode::F(number @ 1..=4) => format!("\x1bO{}", char::from(b'P' + number - 1)).into_bytes(),
+        KeyCode::F(number) => match number {
+            5 => b"\x1b[15~".to_vec(),
+            6 => b"\x1b[17~".to_vec(),
+            7 => b"\x1b[18~".to_vec(),
+            8 => b"\x1b[19~".to_vec(),
+            9 => b"\x1b[20~".to_vec(),
+            10 => b"\x1b[21~".to_vec(),
+            11 => b"\x1b[23~".to_vec(),
+            12 => b"\x1b[24~".to_vec(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+    // Alt sends Escape before the key
+    if key.modifiers.contains(KeyModifiers::ALT) && !bytes.is_empty() {
+        bytes.insert(0, 0x1b);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Odd and cramped screens, down to nothing at all
+    fn areas() -> [Rect; 6] {
+        [
+            Rect::new(0, 0, 80, 23),
+            Rect::new(0, 0, 101, 33),
+            Rect::new(3, 5, 7, 3),
+            Rect::new(0, 0, 1, 1),
+            Rect::new(0, 0, 0, 0),
+            Rect::new(0, 0, 13, 0),
+        ]
+    }
+
+    fn area_of(rect: Rect) -> u32 {
+        u32::from(rect.width) * u32::from(rect.height)
+    }
+
+    #[test]
+    fn pane_rects_tile_the_area() {
+        for area in areas() {
+            for count in 1..=11 {
+                let rects = pane_rects(area, count);
+                assert_eq!(rects.len(), count, "{} panes in {:?}", count, area);
+                for (i, rect) in rects.iter().enumerate() {
+                    assert_eq!(rect.intersection(area), *rect, "pane {} of {} outside {:?}", i, count, area);
+                    for other in &rects[i + 1..] {
+                        assert_eq!(area_of(rect.intersection(*other)), 0, "{:?} overlaps {:?}", rect, other);
+                    }
+                }
+                assert_eq!(rects.iter().map(|&rect| area_of(rect)).sum::<u32>(), area_of(area));
+            }
+        }
+        assert!(pane_rects(areas()[0], 0).is_empty());
+    }
+
+    #[test]
+    fn pane_rects_are_close_to_square() {
+        let rects = pane_rects(Rect::new(0, 0, 90, 31), 5);
+        // Three across the top and two spread over the whole width below
+        assert_eq!(rects.iter().filter(|rect| rect.y == 0).count(), 3);
+        assert_eq!(rects[3], Rect::new(0, 15, 45, 16));
+        assert_eq!(rects[4], Rect::new(45, 15, 45, 16));
+    }
+
+    #[test]
+    fn panes_leave_the_footer_line() {
+        assert_eq!(panes_area(Rect::new(0, 0, 80, 24)), Rect::new(0, 0, 80, 23));
+        assert_eq!(panes_area(Rect::new(0, 0, 80, 0)).height, 0);
+    }
+
+    #[test]
+    fn inner_size_is_never_below_two_by_two() {
+        assert_eq!(inner_size(Rect::new(0, 0, 80, 23)), (21, 78));
+        assert_eq!(inner_size(Rect::new(0, 0, 3, 3)), (2, 2));
+        assert_eq!(inner_size(Rect::new(0, 0, 0, 0)), (2, 2));
+    }
+
+    #[test]
+    fn centered_stays_on_screen() {
+        for area in areas() {
+            let picker = centered(area);
+            assert_eq!(picker.intersection(area), picker, "{:?}", area);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    actions::{check_service_keys, menu_actions},
+    app::App,
+    config::Config,
+    keys::{parse_footer_hints, Action, FooterHint, KeyMap, PICKER_ACTIONS},
+    plugins,
+    sort::check_sort,
+    tailscale::{TailnetHealth, TailscaleNode, TailscaleStatus},
+    theme::Theme,
+    ui,
+};
+use anyhow::Result;
+use crossterm::event::{Event, KeyEventKind};
+use ratatui::{layout::Rect, Frame};
+
+/// The node list with its filter and key bindings, for ratatui applications
+/// that need the user to choose a Tailscale node. The application owns the
+/// terminal and the event loop; it passes events to `handle_event` and draws
+/// the picker with `render` wherever it likes.
+pub struct NodePicker {
+    app: App,
+}
+
+/// What an event meant to the picker
+#[derive(Debug, Clone)]
+pub enum PickerEvent {
+    /// The event moved the selection, edited the filter or was ignored
+    Pending,
+    /// The user chose a node, with Enter or a double click
+    Selected(Box<TailscaleNode>),
+    /// The user backed out with the quit keys, or Esc with no filter typed
+    Cancelled,
+}
+
+/// Settings for a `NodePicker`, checked when it is built
+pub struct PickerBuilder {
+    nodes: Vec<TailscaleNode>,
+    config: Config,
+    health: TailnetHealth,
+    cached: bool,
+    filter: String,
+    selected: Option<String>,
+    /// Whether the picker is the standalone one, with popups, custom actions
+    /// and their footer hints
+    standalone: bool,
+}
+
+impl PickerBuilder {
+    /// Take keys, theme, layout, aliases and usernames from a config instead of the defaults
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Client health shown in the header
+    pub fn health(mut self, health: TailnetHealth) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Mark the nodes as saved by an earlier run, until `NodePicker::set_nodes` replaces them
+    pub fn cached(mut self, cached: bool) -> Self {
+        self.cached = cached;
+        self
+    }
+
+    /// Start with filter text already typed
+    pub fn filter(mut self, filter: impl Into<String>) -> Self {
+        self.filter = filter.into();
+        self
+    }
+
+    /// Start with a node selected by hostname, if the filter shows it
+    pub fn select(mut self, name: impl Into<String>) -> Self {
+        self.selected = Some(name.into());
+        self
+    }
+
+    /// Keep everything the standalone picker offers, rather than just what an embedded one handles
+    pub(crate) fn standalone(mut self) -> Self {
+        self.standalone = true;
+        self
+    }
+
+    /// Build the picker, failing if the config's keys, footer hints, actions, theme or sort are invalid
+    pub fn build(self) -> Result<NodePicker> {
+        let keys = KeyMap::from_config(&self.config.keys)?;
+        let mut footer_hints = parse_footer_hints(self.config.ui.footer_hints.as_deref())?;
+        let actions = if self.standalone {
+            let actions = menu_actions(&self.config.actions, plugins::discover(), &keys)?;
+            check_service_keys(&self.config, &actions, &keys)?;
+            actions
+        } else {
+            // Only hint at keys that do something here
+            footer_hints.retain(|hint| match hint {
+                FooterHint::Action(action) => *action == Action::Quit || PICKER_ACTIONS.contains(action),
+                FooterHint::Navigate => true,
+            });
+            Vec::new()
+        };
+        let theme = Theme::from_config(&self.config.theme)?;
+        check_sort(&self.config)?;
+
+        let mut app = App::new(self.nodes, self.config);
+        app.health = self.health;
+        app.cached = self.cached;
+        app.keys = keys;
+        app.footer_hints = footer_hints;
+        app.actions = actions;
+        app.theme = theme;
+        app.filter = self.filter;
+        app.apply_filter();
+        if let Some(name) = &self.selected {
+            app.select_node(name);
+        }
+        Ok(NodePicker { app })
+    }
+}
+
+impl NodePicker {
+    /// Start building a picker for a list of nodes
+    pub fn builder(nodes: Vec<TailscaleNode>) -> PickerBuilder {
+        PickerBuilder {
+            nodes,
+            config: Config::default(),
+            health: TailnetHealth::default(),
+            cached: false,
+            filter: String::new(),
+            selected: None,
+            standalone: false,
+        }
+    }
+
+    /// Start building a picker for the nodes and health of a `tailscale status` run
+    pub fn from_status(status: TailscaleStatus) -> PickerBuilder {
+        Self::builder(status.nodes).health(status.health).cached(status.cached)
+    }
+
+    /// Handle a terminal event: keys move the selection and edit the filter,
+    /// and the mouse wheel and clicks work on the list
+    pub fn handle_event(&mut self, event: &Event) -> PickerEvent {
+        let chosen = match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press => match self.app.keys.action(key, PICKER_ACTIONS) {
+                Some(Action::Quit) => return PickerEvent::Cancelled,
+                Some(Action::ClearFilter) if self.app.filter.is_empty() => return PickerEvent::Cancelled,
+                // Choosing a folded row lists its nodes instead
+                Some(Action::Select) => !self.app.unfold_selected(),
+                action => {
+                    self.app.navigate(action, key);
+                    false
+                }
+            },
+            // A double click chooses the node under it, or expands the folded row
+            Event::Mouse(mouse) => self.app.mouse(mouse) && !self.app.unfold_selected(),
+            _ => false,
+        };
+
+        match self.selected() {
+            Some(node) if chosen => PickerEvent::Selected(Box::new(node.clone())),
+            _ => PickerEvent::Pending,
+        }
+    }
+
+    /// Draw the picker's header, list and filter line into `area`
+    pub fn render(&mut self, frame: &mut Frame, area: Rect) {
+        ui::render(frame, &mut self.app, area);
+    }
+
+    /// Replace the nodes, like after a fresh `tailscale status`, keeping the selection if it still exists
+    pub fn set_nodes(&mut self, nodes: Vec<TailscaleNode>) {
+        self.app.replace_nodes(nodes);
+        self.app.cached = false;
+    }
+
+    /// The highlighted node, if any node matches the filter
+    pub fn selected(&self) -> Option<&TailscaleNode> {
+        self.app.get_selected_node()
+    }
+
+    /// The text typed into the filter
+    pub fn filter(&self) -> &str {
+        &self.app.filter
+    }
+
+    /// The full state, for the standalone picker's popups and background tasks
+    pub fn into_app(self) -> App {
+        self.app
+    }
+}
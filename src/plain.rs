@@ -0,0 +1,88 @@
+use crate::{
+    config::Config,
+    index::NodeIndex,
+    selector::is_hidden,
+    sort::{active_sort, sort},
+    tailscale::TailscaleNode,
+};
+use anyhow::{anyhow, Result};
+use std::io::{self, BufRead, Write};
+
+/// Whether to pick nodes from a numbered list instead of the terminal UI:
+/// when asked with `--plain`, or when `$TERM` says the terminal can't draw
+/// it. Windows consoles don't set `$TERM`, so only `--plain` counts there.
+pub fn wanted(plain: bool) -> bool {
+    if plain {
+        return true;
+    }
+    if cfg!(windows) {
+        return false;
+    }
+    std::env::var("TERM").map_or(true, |term| term.is_empty() || term == "dumb")
+}
+
+/// Choose a node from a numbered list, read and written a line at a time so
+/// it works over serial consoles and dumb terminals. Typing text lists only
+/// the nodes it matches, the same way the picker's filter does.
+pub fn choose(config: &Config, nodes: &[TailscaleNode]) -> Result<TailscaleNode> {
+    let index = NodeIndex::new(nodes, config);
+    let mut filter = String::new();
+    let mut stdin = io::stdin().lock();
+    loop {
+        let mut positions = index.matches(&filter);
+        positions.retain(|&position| !is_hidden(config, &nodes[position]));
+        if let Some(keys) = active_sort(config) {
+            sort(config, keys, nodes, &mut positions);
+        }
+        let listed: Vec<&TailscaleNode> = positions.iter().map(|&position| &nodes[position]).collect();
+        if listed.is_empty() {
+            println!("No nodes match '{}'", filter);
+        } else {
+            print_menu(config, &listed);
+        }
+
+        let hint = if filter.is_empty() { "" } else { ", nothing to list all" };
+        print!("Node number, text to filter{}, or q to quit: ", hint);
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            println!();
+            return Err(anyhow!("User cancelled"));
+        }
+        let line = line.trim();
+        if line == "q" {
+            return Err(anyhow!("User cancelled"));
+        }
+        if let Ok(number) = line.parse::<usize>() {
+            match number.checked_sub(1).and_then(|choice| listed.get(choice)) {
+                Some(&node) => return Ok(node.clone()),
+                None => println!("There is no node number {}", number),
+            }
+            continue;
+        }
+        filter = line.to_string();
+    }
+}
+
+/// One numbered line per node: name and alias, address and status
+fn print_menu(config: &Config, nodes: &[&TailscaleNode]) {
+    let names: Vec<String> = nodes
+        .iter()
+        .map(|node| match config.alias(&node.name) {
+            Some(alias) => format!("{} ({})", node.name, alias),
+            None => node.name.clone(),
+        })
+        .collect();
+    let number_width = nodes.len().to_string().len();
+    let name_width = names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+    let ip_width = nodes.iter().map(|node| node.ip.len()).max().unwrap_or(0);
+    for (number, (node, name)) in nodes.iter().zip(&names).enumerate() {
+        println!(
+            "{:>number_width$}) {:<name_width$}  {:<ip_width$}  {}",
+            number + 1,
+            name,
+            node.ip,
+            node.status,
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::{anyhow, Result};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The tailscale CLI to run: the configured path if there is one, else the
+/// first hit on PATH or in the platform's usual install locations. Neither the
+/// macOS App Store build nor the Windows installer reliably put it on PATH.
+pub fn tailscale_program(configured: Option<&str>) -> Result<PathBuf> {
+    if let Some(path) = configured {
+        let path = expand_home(path)?;
+        return if path.is_file() {
+            Ok(path)
+        } else {
+            Err(anyhow!("tailscale_path is set to {}, but there is no file there", path.display()))
+        };
+    }
+
+    let mut tried = vec!["tailscale on PATH".to_string()];
+    if let Some(path) = find_on_path("tailscale") {
+        return Ok(path);
+    }
+
+    for candidate in tailscale_locations() {
+        if candidate.is_file() {
+            return Ok(candidate);
+        }
+        tried.push(candidate.display().to_string());
+    }
+
+    Err(anyhow!(
+        "Could not find the tailscale CLI. Looked for:\n  {}\nInstall Tailscale, or set `tailscale_path` in the config file to where the CLI is.",
+        tried.join("\n  ")
+    ))
+}
+
+/// Where Tailscale's installers put the CLI on this platform
+fn tailscale_locations() -> Vec<PathBuf> {
+    if cfg!(windows) {
+        env_locations(&[("ProgramFiles", r"Tailscale\tailscale.exe")])
+    } else if cfg!(target_os = "macos") {
+        [
+            // App Store and standalone builds ship the CLI inside the app bundle
+            "/Applications/Tailscale.app/Contents/MacOS/Tailscale",
+            "/opt/homebrew/bin/tailscale",
+            "/usr/local/bin/tailscale",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect()
+    } else {
+        ["/usr/bin/tailscale", "/usr/local/bin/tailscale", "/snap/bin/tailscale"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect()
+    }
+}
+
+/// The OpenSSH client to run. Windows ships it as an optional feature under
+/// System32, which some shells (MSYS, older PowerShell sessions) leave off PATH.
+pub fn ssh_program() -> PathBuf {
+    find_program("ssh", &[("SystemRoot", r"System32\OpenSSH\ssh.exe")])
+}
+
+/// PuTTY's command-line client, used instead of OpenSSH when configured
+pub fn plink_program() -> PathBuf {
+    find_program(
+        "plink",
+        &[("ProgramFiles", r"PuTTY\plink.exe"), ("ProgramFiles(x86)", r"PuTTY\plink.exe")],
+    )
+}
+
+/// OpenSSH's file copy client, which sits next to ssh
+pub fn scp_program() -> PathBuf {
+    find_program("scp", &[("SystemRoot", r"System32\OpenSSH\scp.exe")])
+}
+
+/// PuTTY's file copy client, used with plink
+pub fn pscp_program() -> PathBuf {
+    find_program(
+        "pscp",
+        &[("ProgramFiles", r"PuTTY\pscp.exe"), ("ProgramFiles(x86)", r"PuTTY\pscp.exe")],
+    )
+}
+
+/// Look a program up on PATH, then in Windows install locations given as an
+/// environment variable holding the base directory and a path below it.
+/// Falls back to the bare name so spawning reports the usual "not found" error.
+fn find_program(name: &str, windows_locations: &[(&str, &str)]) -> PathBuf {
+    if let Some(path) = find_on_path(name) {
+        return path;
+    }
+
+    if cfg!(windows) {
+        for candidate in env_locations(windows_locations) {
+            if candidate.is_file() {
+                return candidate;
+            }
+        }
+    }
+
+    PathBuf::from(name)
+}
+
+/// Paths below directories named by environment variables, skipping unset ones
+fn env_locations(locations: &[(&str, &str)]) -> Vec<PathBuf> {
+    locations
+        .iter()
+        .filter_map(|(base_var, relative)| Some(Path::new(&env::var_os(base_var)?).join(relative)))
+        .collect()
+}
+
+/// The first executable called `name` in a PATH directory
+pub fn find_on_path(name: &str) -> Option<PathBuf> {
+    let file_name = if cfg!(windows) { format!("{}.exe", name) } else { name.to_string() };
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&file_name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Expand a leading `~` to the user's home directory
+pub fn expand_home(path: &str) -> Result<PathBuf> {
+    match path.strip_prefix("~") {
+        Some(rest) => {
+            let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+            Ok(home_dir.join(rest.trim_start_matches('/')))
+        }
+        None => Ok(PathBuf::from(path)),
+    }
+}
+
+/// A command that opens a URL in the default browser
+pub fn open_command(url: &str) -> Command {
+    if cfg!(windows) {
+        // `start` takes its first quoted argument as a window title
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]).arg(url);
+        command
+    } else if cfg!(target_os = "macos") {
+        let mut command = Command::new("open");
+        command.arg(url);
+        command
+    } else {
+        let mut command = Command::new("xdg-open");
+        command.arg(url);
+        command
+    }
+}
+
+/// Build a command that runs `command_line` through the platform shell
+pub fn shell_command(command_line: &str) -> Command {
+    if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(command_line);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(command_line);
+        command
+    }
+}
+
+/// Point stdin back at the terminal after reading piped input, so prompts, the
+/// picker and ssh sessions can still read keys. Does nothing where there is no
+/// `/dev/tty`; on Windows, pass `--nodes-from` a file instead.
+pub fn reattach_stdin() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsRawFd;
+        let tty = std::fs::File::open("/dev/tty").map_err(|err| anyhow!("Failed to open the terminal: {}", err))?;
+        // SAFETY: both descriptors are open, and dup2 only replaces descriptor 0
+        if unsafe { libc::dup2(tty.as_raw_fd(), 0) } < 0 {
+            return Err(anyhow!("Failed to reattach stdin: {}", std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use crate::{actions::set_node_env, config::{is_safe_mode, Config}, model::Node, subprocess, tailscale::TailscaleNode};
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+
+/// Start of the file name that makes an executable on PATH a plugin
+pub const PLUGIN_PREFIX: &str = "ssh-tailscale-";
+
+/// Extensions that make a file runnable on Windows, where there is no executable bit
+const WINDOWS_EXTENSIONS: [&str; 4] = ["exe", "cmd", "bat", "com"];
+
+/// An `ssh-tailscale-<name>` executable found on PATH
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    /// The part after the prefix, which is also its subcommand
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Every plugin on PATH, sorted by name. When two directories have one with
+/// the same name the earlier directory wins, as it does in the shell. Safe mode has none.
+pub fn discover() -> Vec<Plugin> {
+    if is_safe_mode() {
+        return Vec::new();
+    }
+    let Some(path) = env::var_os("PATH") else {
+        return Vec::new();
+    };
+
+    let mut plugins: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for dir in env::split_paths(&path) {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if let Some(name) = plugin_name(&path)
+                && is_executable(&path)
+            {
+                plugins.entry(name).or_insert(path);
+            }
+        }
+    }
+
+    plugins.into_iter().map(|(name, path)| Plugin { name, path }).collect()
+}
+
+/// The plugin providing subcommand `name`, if there is one on PATH
+pub fn find(name: &str) -> Option<Plugin> {
+    discover().into_iter().find(|plugin| plugin.name == name)
+}
+
+/// The plugin name a file would have, without the prefix and, on Windows, the extension
+fn plugin_name(path: &Path) -> Option<String> {
+    let file_name = if cfg!(windows) {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        if !WINDOWS_EXTENSIONS.contains(&extension.as_str()) {
+            return None;
+        }
+        path.file_stem()?
+    } else {
+        path.file_name()?
+    };
+    let name = file_name.to_str()?.strip_prefix(PLUGIN_PREFIX)?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// A plugin's command with `SSH_TAILSCALE` set to this program, so it can call back into it
+fn plugin_command(plugin: &Plugin) -> Command {
+    let mut command = Command::new(&plugin.path);
+    if let Ok(program) = env::current_exe() {
+        command.env("SSH_TAILSCALE", program);
+    }
+    command
+}
+
+/// Run a plugin as a subcommand, with the terminal handed over to it
+pub fn run(plugin: &Plugin, args: &[String]) -> Result<ExitStatus> {
+    subprocess::status(plugin_command(plugin).args(args)).with_context(|| format!("Failed to run plugin {}", plugin.path.display()))
+}
+
+/// The command that runs a plugin from the picker for a node, with the node's
+/// details in the environment. `node_json` has the rest, for its stdin.
+pub fn command_for(plugin: &Plugin, config: &Config, node: &TailscaleNode) -> Command {
+    let (username, _) = config.resolve_username(node);
+    let mut command = plugin_command(plugin);
+    set_node_env(&mut command, node, &username);
+    command
+}
+
+/// Everything known about a node, as the JSON object plugins get on stdin:
+/// a `model::Node`, the same as `--list --emit json` prints
+pub fn node_json(config: &Config, node: &TailscaleNode) -> String {
+    serde_json::to_string(&Node::new(config, node)).unwrap_or_default()
+}
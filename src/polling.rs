@@ -0,0 +1,42 @@
+use crate::config::PollingConfig;
+use std::time::Duration;
+
+/// Refreshes never come closer together than this
+const MIN_STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Nor further apart than this, however big the tailnet
+const MAX_STATUS_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Time added between refreshes for every node, so 1000 nodes refresh every 50 seconds
+const STATUS_INTERVAL_PER_NODE: Duration = Duration::from_millis(50);
+
+/// The interval is at least this many times as long as `tailscale status`
+/// took, so waiting on it is at most a twentieth of the time
+const LATENCY_FACTOR: u32 = 20;
+
+/// Time between enrichments for every node, as each one rewrites files
+/// that grow with the tailnet
+const ENRICH_INTERVAL_PER_NODE: Duration = Duration::from_millis(200);
+
+/// Enrichments are never further apart than this
+const MAX_ENRICH_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Time to wait before the next `tailscale status`, given how many nodes the
+/// last one listed and how long it took
+pub fn status_interval(polling: &PollingConfig, nodes: usize, latency: Duration) -> Duration {
+    if let Some(secs) = polling.status_secs {
+        return Duration::from_secs(secs.max(1));
+    }
+    (STATUS_INTERVAL_PER_NODE * nodes as u32)
+        .max(latency * LATENCY_FACTOR)
+        .clamp(MIN_STATUS_INTERVAL, MAX_STATUS_INTERVAL)
+}
+
+/// Time between updates of presence history, known_hosts and the status cache.
+/// Small tailnets get one on every refresh.
+pub fn enrich_interval(polling: &PollingConfig, nodes: usize, status_interval: Duration) -> Duration {
+    if let Some(secs) = polling.enrich_secs {
+        return Duration::from_secs(secs);
+    }
+    (ENRICH_INTERVAL_PER_NODE * nodes as u32).clamp(status_interval, MAX_ENRICH_INTERVAL.max(status_interval))
+}
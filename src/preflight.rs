@@ -0,0 +1,182 @@
+use crate::{
+    config::{Config, PreflightCheck},
+    platform::tailscale_program,
+    ssh::SshCommand,
+    subprocess,
+    tailscale::TailscaleNode,
+    timeline,
+};
+use anyhow::{anyhow, Result};
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use std::{
+    future::Future,
+    io::{self, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+/// How long a reachability probe may take before the node counts as unreachable
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pings sent while waiting for a direct connection, about one a second
+const DIRECT_PATH_PINGS: u32 = 10;
+
+/// Frames of the spinner shown while a probe runs
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+
+/// Check that the node can be reached before handing the terminal to ssh, which
+/// would otherwise sit in its own connect timeout for minutes
+pub async fn check_reachable(config: &Config, node: &TailscaleNode, ssh: &SshCommand) -> Result<()> {
+    match config.ssh.preflight {
+        PreflightCheck::Off => Ok(()),
+        PreflightCheck::Ping => {
+            if spin(&format!("Pinging {}", node.name), tailscale_ping(config, node)).await {
+                Ok(())
+            } else {
+                Err(offline_error(node))
+            }
+        }
+        PreflightCheck::Tcp => {
+            let port = ssh.port();
+            if spin(&format!("Checking port {} on {}", port, node.name), port_open_at(ssh.host(), port)).await {
+                return Ok(());
+            }
+            // Tell an unreachable node apart from one that is up without sshd
+            if spin(&format!("Pinging {}", node.name), tailscale_ping(config, node)).await {
+                Err(anyhow!(
+                    "{} is online, but nothing answers on port {}.\n\
+                     - Check that sshd is running on it (e.g. `systemctl status ssh`)\n\
+                     - Check that sshd listens on the Tailscale interface, not just localhost\n\
+                     - Check that your tailnet ACLs allow port {} to this node",
+                    node.name,
+                    port,
+                    port
+                ))
+            } else {
+                Err(offline_error(node))
+            }
+        }
+    }
+}
+
+/// Warn when Tailscale reaches the node only through a DERP relay, which makes
+/// typing feel sluggish, and offer to wait while pings set up a direct path.
+/// Only nodes `tailscale status` lists as relayed are pinged.
+pub async fn check_direct_path(config: &Config, node: &TailscaleNode) -> Result<()> {
+    if !config.ssh.relay_warning || !node.status.contains("relay") {
+        return Ok(());
+    }
+    // A node that doesn't answer is left to the reachability check and ssh
+    let Some((true, reply)) = spin(&format!("Pinging {}", node.name), ping(config, node, 1)).await else {
+        return Ok(());
+    };
+    let Some((region, latency)) = relayed_via(&reply) else {
+        return Ok(());
+    };
+
+    println!(
+        "{} is only reachable through the DERP relay \"{}\", with about {} round trips, so typing may lag.",
+        node.name, region, latency
+    );
+    if !io::stdin().is_terminal()
+        || !Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Wait a few seconds for a direct connection?")
+            .default(true)
+            .interact()?
+    {
+        return Ok(());
+    }
+
+    let label = format!("Waiting for a direct connection to {}", node.name);
+    match spin(&label, ping(config, node, DIRECT_PATH_PINGS)).await {
+        Some((_, reply)) if relayed_via(&reply).is_none() && reply.contains(" via ") => {
+            let latency = reply.rsplit_once(" in ").map(|(_, latency)| latency).unwrap_or_default();
+            println!("Direct connection established, {} round trips.", latency);
+        }
+        _ => println!("Still relayed through {}; connecting anyway.", region),
+    }
+    Ok(())
+}
+
+/// The DERP region and round trip time of a ping reply that came back through
+/// a relay, like `pong from web-1 (100.74.180.4) via DERP(fra) in 45ms`
+fn relayed_via(reply: &str) -> Option<(&str, &str)> {
+    let (_, path) = reply.split_once(" via DERP(")?;
+    let (region, latency) = path.split_once(')')?;
+    Some((region, latency.trim().trim_start_matches("in ")))
+}
+
+/// The error for a node that doesn't answer at all
+fn offline_error(node: &TailscaleNode) -> anyhow::Error {
+    anyhow!(
+        "{} didn't answer a tailscale ping.\n\
+         - It may be asleep, powered off or disconnected from Tailscale\n\
+         - Run `tailscale status` to see when it was last online",
+        node.name
+    )
+}
+
+/// Whether a TCP connection to the ssh port succeeds in time
+pub async fn port_open(host: &str) -> bool {
+    port_open_at(host, 22).await
+}
+
+/// Whether a TCP connection to a port succeeds in time
+pub async fn port_open_at(host: &str, port: u16) -> bool {
+    matches!(
+        tokio::time::timeout(PROBE_TIMEOUT, tokio::net::TcpStream::connect((host, port))).await,
+        Ok(Ok(_))
+    )
+}
+
+/// Whether the node answers a single `tailscale ping`
+async fn tailscale_ping(config: &Config, node: &TailscaleNode) -> bool {
+    ping_reply(config, node).await.is_some()
+}
+
+/// The reply to a single `tailscale ping`, like `pong from web-1 (100.74.180.4)
+/// via DERP(fra) in 45ms`, or `None` if the node didn't answer
+pub async fn ping_reply(config: &Config, node: &TailscaleNode) -> Option<String> {
+    match ping(config, node, 1).await? {
+        (true, reply) => Some(reply),
+        (false, _) => None,
+    }
+}
+
+/// Run `tailscale ping`, which stops early once a direct path is up, and return
+/// whether it succeeded along with the last line it printed
+async fn ping(config: &Config, node: &TailscaleNode, count: u32) -> Option<(bool, String)> {
+    let program = tailscale_program(config.tailscale_path.as_deref()).ok()?;
+    let timeout = format!("--timeout={}s", PROBE_TIMEOUT.as_secs());
+    let count = count.to_string();
+    let output = subprocess::output_async(
+        tokio::process::Command::new(program).args(["ping", "-c", &count, &timeout, &node.ip]),
+    )
+    .await
+    .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Some((output.status.success(), stdout.lines().last().unwrap_or_default().trim().to_string()))
+}
+
+/// Show a spinner with a label until the probe finishes, then clear the line
+pub async fn spin<T>(label: &str, probe: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let mut probe = std::pin::pin!(probe);
+    let mut ticker = tokio::time::interval(Duration::from_millis(100));
+    let mut stdout = io::stdout();
+
+    for frame in SPINNER.iter().cycle() {
+        tokio::select! {
+            ok = &mut probe => {
+                print!("\r{:width$}\r", "", width = label.len() + 4);
+                let _ = stdout.flush();
+                timeline::step(label, started);
+                return ok;
+            }
+            _ = ticker.tick() => {
+                print!("\r{} {}...", frame, label);
+                let _ = stdout.flush();
+            }
+        }
+    }
+    unreachable!("the spinner cycles forever")
+}
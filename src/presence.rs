@@ -0,0 +1,63 @@
+use crate::{config::get_data_dir, history::unix_now, secure::write_private, tailscale::TailscaleNode};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::PathBuf, time::Duration};
+
+/// When each node was first seen online in its current online stretch, as
+/// observed by this tool's own `tailscale status` runs. Tailscale doesn't
+/// report how long a peer has been online, so this is the best we have.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Presence {
+    /// Unix timestamp at which each currently online node was first seen online
+    pub online_since: BTreeMap<String, u64>,
+}
+
+impl Presence {
+    /// How long the node has been seen online, if it is online at all
+    pub fn online_for(&self, name: &str) -> Option<Duration> {
+        self.online_since
+            .get(name)
+            .map(|&since| Duration::from_secs(unix_now().saturating_sub(since)))
+    }
+
+    /// Fold a fresh node list into the observations
+    fn observe(&mut self, nodes: &[TailscaleNode]) {
+        let now = unix_now();
+        self.online_since
+            .retain(|name, _| nodes.iter().any(|node| &node.name == name && node.is_online()));
+        for node in nodes.iter().filter(|node| node.is_online()) {
+            self.online_since.entry(node.name.clone()).or_insert(now);
+        }
+    }
+}
+
+/// Get the presence file path, if there is anywhere to keep it
+fn get_presence_path() -> Result<Option<PathBuf>> {
+    Ok(get_data_dir()?.map(|dir| dir.join("presence.json")))
+}
+
+/// Load the recorded observations
+pub fn load_presence() -> Result<Presence> {
+    let Some(presence_path) = get_presence_path()? else {
+        return Ok(Presence::default());
+    };
+
+    if presence_path.exists() {
+        let presence_str = fs::read_to_string(presence_path)?;
+        Ok(serde_json::from_str(&presence_str).unwrap_or_default())
+    } else {
+        Ok(Presence::default())
+    }
+}
+
+/// Record which nodes are online right now
+pub fn record_presence(nodes: &[TailscaleNode]) -> Result<()> {
+    let Some(presence_path) = get_presence_path()? else {
+        return Ok(());
+    };
+    let mut presence = load_presence()?;
+    presence.observe(nodes);
+    write_private(&presence_path, serde_json::to_string_pretty(&presence)?.as_bytes())?;
+    Ok(())
+}
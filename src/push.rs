@@ -0,0 +1,67 @@
+use crate::{config::Config, secrets, ssh::SshCommand, subprocess, tailscale::TailscaleNode};
+use anyhow::{anyhow, Result};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::{path::Path, process::Stdio};
+
+/// Copy a local file to every target in parallel, printing how each copy went
+/// as it finishes and a summary at the end. An empty `remote_path` copies into
+/// each node's home directory.
+pub async fn push(config: &Config, targets: &[&TailscaleNode], file: &Path, remote_path: &str) -> Result<()> {
+    if !file.is_file() {
+        return Err(anyhow!("{} is not a file", file.display()));
+    }
+    if targets.is_empty() {
+        return Err(anyhow!("No nodes left to push to"));
+    }
+
+    let usernames = targets
+        .iter()
+        .map(|node| secrets::connect_username(config, node))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut copies: FuturesUnordered<_> = targets
+        .iter()
+        .zip(usernames)
+        .map(|(node, username)| {
+            // Like broadcasts, copies can't stop to ask for a password
+            let ssh = SshCommand::for_node(config, &username, node).option("BatchMode", "yes");
+            let scp = ssh.scp_command(file, remote_path);
+            let name = node.name.clone();
+            async move {
+                let output = subprocess::output_async(tokio::process::Command::from(scp).stdin(Stdio::null())).await;
+                (name, output)
+            }
+        })
+        .collect();
+
+    let mut failed = Vec::new();
+    while let Some((name, output)) = copies.next().await {
+        match output {
+            Ok(output) if output.status.success() => println!("{}: ok", name),
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let stderr: Vec<&str> = stderr.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+                println!("{}: failed ({}) {}", name, output.status, stderr.join("; "));
+                failed.push(name);
+            }
+            Err(err) => {
+                println!("{}: failed to start scp: {}", name, err);
+                failed.push(name);
+            }
+        }
+    }
+
+    println!(
+        "Pushed {} to {} nodes: {} succeeded, {} failed",
+        file.display(),
+        targets.len(),
+        targets.len() - failed.len(),
+        failed.len()
+    );
+    if !failed.is_empty() {
+        failed.sort();
+        return Err(anyhow!("Failed on: {}", failed.join(", ")));
+    }
+
+    Ok(())
+}
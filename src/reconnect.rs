@@ -0,0 +1,63 @@
+use crate::{
+    config::Config,
+    preflight::{ping_reply, spin},
+    retry::{self, RetryOutcome},
+    ssh::SshCommand,
+    tailscale::TailscaleNode,
+};
+use anyhow::Result;
+use std::time::Duration;
+
+/// How often the node is pinged while waiting for it to answer again
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Keep an interactive session going like autossh: while the network drops it,
+/// wait for the node to answer `tailscale ping` again and start the session over
+/// as the same user. Returns the outcome of the last run. Sessions that end any
+/// other way, including `exit` and the `~.` escape, are left ended.
+pub async fn supervise(
+    config: &Config,
+    node: &TailscaleNode,
+    sessions: &[SshCommand],
+    mut outcome: RetryOutcome,
+) -> Result<RetryOutcome> {
+    let budget = config.reconnect.max_attempts;
+    let wait = Duration::from_secs(config.reconnect.wait_secs);
+    let mut reconnects = 0;
+
+    while outcome.dropped {
+        if reconnects >= budget {
+            println!("Connection to {} dropped again; giving up after {} reconnects", node.name, budget);
+            break;
+        }
+        reconnects += 1;
+        let reason = outcome.stderr_tail.lines().last().unwrap_or_default().trim().to_string();
+        println!("Connection to {} dropped ({})", node.name, reason);
+
+        let label = format!("Waiting for {} to answer", node.name);
+        if spin(&label, tokio::time::timeout(wait, wait_reachable(config, node)))
+            .await
+            .is_err()
+        {
+            println!("{} didn't answer within {} seconds; not reconnecting", node.name, wait.as_secs());
+            break;
+        }
+
+        println!(
+            "Reconnecting to {} as {} ({}/{})...",
+            node.name,
+            sessions[0].user(),
+            reconnects,
+            budget
+        );
+        outcome = retry::run_with_retry(sessions, &config.retry, true).await?;
+    }
+    Ok(outcome)
+}
+
+/// Ping the node until it answers
+async fn wait_reachable(config: &Config, node: &TailscaleNode) {
+    while ping_reply(config, node).await.is_none() {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{
+    config::{Config, RemoteShell},
+    tailscale::TailscaleNode,
+};
+
+/// The shell that runs remote commands on a node: its own setting, then the
+/// `ssh` section's, with `auto` decided by the node's operating system
+pub fn shell_for(config: &Config, node: &TailscaleNode) -> RemoteShell {
+    let shell = config
+        .nodes
+        .get(&node.name)
+        .and_then(|settings| settings.remote_shell)
+        .unwrap_or(config.ssh.remote_shell);
+    match shell {
+        RemoteShell::Auto if node.os.eq_ignore_ascii_case("windows") => RemoteShell::Cmd,
+        RemoteShell::Auto => RemoteShell::Posix,
+        shell => shell,
+    }
+}
+
+/// The command line that runs `args` in the remote shell with each argument
+/// intact. A single argument is taken to be a command line already and passed
+/// as it is, the same as ssh does, so pipes and redirections in it still work.
+pub fn command_line(shell: RemoteShell, args: &[String]) -> String {
+    if let [line] = args {
+        return line.clone();
+    }
+
+    let quoted: Vec<String> = args.iter().map(|arg| quote(shell, arg)).collect();
+    let line = quoted.join(" ");
+
+    // PowerShell reads a quoted first word as a string rather than a command to run
+    match args.first() {
+        Some(program) if shell == RemoteShell::PowerShell && quoted[0] != *program => format!("& {}", line),
+        _ => line,
+    }
+}
+
+/// One argument quoted for `shell`, if it needs to be
+pub fn quote(shell: RemoteShell, arg: &str) -> String {
+    match shell {
+        RemoteShell::Auto | RemoteShell::Posix => quote_posix(arg),
+        RemoteShell::PowerShell => quote_powershell(arg),
+        RemoteShell::Cmd => quote_cmd(arg),
+    }
+}
+
+/// Whether an argument means the same to the shell unquoted: letters, digits,
+/// a few punctuation characters no shell treats specially, and `extra`
+fn is_plain(arg: &str, extra: &str) -> bool {
+    !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-./:".contains(c) || extra.contains(c))
+}
+
+/// Single quotes, with embedded ones written as `'\''`
+fn quote_posix(arg: &str) -> String {
+    if is_plain(arg, "@+,=%") {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Single quotes, which PowerShell takes literally, with embedded ones doubled
+fn quote_powershell(arg: &str) -> String {
+    if is_plain(arg, "\\") {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "''"))
+}
+
+/// Double quotes, escaped the way Windows programs split their command line.
+/// cmd reads the line first: inside quotes it leaves `&`, `|`, `<` and `>`
+/// alone but still expands `%VAR%`, and it doesn't know `\"` is escaped. So
+/// quotes and `%` in the argument go between the quotes, as `\^"` and `^%`.
+fn quote_cmd(arg: &str) -> String {
+    if is_plain(arg, "\\") {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' | '%' => {
+                // Backslashes before a closing quote would escape it
+                quoted.push_str(&"\\".repeat(backslashes * 2));
+                quoted.push_str(if c == '"' { "\"\\^\"\"" } else { "\"^%\"" });
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Split a command line the way `sh` would, as far as the quoting here goes
+    fn split_posix(line: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut word: Option<String> = None;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' => words.extend(word.take()),
+                '\'' => word.get_or_insert_default().extend(chars.by_ref().take_while(|&c| c != '\'')),
+                '\\' => word.get_or_insert_default().extend(chars.next()),
+                c => word.get_or_insert_default().push(c),
+            }
+        }
+        words.extend(word);
+        words
+    }
+
+    /// Split a command line of PowerShell's literal strings
+    fn split_powershell(line: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut word: Option<String> = None;
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' if quoted && chars.peek() == Some(&'\'') => {
+                    chars.next();
+                    word.get_or_insert_default().push('\'');
+                }
+                '\'' => {
+                    quoted = !quoted;
+                    word.get_or_insert_default();
+                }
+                ' ' if !quoted => words.extend(word.take()),
+                c => word.get_or_insert_default().push(c),
+            }
+        }
+        words.extend(word);
+        words
+    }
+
+    /// Split a command line the way cmd passes it on, with carets outside quotes
+    /// escaping the next character, and then the way Windows programs split it
+    fn split_cmd(line: &str) -> Vec<String> {
+        let mut passed = String::new();
+        let mut quoted = false;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '^' if !quoted => passed.extend(chars.next()),
+                c => {
+                    quoted ^= c == '"';
+                    passed.push(c);
+                }
+            }
+        }
+
+        let mut words = Vec::new();
+        let mut word: Option<String> = None;
+        let mut quoted = false;
+        let mut backslashes = 0;
+        for c in passed.chars() {
+            if c == '\\' {
+                backslashes += 1;
+                continue;
+            }
+            let word_so_far = word.get_or_insert_default();
+            if c == '"' {
+                word_so_far.push_str(&"\\".repeat(backslashes / 2));
+                if backslashes % 2 == 1 {
+                    word_so_far.push('"');
+                } else {
+                    quoted = !quoted;
+                }
+            } else {
+                word_so_far.push_str(&"\\".repeat(backslashes));
+                if c == ' ' && !quoted {
+                    words.extend(word.take());
+                } else {
+                    word_so_far.push(c);
+                }
+            }
+            backslashes = 0;
+        }
+        if backslashes > 0 {
+            word.get_or_insert_default().push_str(&"\\".repeat(backslashes));
+        }
+        words.extend(word);
+        words
+    }
+
+    /// Arguments with everything the shells treat specially
+    fn awkward_args() -> Vec<String> {
+        [
+            "echo",
+            "a b",
+            "it's",
+            "",
+            r#"say "hi""#,
+            r"C:\Program Files\",
+            r#"back\\slash\"quote"#,
+            "100%",
+            "%PATH%",
+            r"50% of %USERPROFILE%\",
+            "a & b | c > d",
+            "$HOME `date`",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    #[test]
+    fn posix_quoting_round_trips() {
+        let args = awkward_args();
+        assert_eq!(split_posix(&command_line(RemoteShell::Posix, &args)), args);
+    }
+
+    #[test]
+    fn powershell_quoting_round_trips() {
+        let args = awkward_args();
+        assert_eq!(split_powershell(&command_line(RemoteShell::PowerShell, &args)), args);
+    }
+
+    #[test]
+    fn powershell_runs_a_quoted_program() {
+        let args = [r"C:\tools\my tool.exe", "x"].map(String::from);
+        assert_eq!(command_line(RemoteShell::PowerShell, &args), r"& 'C:\tools\my tool.exe' x");
+    }
+
+    #[test]
+    fn cmd_quoting_round_trips() {
+        let args = awkward_args();
+        assert_eq!(split_cmd(&command_line(RemoteShell::Cmd, &args)), args);
+    }
+
+    #[test]
+    fn cmd_percent_and_quotes_are_never_between_quotes() {
+        assert_eq!(quote(RemoteShell::Cmd, "%PATH%"), r##"""^%"PATH"^%"""##);
+        assert_eq!(quote(RemoteShell::Cmd, r"dir\%"), r#""dir\\"^%"""#);
+        assert_eq!(quote(RemoteShell::Cmd, r#"say "hi""#), r##""say "\^""hi"\^""""##);
+    }
+
+    #[test]
+    fn single_argument_is_already_a_command_line() {
+        let args = ["ls | wc -l".to_string()];
+        for shell in [RemoteShell::Posix, RemoteShell::PowerShell, RemoteShell::Cmd] {
+            assert_eq!(command_line(shell, &args), "ls | wc -l");
+        }
+    }
+}
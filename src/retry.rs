@@ -0,0 +1,102 @@
+use crate::{
+    config::RetryConfig,
+    ssh::{SessionResult, SshCommand},
+};
+use anyhow::{anyhow, Result};
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use std::{
+    io::{self, Write},
+    process::ExitStatus,
+    time::Duration,
+};
+
+/// How a session ended, and which address got through
+pub struct RetryOutcome {
+    /// Exit status of the last ssh run
+    pub status: ExitStatus,
+    /// Index of the session that reached the node, if any did
+    pub reached: Option<usize>,
+    /// The last part of ssh's own error output from the last run
+    pub stderr_tail: String,
+    /// Whether the node was reached, but the connection was lost during the session
+    pub dropped: bool,
+}
+
+/// Run an ssh session, falling through `sessions` (the same session at each of the
+/// node's addresses, preferred first) while the node can't be reached at one, and
+/// retrying with exponential backoff when it can't be reached at any.
+/// Without `auto_retry` the user is asked once before the first retry.
+pub async fn run_with_retry(sessions: &[SshCommand], retry: &RetryConfig, auto_retry: bool) -> Result<RetryOutcome> {
+    let mut confirmed = auto_retry;
+    let mut attempt = 0;
+
+    loop {
+        let (result, reached) = run_any_address(sessions)?;
+        if reached.is_some() || attempt >= retry.max_attempts {
+            return Ok(RetryOutcome {
+                status: result.status,
+                reached,
+                dropped: reached.is_some() && result.is_dropped(),
+                stderr_tail: result.stderr_tail,
+            });
+        }
+
+        let ssh = &sessions[0];
+
+        if !confirmed {
+            confirmed = Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("Could not reach {}. Keep retrying with backoff?", ssh.destination()))
+                .default(true)
+                .interact()?;
+            if !confirmed {
+                return Ok(RetryOutcome {
+                    status: result.status,
+                    reached: None,
+                    stderr_tail: result.stderr_tail,
+                    dropped: false,
+                });
+            }
+        }
+
+        attempt += 1;
+        countdown(backoff_delay(retry, attempt), attempt, retry.max_attempts).await?;
+    }
+}
+
+/// Run the session at each address in turn until one reaches the node, returning
+/// the last result and the index of the session that got through
+fn run_any_address(sessions: &[SshCommand]) -> Result<(SessionResult, Option<usize>)> {
+    let mut last = None;
+    for (index, ssh) in sessions.iter().enumerate() {
+        if index > 0 {
+            println!("Could not reach {}, trying {}...", sessions[index - 1].host(), ssh.host());
+        }
+        let result = ssh.run_session()?;
+        if !result.is_connection_failure() {
+            return Ok((result, Some(index)));
+        }
+        last = Some(result);
+    }
+    Ok((last.ok_or_else(|| anyhow!("No address to connect to"))?, None))
+}
+
+/// Delay before the given retry attempt (starting at 1)
+fn backoff_delay(retry: &RetryConfig, attempt: u32) -> u64 {
+    let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+    retry.initial_delay_secs.saturating_mul(factor).min(retry.max_delay_secs)
+}
+
+/// Show a live countdown on a single terminal line
+async fn countdown(seconds: u64, attempt: u32, max_attempts: u32) -> Result<()> {
+    let mut stdout = io::stdout();
+    for remaining in (1..=seconds).rev() {
+        print!(
+            "\rRetrying in {:>2}s (attempt {}/{}), Ctrl+C to give up ",
+            remaining, attempt, max_attempts
+        );
+        stdout.flush()?;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    println!("\rRetrying now (attempt {}/{}){:30}", attempt, max_attempts, "");
+    Ok(())
+}
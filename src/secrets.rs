@@ -0,0 +1,133 @@
+use crate::{
+    agent,
+    config::{Config, SecretSource},
+    secure::secret_from_command,
+    selector::group_contains,
+    subprocess,
+    tailscale::TailscaleNode,
+};
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    env,
+    process::Stdio,
+    sync::{Mutex, OnceLock},
+};
+
+/// Set when this program is run by `ssh-add` as its askpass helper, to the
+/// command that prints the passphrase
+const ASKPASS_ENV: &str = "SSH_TAILSCALE_ASKPASS";
+
+/// Secrets already fetched, by command, so a group's secret is fetched once per run
+fn cache() -> &'static Mutex<HashMap<String, String>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// The secret manager commands for a node, from the first group (alphabetically)
+/// that contains it and has any, with the group's name
+pub fn source_for<'a>(config: &'a Config, node: &TailscaleNode) -> Option<(&'a str, &'a SecretSource)> {
+    config
+        .secrets
+        .groups
+        .iter()
+        .find(|(group, _)| group_contains(config, group, node))
+        .map(|(group, source)| (group.as_str(), source))
+}
+
+/// Run a secret manager command and return the first line it prints, once
+/// per run of ssh-tailscale
+fn fetch(command: &str) -> Result<String> {
+    if let Some(secret) = cache().lock().ok().and_then(|cache| cache.get(command).cloned()) {
+        return Ok(secret);
+    }
+    let secret = secret_from_command(command, &format!("`{}`", command))?;
+    if let Ok(mut cache) = cache().lock() {
+        cache.insert(command.to_string(), secret.clone());
+    }
+    Ok(secret)
+}
+
+/// The username to connect to a node with: the node's own username when it
+/// has one, else the one from its group's secret manager, else the usual defaults
+pub fn connect_username(config: &Config, node: &TailscaleNode) -> Result<String> {
+    if config.node_settings(&node.name).username.is_none()
+        && let Some((group, source)) = source_for(config, node)
+        && let Some(command) = &source.username
+    {
+        return fetch(command).with_context(|| format!("Failed to get the username for group {}", group));
+    }
+    Ok(config.resolve_username(node).0)
+}
+
+/// Whether the node's username comes from a secret manager, for the picker,
+/// which shows where settings come from without fetching anything
+pub fn username_group<'a>(config: &'a Config, node: &TailscaleNode) -> Option<&'a str> {
+    if config.node_settings(&node.name).username.is_some() {
+        return None;
+    }
+    source_for(config, node)
+        .filter(|(_, source)| source.username.is_some())
+        .map(|(group, _)| group)
+}
+
+/// Load the node's key into the ssh-agent with the passphrase from its
+/// group's secret manager, so ssh never asks for it. The passphrase goes to
+/// `ssh-add` through `SSH_ASKPASS`, answered by this program, and is never
+/// written anywhere. Needs a running agent and a key to load.
+pub fn load_key(config: &Config, node: &TailscaleNode) -> Result<()> {
+    let Some((group, SecretSource { passphrase: Some(command), .. })) = source_for(config, node) else {
+        return Ok(());
+    };
+    let Some(key) = agent::key_to_add(config, node) else {
+        println!(
+            "Warning: group {} has a passphrase command but no key to load; set an identity or ssh.agent_key",
+            group
+        );
+        return Ok(());
+    };
+    match agent::loaded_keys() {
+        None => {
+            println!("Warning: no ssh-agent is running to load {} into", key.display());
+            return Ok(());
+        }
+        Some(loaded) if agent::holds_key(&loaded, &key) => return Ok(()),
+        Some(_) => {}
+    }
+
+    let program = env::current_exe().context("Failed to find this program for SSH_ASKPASS")?;
+    let status = subprocess::status(
+        std::process::Command::new("ssh-add")
+            .arg(&key)
+            .env("SSH_ASKPASS", program)
+            .env("SSH_ASKPASS_REQUIRE", "force")
+            .env(ASKPASS_ENV, command)
+            .stdin(Stdio::null()),
+    )
+    .context("Failed to run ssh-add")?;
+    if !status.success() {
+        println!("Warning: ssh-add couldn't load {} with the passphrase from group {}", key.display(), group);
+    }
+    Ok(())
+}
+
+/// When run as `ssh-add`'s askpass helper, print the passphrase and return the
+/// exit code to end with; `None` when this is a normal run
+pub fn askpass_from_env() -> Option<i32> {
+    let command = env::var(ASKPASS_ENV).ok()?;
+    let prompt = env::args().nth(1).unwrap_or_default();
+    // Answering a rejected passphrase again would only loop
+    if prompt.starts_with("Bad passphrase") {
+        return Some(1);
+    }
+    match fetch(&command) {
+        Ok(passphrase) => {
+            println!("{}", passphrase);
+            Some(0)
+        }
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            Some(1)
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{platform::shell_command, subprocess};
+use age::secrecy::SecretString;
+use anyhow::{anyhow, Context, Result};
+use dialoguer::{theme::ColorfulTheme, Password};
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+/// Environment variable holding the passphrase, for scripts that can't answer a prompt
+pub const PASSPHRASE_ENV: &str = "SSH_TAILSCALE_PASSPHRASE";
+
+/// Environment variable holding a command that prints the passphrase, like a
+/// keychain lookup. It can't live in the config, which may be encrypted itself.
+pub const PASSPHRASE_COMMAND_ENV: &str = "SSH_TAILSCALE_PASSPHRASE_COMMAND";
+
+/// Set when the config asks for sensitive files to be encrypted
+static ENCRYPT: AtomicBool = AtomicBool::new(false);
+
+/// Passphrase entered during this run, so it is only asked for once
+static PASSPHRASE: Mutex<Option<SecretString>> = Mutex::new(None);
+
+/// Encrypt sensitive files written from now on
+pub fn enable_encryption() {
+    ENCRYPT.store(true, Ordering::Relaxed);
+}
+
+/// Whether sensitive files are being encrypted
+pub fn encryption_enabled() -> bool {
+    ENCRYPT.load(Ordering::Relaxed)
+}
+
+/// Create a directory only the current user can enter
+pub fn create_private_dir(dir: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)?;
+    Ok(())
+}
+
+/// Write a file only the current user can read
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+
+    // The mode above only applies to new files; tighten ones left by older versions too
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+
+    file.write_all(contents)?;
+    Ok(())
+}
+
+/// Warnings for a state directory, or the files in it, that other users can access
+pub fn permission_warnings(dir: &Path) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let is_shared = |path: &Path| {
+            fs::metadata(path)
+                .map(|metadata| metadata.permissions().mode() & 0o077 != 0)
+                .unwrap_or(false)
+        };
+
+        if is_shared(dir) {
+            warnings.push(format!("{} is accessible by other users; run `chmod 700 {}`", dir.display(), dir.display()));
+        }
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.is_file() && is_shared(&path) {
+                warnings.push(format!("{} is readable by other users; run `chmod 600 {}`", path.display(), path.display()));
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = dir;
+
+    warnings
+}
+
+/// Read a sensitive file, decrypting it if it was saved encrypted. Either form
+/// is read regardless of the current setting, so switching it on or off
+/// carries the existing contents over on the next save.
+pub fn read_sensitive(path: &Path) -> Result<Option<String>> {
+    let encrypted_path = encrypted_path(path);
+
+    if encrypted_path.exists() {
+        let ciphertext = fs::read(&encrypted_path)?;
+        let identity = age::scrypt::Identity::new(passphrase(false)?);
+        let plaintext = age::decrypt(&identity, &ciphertext)
+            .map_err(|err| anyhow!("Could not decrypt {} (wrong passphrase?): {}", encrypted_path.display(), err))?;
+        return Ok(Some(String::from_utf8(plaintext).context("Decrypted file is not valid UTF-8")?));
+    }
+
+    if path.exists() {
+        return Ok(Some(fs::read_to_string(path)?));
+    }
+
+    Ok(None)
+}
+
+/// Write a sensitive file, encrypted with the passphrase if encryption is on,
+/// removing the copy in the other form
+pub fn write_sensitive(path: &Path, contents: &str) -> Result<()> {
+    write_sensitive_as(path, contents, encryption_enabled())
+}
+
+/// Write a sensitive file encrypted or not, whatever the current setting,
+/// removing the copy in the other form
+pub fn write_sensitive_as(path: &Path, contents: &str, encrypt: bool) -> Result<()> {
+    let encrypted_path = encrypted_path(path);
+
+    if encrypt {
+        // Confirm the passphrase when it is being chosen rather than entered again
+        let recipient = age::scrypt::Recipient::new(passphrase(!encrypted_path.exists())?);
+        let ciphertext = age::encrypt(&recipient, contents.as_bytes())
+            .map_err(|err| anyhow!("Could not encrypt {}: {}", path.display(), err))?;
+        write_private(&encrypted_path, &ciphertext)?;
+        remove_if_exists(path)
+    } else {
+        write_private(path, contents.as_bytes())?;
+        remove_if_exists(&encrypted_path)
+    }
+}
+
+/// Whether a sensitive file is saved in its encrypted form
+pub fn is_encrypted(path: &Path) -> bool {
+    encrypted_path(path).exists()
+}
+
+/// The path of the encrypted form of a file
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".age");
+    path.with_file_name(name)
+}
+
+/// Delete a file, ignoring that it may not be there
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The passphrase for encrypted files, from the environment, the passphrase
+/// command, or asked for; once per run
+fn passphrase(confirm: bool) -> Result<SecretString> {
+    let mut cached = PASSPHRASE.lock().map_err(|_| anyhow!("Passphrase lock poisoned"))?;
+    if let Some(passphrase) = cached.as_ref() {
+        return Ok(passphrase.clone());
+    }
+
+    let command = env::var(PASSPHRASE_COMMAND_ENV).ok().filter(|command| !command.is_empty());
+    let passphrase = match (env::var(PASSPHRASE_ENV), command) {
+        (Ok(passphrase), _) if !passphrase.is_empty() => passphrase,
+        (_, Some(command)) => secret_from_command(&command, &format!("{} `{}`", PASSPHRASE_COMMAND_ENV, command))?,
+        _ => {
+            let theme = ColorfulTheme::default();
+            let mut prompt = Password::with_theme(&theme).with_prompt("Passphrase for ssh-tailscale files");
+            if confirm {
+                prompt = prompt.with_confirmation("Repeat passphrase", "Passphrases don't match");
+            }
+            prompt.interact()?
+        }
+    };
+
+    let passphrase = SecretString::from(passphrase);
+    *cached = Some(passphrase.clone());
+    Ok(passphrase)
+}
+
+/// Run a command that hands over a secret, like a password manager's CLI,
+/// through the shell and take the first line it prints. Its stderr stays on
+/// the terminal, for unlock prompts. Errors refer to the command as `label`.
+pub fn secret_from_command(command: &str, label: &str) -> Result<String> {
+    let output = subprocess::output(shell_command(command).stdin(Stdio::inherit()).stderr(Stdio::inherit()))
+        .with_context(|| format!("Failed to run {}", label))?;
+    if !output.status.success() {
+        return Err(anyhow!("{} exited with {}", label, output.status));
+    }
+    let secret = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('\r')
+        .to_string();
+    if secret.is_empty() {
+        return Err(anyhow!("{} printed nothing", label));
+    }
+    Ok(secret)
+}
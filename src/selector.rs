@@ -0,0 +1,214 @@
+use crate::{
+    config::Config,
+    tailscale::{Platform, TailscaleNode},
+};
+
+/// A way of picking nodes on the command line
+#[derive(Clone, Debug)]
+pub enum Selector {
+    /// Hostnames matching a glob pattern (`*` and `?`), case-insensitively
+    Glob(String),
+    /// Members of a group defined in the config, written `group:<name>`
+    Group(String),
+    /// Nodes with an ACL tag, written `tag:<name>` like in Tailscale
+    Tag(String),
+}
+
+impl Selector {
+    /// Parse a selector as typed by the user
+    pub fn parse(text: &str) -> Self {
+        if let Some(group) = text.strip_prefix("group:") {
+            Selector::Group(group.to_string())
+        } else if text.starts_with("tag:") {
+            Selector::Tag(text.to_string())
+        } else {
+            Selector::Glob(text.to_string())
+        }
+    }
+
+    /// Whether the node is picked by this selector
+    pub fn matches(&self, node: &TailscaleNode, config: &Config) -> bool {
+        match self {
+            Selector::Glob(pattern) => glob_match(pattern, &node.name),
+            Selector::Group(group) => group_contains(config, group, node),
+            Selector::Tag(tag) => has_tag(node, tag),
+        }
+    }
+}
+
+/// Whether a node belongs to a group from the config. Members are hostnames,
+/// hostname globs like `web-*`, or tags like `tag:prod`.
+pub fn group_contains(config: &Config, group: &str, node: &TailscaleNode) -> bool {
+    config
+        .groups
+        .get(group)
+        .is_some_and(|members| members.iter().any(|member| member_matches(member, node)))
+}
+
+/// Whether the picker leaves a node out: this machine unless `ui.show_self` is
+/// on, and the nodes of `ui.hide`, whose entries are device types, or tags and
+/// hostname globs like group members
+pub fn is_hidden(config: &Config, node: &TailscaleNode) -> bool {
+    is_left_out_self(config, node)
+        || config.ui.hide.iter().any(|entry| match entry.to_lowercase().as_str() {
+            "mobile" => node.platform().is_mobile(),
+            "ios" => node.platform() == Platform::Ios,
+            "android" => node.platform() == Platform::Android,
+            "mullvad" => node.mullvad,
+            _ => member_matches(entry, node),
+        })
+}
+
+/// Whether the node is this machine and `ui.show_self` is off
+pub fn is_left_out_self(config: &Config, node: &TailscaleNode) -> bool {
+    node.is_self && !config.ui.show_self
+}
+
+/// Whether a node is a group member: a tag it has, or a glob its hostname matches
+pub fn member_matches(member: &str, node: &TailscaleNode) -> bool {
+    if member.starts_with("tag:") {
+        has_tag(node, member)
+    } else {
+        glob_match(member, &node.name)
+    }
+}
+
+/// Whether a node has an ACL tag like `tag:prod`, ignoring case
+fn has_tag(node: &TailscaleNode, tag: &str) -> bool {
+    node.tags.iter().any(|node_tag| node_tag.eq_ignore_ascii_case(tag))
+}
+
+/// Match `text` against a glob `pattern` supporting `*` and `?`, ignoring case
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    // Iterative matcher that backtracks to the most recent `*`
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, os: &str, tags: &[&str]) -> TailscaleNode {
+        TailscaleNode {
+            id: String::new(),
+            name: name.to_string(),
+            ip: "100.64.0.1".to_string(),
+            ipv6: String::new(),
+            dns_name: String::new(),
+            suggested_user: String::new(),
+            os: os.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            status: "idle".to_string(),
+            tailscale_ssh: false,
+            last_seen: None,
+            rx_bytes: 0,
+            tx_bytes: 0,
+            mullvad: false,
+            is_self: false,
+        }
+    }
+
+    #[test]
+    fn globs_match_whole_hostnames() {
+        let cases = [
+            ("web-*", "web-1", true),
+            ("web-*", "WEB-PROD", true),
+            ("web-*", "web", false),
+            ("web-*", "old-web-1", false),
+            ("*-db", "prod-db", true),
+            ("*-db", "prod-db-2", false),
+            ("web-?", "web-1", true),
+            ("web-?", "web-10", false),
+            ("*", "", true),
+            ("", "", true),
+            ("", "web", false),
+            ("w*b*1", "web-prod-1", true),
+            ("w*b*1", "web-prod-2", false),
+            ("**", "anything", true),
+            ("a*a*a", "aaa", true),
+            ("a*a*a", "aa", false),
+            ("café-?", "CAFÉ-1", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(glob_match(pattern, text), expected, "{} against {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn selectors_are_globs_groups_or_tags() {
+        let mut config = Config::default();
+        config.groups.insert("web".to_string(), vec!["web-*".to_string(), "tag:edge".to_string()]);
+        let web = node("web-1", "linux", &[]);
+        let edge = node("proxy", "linux", &["tag:Edge"]);
+        let db = node("db", "linux", &["tag:db"]);
+
+        let cases = [
+            ("web-*", [true, false, false]),
+            ("group:web", [true, true, false]),
+            ("group:nope", [false, false, false]),
+            ("tag:edge", [false, true, false]),
+            ("tag:d", [false, false, false]),
+        ];
+        for (selector, expected) in cases {
+            let selector = Selector::parse(selector);
+            let matched = [&web, &edge, &db].map(|node| selector.matches(node, &config));
+            assert_eq!(matched, expected, "{:?}", selector);
+        }
+    }
+
+    #[test]
+    fn hide_entries_are_device_types_tags_or_globs() {
+        let mut mullvad = node("se-sto-wg-001", "linux", &[]);
+        mullvad.mullvad = true;
+        let mut this_machine = node("laptop", "macOS", &[]);
+        this_machine.is_self = true;
+        let nodes = [
+            node("janes-iphone", "iOS", &[]),
+            node("pixel", "android", &[]),
+            mullvad,
+            node("kiosk-1", "linux", &["tag:kiosk"]),
+            node("printer-2", "linux", &[]),
+            node("web-1", "linux", &[]),
+            this_machine,
+        ];
+
+        let cases: [(&[&str], [bool; 7]); 7] = [
+            (&[], [false, false, false, false, false, false, true]),
+            (&["mobile"], [true, true, false, false, false, false, true]),
+            (&["iOS"], [true, false, false, false, false, false, true]),
+            (&["android"], [false, true, false, false, false, false, true]),
+            (&["Mullvad"], [false, false, true, false, false, false, true]),
+            (&["tag:kiosk", "printer-*"], [false, false, false, true, true, false, true]),
+            (&["web-?"], [false, false, false, false, false, true, true]),
+        ];
+        for (hide, expected) in cases {
+            let mut config = Config::default();
+            config.ui.hide = hide.iter().map(|entry| entry.to_string()).collect();
+            assert_eq!(nodes.each_ref().map(|node| is_hidden(&config, node)), expected, "{:?}", hide);
+        }
+
+        let mut config = Config::default();
+        config.ui.show_self = true;
+        assert!(!is_hidden(&config, &nodes[6]));
+    }
+}
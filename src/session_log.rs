@@ -0,0 +1,176 @@
+use crate::{
+    cli::Emit,
+    config::get_data_dir,
+    history::{unix_now, utc_parts},
+    model::{Listing, Session},
+    platform::find_on_path,
+    secure::create_private_dir,
+    subprocess,
+};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Extension of a recorded session
+const TYPESCRIPT_EXTENSION: &str = "typescript";
+
+/// Extension of a session manifest
+const MANIFEST_EXTENSION: &str = "manifest.json";
+
+/// Directory holding recorded sessions, one subdirectory per node
+pub fn logs_dir() -> Result<Option<PathBuf>> {
+    Ok(get_data_dir()?.map(|dir| dir.join("logs")))
+}
+
+/// Create the typescript path for a new session on a node, or `None` if
+/// recording isn't possible here (no state directory, or no `script`)
+pub fn new_typescript(node: &str) -> Result<Option<PathBuf>> {
+    let Some(logs_dir) = logs_dir()? else {
+        println!("Not recording this session: there is nowhere to keep logs");
+        return Ok(None);
+    };
+    if cfg!(windows) || find_on_path("script").is_none() {
+        println!("Not recording this session: the `script` utility is not available");
+        return Ok(None);
+    }
+
+    let path = new_session_file(&logs_dir, node, TYPESCRIPT_EXTENSION)?;
+    println!("Recording session to {}", path.display());
+    Ok(Some(path))
+}
+
+/// Create the manifest path for a new session on a node: next to its
+/// typescript if it is recorded, or `None` if there is nowhere to keep it
+pub fn new_manifest(node: &str, typescript: Option<&Path>) -> Result<Option<PathBuf>> {
+    if let Some(typescript) = typescript {
+        return Ok(Some(typescript.with_extension(MANIFEST_EXTENSION)));
+    }
+    let Some(logs_dir) = logs_dir()? else {
+        return Ok(None);
+    };
+    Ok(Some(new_session_file(&logs_dir, node, MANIFEST_EXTENSION)?))
+}
+
+/// A file for a session starting now, named by its start time in the node's directory
+fn new_session_file(logs_dir: &Path, node: &str, extension: &str) -> Result<PathBuf> {
+    let node_dir = logs_dir.join(node);
+    create_private_dir(&node_dir)?;
+    Ok(node_dir.join(format!("{}.{}", file_timestamp(unix_now()), extension)))
+}
+
+/// Wrap a command so its terminal session is recorded to `typescript`, with
+/// timing data next to it where `script` supports it
+pub fn recording_command(program: &Path, args: &[String], typescript: &Path) -> Command {
+    let mut command = Command::new("script");
+    if cfg!(target_os = "linux") {
+        // util-linux takes the command as a single shell string
+        let shell_command = std::iter::once(program.to_string_lossy().into_owned())
+            .chain(args.iter().cloned())
+            .map(|arg| shell_quote(&arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        command
+            .args(["--quiet", "--return", "--flush"])
+            .arg(format!("--log-timing={}", timing_path(typescript).display()))
+            .arg("--command")
+            .arg(shell_command)
+            .arg(typescript);
+    } else {
+        // BSD and macOS take the command as trailing arguments
+        command.arg("-q").arg(typescript).arg(program).args(args);
+    }
+    command
+}
+
+/// Print recorded sessions, newest last, optionally for one node only
+pub fn list_logs(node: Option<&str>, emit: Emit) -> Result<()> {
+    let logs = find_logs(node)?;
+    if emit == Emit::Json {
+        return Listing::new(logs.iter().map(|log| Session::from_typescript(log)).collect()).print();
+    }
+    if logs.is_empty() {
+        println!("No recorded sessions");
+    }
+    for log in logs {
+        println!("{}", log.display());
+    }
+    Ok(())
+}
+
+/// Print every line of recorded sessions containing `pattern`, prefixed with its file
+pub fn grep_logs(pattern: &str, node: Option<&str>) -> Result<()> {
+    for log in find_logs(node)? {
+        let contents = String::from_utf8_lossy(&fs::read(&log)?).into_owned();
+        for line in contents.lines().filter(|line| line.contains(pattern)) {
+            println!("{}: {}", log.display(), line.trim_end_matches('\r'));
+        }
+    }
+    Ok(())
+}
+
+/// Play a recorded session back, in real time when timing data and
+/// `scriptreplay` are available, otherwise by printing it
+pub fn replay_log(path: &Path) -> Result<()> {
+    if !path.is_file() {
+        return Err(anyhow!("No recorded session at {}", path.display()));
+    }
+
+    let timing = timing_path(path);
+    if timing.is_file() && find_on_path("scriptreplay").is_some() {
+        subprocess::status(Command::new("scriptreplay").arg("--log-timing").arg(&timing).arg(path))
+            .context("Failed to run scriptreplay")?;
+    } else {
+        let contents = fs::read(path)?;
+        std::io::Write::write_all(&mut std::io::stdout(), &contents)?;
+    }
+    Ok(())
+}
+
+/// Typescripts under the logs directory, sorted by path (node, then time)
+fn find_logs(node: Option<&str>) -> Result<Vec<PathBuf>> {
+    let Some(logs_dir) = logs_dir()? else {
+        return Ok(Vec::new());
+    };
+
+    let node_dirs: Vec<PathBuf> = match node {
+        Some(node) => vec![logs_dir.join(node)],
+        None => fs::read_dir(&logs_dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .collect(),
+    };
+
+    let mut logs: Vec<PathBuf> = node_dirs
+        .iter()
+        .flat_map(|dir| fs::read_dir(dir).into_iter().flatten().flatten())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == TYPESCRIPT_EXTENSION))
+        .collect();
+    logs.sort();
+    Ok(logs)
+}
+
+/// Timing data recorded alongside a typescript
+pub fn timing_path(typescript: &Path) -> PathBuf {
+    typescript.with_extension("timing")
+}
+
+/// Quote an argument for a POSIX shell
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=@:,%+".contains(c)) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// A UTC timestamp that sorts correctly as a file name, like `2024-05-01T13-45-00Z`
+fn file_timestamp(unix_seconds: u64) -> String {
+    let (year, month, day, hour, minute, second) = utc_parts(unix_seconds);
+    format!("{:04}-{:02}-{:02}T{:02}-{:02}-{:02}Z", year, month, day, hour, minute, second)
+}
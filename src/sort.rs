@@ -0,0 +1,121 @@
+use crate::{
+    config::{Config, SortField, SortKey},
+    tailscale::TailscaleNode,
+};
+use anyhow::{anyhow, Result};
+use std::{cmp::Ordering, net::IpAddr};
+
+/// A node's value for one sort field
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Value {
+    Number(u64),
+    Address(IpAddr),
+    Text(String),
+}
+
+/// A value with its key's direction. Nodes without a value come last either way.
+#[derive(Debug, PartialEq, Eq)]
+struct Directed {
+    value: Option<Value>,
+    descending: bool,
+}
+
+impl Ord for Directed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (&self.value, &other.value) {
+            (Some(value), Some(other_value)) if self.descending => other_value.cmp(value),
+            (Some(value), Some(other_value)) => value.cmp(other_value),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+}
+
+impl PartialOrd for Directed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The named sort `ui.sort` picks, if any
+pub fn active_sort(config: &Config) -> Option<&[SortKey]> {
+    let name = config.ui.sort.as_ref()?;
+    config.sorts.get(name).map(Vec::as_slice)
+}
+
+/// Fail if `ui.sort` names a sort that isn't in `sorts`
+pub fn check_sort(config: &Config) -> Result<()> {
+    match &config.ui.sort {
+        Some(name) if !config.sorts.contains_key(name) => {
+            Err(anyhow!("`ui.sort` is '{}', but there is no sort by that name in `sorts`", name))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Order positions into `nodes` by each key in turn. Nodes that tie on every
+/// key keep their order, which is Tailscale's.
+pub fn sort(config: &Config, keys: &[SortKey], nodes: &[TailscaleNode], positions: &mut [usize]) {
+    if keys.is_empty() {
+        return;
+    }
+    positions.sort_by_cached_key(|&position| {
+        keys.iter()
+            .map(|key| Directed {
+                value: value(config, key.field, &nodes[position]),
+                descending: key.descending,
+            })
+            .collect::<Vec<_>>()
+    });
+}
+
+/// A node's value for a field, `None` when it has none
+fn value(config: &Config, field: SortField, node: &TailscaleNode) -> Option<Value> {
+    let text = |text: &str| (!text.is_empty()).then(|| Value::Text(text.to_lowercase()));
+    match field {
+        SortField::Name => text(&node.name),
+        SortField::Alias => text(config.alias(&node.name).unwrap_or(&node.name)),
+        SortField::Status => Some(Value::Number(status_rank(node))),
+        SortField::Tag => node.tags.iter().min().and_then(|tag| text(tag)),
+        SortField::Os => text(&node.os),
+        SortField::Owner => text(&node.suggested_user),
+        SortField::User => text(&config.resolve_username(node).0),
+        SortField::Ip => match node.ip.parse() {
+            Ok(address) => Some(Value::Address(address)),
+            Err(_) => text(&node.ip),
+        },
+        SortField::Seen if node.is_online() => Some(Value::Number(u64::MAX)),
+        SortField::Seen => node.last_seen.map(Value::Number),
+        SortField::Traffic => Some(Value::Number(node.rx_bytes + node.tx_bytes)),
+    }
+}
+
+/// Direct connections first, then relayed ones, then idle and offline nodes.
+/// `tailscale status` has no latencies, but a relay is the usual slow path.
+fn status_rank(node: &TailscaleNode) -> u64 {
+    if node.status.starts_with("active") && node.status.contains("direct") {
+        0
+    } else if node.status.starts_with("active") {
+        1
+    } else if node.is_online() {
+        2
+    } else {
+        3
+    }
+}
+
+/// How a sort reads in the picker, like `tag, status, name desc`
+pub fn describe(keys: &[SortKey]) -> String {
+    let keys: Vec<String> = keys
+        .iter()
+        .map(|key| {
+            if key.descending {
+                format!("{} desc", key.field.name())
+            } else {
+                key.field.name().to_string()
+            }
+        })
+        .collect();
+    if keys.is_empty() { "no keys".to_string() } else { keys.join(", ") }
+}
@@ -0,0 +1,826 @@
+use crate::{
+    address::{self, Destination},
+    config::{Config, RemoteShell, SessionLimit, SshClient, Transport, X11Forwarding},
+    humanize, identities, platform, remote_shell, session_log,
+    subprocess::{self, Subprocess},
+    tailscale::TailscaleNode,
+    timeline,
+};
+use anyhow::{Context, Result};
+use std::{
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How much of ssh's own error output is kept for diagnosing a failed session
+const STDERR_TAIL_BYTES: usize = 8 * 1024;
+
+/// How often a time-limited session checks whether its time is up
+const LIMIT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Minutes of warning before a session limit ends a session, unless configured
+const DEFAULT_WARN_MINUTES: u64 = 5;
+
+/// What OpenSSH logs at `LogLevel=VERBOSE` once the handshake and login are done
+const AUTHENTICATED_MESSAGE: &str = "Authenticated to ";
+
+/// Fragments of ssh error messages that mean the node could not be reached at all
+const CONNECTION_FAILURE_MESSAGES: &[&str] = &[
+    "Connection refused",
+    "Connection timed out",
+    "Operation timed out",
+    "No route to host",
+    "Network is unreachable",
+    "Connection reset by peer",
+    "Connection closed by remote host",
+    "kex_exchange_identification",
+    "Could not resolve hostname",
+];
+
+/// Parts of the messages ssh and plink exit with when an established connection
+/// is lost. Leaving with the `~.` escape only says "Connection to ... closed.".
+const DROPPED_MESSAGES: &[&str] = &[
+    "Broken pipe",
+    "closed by remote host",
+    "not responding",
+    "client_loop: send disconnect",
+    "Remote side unexpectedly closed network connection",
+];
+
+/// Prefix plink puts on errors about reaching the host, as opposed to authentication
+const PLINK_NETWORK_ERROR: &str = "FATAL ERROR: Network error";
+
+/// An ssh option from the config, with the config layer that set it
+pub struct SourcedOption {
+    /// Option name as understood by `ssh -o`
+    pub key: &'static str,
+    /// Option value
+    pub value: String,
+    /// Where in the config the option came from
+    pub source: &'static str,
+}
+
+/// What a session forwards to the node, and whether it always gets a terminal
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Forwarding {
+    /// `-A`
+    pub agent: bool,
+    /// `-X` or `-Y`
+    pub x11: X11Forwarding,
+    /// `-t`, which matters for remote commands; interactive sessions get a terminal anyway
+    pub tty: bool,
+}
+
+/// Forwarding turned on or off for this run, by command line flags or keys in
+/// the picker, over what the config says
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ForwardingToggles {
+    pub agent: Option<bool>,
+    pub x11: Option<X11Forwarding>,
+    pub tty: Option<bool>,
+}
+
+impl ForwardingToggles {
+    /// The forwarding with these toggles applied
+    pub fn apply(&self, forwarding: Forwarding) -> Forwarding {
+        Forwarding {
+            agent: self.agent.unwrap_or(forwarding.agent),
+            x11: self.x11.unwrap_or(forwarding.x11),
+            tty: self.tty.unwrap_or(forwarding.tty),
+        }
+    }
+}
+
+/// The forwarding the config asks for on a node: its own settings, else the `ssh` section's
+pub fn forwarding_for(config: &Config, node: &TailscaleNode) -> Forwarding {
+    let settings = config.node_settings(&node.name);
+    Forwarding {
+        agent: settings.forward_agent.unwrap_or(config.ssh.forward_agent),
+        x11: settings.forward_x11.unwrap_or(config.ssh.forward_x11),
+        tty: settings.force_tty.unwrap_or(config.ssh.force_tty),
+    }
+}
+
+/// The tmux session `--tmux` attaches to when the config names none
+pub const DEFAULT_TMUX_SESSION: &str = "main";
+
+/// The tmux session an interactive session to the node attaches to, if any:
+/// the node's `tmux_session`, else the `ssh` section's, where an empty name
+/// means none. `wanted` is `--tmux` or `--no-tmux`, which win over the config.
+pub fn tmux_session_for(config: &Config, node: &TailscaleNode, wanted: Option<bool>) -> Option<String> {
+    let configured = config
+        .node_settings(&node.name)
+        .tmux_session
+        .or_else(|| config.ssh.tmux_session.clone())
+        .filter(|session| !session.is_empty());
+    match wanted {
+        Some(false) => None,
+        Some(true) => Some(configured.unwrap_or_else(|| DEFAULT_TMUX_SESSION.to_string())),
+        None => configured,
+    }
+}
+
+/// The strictest of the session limits set for the node itself and for its
+/// tags, or `None` if nothing limits its sessions
+pub fn session_limit_for(config: &Config, node: &TailscaleNode) -> Option<SessionLimit> {
+    let own = config.node_settings(&node.name).session_limit;
+    let tagged = config
+        .session_limits
+        .tags
+        .iter()
+        .filter(|(tag, _)| node.tags.iter().any(|node_tag| node_tag.eq_ignore_ascii_case(tag)))
+        .map(|(_, limit)| limit);
+    own.iter().chain(tagged).fold(None, |strictest: Option<SessionLimit>, limit| {
+        Some(strictest.map_or_else(|| limit.clone(), |strictest| strictest.strictest(limit)))
+    })
+}
+
+/// The first configured transport the node supports, falling back to the ssh client
+pub fn transport_for(config: &Config, node: &TailscaleNode) -> Transport {
+    config
+        .ssh
+        .transports
+        .iter()
+        .copied()
+        .find(|transport| *transport == Transport::Ssh || node.tailscale_ssh)
+        .unwrap_or_default()
+}
+
+/// The `-o` options the config adds for a node, in the order they are passed
+pub fn configured_options(config: &Config, node: &TailscaleNode) -> Vec<SourcedOption> {
+    let settings = &config.ssh;
+    let mut options = Vec::new();
+    // plink takes the port as `-P`, but `tailscale ssh` always uses port 22
+    if let Some(port) = config.nodes.get(&node.name).and_then(|settings| settings.port)
+        && transport_for(config, node) != Transport::Tailscale
+    {
+        options.push(SourcedOption { key: "Port", value: port.to_string(), source: "config node port" });
+    }
+    // Neither plink nor `tailscale ssh` has an equivalent of `-o`, so none of these reach them
+    if settings.client == SshClient::Plink || transport_for(config, node) == Transport::Tailscale {
+        return options;
+    }
+
+    let limit = session_limit_for(config, node).unwrap_or_default();
+    let mut push_from = |key, value: String, source| options.push(SourcedOption { key, value, source });
+    match (limit.server_alive_interval, settings.server_alive_interval) {
+        (Some(interval), _) => push_from("ServerAliveInterval", interval.to_string(), "config session_limits"),
+        (None, Some(interval)) => push_from("ServerAliveInterval", interval.to_string(), "config ssh"),
+        (None, None) => {}
+    }
+    match (limit.server_alive_count_max, settings.server_alive_count_max) {
+        (Some(count), _) => push_from("ServerAliveCountMax", count.to_string(), "config session_limits"),
+        (None, Some(count)) => push_from("ServerAliveCountMax", count.to_string(), "config ssh"),
+        (None, None) => {}
+    }
+
+    let mut push = |key, value: String| options.push(SourcedOption { key, value, source: "config ssh" });
+    if settings.control_master {
+        push("ControlMaster", "auto".to_string());
+        push("ControlPath", settings.control_path.clone());
+        push("ControlPersist", settings.control_persist.clone());
+    }
+    if let Some(known_hosts) = &settings.known_hosts {
+        push("UserKnownHostsFile", known_hosts.clone());
+        // Hashed names couldn't be matched to nodes when their address moves
+        push("HashKnownHosts", "no".to_string());
+    }
+    if let Some((identity, _)) = identities::identity_for(config, node) {
+        // Without IdentitiesOnly, ssh still offers every agent key first and may hit MaxAuthTries
+        options.push(SourcedOption { key: "IdentityFile", value: identity, source: "config identity" });
+        options.push(SourcedOption {
+            key: "IdentitiesOnly",
+            value: "yes".to_string(),
+            source: "config identity",
+        });
+    }
+    if let Some(hops) = address::proxy_jump(config, node) {
+        options.push(SourcedOption { key: "ProxyJump", value: hops, source: "config node jump" });
+    }
+
+    let node_settings = config.node_settings(&node.name);
+    if !node_settings.send_env.is_empty() {
+        options.push(SourcedOption {
+            key: "SendEnv",
+            value: node_settings.send_env.join(" "),
+            source: "config node send_env",
+        });
+    }
+    if !node_settings.set_env.is_empty() {
+        // ssh only honours the first SetEnv, so all variables go into one
+        let value = node_settings
+            .set_env
+            .iter()
+            .map(|(name, value)| quote_set_env(name, value))
+            .collect::<Vec<_>>()
+            .join(" ");
+        options.push(SourcedOption { key: "SetEnv", value, source: "config node set_env" });
+    }
+
+    options
+}
+
+/// A command as one line for this machine's shell, after the variables it sets
+pub fn shell_line(command: &Command) -> String {
+    let shell = if cfg!(windows) { RemoteShell::Cmd } else { RemoteShell::Posix };
+    let mut parts: Vec<String> = command
+        .get_envs()
+        .filter_map(|(name, value)| Some((name.to_string_lossy(), value?.to_string_lossy())))
+        .map(|(name, value)| match shell {
+            RemoteShell::Cmd => format!("set \"{}={}\" &&", name, value),
+            _ => format!("{}={}", name, remote_shell::quote(shell, &value)),
+        })
+        .collect();
+    parts.extend(
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| remote_shell::quote(shell, &arg.to_string_lossy())),
+    );
+    parts.join(" ")
+}
+
+/// One `NAME=value` of a `SetEnv` list, in double quotes if the value has spaces or quotes
+fn quote_set_env(name: &str, value: &str) -> String {
+    if value.is_empty() || value.contains([' ', '\t', '"']) {
+        format!("\"{}={}\"", name, value.replace('"', "\\\""))
+    } else {
+        format!("{}={}", name, value)
+    }
+}
+
+/// How an interactive ssh session ended
+pub struct SessionResult {
+    /// Exit status of the ssh process
+    pub status: ExitStatus,
+    /// The last part of ssh's own error output
+    pub stderr_tail: String,
+}
+
+impl SessionResult {
+    /// Whether ssh failed to reach the node, as opposed to the session itself failing
+    pub fn is_connection_failure(&self) -> bool {
+        // ssh reserves 255 for its own errors; remote commands can't produce it on their own
+        let ssh_failure = self.status.code() == Some(255)
+            && CONNECTION_FAILURE_MESSAGES
+                .iter()
+                .any(|message| self.stderr_tail.contains(message));
+        ssh_failure || self.stderr_tail.contains(PLINK_NETWORK_ERROR)
+    }
+
+    /// Whether the network took down a session that had got through, rather
+    /// than the user or the remote shell ending it
+    pub fn is_dropped(&self) -> bool {
+        self.status.code() == Some(255)
+            && !self.is_connection_failure()
+            && DROPPED_MESSAGES.iter().any(|message| self.stderr_tail.contains(message))
+    }
+}
+
+/// Builder for an `ssh` invocation against a Tailscale node
+#[derive(Clone, Debug)]
+pub struct SshCommand {
+    /// Client program the invocation is built for
+    client: SshClient,
+    /// Whether to go through `tailscale ssh` instead of the client
+    transport: Transport,
+    /// Configured location of the tailscale CLI, for the Tailscale transport
+    tailscale_path: Option<String>,
+    /// Login user on the remote node
+    user: String,
+    /// Address of the remote node
+    host: String,
+    /// Why the node's IP is used instead of its DNS name, if it had to be
+    fallback_reason: Option<String>,
+    /// `-o Key=Value` options passed before the destination
+    options: Vec<(String, String)>,
+    /// Shell on the node that runs the remote command
+    remote_shell: RemoteShell,
+    /// Command to run remotely instead of an interactive shell
+    remote_command: Vec<String>,
+    /// Where to record the terminal session, if it is being recorded
+    typescript: Option<PathBuf>,
+    /// Variables set for the ssh process itself
+    env: Vec<(String, String)>,
+    /// Longest an interactive session may last, and how long before that to warn
+    time_limit: Option<(Duration, Duration)>,
+    /// Forward a local port to a port on the node, without a shell
+    local_forward: Option<(u16, u16)>,
+    /// Agent and X11 forwarding and terminal allocation
+    forwarding: Forwarding,
+    /// tmux session an interactive session attaches to instead of a plain shell
+    tmux_session: Option<String>,
+    /// Command an interactive session runs before the login shell
+    startup_command: Option<String>,
+}
+
+/// Which way a file is copied
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CopyDirection {
+    /// From this machine to the node
+    Upload,
+    /// From the node to this machine
+    Download,
+}
+
+impl SshCommand {
+    /// Start building an ssh invocation for `user@host`
+    pub fn new(user: &str, host: &str) -> Self {
+        Self {
+            client: SshClient::OpenSsh,
+            transport: Transport::Ssh,
+            tailscale_path: None,
+            user: user.to_string(),
+            host: host.to_string(),
+            fallback_reason: None,
+            options: Vec::new(),
+            remote_shell: RemoteShell::Posix,
+            remote_command: Vec::new(),
+            typescript: None,
+            env: Vec::new(),
+            time_limit: None,
+            local_forward: None,
+            forwarding: Forwarding::default(),
+            tmux_session: None,
+            startup_command: None,
+        }
+    }
+
+    /// Start building an ssh invocation for a node, with the configured options applied
+    pub fn for_node(config: &Config, user: &str, node: &TailscaleNode) -> Self {
+        let ssh = Self {
+            client: config.ssh.client,
+            transport: transport_for(config, node),
+            tailscale_path: config.tailscale_path.clone(),
+            remote_shell: remote_shell::shell_for(config, node),
+            env: config
+                .node_settings(&node.name)
+                .env
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            time_limit: session_limit_for(config, node).and_then(|limit| {
+                let max = Duration::from_secs(limit.max_session_minutes? * 60);
+                let warn = Duration::from_secs(limit.warn_minutes.unwrap_or(DEFAULT_WARN_MINUTES) * 60);
+                Some((max, warn.min(max)))
+            }),
+            forwarding: forwarding_for(config, node),
+            ..Self::new(user, "")
+        }
+        .at_destination(&address::destination(config, node));
+        configured_options(config, node)
+            .into_iter()
+            .fold(ssh, |ssh, option| ssh.option(option.key, option.value))
+    }
+
+    /// Connect to another of the node's addresses
+    pub fn at_destination(mut self, destination: &Destination) -> Self {
+        self.host = destination.host.clone();
+        self.fallback_reason = destination.fallback_reason.clone();
+        self
+    }
+
+    /// Add a `-o Key=Value` option
+    pub fn option(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.options.push((key.to_string(), value.into()));
+        self
+    }
+
+    /// Turn forwarding on or off for this session, over what the config says
+    pub fn toggled(mut self, toggles: &ForwardingToggles) -> Self {
+        self.forwarding = toggles.apply(self.forwarding);
+        self
+    }
+
+    /// Attach to a tmux session on the node, creating it if needed, instead of
+    /// opening a plain shell, so reconnecting picks up where the session left off.
+    /// A remote command still runs as given, and Windows nodes and `tailscale ssh`
+    /// get a plain shell.
+    pub fn attach_tmux(mut self, session: Option<String>) -> Self {
+        self.tmux_session = session;
+        self
+    }
+
+    /// Run a command, like `cd /srv/app`, before the login shell of an
+    /// interactive session, which like tmux only works on POSIX nodes
+    pub fn startup(mut self, command: Option<String>) -> Self {
+        self.startup_command = command;
+        self
+    }
+
+    /// Connect to `port` instead of the configured one
+    pub fn on_port(mut self, port: u16) -> Self {
+        self.options.retain(|(key, _)| !key.eq_ignore_ascii_case("Port"));
+        self.option("Port", port.to_string())
+    }
+
+    /// Run the given command on the node instead of opening a shell. Several
+    /// arguments are quoted for the node's shell so each arrives intact; a
+    /// single one is a command line for the shell to interpret.
+    pub fn remote_command(mut self, command: Vec<String>) -> Self {
+        self.remote_command = if command.is_empty() {
+            command
+        } else {
+            vec![remote_shell::command_line(self.remote_shell, &command)]
+        };
+        self
+    }
+
+    /// Forward `local_port` on this machine to `remote_port` on the node's
+    /// localhost instead of opening a shell. `tailscale ssh` can't forward ports.
+    pub fn local_forward(mut self, local_port: u16, remote_port: u16) -> Self {
+        self.local_forward = Some((local_port, remote_port));
+        self
+    }
+
+    /// The `-N -L` arguments for the forward, which ssh and plink spell the same
+    fn forward_args(&self) -> Vec<String> {
+        match self.local_forward {
+            Some((local_port, remote_port)) => vec![
+                "-N".to_string(),
+                "-L".to_string(),
+                format!("{}:localhost:{}", local_port, remote_port),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    /// Record the terminal session to a typescript file
+    pub fn record_to(mut self, typescript: PathBuf) -> Self {
+        self.typescript = Some(typescript);
+        self
+    }
+
+    /// The typescript the session is recorded to, if any
+    pub fn typescript(&self) -> Option<&Path> {
+        self.typescript.as_deref()
+    }
+
+    /// The login user on the remote node
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    /// The host name or address connected to
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The port connected to: the `Port` option, or ssh's default of 22
+    pub fn port(&self) -> u16 {
+        self.options
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("Port"))
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(22)
+    }
+
+    /// Whether it runs a command rather than an interactive shell
+    pub fn has_remote_command(&self) -> bool {
+        !self.remote_command.is_empty()
+    }
+
+    /// The node's session limit and how long before it to warn, for sessions
+    /// that aren't run by `run_session` and so have to enforce it themselves
+    pub fn time_limit(&self) -> Option<(Duration, Duration)> {
+        self.time_limit
+    }
+
+    /// Why the node's IP is used although its DNS name was asked for
+    pub fn fallback_reason(&self) -> Option<&str> {
+        self.fallback_reason.as_deref()
+    }
+
+    /// The `user@host` destination argument
+    pub fn destination(&self) -> String {
+        format!("{}@{}", self.user, self.host)
+    }
+
+    /// The `user@host` part of an scp or rsync remote path; IPv6 addresses need
+    /// brackets so their colons aren't taken for the path separator
+    pub fn copy_destination(&self) -> String {
+        if self.host.contains(':') {
+            format!("{}@[{}]", self.user, self.host)
+        } else {
+            self.destination()
+        }
+    }
+
+    /// An `scp` copying a local file to `remote_path` on the node, with the same
+    /// `-o` options as ssh, or `pscp` for plink. An empty `remote_path` means the
+    /// home directory. `tailscale ssh` can't copy files, so scp is used for it too.
+    pub fn scp_command(&self, local: &Path, remote_path: &str) -> Command {
+        self.copy_command(CopyDirection::Upload, local, remote_path, false)
+    }
+
+    /// Like `scp_command`, copying either way, and whole directories with `recursive`
+    pub fn copy_command(&self, direction: CopyDirection, local: &Path, remote_path: &str, recursive: bool) -> Command {
+        let mut command = if self.client == SshClient::Plink {
+            let mut command = Command::new(platform::pscp_program());
+            if self.options.iter().any(|(key, value)| key == "BatchMode" && value == "yes") {
+                command.arg("-batch");
+            }
+            if self.port() != 22 {
+                command.arg("-P").arg(self.port().to_string());
+            }
+            command
+        } else {
+            let mut command = Command::new(platform::scp_program());
+            for (key, value) in &self.options {
+                command.arg("-o").arg(format!("{}={}", key, value));
+            }
+            command
+        };
+        if recursive {
+            command.arg("-r");
+        }
+        if self.client != SshClient::Plink {
+            command.arg("--");
+        }
+        let remote = format!("{}:{}", self.copy_destination(), remote_path);
+        match direction {
+            CopyDirection::Upload => command.arg(local).arg(remote),
+            CopyDirection::Download => command.arg(remote).arg(local),
+        };
+        command.envs(self.env.iter().map(|(name, value)| (name, value)));
+        command
+    }
+
+    /// All arguments passed to the ssh binary, in order
+    pub fn args(&self) -> Vec<String> {
+        if self.transport == Transport::Tailscale {
+            return self.tailscale_args();
+        }
+        if self.client == SshClient::Plink {
+            return self.plink_args();
+        }
+
+        let mut args = Vec::new();
+        for (key, value) in &self.options {
+            args.push("-o".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+        args.extend(self.forwarding_args());
+        args.extend(self.forward_args());
+        args.push(self.destination());
+        let remote_command = self.session_command();
+        if !remote_command.is_empty() {
+            // Stop ssh from interpreting anything in the remote command as its own flags
+            args.push("--".to_string());
+            args.extend(remote_command);
+        }
+        args
+    }
+
+    /// Arguments for plink, which takes the user separately and has no `-o`.
+    /// `BatchMode` maps to `-batch` and `Port` to `-P`; other options are dropped.
+    fn plink_args(&self) -> Vec<String> {
+        let mut args = self.plink_options();
+        args.extend(self.forwarding_args());
+        args.extend(self.forward_args());
+        args.extend(["-l".to_string(), self.user.clone(), self.host.clone()]);
+        // plink treats everything after the host as the remote command
+        args.extend(self.session_command());
+        args
+    }
+
+    /// The options plink understands, in its own flags
+    fn plink_options(&self) -> Vec<String> {
+        let mut args = vec!["-ssh".to_string()];
+        if self.options.iter().any(|(key, value)| key == "BatchMode" && value == "yes") {
+            args.push("-batch".to_string());
+        }
+        if self.port() != 22 {
+            args.extend(["-P".to_string(), self.port().to_string()]);
+        }
+        args
+    }
+
+    /// What runs on the node: the remote command, or else whatever starts the
+    /// interactive shell: attaching to the tmux session, running the startup
+    /// command first, or both, with the startup command in a new tmux session
+    fn session_command(&self) -> Vec<String> {
+        if !self.starts_shell_itself() {
+            return self.remote_command.clone();
+        }
+        let shell = self
+            .startup_command
+            .as_ref()
+            .map(|command| format!("{}; exec \"${{SHELL:-/bin/sh}}\" -l", command));
+        match (&self.tmux_session, shell) {
+            (Some(session), shell) => {
+                let mut tmux = ["tmux", "new", "-A", "-s", session].map(String::from).to_vec();
+                tmux.extend(shell);
+                vec![remote_shell::command_line(self.remote_shell, &tmux)]
+            }
+            (None, Some(shell)) => vec![shell],
+            (None, None) => Vec::new(),
+        }
+    }
+
+    /// Whether the session starts its shell with a command of its own, for tmux
+    /// or a startup command: an interactive shell on a POSIX node through the
+    /// ssh client, which is given a terminal for it
+    fn starts_shell_itself(&self) -> bool {
+        (self.tmux_session.is_some() || self.startup_command.is_some())
+            && self.remote_command.is_empty()
+            && self.remote_shell == RemoteShell::Posix
+            && self.local_forward.is_none()
+            && self.transport == Transport::Ssh
+    }
+
+    /// `-A`, `-X` or `-Y`, and `-t` as asked for. plink has no `-Y`, so it gets `-X` for both.
+    /// A port forward runs no session, so it gets none of them.
+    fn forwarding_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.local_forward.is_some() {
+            return args;
+        }
+        if self.forwarding.agent {
+            args.push("-A".to_string());
+        }
+        match self.forwarding.x11 {
+            X11Forwarding::Off => {}
+            X11Forwarding::Trusted if self.client != SshClient::Plink => args.push("-Y".to_string()),
+            X11Forwarding::Untrusted | X11Forwarding::Trusted => args.push("-X".to_string()),
+        }
+        if self.forwarding.tty || self.starts_shell_itself() {
+            args.push("-t".to_string());
+        }
+        args
+    }
+
+    /// Arguments for `tailscale ssh`, which only takes the destination and command
+    fn tailscale_args(&self) -> Vec<String> {
+        let mut args = vec!["ssh".to_string(), self.destination()];
+        args.extend(self.remote_command.iter().cloned());
+        args
+    }
+
+    /// The ssh program and options as one string, for tools like rsync's `-e`:
+    /// the same client `command` runs, which for plink means its own flags
+    pub fn transport_command(&self) -> String {
+        let (program, options) = match self.client {
+            SshClient::OpenSsh => (
+                platform::ssh_program(),
+                self.options
+                    .iter()
+                    .flat_map(|(key, value)| ["-o".to_string(), format!("{}={}", key, value)])
+                    .collect(),
+            ),
+            SshClient::Plink => (platform::plink_program(), self.plink_options()),
+        };
+        std::iter::once(program.to_string_lossy().into_owned())
+            .chain(options)
+            .map(|part| quote_transport_arg(&part))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// The command as one line for this machine's shell, to run by hand or
+    /// paste into other tools
+    pub fn shell_line(&self) -> String {
+        shell_line(&self.command())
+    }
+
+    /// Build the process command without running it
+    pub fn command(&self) -> Command {
+        let program = match (self.transport, self.client) {
+            // A missing CLI shows up as the usual spawn error
+            (Transport::Tailscale, _) => platform::tailscale_program(self.tailscale_path.as_deref())
+                .unwrap_or_else(|_| "tailscale".into()),
+            (Transport::Ssh, SshClient::OpenSsh) => platform::ssh_program(),
+            (Transport::Ssh, SshClient::Plink) => platform::plink_program(),
+        };
+        let mut command = match &self.typescript {
+            Some(typescript) => session_log::recording_command(&program, &self.args(), typescript),
+            None => {
+                let mut command = Command::new(program);
+                command.args(self.args());
+                command
+            }
+        };
+        command.envs(self.env.iter().map(|(name, value)| (name, value)));
+        command
+    }
+
+    /// Run ssh attached to the current terminal and wait for it to exit
+    pub fn run_interactive(&self) -> Result<std::process::ExitStatus> {
+        subprocess::status(
+            self.command()
+                .stdin(Stdio::inherit())
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit()),
+        )
+        .context("Failed to execute SSH command")
+    }
+
+    /// Run an interactive session, passing ssh's error output through while keeping
+    /// its tail so connection failures can be told apart from remote failures.
+    /// With a TTY allocated the remote side's stderr arrives on stdout, so only
+    /// ssh's own diagnostics go through the pipe.
+    pub fn run_session(&self) -> Result<SessionResult> {
+        let spawned = Instant::now();
+        let mut command = self.command();
+        command.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::piped());
+        let subprocess = Subprocess::start(&command);
+        let mut child = command
+            .spawn()
+            .inspect_err(|err| subprocess.finish(Err(err)))
+            .context("Failed to execute SSH command")?;
+
+        let mut child_stderr = child.stderr.take().context("Failed to capture SSH error output")?;
+        let forwarder = thread::spawn(move || {
+            let mut tail = Vec::new();
+            let mut authenticated = None;
+            let mut buffer = [0u8; 4096];
+            while let Ok(read) = child_stderr.read(&mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                let _ = io::stderr().write_all(&buffer[..read]);
+                tail.extend_from_slice(&buffer[..read]);
+                if tail.len() > STDERR_TAIL_BYTES {
+                    tail.drain(..tail.len() - STDERR_TAIL_BYTES);
+                }
+                if authenticated.is_none() && String::from_utf8_lossy(&tail).contains(AUTHENTICATED_MESSAGE) {
+                    authenticated = Some(Instant::now());
+                }
+            }
+            (String::from_utf8_lossy(&tail).into_owned(), authenticated)
+        });
+
+        let status = match self.time_limit {
+            Some((max, warn)) => wait_with_limit(&mut child, max, warn)?,
+            None => child.wait().context("Failed to wait for SSH command")?,
+        };
+        subprocess.finish(Ok(&status));
+        let (stderr_tail, authenticated) = forwarder.join().unwrap_or_default();
+        let result = SessionResult { status, stderr_tail };
+
+        // Only OpenSSH at a verbose log level says when the handshake is done
+        match authenticated {
+            Some(authenticated) => {
+                timeline::step_with_note("ssh handshake", authenticated - spawned, Some(self.host.clone()));
+                timeline::step("session", authenticated);
+            }
+            None if result.is_connection_failure() => {
+                timeline::step_with_note("ssh, could not connect", spawned.elapsed(), Some(self.host.clone()));
+            }
+            None => timeline::step_with_note("ssh", spawned.elapsed(), Some(self.host.clone())),
+        }
+
+        Ok(result)
+    }
+}
+
+/// Wait for ssh, warning `warn` before `max` has passed and ending the session
+/// at `max`. The terminal is in raw mode, so messages need explicit `\r\n`.
+fn wait_with_limit(child: &mut Child, max: Duration, warn: Duration) -> Result<ExitStatus> {
+    let started = Instant::now();
+    let mut warned = false;
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait for SSH command")? {
+            return Ok(status);
+        }
+        let elapsed = started.elapsed();
+        if elapsed >= max {
+            eprint!("\r\n{}\r\n", limit_reached_message(max));
+            let _ = child.kill();
+            return child.wait().context("Failed to wait for SSH command");
+        }
+        if !warned && elapsed + warn >= max {
+            warned = true;
+            eprint!("\r\n{}\r\n", limit_warning_message(max, elapsed));
+        }
+        thread::sleep(LIMIT_POLL_INTERVAL);
+    }
+}
+
+/// What a session is told when the node's session limit ends it
+pub fn limit_reached_message(max: Duration) -> String {
+    format!("[ssh-tailscale] This node's {} session limit is reached; disconnecting.", humanize::duration(max))
+}
+
+/// What a session is told `elapsed` into it, once the node's session limit is near
+pub fn limit_warning_message(max: Duration, elapsed: Duration) -> String {
+    format!(
+        "[ssh-tailscale] This session will be disconnected in {}, at the node's {} session limit.",
+        humanize::duration(Duration::from_secs(max.saturating_sub(elapsed).as_secs_f64().ceil() as u64)),
+        humanize::duration(max)
+    )
+}
+
+/// Quote an argument for a transport string. rsync splits these on spaces and
+/// understands single and double quotes, but not backslash escapes.
+fn quote_transport_arg(arg: &str) -> String {
+    if !arg.contains(char::is_whitespace) && !arg.contains(['\'', '"']) {
+        arg.to_string()
+    } else if !arg.contains('\'') {
+        format!("'{}'", arg)
+    } else {
+        format!("\"{}\"", arg)
+    }
+}
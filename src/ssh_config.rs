@@ -0,0 +1,35 @@
+use crate::{
+    address,
+    config::Config,
+    secrets,
+    ssh::configured_options,
+    tailscale::TailscaleNode,
+};
+
+/// `Host` blocks for every node that can be connected to, with the address,
+/// user and options ssh-tailscale would use, so plain `ssh web-1`, scp, rsync
+/// and editors reach the nodes the same way. Aliases are added as host names.
+pub fn render(config: &Config, nodes: &[TailscaleNode]) -> String {
+    let mut out = String::from(
+        "# Tailscale nodes, written by `ssh-tailscale export-ssh-config`.\n\
+         # Addresses change when nodes are re-added, so write this again rather than editing it.\n",
+    );
+    for node in nodes.iter().filter(|node| !node.platform().is_mobile()) {
+        let mut names = vec![node.name.as_str()];
+        if let Some(alias) = config.alias(&node.name)
+            && !alias.contains(char::is_whitespace)
+        {
+            names.push(alias);
+        }
+        out.push_str(&format!("\nHost {}\n", names.join(" ")));
+        out.push_str(&format!("    HostName {}\n", address::destination(config, node).host));
+        // A username from a secret manager is fetched when connecting and never written down
+        if secrets::username_group(config, node).is_none() {
+            out.push_str(&format!("    User {}\n", config.resolve_username(node).0));
+        }
+        for option in configured_options(config, node) {
+            out.push_str(&format!("    {} {}\n", option.key, option.value));
+        }
+    }
+    out
+}
@@ -0,0 +1,189 @@
+use anyhow::{anyhow, Result};
+
+/// The parts of an `ssh://[user@]host[:port]` URI, as terminal emulators and
+/// launchers hand them over
+#[derive(Debug)]
+pub struct SshUri {
+    pub user: Option<String>,
+    /// Hostname, alias, MagicDNS name or IP of the node
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl SshUri {
+    /// Parse an `ssh://` URI, or `None` if the argument isn't one. Connection
+    /// parameters after `;` in the user part, like a host key fingerprint, are ignored.
+    pub fn parse(arg: &str) -> Result<Option<Self>> {
+        if !arg.get(..6).is_some_and(|scheme| scheme.eq_ignore_ascii_case("ssh://")) {
+            return Ok(None);
+        }
+        let rest = &arg[6..];
+        let invalid = |why: &str| anyhow!("Invalid ssh URI '{}': {}", arg, why);
+
+        // Only a shell can be opened, so there is no path beyond a trailing slash
+        let authority = rest.strip_suffix('/').unwrap_or(rest);
+        if authority.contains(['/', '?', '#']) {
+            return Err(invalid("only ssh://[user@]host[:port] is supported"));
+        }
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => {
+                let user = user.split(';').next().unwrap_or_default();
+                (Some(decode(user).ok_or_else(|| invalid("bad %-escape in the user"))?), host_port)
+            }
+            None => (None, authority),
+        };
+
+        // An IPv6 address is written in brackets, so its colons aren't taken for the port
+        let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+            let (host, after) = bracketed.split_once(']').ok_or_else(|| invalid("missing ']'"))?;
+            match after {
+                "" => (host.to_string(), None),
+                _ => (host.to_string(), Some(after.strip_prefix(':').ok_or_else(|| invalid("junk after ']'"))?)),
+            }
+        } else {
+            match host_port.split_once(':') {
+                Some((host, port)) => (host.to_string(), Some(port)),
+                None => (host_port.to_string(), None),
+            }
+        };
+        let host = decode(&host).ok_or_else(|| invalid("bad %-escape in the host"))?;
+        if host.is_empty() {
+            return Err(invalid("no host"));
+        }
+        let port = match port {
+            None | Some("") => None,
+            Some(port) => Some(
+                port.parse()
+                    .ok()
+                    .filter(|&port: &u16| port != 0)
+                    .ok_or_else(|| invalid("the port isn't a number from 1 to 65535"))?,
+            ),
+        };
+        Ok(Some(SshUri {
+            user: user.filter(|user| !user.is_empty()),
+            host,
+            port,
+        }))
+    }
+}
+
+/// The `ssh://` URI for connecting to `host` as `user`, leaving out the default port
+pub fn format(user: Option<&str>, host: &str, port: u16) -> String {
+    let mut uri = String::from("ssh://");
+    if let Some(user) = user {
+        uri.push_str(&encode(user));
+        uri.push('@');
+    }
+    if host.contains(':') {
+        uri.push_str(&format!("[{}]", host));
+    } else {
+        uri.push_str(host);
+    }
+    if port != 22 {
+        uri.push_str(&format!(":{}", port));
+    }
+    uri
+}
+
+/// Escape the characters that would end or split the user part of a URI
+fn encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,=".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Undo `%XX` escapes, or `None` if one is malformed or the result isn't UTF-8
+fn decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(after.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &after[2..];
+        } else {
+            bytes.push(byte);
+            rest = after;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(arg: &str) -> SshUri {
+        SshUri::parse(arg).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parses_user_host_and_port() {
+        let uri = parsed("ssh://deploy@web-1:2222");
+        assert_eq!(uri.user.as_deref(), Some("deploy"));
+        assert_eq!(uri.host, "web-1");
+        assert_eq!(uri.port, Some(2222));
+
+        let uri = parsed("SSH://web-1/");
+        assert_eq!(uri.user, None);
+        assert_eq!(uri.host, "web-1");
+        assert_eq!(uri.port, None);
+    }
+
+    #[test]
+    fn parses_ipv6_escapes_and_parameters() {
+        let uri = parsed("ssh://[fd7a:115c:a1e0::1]:22");
+        assert_eq!(uri.host, "fd7a:115c:a1e0::1");
+        assert_eq!(uri.port, Some(22));
+        assert_eq!(parsed("ssh://[fd7a::1]").port, None);
+
+        let uri = parsed("ssh://jane%40corp;fingerprint=SHA256-abc@web-1:");
+        assert_eq!(uri.user.as_deref(), Some("jane@corp"));
+        assert_eq!(uri.port, None);
+        assert_eq!(parsed("ssh://@web-1").user, None);
+    }
+
+    #[test]
+    fn other_arguments_are_not_uris() {
+        for arg in ["web-1", "ssh:/web-1", "sftp://web-1", "ssh:", "ssé://web-1"] {
+            assert!(SshUri::parse(arg).unwrap().is_none(), "{}", arg);
+        }
+    }
+
+    #[test]
+    fn refuses_malformed_uris() {
+        for arg in [
+            "ssh://",
+            "ssh://user@",
+            "ssh://web-1/home",
+            "ssh://web-1?x=1",
+            "ssh://web-1:0",
+            "ssh://web-1:65536",
+            "ssh://web-1:ssh",
+            "ssh://[fd7a::1",
+            "ssh://[fd7a::1]22",
+            "ssh://bad%zz@web-1",
+            "ssh://web%2",
+        ] {
+            assert!(SshUri::parse(arg).is_err(), "{}", arg);
+        }
+    }
+
+    #[test]
+    fn formats_what_it_parses() {
+        assert_eq!(format(Some("deploy"), "web-1", 22), "ssh://deploy@web-1");
+        assert_eq!(format(None, "fd7a::1", 2222), "ssh://[fd7a::1]:2222");
+        for (user, host, port) in [(Some("jane@corp; x"), "web-1.tail.ts.net", 2200), (None, "fd7a:115c::1", 22)] {
+            let uri = parsed(&format(user, host, port));
+            assert_eq!(uri.user.as_deref(), user);
+            assert_eq!(uri.host, host);
+            assert_eq!(uri.port, Some(port).filter(|&port| port != 22));
+        }
+    }
+}
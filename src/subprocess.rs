@@ -0,0 +1,77 @@
+use std::{
+    io,
+    process::{Command, ExitStatus, Output},
+    time::Instant,
+};
+use tracing::{field, info_span, Span};
+
+/// One run of an external program, as a span recording its argv, how long it
+/// ran and how it exited. The span closes, and is written out, when this drops.
+pub struct Subprocess {
+    span: Span,
+    started: Instant,
+}
+
+impl Subprocess {
+    /// Open the span for a command about to start
+    pub fn start(command: &Command) -> Self {
+        let program = command.get_program().to_string_lossy();
+        let argv: Vec<String> = std::iter::once(program.to_string())
+            .chain(command.get_args().map(|arg| arg.to_string_lossy().into_owned()))
+            .collect();
+        let span = info_span!(
+            "subprocess",
+            program = %program,
+            argv = ?argv,
+            duration_ms = field::Empty,
+            exit = field::Empty,
+        );
+        // Long runs like sessions only close their span when they end
+        tracing::debug!(parent: &span, "started");
+        Subprocess {
+            span,
+            started: Instant::now(),
+        }
+    }
+
+    /// Record how the command ended, or why it couldn't be run
+    pub fn finish(&self, status: Result<&ExitStatus, &io::Error>) {
+        self.span.record("duration_ms", self.started.elapsed().as_millis() as u64);
+        match status {
+            Ok(status) => self.span.record("exit", field::display(status)),
+            Err(err) => self.span.record("exit", field::display(format!("failed to start: {}", err))),
+        };
+    }
+}
+
+/// `Command::output`, traced
+pub fn output(command: &mut Command) -> io::Result<Output> {
+    let subprocess = Subprocess::start(command);
+    let output = command.output();
+    subprocess.finish(output.as_ref().map(|output| &output.status));
+    output
+}
+
+/// `Command::status`, traced
+pub fn status(command: &mut Command) -> io::Result<ExitStatus> {
+    let subprocess = Subprocess::start(command);
+    let status = command.status();
+    subprocess.finish(status.as_ref());
+    status
+}
+
+/// `tokio::process::Command::output`, traced
+pub async fn output_async(command: &mut tokio::process::Command) -> io::Result<Output> {
+    let subprocess = Subprocess::start(command.as_std());
+    let output = command.output().await;
+    subprocess.finish(output.as_ref().map(|output| &output.status));
+    output
+}
+
+/// `tokio::process::Command::status`, traced
+pub async fn status_async(command: &mut tokio::process::Command) -> io::Result<ExitStatus> {
+    let subprocess = Subprocess::start(command.as_std());
+    let status = command.status().await;
+    subprocess.finish(status.as_ref());
+    status
+}
@@ -0,0 +1,48 @@
+use crate::{address, config::Config, preflight::port_open_at, tailscale::TailscaleNode};
+use futures::stream::{self, StreamExt};
+use std::collections::HashMap;
+
+/// Whether a node's ssh port answered the health sweep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortState {
+    /// Being probed
+    Checking,
+    Open,
+    /// Online by Tailscale's account, but nothing answered on the ssh port
+    Closed,
+}
+
+/// Online nodes whose ssh port hasn't been probed yet this run, marked as being
+/// probed, with the host and port ssh would connect to
+pub fn unprobed(
+    config: &Config,
+    nodes: &[TailscaleNode],
+    ports: &mut HashMap<String, PortState>,
+) -> Vec<(String, String, u16)> {
+    if !config.ssh.health_sweep {
+        return Vec::new();
+    }
+    let mut targets = Vec::new();
+    for node in nodes.iter().filter(|node| node.is_online() && !node.platform().is_mobile()) {
+        if !ports.contains_key(&node.name) {
+            ports.insert(node.name.clone(), PortState::Checking);
+            let port = config.node_settings(&node.name).port.unwrap_or(22);
+            targets.push((node.name.clone(), address::destination(config, node).host, port));
+        }
+    }
+    targets
+}
+
+/// Probe the ssh port of each `(node, host, port)`, at most `parallelism` at a
+/// time, and report each node's state as its probe finishes
+pub async fn sweep(targets: Vec<(String, String, u16)>, parallelism: usize, report: impl Fn(String, PortState)) {
+    let mut probes = stream::iter(targets)
+        .map(|(node, host, port)| async move {
+            let state = if port_open_at(&host, port).await { PortState::Open } else { PortState::Closed };
+            (node, state)
+        })
+        .buffer_unordered(parallelism.max(1));
+    while let Some((node, state)) = probes.next().await {
+        report(node, state);
+    }
+}
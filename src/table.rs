@@ -0,0 +1,203 @@
+use crate::{config::Config, humanize, tailscale::TailscaleNode};
+use anyhow::{anyhow, Result};
+use console::Style;
+use std::{
+    env,
+    io::{self, IsTerminal},
+};
+
+/// Whether `--list` output is colored
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorChoice {
+    /// When stdout is a terminal and `NO_COLOR` isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Parse `auto`, `always` or `never`
+    pub fn parse(text: &str) -> Result<Self> {
+        match text {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            other => Err(anyhow!("Unknown color choice '{}'; use auto, always or never", other)),
+        }
+    }
+
+    /// Whether to color output on stdout
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// A column `--list` can print
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListColumn {
+    Name,
+    Alias,
+    Ip,
+    Ipv6,
+    Dns,
+    User,
+    Owner,
+    Os,
+    Tags,
+    Status,
+    Seen,
+}
+
+/// What `--list` prints without `--columns`
+pub const DEFAULT_COLUMNS: &[ListColumn] = &[
+    ListColumn::Name,
+    ListColumn::Ip,
+    ListColumn::User,
+    ListColumn::Os,
+    ListColumn::Status,
+];
+
+/// Every column, in the order they are listed in help texts
+const ALL_COLUMNS: [ListColumn; 11] = [
+    ListColumn::Name,
+    ListColumn::Alias,
+    ListColumn::Ip,
+    ListColumn::Ipv6,
+    ListColumn::Dns,
+    ListColumn::User,
+    ListColumn::Owner,
+    ListColumn::Os,
+    ListColumn::Tags,
+    ListColumn::Status,
+    ListColumn::Seen,
+];
+
+impl ListColumn {
+    /// Name used in `--columns` and, uppercased, as the heading
+    pub fn name(self) -> &'static str {
+        match self {
+            ListColumn::Name => "name",
+            ListColumn::Alias => "alias",
+            ListColumn::Ip => "ip",
+            ListColumn::Ipv6 => "ipv6",
+            ListColumn::Dns => "dns",
+            ListColumn::User => "user",
+            ListColumn::Owner => "owner",
+            ListColumn::Os => "os",
+            ListColumn::Tags => "tags",
+            ListColumn::Status => "status",
+            ListColumn::Seen => "seen",
+        }
+    }
+
+    /// Parse a comma-separated list of column names, like `name,ip,status`
+    pub fn parse_list(text: &str) -> Result<Vec<Self>> {
+        text.split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                ALL_COLUMNS.into_iter().find(|column| column.name() == name).ok_or_else(|| {
+                    let known: Vec<&str> = ALL_COLUMNS.iter().map(|column| column.name()).collect();
+                    anyhow!("Unknown column '{}'; use {}", name, known.join(", "))
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .and_then(|columns| {
+                if columns.is_empty() {
+                    Err(anyhow!("--columns needs at least one column"))
+                } else {
+                    Ok(columns)
+                }
+            })
+    }
+
+    /// The column's text for a node; empty values are `-` so every row splits into the same fields
+    fn value(self, config: &Config, node: &TailscaleNode) -> String {
+        let value = match self {
+            ListColumn::Name => node.name.clone(),
+            ListColumn::Alias => config.alias(&node.name).unwrap_or_default().to_string(),
+            ListColumn::Ip => node.ip.clone(),
+            ListColumn::Ipv6 => node.ipv6.clone(),
+            ListColumn::Dns => node.dns_name.clone(),
+            ListColumn::User => config.resolve_username(node).0,
+            ListColumn::Owner => node.suggested_user.clone(),
+            ListColumn::Os => node.os.clone(),
+            ListColumn::Tags => node.tags.join(","),
+            ListColumn::Status => node.status.clone(),
+            ListColumn::Seen => match node.last_seen {
+                Some(last_seen) if !node.is_online() => humanize::timestamp(last_seen, config.ui.absolute_times),
+                _ => String::new(),
+            },
+        };
+        if value.is_empty() { "-".to_string() } else { value }
+    }
+}
+
+/// Print nodes as a table with aligned columns. Headings are only printed to a
+/// terminal, so scripts get one line per node.
+pub fn print_nodes(config: &Config, nodes: &[TailscaleNode], columns: &[ListColumn], color: ColorChoice) {
+    let color = color.enabled();
+    let headings = io::stdout()
+        .is_terminal()
+        .then(|| columns.iter().map(|column| column.name().to_uppercase()).collect::<Vec<_>>());
+    // Only people reading the table are told which node is this machine; scripts get the bare name
+    let rows: Vec<Vec<String>> = nodes
+        .iter()
+        .map(|node| {
+            columns
+                .iter()
+                .map(|column| match column {
+                    ListColumn::Name if node.is_self && headings.is_some() => {
+                        format!("{} (this machine)", column.value(config, node))
+                    }
+                    _ => column.value(config, node),
+                })
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|index| {
+            headings
+                .iter()
+                .chain(&rows)
+                .map(|row| row[index].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let print_row = |cells: &[String], style_of: &dyn Fn(usize) -> Style| {
+        let line: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(index, cell)| {
+                // The last column isn't padded, so lines don't end in spaces
+                let padded = if index + 1 == cells.len() {
+                    cell.clone()
+                } else {
+                    format!("{:<width$}", cell, width = widths[index])
+                };
+                style_of(index).force_styling(color).apply_to(padded).to_string()
+            })
+            .collect();
+        println!("{}", line.join("  "));
+    };
+
+    if let Some(headings) = &headings {
+        print_row(headings, &|_| Style::new().bold());
+    }
+    for (node, row) in nodes.iter().zip(&rows) {
+        print_row(row, &|index| match columns[index] {
+            ListColumn::Status if node.status.contains("active") => Style::new().green(),
+            ListColumn::Status if !node.is_online() => Style::new().dim(),
+            ListColumn::Tags => Style::new().cyan(),
+            _ => Style::new(),
+        });
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{
+    config::Config,
+    humanize,
+    platform::tailscale_program,
+    subprocess::{self, Subprocess},
+    tailscale::TailscaleNode,
+};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    io::{self, IsTerminal, Write},
+    path::Path,
+    process::Stdio,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// How much of the file is handed to `tailscale file cp` at a time
+const CHUNK_BYTES: usize = 256 * 1024;
+
+/// Send a file to a node with Taildrop. The file is piped to `tailscale file cp`
+/// so that `progress` can be called with the bytes sent so far and the total.
+pub async fn send(
+    config: &Config,
+    node: &TailscaleNode,
+    file: &Path,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<()> {
+    if !node.is_online() {
+        return Err(anyhow!("{} is offline; Taildrop only sends to online nodes", node.name));
+    }
+    let name = file
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a file", file.display()))?
+        .to_string_lossy()
+        .into_owned();
+    let mut source = tokio::fs::File::open(file)
+        .await
+        .with_context(|| format!("Failed to open {}", file.display()))?;
+    let total = source.metadata().await?.len();
+
+    let program = tailscale_program(config.tailscale_path.as_deref())?;
+    let mut command = tokio::process::Command::new(&program);
+    command
+        .args(["file", "cp", "--name", &name, "-", &format!("{}:", node.ip)])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        // Stopping the transfer must not let tailscale deliver the part sent so far
+        .kill_on_drop(true);
+    let subprocess = Subprocess::start(command.as_std());
+    let mut child = command
+        .spawn()
+        .inspect_err(|err| subprocess.finish(Err(err)))
+        .with_context(|| format!("Failed to execute '{} file cp'", program.display()))?;
+
+    // A write fails when tailscale gives up early; its error output says why
+    let mut stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin for tailscale"))?;
+    let mut buffer = vec![0; CHUNK_BYTES];
+    let mut sent = 0;
+    progress(sent, total);
+    loop {
+        let read = source.read(&mut buffer).await?;
+        if read == 0 || stdin.write_all(&buffer[..read]).await.is_err() {
+            break;
+        }
+        sent += read as u64;
+        progress(sent, total);
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().await;
+    subprocess.finish(output.as_ref().map(|output| &output.status));
+    let output = output?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("Sending {} to {} failed: {}", name, node.name, stderr.trim()));
+    }
+    Ok(())
+}
+
+/// Send a file from the command line, redrawing a progress line on a terminal
+pub async fn send_with_progress(config: &Config, node: &TailscaleNode, file: &Path) -> Result<()> {
+    let interactive = io::stdout().is_terminal();
+    let label = format!("Sending {} to {}", file.display(), node.name);
+    send(config, node, file, |sent, total| {
+        if interactive {
+            print!("\r{}: {}", label, progress_text(sent, total));
+            let _ = io::stdout().flush();
+        }
+    })
+    .await
+    .inspect_err(|_| {
+        if interactive {
+            println!();
+        }
+    })?;
+    if interactive {
+        println!();
+    }
+    println!("Sent {} to {}", file.display(), node.name);
+    Ok(())
+}
+
+/// How far a transfer has got, like `12.0 MiB of 40.0 MiB (30%)`
+pub fn progress_text(sent: u64, total: u64) -> String {
+    let percent = (sent * 100).checked_div(total).unwrap_or(100);
+    format!("{} of {} ({}%)", humanize::bytes(sent), humanize::bytes(total), percent)
+}
+
+/// Move files other nodes sent with Taildrop into `dir`, naming each as it
+/// arrives. Files with a name already in `dir` are renamed rather than
+/// overwritten. With `wait`, waits for a file when none are waiting.
+pub async fn receive(config: &Config, dir: &Path, wait: bool) -> Result<()> {
+    if !dir.is_dir() {
+        return Err(anyhow!("{} is not a directory", dir.display()));
+    }
+    let program = tailscale_program(config.tailscale_path.as_deref())?;
+    let mut command = tokio::process::Command::new(&program);
+    command.args(["file", "get", "--verbose", "--conflict=rename"]);
+    if wait {
+        command.arg("--wait");
+        println!("Waiting for files...");
+    }
+    command.arg(dir);
+    let status = subprocess::status_async(&mut command)
+        .await
+        .with_context(|| format!("Failed to execute '{} file get'", program.display()))?;
+    if !status.success() {
+        return Err(anyhow!("tailscale file get failed with {}", status));
+    }
+    Ok(())
+}
@@ -0,0 +1,711 @@
+use crate::{
+    config::{get_data_dir, Config},
+    history::unix_from_utc,
+    platform::tailscale_program,
+    known_hosts,
+    presence::record_presence,
+    secure::{encryption_enabled, write_private},
+    subprocess,
+    timeline,
+};
+use anyhow::{anyhow, Context, Result};
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use serde::{de::IgnoredAny, Deserialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, IsTerminal},
+    path::PathBuf,
+    sync::OnceLock,
+    time::Instant,
+};
+
+/// Nodes given with `--nodes-from`, which stand in for `tailscale status` for the whole run
+static NODE_LIST: OnceLock<Vec<TailscaleNode>> = OnceLock::new();
+
+/// Use these nodes instead of asking Tailscale, from now on
+pub fn use_node_list(nodes: Vec<TailscaleNode>) {
+    let _ = NODE_LIST.set(nodes);
+}
+
+/// The nodes given with `--nodes-from`, if any
+pub fn node_list() -> Option<&'static [TailscaleNode]> {
+    NODE_LIST.get().map(Vec::as_slice)
+}
+
+/// Represents a Tailscale node from the 'tailscale status' command
+#[derive(Debug, Clone)]
+pub struct TailscaleNode {
+    /// Tailscale's ID for the node, which stays the same when it is renamed or readdressed
+    pub id: String,
+    /// Hostname of the node
+    pub name: String,
+    /// IP address of the node
+    pub ip: String,
+    /// Tailscale IPv6 address of the node, empty if it has none
+    pub ipv6: String,
+    /// MagicDNS name like `web-1.tail1234.ts.net`, empty when MagicDNS is off
+    pub dns_name: String,
+    /// Suggested username from the node owner's login; empty for tagged nodes
+    pub suggested_user: String,
+    /// Operating system reported by the node (linux, windows, macOS, ...)
+    pub os: String,
+    /// ACL tags applied to the node, like `tag:server`
+    pub tags: Vec<String>,
+    /// Connection status (active, offline, etc.)
+    pub status: String,
+    /// Whether the node runs Tailscale SSH, so `tailscale ssh` can reach it
+    pub tailscale_ssh: bool,
+    /// When the coordination server last heard from the node, in seconds since the Unix epoch
+    pub last_seen: Option<u64>,
+    /// Bytes received from the node since this machine's client started
+    pub rx_bytes: u64,
+    /// Bytes sent to the node since this machine's client started
+    pub tx_bytes: u64,
+    /// Whether this is one of the Mullvad exit nodes Tailscale offers, rather than a machine of the tailnet
+    pub mullvad: bool,
+    /// Whether this is the machine ssh-tailscale runs on, the `Self` entry of the status
+    pub is_self: bool,
+}
+
+impl TailscaleNode {
+    /// Whether the node is currently connected to the tailnet
+    pub fn is_online(&self) -> bool {
+        !self.status.starts_with("offline")
+    }
+
+    /// Whether two node records describe the same machine, even if it was renamed
+    pub fn same_node(&self, other: &TailscaleNode) -> bool {
+        if self.id.is_empty() || other.id.is_empty() {
+            self.name == other.name
+        } else {
+            self.id == other.id
+        }
+    }
+
+    /// What changed about the node's name, address, tags and OS since `earlier`,
+    /// which decide where its settings are kept
+    pub fn identity_changes(&self, earlier: &TailscaleNode) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.name != earlier.name {
+            changes.push(format!("renamed from {} to {}", earlier.name, self.name));
+        }
+        if self.ip != earlier.ip {
+            changes.push(format!("IP changed from {} to {}", earlier.ip, self.ip));
+        }
+        if self.tags != earlier.tags {
+            let tags = if self.tags.is_empty() { "none".to_string() } else { self.tags.join(", ") };
+            changes.push(format!("tags are now {}", tags));
+        }
+        if self.os != earlier.os {
+            changes.push(format!("OS is now {}", self.os));
+        }
+        changes
+    }
+
+    /// The platform the node reports, from its OS field
+    pub fn platform(&self) -> Platform {
+        Platform::from_os(&self.os)
+    }
+}
+
+/// The kinds of OS Tailscale reports, which decide some defaults
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Platform {
+    Linux,
+    Windows,
+    MacOs,
+    /// iOS, iPadOS and tvOS
+    Ios,
+    Android,
+    /// Anything else, like FreeBSD, or no OS at all
+    Other,
+}
+
+impl Platform {
+    /// The platform for an OS name from `tailscale status`, like `linux` or `macOS`
+    pub fn from_os(os: &str) -> Platform {
+        match os.to_lowercase().as_str() {
+            "linux" => Platform::Linux,
+            "windows" => Platform::Windows,
+            "macos" => Platform::MacOs,
+            "ios" | "tvos" => Platform::Ios,
+            "android" => Platform::Android,
+            _ => Platform::Other,
+        }
+    }
+
+    /// Short label for the picker's OS column
+    pub fn abbreviation(self) -> &'static str {
+        match self {
+            Platform::Linux => "lnx",
+            Platform::Windows => "win",
+            Platform::MacOs => "mac",
+            Platform::Ios => "ios",
+            Platform::Android => "and",
+            Platform::Other => "",
+        }
+    }
+
+    /// Phones and tablets, which run no SSH server to connect to
+    pub fn is_mobile(self) -> bool {
+        matches!(self, Platform::Ios | Platform::Android)
+    }
+
+    /// The username nodes on this platform usually take, when nothing is configured
+    /// and the node has no owner to take it from
+    pub fn default_username(self) -> Option<&'static str> {
+        match self {
+            Platform::Windows => Some("Administrator"),
+            _ => None,
+        }
+    }
+}
+
+/// State of this machine's Tailscale client, from the same status output as the nodes
+#[derive(Clone, Default)]
+pub struct TailnetHealth {
+    /// Version of the local Tailscale client, without the build suffix
+    pub version: String,
+    /// Backend state, like `Running`, `Stopped` or `NeedsLogin`
+    pub backend_state: String,
+    /// Region code of this machine's home DERP relay
+    pub derp_home: String,
+    /// Whether MagicDNS is enabled for the tailnet
+    pub magic_dns: bool,
+    /// The peer currently used as exit node, if any
+    pub exit_node: Option<String>,
+    /// Problems reported by the client itself
+    pub warnings: Vec<String>,
+    /// This machine's name and Tailscale IPv4 address, as other nodes see it
+    pub self_name: String,
+    pub self_ip: String,
+    /// Name of the tailnet, like `example.com` or `jane@example.com`
+    pub tailnet: String,
+}
+
+/// Everything read from one `tailscale status --json` run
+pub struct TailscaleStatus {
+    /// This machine's client state
+    pub health: TailnetHealth,
+    /// All nodes, this machine first
+    pub nodes: Vec<TailscaleNode>,
+    /// Whether this was saved by an earlier run rather than read just now
+    pub cached: bool,
+}
+
+/// Run 'tailscale status' and parse the output into a list of nodes
+pub async fn get_tailscale_nodes(config: &Config) -> Result<Vec<TailscaleNode>> {
+    Ok(get_tailscale_status(config).await?.nodes)
+}
+
+/// Run 'tailscale status' and parse the nodes and the local client's health
+pub async fn get_tailscale_status(config: &Config) -> Result<TailscaleStatus> {
+    // A given list isn't the live tailnet, so nothing is recorded or pruned from it
+    if let Some(nodes) = node_list() {
+        return Ok(TailscaleStatus {
+            health: TailnetHealth::default(),
+            nodes: nodes.to_vec(),
+            cached: false,
+        });
+    }
+
+    let started = Instant::now();
+    let mut output_str = fetch_tailscale_status(config).await?;
+    let mut status = parse_tailscale_status(&output_str)?;
+    timeline::step("tailscale status", started);
+
+    // A stopped or logged out client lists no nodes worth picking; offer to connect it first
+    if let Some(reason) = not_connected_reason(&status.health.backend_state) {
+        start_tailscale(config, &status.health.backend_state, reason)?;
+        output_str = fetch_tailscale_status(config).await?;
+        status = parse_tailscale_status(&output_str)?;
+    }
+    
+    // If the status had no usable nodes, log it for a bug report
+    if status.nodes.is_empty() && !output_str.trim().is_empty() {
+        tracing::warn!(output = %output_str, "no nodes in tailscale status");
+        println!("Warning: No nodes found in tailscale status output. Run with -vv to log it for a bug report.");
+    }
+
+    // Tracking online time and caching are best-effort and must never block node discovery
+    let _ = record_presence(&status.nodes);
+    let _ = known_hosts::prune(config, &status.nodes);
+    let _ = cache_status(&output_str);
+    
+    Ok(status)
+}
+
+/// Why the client isn't connected to the tailnet, for backend states that mean it isn't
+fn not_connected_reason(backend_state: &str) -> Option<&'static str> {
+    match backend_state {
+        "Stopped" => Some("Tailscale is stopped"),
+        "NeedsLogin" => Some("Tailscale is logged out"),
+        "NeedsMachineAuth" => Some("This machine is waiting for a tailnet admin to approve it"),
+        _ => None,
+    }
+}
+
+/// Offer to run `tailscale up`, which connects the client and, when it is
+/// logged out, prints the login URL and waits until the login is done.
+/// Fails with what to do instead when declined or there is no terminal to ask on.
+fn start_tailscale(config: &Config, backend_state: &str, reason: &str) -> Result<()> {
+    if backend_state == "NeedsMachineAuth" {
+        return Err(anyhow!("{}; approve it in the admin console and try again", reason));
+    }
+    if !io::stdin().is_terminal()
+        || !Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("{}. Run `tailscale up` now?", reason))
+            .default(true)
+            .interact()?
+    {
+        return Err(anyhow!("{}; run `tailscale up` to connect", reason));
+    }
+
+    let program = tailscale_program(config.tailscale_path.as_deref())?;
+    let status = subprocess::status(std::process::Command::new(&program).arg("up"))
+        .with_context(|| format!("Failed to execute '{} up'", program.display()))?;
+    if !status.success() {
+        return Err(anyhow!(
+            "`tailscale up` exited with {}. On Linux it may need sudo, or `sudo tailscale set --operator=$USER` once.",
+            status
+        ));
+    }
+    Ok(())
+}
+
+/// Get the path of the saved `tailscale status` output, if there is anywhere to keep it
+fn get_status_cache_path() -> Result<Option<PathBuf>> {
+    // The node list maps out the tailnet, so it isn't kept at all when state is meant to be encrypted
+    if encryption_enabled() {
+        return Ok(None);
+    }
+    Ok(get_data_dir()?.map(|dir| dir.join("status-cache.json")))
+}
+
+/// Save the output of a successful `tailscale status` run for the next start
+pub fn cache_status(output: &str) -> Result<()> {
+    if let Some(cache_path) = get_status_cache_path()? {
+        write_private(&cache_path, output.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// The nodes from the last successful `tailscale status` run, if any were
+/// saved, so the picker can show them while a fresh list is fetched
+pub fn load_cached_status() -> Option<TailscaleStatus> {
+    if node_list().is_some() {
+        return None;
+    }
+    let cache_path = get_status_cache_path().ok()??;
+    let output = fs::read_to_string(cache_path).ok()?;
+    let status = parse_tailscale_status(&output).ok()?;
+    if status.nodes.is_empty() {
+        return None;
+    }
+    Some(TailscaleStatus { cached: true, ..status })
+}
+
+/// Run the 'tailscale status --json' command and return its standard output
+pub async fn fetch_tailscale_status(config: &Config) -> Result<String> {
+    let program = tailscale_program(config.tailscale_path.as_deref())?;
+
+    // Run 'tailscale status' command
+    let output = subprocess::output_async(tokio::process::Command::new(&program).args(["status", "--json"]))
+        .await
+        .with_context(|| format!("Failed to execute '{} status'", program.display()))?;
+    
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "Tailscale status command failed: {}. Make sure Tailscale is connected.", 
+            error
+        ));
+    }
+    
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    tracing::debug!(bytes = stdout.len(), output = %stdout, "tailscale status output");
+    Ok(stdout)
+}
+
+/// The parts of `tailscale status --json` this tool reads
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StatusJson {
+    /// Client version, like `1.76.1-t1234abcd-gdeadbeef`
+    #[serde(default)]
+    version: String,
+    /// Backend state, like `Running`
+    #[serde(default)]
+    backend_state: String,
+    /// Problems reported by the client
+    #[serde(default, deserialize_with = "null_as_default")]
+    health: Vec<String>,
+    /// Tailnet-wide settings
+    current_tailnet: Option<TailnetJson>,
+    /// The local node
+    #[serde(rename = "Self")]
+    self_node: Option<PeerJson>,
+    /// Every other node in the tailnet, keyed by node key
+    #[serde(default, deserialize_with = "null_as_default")]
+    peer: BTreeMap<String, PeerJson>,
+    /// Tailnet users, keyed by user ID
+    #[serde(default, deserialize_with = "null_as_default")]
+    user: BTreeMap<String, UserJson>,
+}
+
+/// Tailnet settings as described by `tailscale status --json`
+#[derive(Deserialize)]
+struct TailnetJson {
+    #[serde(rename = "Name", default)]
+    name: String,
+    #[serde(rename = "MagicDNSEnabled", default)]
+    magic_dns_enabled: bool,
+}
+
+/// A single node as described by `tailscale status --json`
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PeerJson {
+    #[serde(rename = "ID", default)]
+    id: String,
+    #[serde(default)]
+    host_name: String,
+    #[serde(rename = "DNSName", default)]
+    dns_name: String,
+    #[serde(rename = "OS", default)]
+    os: String,
+    #[serde(rename = "UserID", default)]
+    user_id: i64,
+    #[serde(rename = "TailscaleIPs", default, deserialize_with = "null_as_default")]
+    tailscale_ips: Vec<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    tags: Vec<String>,
+    #[serde(default)]
+    online: bool,
+    #[serde(default)]
+    active: bool,
+    #[serde(default)]
+    cur_addr: String,
+    #[serde(default)]
+    relay: String,
+    /// Whether this peer is the exit node currently in use
+    #[serde(default)]
+    exit_node: bool,
+    /// Host keys of the node's Tailscale SSH server; only present when it runs one
+    #[serde(rename = "sshHostKeys", default, deserialize_with = "null_as_default")]
+    ssh_host_keys: Vec<String>,
+    /// RFC 3339 time, or the zero time for nodes that are online or never seen
+    #[serde(default)]
+    last_seen: String,
+    #[serde(default)]
+    rx_bytes: u64,
+    #[serde(default)]
+    tx_bytes: u64,
+    /// Where a Mullvad exit node is; ordinary peers have none. Only whether it
+    /// is there matters, so it isn't kept.
+    #[serde(default)]
+    location: Option<IgnoredAny>,
+}
+
+/// A tailnet user as described by `tailscale status --json`
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct UserJson {
+    #[serde(default)]
+    login_name: String,
+}
+
+/// Deserialize a JSON `null` as the type's default value
+fn null_as_default<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl PeerJson {
+    /// Convert to a node, or `None` if it has no usable name or address
+    fn into_node(self, users: &BTreeMap<String, UserJson>, magic_dns: bool) -> Option<TailscaleNode> {
+        // Prefer the MagicDNS short name, which is what `tailscale status` shows
+        let name = self
+            .dns_name
+            .split('.')
+            .next()
+            .filter(|label| !label.is_empty())
+            .map(str::to_string)
+            .unwrap_or(self.host_name);
+        let ip = self
+            .tailscale_ips
+            .iter()
+            .find(|ip| ip.contains('.'))
+            .or_else(|| self.tailscale_ips.first())
+            .cloned()
+            .unwrap_or_default();
+        let ipv6 = self
+            .tailscale_ips
+            .iter()
+            .find(|ip| ip.contains(':'))
+            .cloned()
+            .unwrap_or_default();
+
+        // Only add nodes with non-empty names and IPs
+        if name.is_empty() || ip.is_empty() {
+            tracing::debug!(
+                id = %self.id,
+                name = %name,
+                ips = ?self.tailscale_ips,
+                "skipped a node without a name or IPv4/IPv6 address"
+            );
+            return None;
+        }
+
+        // The login name without its domain, as shown by `tailscale status`. Tagged
+        // nodes belong to the placeholder "tagged-devices" user, which no one logs in as.
+        let suggested_user = users
+            .get(&self.user_id.to_string())
+            .filter(|_| self.tags.is_empty())
+            .map(|user| user.login_name.split('@').next().unwrap_or_default().to_string())
+            .unwrap_or_default();
+
+        let status = if !self.online {
+            "offline".to_string()
+        } else if self.active && !self.cur_addr.is_empty() {
+            "active; direct".to_string()
+        } else if self.active {
+            format!("active; relay \"{}\"", self.relay)
+        } else {
+            "idle".to_string()
+        };
+
+        let mullvad = self.location.is_some() || self.dns_name.trim_end_matches('.').ends_with(".mullvad.ts.net");
+
+        // The name only resolves with MagicDNS, so don't offer it otherwise
+        let dns_name = if magic_dns {
+            self.dns_name.trim_end_matches('.').to_string()
+        } else {
+            String::new()
+        };
+
+        Some(TailscaleNode {
+            id: self.id,
+            name,
+            ip,
+            ipv6,
+            dns_name,
+            suggested_user,
+            os: self.os,
+            tags: self.tags,
+            status,
+            tailscale_ssh: !self.ssh_host_keys.is_empty(),
+            last_seen: parse_timestamp(&self.last_seen),
+            rx_bytes: self.rx_bytes,
+            tx_bytes: self.tx_bytes,
+            mullvad,
+            is_self: false,
+        })
+    }
+}
+
+/// Seconds since the Unix epoch of an RFC 3339 time like `2024-05-01T13:45:00.123Z`.
+/// Go's zero time, which Tailscale uses for "never", comes out as `None`.
+fn parse_timestamp(text: &str) -> Option<u64> {
+    let (date, time) = text.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    // Everything after the seconds is a fraction and an offset, which may be `Z` or `+02:00`
+    let offset_start = time.find(['Z', 'z', '+', '-']).unwrap_or(time.len());
+    let (clock, offset) = time.split_at(offset_start);
+    let mut clock = clock.splitn(3, ':');
+    let hour = clock.next()?.parse::<u64>().ok()?;
+    let minute = clock.next()?.parse::<u64>().ok()?;
+    let second = clock.next()?.split('.').next()?.parse::<u64>().ok()?;
+    let offset_secs = match offset.split_at_checked(1) {
+        Some((sign @ ("+" | "-"), rest)) => {
+            let (hours, minutes) = rest.split_once(':')?;
+            let secs = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+            if sign == "+" { secs } else { -secs }
+        }
+        _ => 0,
+    };
+
+    let local = unix_from_utc(year, month, day, hour, minute, second)?;
+    u64::try_from(local as i64 - offset_secs).ok().filter(|&secs| secs > 0)
+}
+
+/// Parse the output of 'tailscale status --json' into the client's health and a list of nodes, this machine first
+pub fn parse_tailscale_status(output_str: &str) -> Result<TailscaleStatus> {
+    let status: StatusJson = serde_json::from_str(output_str)
+        .inspect_err(|err| tracing::warn!(error = %err, output = %output_str, "unparsable tailscale status"))
+        .context("Failed to parse 'tailscale status --json' output")?;
+
+    let magic_dns = status.current_tailnet.as_ref().is_some_and(|tailnet| tailnet.magic_dns_enabled);
+    let tailnet = status.current_tailnet.map(|tailnet| tailnet.name).unwrap_or_default();
+
+    let mut exit_node = None;
+    let mut peers = Vec::new();
+    for peer in status.peer.into_values() {
+        let is_exit_node = peer.exit_node;
+        if let Some(node) = peer.into_node(&status.user, magic_dns) {
+            if is_exit_node {
+                exit_node = Some(node.name.clone());
+            }
+            peers.push(node);
+        }
+    }
+    peers.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // The local node's relay is its home DERP region
+    let derp_home = status.self_node.as_ref().map(|node| node.relay.clone()).unwrap_or_default();
+    let self_node = status
+        .self_node
+        .and_then(|node| node.into_node(&status.user, magic_dns))
+        .map(|node| TailscaleNode { is_self: true, ..node });
+    let health = TailnetHealth {
+        version: status.version.split('-').next().unwrap_or_default().to_string(),
+        backend_state: status.backend_state,
+        derp_home,
+        magic_dns,
+        exit_node,
+        warnings: status.health,
+        self_name: self_node.as_ref().map(|node| node.name.clone()).unwrap_or_default(),
+        self_ip: self_node.as_ref().map(|node| node.ip.clone()).unwrap_or_default(),
+        tailnet,
+    };
+
+    let mut nodes: Vec<TailscaleNode> = self_node.into_iter().collect();
+    nodes.extend(peers);
+    tracing::debug!(
+        nodes = nodes.len(),
+        backend_state = %health.backend_state,
+        magic_dns,
+        exit_node = health.exit_node.as_deref(),
+        "parsed tailscale status"
+    );
+    
+    Ok(TailscaleStatus { health, nodes, cached: false })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timestamps_with_any_offset() {
+        assert_eq!(parse_timestamp("2024-05-01T13:45:00Z"), Some(1_714_571_100));
+        assert_eq!(parse_timestamp("2024-05-01T13:45:00.123456789z"), Some(1_714_571_100));
+        assert_eq!(parse_timestamp("2024-05-01T15:45:00+02:00"), Some(1_714_571_100));
+        assert_eq!(parse_timestamp("2024-05-01T06:45:00.5-07:00"), Some(1_714_571_100));
+        assert_eq!(parse_timestamp("2024-02-29T23:59:59Z"), Some(1_709_251_199));
+        assert_eq!(parse_timestamp("1970-01-01T00:00:01"), Some(1));
+    }
+
+    #[test]
+    fn never_and_broken_timestamps_are_none() {
+        for text in [
+            "0001-01-01T00:00:00Z",
+            "",
+            "2024-05-01",
+            "2024-05-01T13:45Z",
+            "2024-05T13:45:00Z",
+            "2024-05-01T13:45:xxZ",
+            "2024-05-01T13:45:00+02",
+            "yesterday",
+        ] {
+            assert_eq!(parse_timestamp(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_an_empty_status() {
+        for output in ["{}", r#"{"Peer": null, "User": null, "Health": null, "Self": null, "CurrentTailnet": null}"#] {
+            let status = parse_tailscale_status(output).unwrap();
+            assert!(status.nodes.is_empty());
+            assert_eq!(status.health.version, "");
+            assert_eq!(status.health.self_name, "");
+            assert!(!status.health.magic_dns);
+            assert!(!status.cached);
+        }
+        assert!(parse_tailscale_status("tailscale is not running").is_err());
+    }
+
+    #[test]
+    fn fills_in_missing_peer_fields() {
+        let status = parse_tailscale_status(
+            r#"{
+                "Version": "1.76.1-t1234abcd-gdeadbeef",
+                "BackendState": "Running",
+                "Health": ["not connected to home DERP region 4"],
+                "CurrentTailnet": {"Name": "example.com"},
+                "Self": {"HostName": "laptop", "TailscaleIPs": ["100.64.0.1"], "Online": true, "Relay": "fra"},
+                "Peer": {
+                    "nodekey:b": {"HostName": "Bare", "TailscaleIPs": ["fd7a:115c:a1e0::2"], "sshHostKeys": null, "Tags": null},
+                    "nodekey:a": {
+                        "ID": "n1",
+                        "HostName": "Web-1",
+                        "DNSName": "web-1.tail1234.ts.net.",
+                        "OS": "linux",
+                        "UserID": 7,
+                        "TailscaleIPs": ["fd7a:115c:a1e0::1", "100.64.0.2"],
+                        "Online": true,
+                        "Active": true,
+                        "CurAddr": "203.0.113.5:41641",
+                        "ExitNode": true,
+                        "sshHostKeys": ["ssh-ed25519 AAAA"],
+                        "LastSeen": "0001-01-01T00:00:00Z"
+                    },
+                    "nodekey:c": {"HostName": "no-address", "TailscaleIPs": []},
+                    "nodekey:d": {"TailscaleIPs": ["100.64.0.9"]}
+                },
+                "User": {"7": {"LoginName": "jane@example.com"}}
+            }"#,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = status.nodes.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(names, ["laptop", "Bare", "web-1"]);
+        assert!(status.nodes[0].is_self);
+        assert_eq!(status.health.version, "1.76.1");
+        assert_eq!(status.health.derp_home, "fra");
+        assert_eq!(status.health.exit_node.as_deref(), Some("web-1"));
+        assert_eq!(status.health.self_ip, "100.64.0.1");
+        assert_eq!(status.health.tailnet, "example.com");
+        assert_eq!(status.health.warnings.len(), 1);
+
+        let bare = &status.nodes[1];
+        assert_eq!(bare.ip, "fd7a:115c:a1e0::2");
+        assert_eq!(bare.ipv6, "fd7a:115c:a1e0::2");
+        assert_eq!(bare.status, "offline");
+        assert_eq!(bare.suggested_user, "");
+        assert!(!bare.tailscale_ssh);
+        assert_eq!(bare.last_seen, None);
+
+        let web = &status.nodes[2];
+        assert_eq!(web.ip, "100.64.0.2");
+        assert_eq!(web.status, "active; direct");
+        assert_eq!(web.suggested_user, "jane");
+        // Without MagicDNS the name doesn't resolve
+        assert_eq!(web.dns_name, "");
+        assert!(web.tailscale_ssh);
+        assert_eq!(web.last_seen, None);
+    }
+
+    #[test]
+    fn tagged_nodes_have_no_suggested_user() {
+        let status = parse_tailscale_status(
+            r#"{
+                "CurrentTailnet": {"MagicDNSEnabled": true},
+                "Peer": {"k": {"DNSName": "db.tail1234.ts.net.", "UserID": 7, "Tags": ["tag:db"], "TailscaleIPs": ["100.64.0.3"], "Online": true, "Active": true, "Relay": "ams"}},
+                "User": {"7": {"LoginName": "tagged-devices"}}
+            }"#,
+        )
+        .unwrap();
+        let db = &status.nodes[0];
+        assert_eq!(db.name, "db");
+        assert_eq!(db.dns_name, "db.tail1234.ts.net");
+        assert_eq!(db.suggested_user, "");
+        assert_eq!(db.status, r#"active; relay "ams""#);
+    }
+}
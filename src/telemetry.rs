@@ -0,0 +1,119 @@
+use crate::config::get_data_dir;
+use anyhow::Result;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tracing_subscriber::{filter::LevelFilter, fmt::format::FmtSpan, layer::SubscriberExt, Layer, Registry};
+
+/// The log file is rotated at startup once it grows past this
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+/// How many rotated logs are kept, as `ssh-tailscale.log.1` (the newest) and up
+const KEPT_LOGS: usize = 3;
+
+/// The OTLP exporter of this run, flushed before the program exits
+#[cfg(feature = "otlp")]
+static PROVIDER: Mutex<Option<opentelemetry_sdk::trace::TracerProvider>> = Mutex::new(None);
+
+/// Get the debug log path, if there is anywhere to keep it
+pub fn get_debug_log_path() -> Result<Option<PathBuf>> {
+    Ok(get_data_dir()?.map(|dir| dir.join("debug.log")))
+}
+
+/// Get the path of the log `-v` writes, if there is anywhere to keep it
+pub fn get_log_path() -> Result<Option<PathBuf>> {
+    Ok(get_data_dir()?.map(|dir| dir.join("ssh-tailscale.log")))
+}
+
+/// Start collecting spans and events. With `--debug-log` each span is appended
+/// to the debug log as a line of JSON when it closes. With `verbosity` 1 (`-v`)
+/// spans and info events are written as text to the log file, and with 2
+/// (`-vv`) debug events too. Builds with the `otlp` feature also export spans
+/// when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+pub fn init(debug_log: bool, verbosity: u8) -> Result<()> {
+    let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> = Vec::new();
+
+    if verbosity > 0 && let Some(path) = get_log_path()? {
+        rotate(&path)?;
+        let level = if verbosity > 1 { LevelFilter::DEBUG } else { LevelFilter::INFO };
+        layers.push(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_span_events(FmtSpan::CLOSE)
+                .with_writer(Mutex::new(open_log(&path)?))
+                .with_filter(level)
+                .boxed(),
+        );
+    }
+
+    if debug_log && let Some(path) = get_debug_log_path()? {
+        let file = open_log(&path)?;
+        layers.push(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_span_events(FmtSpan::CLOSE)
+                .with_writer(Mutex::new(file))
+                .boxed(),
+        );
+    }
+
+    #[cfg(feature = "otlp")]
+    if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_some() {
+        layers.push(otlp_layer()?);
+    }
+
+    if !layers.is_empty() {
+        tracing::subscriber::set_global_default(Registry::default().with(layers))?;
+    }
+    Ok(())
+}
+
+/// Open a log file for appending, readable only by the user
+fn open_log(path: &Path) -> Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    Ok(options.open(path)?)
+}
+
+/// Move a log past `MAX_LOG_BYTES` aside as `<log>.1`, shifting older ones up
+/// and dropping the oldest, so a fresh log is started
+fn rotate(path: &Path) -> Result<()> {
+    if fs::metadata(path).map_or(true, |metadata| metadata.len() < MAX_LOG_BYTES) {
+        return Ok(());
+    }
+    let rotated = |index: usize| PathBuf::from(format!("{}.{}", path.display(), index));
+    let _ = fs::remove_file(rotated(KEPT_LOGS));
+    for index in (1..KEPT_LOGS).rev() {
+        let _ = fs::rename(rotated(index), rotated(index + 1));
+    }
+    fs::rename(path, rotated(1))?;
+    Ok(())
+}
+
+/// A layer sending spans to the collector at `OTEL_EXPORTER_OTLP_ENDPOINT`
+#[cfg(feature = "otlp")]
+fn otlp_layer() -> Result<Box<dyn Layer<Registry> + Send + Sync>> {
+    use opentelemetry::{trace::TracerProvider as _, KeyValue};
+    use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder().with_tonic().build()?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new("service.name", "ssh-tailscale")]))
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("ssh-tailscale"));
+    *PROVIDER.lock().unwrap_or_else(|err| err.into_inner()) = Some(provider);
+    Ok(layer.boxed())
+}
+
+/// Send any spans still waiting for the OTLP collector. Call it before exiting.
+pub fn flush() {
+    #[cfg(feature = "otlp")]
+    if let Some(provider) = PROVIDER.lock().unwrap_or_else(|err| err.into_inner()).take() {
+        let _ = provider.shutdown();
+    }
+}
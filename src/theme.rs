@@ -0,0 +1,212 @@
+use crate::config::{ColorMode, ThemeConfig, ThemePreset};
+use anyhow::{anyhow, Result};
+use ratatui::style::{Color, Modifier, Style};
+use std::{env, str::FromStr};
+
+/// Environment variable that turns colors off in any program that honors it
+const NO_COLOR_ENV: &str = "NO_COLOR";
+
+/// Styles for each role text plays in the picker
+#[derive(Debug, Clone)]
+pub struct Theme {
+    /// Header title
+    pub title: Style,
+    /// Selected row in lists
+    pub highlight: Style,
+    /// Active nodes, a running client and the username that will be used
+    pub active: Style,
+    /// Idle and offline nodes
+    pub inactive: Style,
+    /// Secondary text like users, labels and hints
+    pub muted: Style,
+    /// Least important text, like where a setting came from
+    pub faint: Style,
+    /// Markers worth noticing, like Tailscale SSH and the exit node
+    pub accent: Style,
+    /// Warnings and messages
+    pub warning: Style,
+    /// Tag chips
+    pub chip: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::preset(ThemePreset::Dark)
+    }
+}
+
+impl Theme {
+    /// Build the theme from the config's `theme` section
+    pub fn from_config(config: &ThemeConfig) -> Result<Self> {
+        let mut theme = Self::preset(config.preset);
+
+        if let Some(color) = &config.highlight {
+            theme.highlight = theme.highlight.bg(parse_color("highlight", color)?);
+        }
+        if let Some(color) = &config.active {
+            theme.active = theme.active.fg(parse_color("active", color)?);
+        }
+        if let Some(color) = &config.inactive {
+            theme.inactive = theme.inactive.fg(parse_color("inactive", color)?);
+        }
+        if let Some(color) = &config.header {
+            theme.title = theme.title.fg(parse_color("header", color)?);
+        }
+        match config.header_bold {
+            Some(true) => theme.title = theme.title.add_modifier(Modifier::BOLD),
+            Some(false) => theme.title = theme.title.remove_modifier(Modifier::BOLD),
+            None => {}
+        }
+
+        let no_color = env::var_os(NO_COLOR_ENV).is_some_and(|value| !value.is_empty());
+        let mode = if no_color { ColorMode::None } else { config.colors };
+        Ok(theme.limited_to(mode))
+    }
+
+    /// One of the built-in palettes
+    fn preset(preset: ThemePreset) -> Self {
+        let fg = |color| Style::default().fg(color);
+        match preset {
+            ThemePreset::Dark => Self {
+                title: fg(Color::Green).add_modifier(Modifier::BOLD),
+                highlight: Style::default().bg(Color::DarkGray).add_modifier(Modifier::BOLD),
+                active: fg(Color::Green),
+                inactive: fg(Color::Red),
+                muted: fg(Color::Gray),
+                faint: fg(Color::DarkGray),
+                accent: fg(Color::Cyan),
+                warning: fg(Color::Yellow),
+                chip: fg(Color::Black).bg(Color::Blue),
+            },
+            // Dark enough to read on white, with a pale selection instead of a dark one
+            ThemePreset::Light => Self {
+                title: fg(Color::Rgb(0x1a, 0x7f, 0x37)).add_modifier(Modifier::BOLD),
+                highlight: Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Rgb(0xcc, 0xdd, 0xf5))
+                    .add_modifier(Modifier::BOLD),
+                active: fg(Color::Rgb(0x1a, 0x7f, 0x37)),
+                inactive: fg(Color::Rgb(0xcf, 0x22, 0x2e)),
+                muted: fg(Color::Rgb(0x57, 0x60, 0x6a)),
+                faint: fg(Color::Rgb(0x8c, 0x95, 0x9f)),
+                accent: fg(Color::Rgb(0x09, 0x69, 0xda)),
+                warning: fg(Color::Rgb(0x9a, 0x67, 0x00)),
+                chip: fg(Color::White).bg(Color::Rgb(0x09, 0x69, 0xda)),
+            },
+            ThemePreset::Solarized => Self {
+                title: fg(Color::Rgb(0x85, 0x99, 0x00)).add_modifier(Modifier::BOLD),
+                highlight: Style::default()
+                    .fg(Color::Rgb(0x93, 0xa1, 0xa1))
+                    .bg(Color::Rgb(0x07, 0x36, 0x42))
+                    .add_modifier(Modifier::BOLD),
+                active: fg(Color::Rgb(0x85, 0x99, 0x00)),
+                inactive: fg(Color::Rgb(0xdc, 0x32, 0x2f)),
+                muted: fg(Color::Rgb(0x83, 0x94, 0x96)),
+                faint: fg(Color::Rgb(0x58, 0x6e, 0x75)),
+                accent: fg(Color::Rgb(0x2a, 0xa1, 0x98)),
+                warning: fg(Color::Rgb(0xb5, 0x89, 0x00)),
+                chip: fg(Color::Rgb(0xfd, 0xf6, 0xe3)).bg(Color::Rgb(0x26, 0x8b, 0xd2)),
+            },
+        }
+    }
+
+    /// Reduce every style to the colors the terminal can show
+    fn limited_to(self, mode: ColorMode) -> Self {
+        let limit = |style: Style| match mode {
+            ColorMode::Truecolor => style,
+            ColorMode::Ansi16 => Style {
+                fg: style.fg.map(to_ansi16),
+                bg: style.bg.map(to_ansi16),
+                ..style
+            },
+            ColorMode::None => Style {
+                fg: None,
+                bg: None,
+                ..style
+            },
+        };
+
+        let mut highlight = limit(self.highlight);
+        if mode == ColorMode::None {
+            // Without a background color the selection needs another way to stand out
+            highlight = highlight.add_modifier(Modifier::REVERSED);
+        }
+
+        Self {
+            title: limit(self.title),
+            highlight,
+            active: limit(self.active),
+            inactive: limit(self.inactive),
+            muted: limit(self.muted),
+            faint: limit(self.faint),
+            accent: limit(self.accent),
+            warning: limit(self.warning),
+            chip: limit(self.chip),
+        }
+    }
+}
+
+/// Parse a color from the config, naming the setting in the error
+fn parse_color(setting: &str, value: &str) -> Result<Color> {
+    Color::from_str(value).map_err(|_| {
+        anyhow!(
+            "theme.{} is set to '{}', which is not a color; use a name like `blue` or `lightred`, or `#rrggbb`",
+            setting,
+            value
+        )
+    })
+}
+
+/// The 16 basic ANSI colors with their usual RGB values
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// The closest basic ANSI color to any color
+fn to_ansi16(color: Color) -> Color {
+    let (r, g, b) = match color {
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Indexed(index) if index < 16 => return ANSI16[usize::from(index)].0,
+        Color::Indexed(index) => indexed_rgb(index),
+        basic => return basic,
+    };
+
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        [(r, r2), (g, g2), (b, b2)]
+            .into_iter()
+            .map(|(a, b)| (i32::from(a) - i32::from(b)).pow(2))
+            .sum::<i32>()
+    };
+    ANSI16
+        .into_iter()
+        .min_by_key(|&(_, rgb)| distance(rgb))
+        .map(|(color, _)| color)
+        .unwrap_or(Color::Reset)
+}
+
+/// RGB value of an xterm 256-color palette entry above the basic 16
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    if index >= 232 {
+        let level = 8 + (index - 232) * 10;
+        return (level, level, level);
+    }
+    // A 6x6x6 color cube
+    let cube = index - 16;
+    let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+    (level(cube / 36), level(cube / 6 % 6), level(cube % 6))
+}
@@ -0,0 +1,70 @@
+use std::{
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Steps of a connection recorded with `-v`, in the order they finished
+static TIMELINE: Mutex<Option<Vec<Step>>> = Mutex::new(None);
+
+/// When the program started, for the total
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// One timed step, like the `tailscale status` run or the ssh handshake
+struct Step {
+    label: String,
+    duration: Duration,
+    /// Extra context, like the address connected to
+    note: Option<String>,
+}
+
+/// Start recording steps, for `-v`
+pub fn enable() {
+    LazyLock::force(&STARTED);
+    *TIMELINE.lock().unwrap_or_else(|err| err.into_inner()) = Some(Vec::new());
+}
+
+/// Record a step that began at `started` and has just finished
+pub fn step(label: &str, started: Instant) {
+    step_with_note(label, started.elapsed(), None);
+}
+
+/// Record a step of known length, with context shown next to it
+pub fn step_with_note(label: &str, duration: Duration, note: Option<String>) {
+    tracing::info!(step = label, duration_ms = duration.as_millis() as u64, note = note.as_deref(), "timing");
+    if let Some(steps) = TIMELINE.lock().unwrap_or_else(|err| err.into_inner()).as_mut() {
+        steps.push(Step {
+            label: label.to_string(),
+            duration,
+            note,
+        });
+    }
+}
+
+/// Print the recorded steps and the total to stderr, if recording is on
+pub fn print() {
+    let guard = TIMELINE.lock().unwrap_or_else(|err| err.into_inner());
+    let Some(steps) = guard.as_ref() else {
+        return;
+    };
+
+    let width = steps.iter().map(|step| step.label.len()).max().unwrap_or(0).max("total".len());
+    eprintln!("\nTimeline:");
+    for step in steps {
+        let note = step.note.as_ref().map(|note| format!("  {}", note)).unwrap_or_default();
+        eprintln!("  {:<width$}  {:>8}{}", step.label, format_duration(step.duration), note, width = width);
+    }
+    eprintln!("  {:<width$}  {:>8}", "total", format_duration(STARTED.elapsed()), width = width);
+}
+
+/// A duration in the largest unit that keeps it readable: `85ms`, `2.4s`, `5m 03s`
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else if millis < 60_000 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else {
+        let secs = duration.as_secs();
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    }
+}
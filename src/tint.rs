@@ -0,0 +1,73 @@
+use crate::{
+    config::{Config, SessionColor},
+    tailscale::TailscaleNode,
+};
+use console::Style;
+use std::io::{self, IsTerminal, Write};
+
+/// The session color for a node: its own, else the one for the first of its
+/// tags (alphabetically) that has one
+pub fn color_for(config: &Config, node: &TailscaleNode) -> Option<SessionColor> {
+    config.node_settings(&node.name).session_color.or_else(|| {
+        config
+            .session_colors
+            .tags
+            .iter()
+            .find(|(tag, _)| node.tags.iter().any(|node_tag| node_tag.eq_ignore_ascii_case(tag)))
+            .map(|(_, color)| color.clone())
+    })
+}
+
+/// Keeps the terminal marked for a node while it lives, and puts the
+/// background and window title back when dropped
+pub struct Tint {
+    background: bool,
+    title: bool,
+}
+
+/// Mark the terminal for a session to the node: print the marker, tint the
+/// background with OSC 11 and prefix the window title with the marker.
+/// Terminals that don't know the sequences ignore them. Does nothing when
+/// stdout isn't a terminal or the node has no session color.
+pub fn apply(config: &Config, node: &TailscaleNode, username: &str) -> Option<Tint> {
+    let color = color_for(config, node)?;
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    let background = color.background.as_deref().map(clean).filter(|background| !background.is_empty());
+    let marker = color.marker.as_deref().map(clean).filter(|marker| !marker.is_empty());
+
+    let mut stdout = io::stdout();
+    if let Some(marker) = &marker {
+        println!("{}", Style::new().bold().reverse().apply_to(format!(" {} ", marker)));
+        // Save the current title on the terminal's title stack, then set ours
+        let _ = write!(stdout, "\x1b[22;2t\x1b]2;[{}] {}@{}\x07", marker, username, node.name);
+    }
+    if let Some(background) = &background {
+        let _ = write!(stdout, "\x1b]11;{}\x07", background);
+    }
+    let _ = stdout.flush();
+    Some(Tint {
+        background: background.is_some(),
+        title: marker.is_some(),
+    })
+}
+
+impl Drop for Tint {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.background {
+            // OSC 111 resets the background to the terminal's default
+            let _ = write!(stdout, "\x1b]111\x07");
+        }
+        if self.title {
+            let _ = write!(stdout, "\x1b[23;2t");
+        }
+        let _ = stdout.flush();
+    }
+}
+
+/// Drop control characters, which could end the escape sequence early
+fn clean(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
@@ -0,0 +1,222 @@
+use crate::{
+    config::{Config, SshClient},
+    known_hosts::known_hosts_path,
+    platform::tailscale_program,
+    preflight::{ping_reply, port_open, spin},
+    subprocess,
+    tailscale::TailscaleNode,
+};
+use anyhow::Result;
+use std::{process::Stdio, time::Duration};
+
+/// How long the `tailscale ssh` attempt and the host key scan may take
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of checks, for the `[n/4]` step labels
+const STEPS: usize = 4;
+
+/// What became of a check
+enum Check {
+    Passed(String),
+    Failed(String),
+    Skipped(String),
+}
+
+impl Check {
+    fn passed(&self) -> bool {
+        matches!(self, Check::Passed(_))
+    }
+
+    fn failed(&self) -> bool {
+        matches!(self, Check::Failed(_))
+    }
+}
+
+/// What the checks found, for choosing the fix
+struct Findings {
+    ping: Check,
+    port: Check,
+    tailscale_ssh: Check,
+    host_key: HostKey,
+}
+
+/// What the known_hosts check found
+enum HostKey {
+    /// No key recorded yet, or one matching what the node offers
+    Fine,
+    /// The recorded key differs from every key the node offers
+    Changed,
+    Unknown,
+}
+
+/// Work out why ssh couldn't get through to a node, one check at a time, and
+/// end with the fix that fits what was found. `stderr_tail` is ssh's own error
+/// output from the failed attempt, if there was one.
+pub async fn run(config: &Config, node: &TailscaleNode, username: &str, host: &str, stderr_tail: &str) -> Result<()> {
+    println!("Troubleshooting the connection to {}@{} at {}\n", username, node.name, host);
+
+    let ping = match spin(&step_label(1, "Pinging over Tailscale"), ping_reply(config, node)).await {
+        Some(reply) => Check::Passed(reply),
+        None => Check::Failed("no answer; the node isn't reachable over the tailnet".to_string()),
+    };
+    report(1, "tailscale ping", &ping);
+
+    let port = if spin(&step_label(2, "Probing port 22"), port_open(host)).await {
+        Check::Passed(format!("{}:22 accepts connections", host))
+    } else {
+        Check::Failed(format!("nothing answers on {}:22", host))
+    };
+    report(2, "port 22", &port);
+
+    let tailscale_ssh = if !node.tailscale_ssh {
+        Check::Skipped("the node doesn't offer Tailscale SSH".to_string())
+    } else if !ping.passed() {
+        Check::Skipped("the node didn't answer the ping".to_string())
+    } else {
+        spin(&step_label(3, "Trying tailscale ssh"), try_tailscale_ssh(config, node, username)).await
+    };
+    report(3, "tailscale ssh", &tailscale_ssh);
+
+    let host_key = if config.ssh.client == SshClient::Plink {
+        (Check::Skipped("plink keeps host keys in the registry".to_string()), HostKey::Unknown)
+    } else if !port.passed() {
+        (Check::Skipped("port 22 is closed, so there is no key to compare".to_string()), HostKey::Unknown)
+    } else {
+        spin(&step_label(4, "Comparing host keys"), check_host_key(config, host, stderr_tail)).await
+    };
+    report(4, "known_hosts", &host_key.0);
+
+    let findings = Findings {
+        ping,
+        port,
+        tailscale_ssh,
+        host_key: host_key.1,
+    };
+    println!("\nSuggested fix:\n{}", suggestion(config, node, username, host, &findings));
+    Ok(())
+}
+
+fn step_label(step: usize, label: &str) -> String {
+    format!("[{}/{}] {}", step, STEPS, label)
+}
+
+/// Print how a check went on one line
+fn report(step: usize, name: &str, check: &Check) {
+    let (mark, detail) = match check {
+        Check::Passed(detail) => ("ok", detail),
+        Check::Failed(detail) => ("FAILED", detail),
+        Check::Skipped(detail) => ("skipped", detail),
+    };
+    println!("[{}/{}] {:<14} {:<8} {}", step, STEPS, name, mark, detail);
+}
+
+/// Run `true` on the node through `tailscale ssh`, which needs neither sshd nor keys
+async fn try_tailscale_ssh(config: &Config, node: &TailscaleNode, username: &str) -> Check {
+    let Ok(program) = tailscale_program(config.tailscale_path.as_deref()) else {
+        return Check::Skipped("the tailscale CLI wasn't found".to_string());
+    };
+    let mut command = tokio::process::Command::new(program);
+    command
+        .args(["ssh", &format!("{}@{}", username, node.ip), "true"])
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    let attempt = subprocess::output_async(&mut command);
+    match tokio::time::timeout(STEP_TIMEOUT, attempt).await {
+        Ok(Ok(output)) if output.status.success() => Check::Passed(format!("logged in as {}", username)),
+        Ok(Ok(output)) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Check::Failed(stderr.lines().last().unwrap_or("it failed").trim().to_string())
+        }
+        Ok(Err(err)) => Check::Failed(format!("couldn't run tailscale: {}", err)),
+        Err(_) => Check::Failed("no answer in time; the tailnet policy may require a browser check".to_string()),
+    }
+}
+
+/// Compare the key recorded for the host with the keys the node offers now
+async fn check_host_key(config: &Config, host: &str, stderr_tail: &str) -> (Check, HostKey) {
+    if stderr_tail.contains("REMOTE HOST IDENTIFICATION HAS CHANGED") {
+        return (Check::Failed("ssh reported a changed host key".to_string()), HostKey::Changed);
+    }
+
+    let mut lookup = tokio::process::Command::new("ssh-keygen");
+    lookup.arg("-F").arg(host);
+    if let Ok(Some(path)) = known_hosts_path(config) {
+        lookup.arg("-f").arg(path);
+    }
+    let recorded: Vec<String> = match subprocess::output_async(lookup.stderr(Stdio::null())).await {
+        Ok(output) => key_fields(&String::from_utf8_lossy(&output.stdout)),
+        Err(err) => return (Check::Skipped(format!("couldn't run ssh-keygen: {}", err)), HostKey::Unknown),
+    };
+    if recorded.is_empty() {
+        return (Check::Passed("no key recorded yet; ssh will ask to trust it".to_string()), HostKey::Fine);
+    }
+
+    let mut scan = tokio::process::Command::new("ssh-keyscan");
+    scan.args(["-T", &STEP_TIMEOUT.as_secs().to_string(), "--", host])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    let offered = match subprocess::output_async(&mut scan).await {
+        Ok(output) => key_fields(&String::from_utf8_lossy(&output.stdout)),
+        Err(err) => return (Check::Skipped(format!("couldn't run ssh-keyscan: {}", err)), HostKey::Unknown),
+    };
+    if offered.is_empty() {
+        (Check::Skipped("the node didn't offer any host keys".to_string()), HostKey::Unknown)
+    } else if offered.iter().any(|key| recorded.contains(key)) {
+        (Check::Passed("the recorded key matches".to_string()), HostKey::Fine)
+    } else {
+        (Check::Failed("the node offers a different key than the one recorded".to_string()), HostKey::Changed)
+    }
+}
+
+/// The `type key` part of each known_hosts line, leaving out hosts and comments
+fn key_fields(text: &str) -> Vec<String> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            Some(format!("{} {}", fields.next()?, fields.next()?))
+        })
+        .collect()
+}
+
+/// The fix that fits the first thing found wrong
+fn suggestion(config: &Config, node: &TailscaleNode, username: &str, host: &str, findings: &Findings) -> String {
+    if findings.ping.failed() {
+        return format!(
+            "{} is not reachable over Tailscale. Make sure it is powered on and logged in to the tailnet \
+             (`tailscale status` shows when it was last seen), and that this machine is connected too.",
+            node.name
+        );
+    }
+    if findings.port.failed() && findings.tailscale_ssh.passed() {
+        return format!(
+            "sshd can't be reached, but Tailscale SSH works. Add \"tailscale\" to `transports` in the `ssh` \
+             section of the config to connect to {} that way.",
+            node.name
+        );
+    }
+    if findings.port.failed() {
+        return format!(
+            "{} is online, but nothing answers on port 22. Check that sshd is running and listens on the \
+             Tailscale interface, and that your tailnet ACLs allow port 22 to it.",
+            node.name
+        );
+    }
+    if let HostKey::Changed = findings.host_key {
+        let file = match known_hosts_path(config) {
+            Ok(Some(path)) => format!(" -f {}", path.display()),
+            _ => String::new(),
+        };
+        return format!(
+            "The host key of {} changed. That is expected if the node was reinstalled or its address went to \
+             another node; if so, remove the old key with `ssh-keygen -R {}{}` and connect again. Otherwise, \
+             find out why before trusting the new key.",
+            host, host, file
+        );
+    }
+    format!(
+        "The network path to {} looks fine, so ssh most likely failed to log in. Check that {} is the right \
+         username (Ctrl+U in the picker changes it) and that the node accepts one of your keys.",
+        node.name, username
+    )
+}
@@ -0,0 +1,1414 @@
+use crate::{
+    actions::{browse_action, command_for, forward_port, ActionKind},
+    app::{App, CommandOutput, Diagnostics, HelpScreen, Mode, NodeEditor, NodeField, SortDraft, SortEditor, UsernameEditor},
+    changes::TailnetChanges,
+    clipboard,
+    config::{get_config_path, is_ephemeral, save_config, Config, SortField, SortKey},
+    cooldowns::{load_cooldowns, start_cooldown},
+    dotfiles,
+    keys::{
+        Action, ACTION_MENU_ACTIONS, BROWSE_ACTIONS, CHANGES_ACTIONS, DIAGNOSTICS_ACTIONS, GROUP_ACTIONS, HELP_ACTIONS, OUTPUT_ACTIONS,
+        SORT_ACTIONS, TUNNEL_ACTIONS, USERNAME_ACTIONS,
+    },
+    picker::NodePicker,
+    platform::{expand_home, tailscale_program},
+    polling,
+    known_hosts,
+    netcheck::{self, NetcheckReport},
+    presence::record_presence,
+    secrets,
+    ssh::{forwarding_for, tmux_session_for, ForwardingToggles, SshCommand},
+    ssh_uri,
+    subprocess::Subprocess,
+    sweep::{self, PortState},
+    taildrop::{self, progress_text},
+    tailscale::{
+        cache_status, fetch_tailscale_status, node_list, parse_tailscale_status, TailscaleNode, TailscaleStatus,
+    },
+    tunnels::{self, TunnelState},
+    ui::ui,
+};
+use anyhow::{anyhow, Result};
+use crossterm::{
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, EventStream, KeyCode, KeyEvent, KeyEventKind, MouseEvent,
+        MouseEventKind,
+    },
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use futures::StreamExt;
+use ratatui::{backend::CrosstermBackend, Terminal};
+use std::{
+    io,
+    panic::{self, PanicHookInfo},
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    sync::mpsc,
+};
+
+/// How often cooldowns started by other runs are picked up
+const COOLDOWN_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Shortest `ui.tick_ms`, so a typo can't keep the terminal redrawing nonstop
+const MIN_TICK_MS: u64 = 16;
+
+/// Lines scrolled by one notch of the mouse wheel in the command output pane
+const MOUSE_SCROLL_LINES: isize = 3;
+
+/// Messages delivered to the event loop from background tasks
+enum AppEvent {
+    /// Fresh nodes and client health from a background `tailscale status` run
+    StatusRefreshed(TailscaleStatus),
+    /// A line of output from a one-off command
+    CommandOutputLine {
+        node: String,
+        command: String,
+        line: String,
+    },
+    /// A one-off command has finished
+    CommandFinished {
+        node: String,
+        command: String,
+        status: String,
+    },
+    /// The health sweep has probed a node's ssh port
+    PortProbed { node: String, state: PortState },
+    /// `tailscale netcheck` has finished, with its report or why there is none
+    NetcheckFinished(Result<NetcheckReport, String>),
+}
+
+/// Run the terminal UI for node selection
+pub async fn run_tui(
+    status: TailscaleStatus,
+    config: &mut Config,
+    forwarding: &mut ForwardingToggles,
+) -> Result<TailscaleNode> {
+    // Check the key bindings, hints, actions and theme while errors can still be printed normally,
+    // and start on the last used node if it is still there, by its ID in case it was renamed
+    let last_selected = status
+        .nodes
+        .iter()
+        .find(|node| !config.last_selected_id.is_empty() && node.id == config.last_selected_id)
+        .map_or_else(|| config.last_selected_node.clone(), |node| node.name.clone());
+    let picker = NodePicker::from_status(status)
+        .config(config.clone())
+        .select(last_selected)
+        .standalone()
+        .build()?;
+
+    // Put the terminal back if anything panics while it is in raw mode, which
+    // otherwise leaves Windows Terminal and conhost without echo or line editing
+    let previous_hook = hook_restore_terminal();
+
+    // Setup terminal
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    
+    // Flush to ensure all terminal commands are processed
+    io::Write::flush(&mut stdout)?;
+    
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+    
+    // Additional terminal stabilization for Windows
+    terminal.clear()?;
+
+    let mut app = picker.into_app();
+    app.forwarding = *forwarding;
+    app.tunnels = tunnels::autostart(&app.config, &app.nodes);
+    app.cooldowns = load_cooldowns().unwrap_or_default();
+    if app.config.ui.start_in_groups && !app.config.groups.is_empty() {
+        open_groups(&mut app);
+    }
+
+    // Run the event loop, making sure the terminal is restored even if it fails
+    let result = run_event_loop(&mut terminal, &mut app).await;
+
+    // Restore terminal state
+    restore_terminal();
+    // Drop the restoring hook now that the terminal is back to normal
+    unhook_restore_terminal(previous_hook);
+
+    // Hand back any settings and forwarding changed from inside the UI
+    *config = app.config;
+    *forwarding = app.forwarding;
+
+    // Return result or propagate error
+    result
+}
+
+/// Leave raw mode and the alternate screen. Every step is attempted even if an
+/// earlier one fails, so a half-restored console is never left behind.
+pub(crate) fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture, crossterm::cursor::Show);
+}
+
+/// A panic hook that can be shared between the hook wrapping it and whoever puts it back
+pub(crate) type PanicHook = Arc<dyn Fn(&PanicHookInfo<'_>) + Send + Sync>;
+
+/// Restore the terminal before any panic is reported, returning the hook that
+/// was installed before so it can be put back afterwards
+pub(crate) fn hook_restore_terminal() -> PanicHook {
+    let previous: PanicHook = Arc::from(panic::take_hook());
+    let report = Arc::clone(&previous);
+    panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        report(info);
+    }));
+    previous
+}
+
+/// Put back the panic hook that `hook_restore_terminal` replaced
+pub(crate) fn unhook_restore_terminal(previous: PanicHook) {
+    let _ = panic::take_hook();
+    panic::set_hook(Box::new(move |info| previous(info)));
+}
+
+/// Drive the UI until a node is selected or the user exits
+async fn run_event_loop(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut App,
+) -> Result<TailscaleNode> {
+    // Draw the initial UI before starting event loop
+    terminal.draw(|f| ui(f, app))?;
+    
+    // Add a delay to let the terminal settle on Windows and ensure first draw is complete
+    if cfg!(windows) {
+        tokio::time::sleep(Duration::from_millis(150)).await;
+    }
+    
+    // Clear any pending events that might have been generated during terminal setup
+    // This is particularly important on Windows/MINGW where spurious events can occur
+    while event::poll(Duration::from_millis(0))? {
+        let _ = event::read()?; // Discard any pending events
+    }
+
+    // Background tasks report back to the event loop through this channel
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let refresh_task = tokio::spawn(refresh_nodes_periodically(
+        app.config.clone(),
+        tx.clone(),
+        app.nodes.len(),
+        app.cached,
+    ));
+    start_health_sweep(app, &tx);
+
+    let mut events = EventStream::new();
+    let mut tick = tokio::time::interval(Duration::from_millis(app.config.ui.tick_ms.max(MIN_TICK_MS)));
+    let mut cooldown_check = tokio::time::interval(COOLDOWN_CHECK_INTERVAL);
+
+    // This loop runs until a node is selected or the user exits
+    let result = loop {
+        // Draw the UI (redraw for any changes)
+        terminal.draw(|f| ui(f, app))?;
+
+        tokio::select! {
+            maybe_event = events.next() => {
+                let event = match maybe_event {
+                    Some(event) => event?,
+                    None => break Err(anyhow!("Terminal event stream closed")),
+                };
+                if let Some(result) = handle_event(app, event, &tx) {
+                    break result;
+                }
+            }
+            Some(app_event) = rx.recv() => {
+                match app_event {
+                    AppEvent::StatusRefreshed(status) => {
+                        // The first fresh list shows what changed since the one saved by the last run
+                        if app.cached {
+                            let changes = TailnetChanges::between(&app.nodes, &status.nodes);
+                            if !changes.is_empty() {
+                                app.changes = Some(changes);
+                            }
+                        }
+                        app.replace_nodes(status.nodes);
+                        app.health = status.health;
+                        app.cached = false;
+                        start_health_sweep(app, &tx);
+                    }
+                    AppEvent::PortProbed { node, state } => {
+                        app.ports.insert(node, state);
+                    }
+                    AppEvent::NetcheckFinished(report) => {
+                        if let Mode::Diagnostics(diagnostics) = &mut app.mode {
+                            diagnostics.report = Some(report);
+                        }
+                    }
+                    // Only fill in the pane if it is still showing this command
+                    AppEvent::CommandOutputLine { node, command, line } => {
+                        if let Mode::CommandOutput(output) = &mut app.mode
+                            && output.node == node
+                            && output.command == command
+                            && output.status.is_none()
+                        {
+                            output.lines.push(line);
+                        }
+                    }
+                    AppEvent::CommandFinished { node, command, status } => {
+                        if let Mode::CommandOutput(output) = &mut app.mode
+                            && output.node == node
+                            && output.command == command
+                            && output.status.is_none()
+                        {
+                            output.status = Some(status);
+                        }
+                    }
+                }
+            }
+            // Periodic redraw so time-based UI state stays current
+            _ = tick.tick() => {}
+            // Pick up cooldowns started by other runs
+            _ = cooldown_check.tick() => {
+                if let Ok(cooldowns) = load_cooldowns() {
+                    app.cooldowns = cooldowns;
+                }
+            }
+        }
+    };
+
+    refresh_task.abort();
+    result
+}
+
+/// Probe the ssh port of every online node not probed yet this run, in the
+/// background. Nodes that come online later are probed when a refresh lists them.
+fn start_health_sweep(app: &mut App, tx: &mpsc::UnboundedSender<AppEvent>) {
+    let targets = sweep::unprobed(&app.config, &app.nodes, &mut app.ports);
+    if targets.is_empty() {
+        return;
+    }
+    let parallelism = app.config.ssh.health_sweep_parallelism;
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        sweep::sweep(targets, parallelism, |node, state| {
+            let _ = tx.send(AppEvent::PortProbed { node, state });
+        })
+        .await;
+    });
+}
+
+/// Handle a single terminal event, returning a result once the picker is done
+fn handle_event(
+    app: &mut App,
+    event: Event,
+    tx: &mpsc::UnboundedSender<AppEvent>,
+) -> Option<Result<TailscaleNode>> {
+    // Only process key press events, not key release events
+    // This prevents double triggering on Windows/MINGW
+    // Other event types (resize, focus, etc.) are ignored
+    let key = match event {
+        Event::Key(key) => key,
+        Event::Mouse(mouse) => return handle_mouse(app, mouse),
+        _ => return None,
+    };
+    if key.kind != KeyEventKind::Press {
+        return None;
+    }
+
+    // Exit on the quit keys (Ctrl+C or Ctrl+Q by default), whatever mode we are in
+    if app.keys.action(&key, &[]) == Some(Action::Quit) {
+        return Some(Err(anyhow!("User cancelled")));
+    }
+
+    match app.mode {
+        Mode::Browse => handle_browse_key(app, key, tx),
+        Mode::CommandPrompt { .. } => {
+            handle_prompt_key(app, key, tx);
+            None
+        }
+        Mode::SendPrompt { .. } => {
+            handle_send_prompt_key(app, key, tx);
+            None
+        }
+        Mode::CommandOutput(_) => {
+            handle_output_key(app, key);
+            None
+        }
+        Mode::UsernameSettings(_) => {
+            handle_username_settings_key(app, key);
+            None
+        }
+        Mode::EditNode(_) => {
+            handle_node_editor_key(app, key);
+            None
+        }
+        Mode::Help(_) => {
+            handle_help_key(app, key);
+            None
+        }
+        Mode::ActionMenu { .. } => {
+            handle_action_menu_key(app, key, tx);
+            None
+        }
+        Mode::Groups { .. } => {
+            handle_groups_key(app, key);
+            None
+        }
+        Mode::Tunnels { .. } => {
+            handle_tunnels_key(app, key);
+            None
+        }
+        Mode::Changes { .. } => {
+            handle_changes_key(app, key);
+            None
+        }
+        Mode::Diagnostics(_) => {
+            handle_diagnostics_key(app, key, tx);
+            None
+        }
+        Mode::Sorts(_) => {
+            handle_sorts_key(app, key);
+            None
+        }
+    }
+}
+
+/// Handle a key while browsing and filtering the node list
+fn handle_browse_key(
+    app: &mut App,
+    key: KeyEvent,
+    tx: &mpsc::UnboundedSender<AppEvent>,
+) -> Option<Result<TailscaleNode>> {
+    match app.keys.action(&key, BROWSE_ACTIONS) {
+        // Connect to the selected node
+        Some(Action::Select) => return choose_selected(app),
+        // Open the one-off command prompt for the selected node
+        Some(Action::RunCommand) if app.get_selected_node().is_some() => {
+            app.mode = Mode::CommandPrompt { input: String::new() };
+        }
+        // Edit default usernames for the scopes that apply to the selected node
+        Some(Action::EditUsernames) => {
+            if let Some(node) = app.get_selected_node() {
+                app.mode = Mode::UsernameSettings(Box::new(UsernameEditor::new(node.clone())));
+            }
+        }
+        // Edit the username, alias, port and notes kept for the selected node
+        Some(Action::EditNode) => {
+            if let Some(node) = app.get_selected_node() {
+                app.mode = Mode::EditNode(Box::new(NodeEditor::new(node.clone())));
+            }
+        }
+        // Sync dotfiles to the selected node
+        Some(Action::SyncDotfiles) => start_dotfiles_sync(app, tx),
+        Some(Action::Help) => open_help(app),
+        // Choose a custom action to run on the selected node
+        Some(Action::Actions) if app.get_selected_node().is_some() => {
+            app.mode = Mode::ActionMenu { cursor: 0, confirm: None };
+        }
+        Some(Action::Browse) => browse_selected(app, tx),
+        // Ask for a file to send to the selected node with Taildrop
+        Some(Action::SendFile) if app.get_selected_node().is_some() => {
+            app.mode = Mode::SendPrompt { input: String::new() };
+        }
+        Some(Action::ShowCommand) => show_ssh_command(app),
+        Some(Action::CopyUri) => copy_node_uri(app),
+        Some(action @ (Action::ToggleAgent | Action::ToggleX11 | Action::ToggleTty)) => toggle_forwarding(app, action),
+        Some(Action::Groups) => open_groups(app),
+        Some(Action::Tunnels) => app.mode = Mode::Tunnels { cursor: 0 },
+        Some(Action::Changes) => app.mode = Mode::Changes { scroll: 0 },
+        Some(Action::Diagnostics) => start_netcheck(app, tx),
+        Some(Action::Sort) => open_sorts(app),
+        // Moving around and filtering work the same as in an embedded picker
+        action => app.navigate(action, &key),
+    }
+
+    None
+}
+
+/// Handle the scroll wheel and clicks: scrolling moves the selection or the
+/// output pane, a click selects a node and a double click connects to it
+fn handle_mouse(app: &mut App, mouse: MouseEvent) -> Option<Result<TailscaleNode>> {
+    if matches!(app.mode, Mode::Browse) {
+        if app.mouse(&mouse) {
+            return choose_selected(app);
+        }
+        return None;
+    }
+
+    match (&mut app.mode, mouse.kind) {
+        (Mode::CommandOutput(output), MouseEventKind::ScrollUp) => output.scroll_by(-MOUSE_SCROLL_LINES),
+        (Mode::CommandOutput(output), MouseEventKind::ScrollDown) => output.scroll_by(MOUSE_SCROLL_LINES),
+        _ => {}
+    }
+    None
+}
+
+/// Finish with the selected node, unless it is a phone or tablet, which
+/// can't be connected to; that only gets a note
+fn choose_selected(app: &mut App) -> Option<Result<TailscaleNode>> {
+    // Choosing a folded row lists its nodes instead
+    if app.unfold_selected() {
+        return None;
+    }
+    let node = app.get_selected_node()?.clone();
+    if node.platform().is_mobile() {
+        let message = format!(
+            "{} runs {}, which has no SSH server to connect to. Add \"mobile\" to `hide` in the ui section to \
+             leave phones and tablets out of the list.",
+            node.name, node.os
+        );
+        show_message(app, node.name, "Can't connect", &message);
+        return None;
+    }
+    Some(Ok(node))
+}
+
+/// Handle a key while typing a one-off command
+fn handle_prompt_key(app: &mut App, key: KeyEvent, tx: &mpsc::UnboundedSender<AppEvent>) {
+    let Mode::CommandPrompt { input } = &mut app.mode else {
+        return;
+    };
+
+    match key.code {
+        KeyCode::Esc => app.mode = Mode::Browse,
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        KeyCode::Char(c) => input.push(c),
+        KeyCode::Enter => {
+            let command = input.trim().to_string();
+            if command.is_empty() {
+                return;
+            }
+            let Some(node) = app.get_selected_node() else {
+                app.mode = Mode::Browse;
+                return;
+            };
+
+            // Never prompt for a password: the terminal belongs to the UI right now
+            let (username, _) = app.config.resolve_username(node);
+            let ssh = SshCommand::for_node(&app.config, &username, node)
+                .option("BatchMode", "yes")
+                .remote_command(vec![command.clone()]);
+            let node_name = node.name.clone();
+            start_background_commands(app, tx, node_name, command, vec![ssh.command()]);
+        }
+        _ => {}
+    }
+}
+
+/// Handle a key while typing the path of a file to send with Taildrop
+fn handle_send_prompt_key(app: &mut App, key: KeyEvent, tx: &mpsc::UnboundedSender<AppEvent>) {
+    let Mode::SendPrompt { input } = &mut app.mode else {
+        return;
+    };
+
+    match key.code {
+        KeyCode::Esc => app.mode = Mode::Browse,
+        KeyCode::Backspace => {
+            input.pop();
+        }
+        KeyCode::Char(c) => input.push(c),
+        KeyCode::Enter => {
+            let path = input.trim().to_string();
+            if path.is_empty() {
+                return;
+            }
+            let Some(node) = app.get_selected_node().cloned() else {
+                app.mode = Mode::Browse;
+                return;
+            };
+            let title = format!("send {}", path);
+            match expand_home(&path) {
+                Ok(file) if file.is_file() => start_taildrop(app, tx, node, file, title),
+                Ok(file) => show_message(app, node.name, &title, &format!("{} is not a file", file.display())),
+                Err(err) => show_message(app, node.name, &title, &format!("{:#}", err)),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Send a file with Taildrop in the background, reporting every tenth of it in the output pane
+fn start_taildrop(
+    app: &mut App,
+    tx: &mpsc::UnboundedSender<AppEvent>,
+    node: TailscaleNode,
+    file: PathBuf,
+    title: String,
+) {
+    let config = app.config.clone();
+    let pane_node = node.name.clone();
+    let (node_name, command, tx) = (node.name.clone(), title.clone(), tx.clone());
+    let task = tokio::spawn(async move {
+        let send_line = |line: String| {
+            let _ = tx.send(AppEvent::CommandOutputLine {
+                node: node_name.clone(),
+                command: command.clone(),
+                line,
+            });
+        };
+        let mut reported = None;
+        let result = taildrop::send(&config, &node, &file, |sent, total| {
+            let tenth = (sent * 10).checked_div(total).unwrap_or(10);
+            if reported != Some(tenth) {
+                reported = Some(tenth);
+                send_line(progress_text(sent, total));
+            }
+        })
+        .await;
+        let status = match result {
+            Ok(()) => "sent".to_string(),
+            Err(err) => {
+                send_line(format!("{:#}", err));
+                "failed".to_string()
+            }
+        };
+        let _ = tx.send(AppEvent::CommandFinished {
+            node: node_name,
+            command,
+            status,
+        });
+    });
+
+    app.mode = Mode::CommandOutput(CommandOutput {
+        node: pane_node,
+        command: title,
+        lines: Vec::new(),
+        status: None,
+        scroll: 0,
+        follow: true,
+        task: Some(task.abort_handle()),
+    });
+}
+
+/// Sync the configured dotfiles to the selected node, showing progress in the output pane
+fn start_dotfiles_sync(app: &mut App, tx: &mpsc::UnboundedSender<AppEvent>) {
+    let Some(node) = app.get_selected_node() else {
+        return;
+    };
+    let node_name = node.name.clone();
+
+    let Some(dotfiles) = &app.config.dotfiles else {
+        show_message(app, node_name, "sync dotfiles", "No `dotfiles` section in the config file");
+        return;
+    };
+
+    // Never prompt for a password: the terminal belongs to the UI right now
+    let (username, _) = app.config.resolve_username(node);
+    let ssh = SshCommand::for_node(&app.config, &username, node).option("BatchMode", "yes");
+    match dotfiles::sync_commands(dotfiles, &ssh) {
+        Ok(commands) => start_background_commands(app, tx, node_name, "sync dotfiles".to_string(), commands),
+        Err(err) => show_message(app, node_name, "sync dotfiles", &format!("{:#}", err)),
+    }
+}
+
+/// Show the ssh command that connecting to the selected node would run, like `--dry-run`
+fn show_ssh_command(app: &mut App) {
+    let Some(node) = app.get_selected_node() else {
+        return;
+    };
+    let (username, _) = app.config.resolve_username(node);
+    let line = SshCommand::for_node(&app.config, &username, node)
+        .toggled(&app.forwarding)
+        .attach_tmux(tmux_session_for(&app.config, node, None))
+        .shell_line();
+    show_message(app, node.name.clone(), "ssh command", &line);
+}
+
+/// Turn agent forwarding or a forced terminal on or off, or move X11 forwarding
+/// on to its next setting, for the session the picker starts. The detail pane
+/// is opened to show the result.
+fn toggle_forwarding(app: &mut App, action: Action) {
+    let Some(node) = app.get_selected_node() else {
+        return;
+    };
+    let current = app.forwarding.apply(forwarding_for(&app.config, node));
+    match action {
+        Action::ToggleAgent => app.forwarding.agent = Some(!current.agent),
+        Action::ToggleX11 => app.forwarding.x11 = Some(current.x11.next()),
+        Action::ToggleTty => app.forwarding.tty = Some(!current.tty),
+        _ => return,
+    }
+    app.show_details = true;
+}
+
+/// Copy the `ssh://` URI for the selected node, for terminals and launchers,
+/// and show it in case the terminal doesn't take it
+fn copy_node_uri(app: &mut App) {
+    let Some(node) = app.get_selected_node() else {
+        return;
+    };
+    // A username from a secret manager is only fetched when connecting
+    let user = secrets::username_group(&app.config, node)
+        .is_none()
+        .then(|| app.config.resolve_username(node).0);
+    let ssh = SshCommand::for_node(&app.config, user.as_deref().unwrap_or_default(), node);
+    let uri = ssh_uri::format(user.as_deref(), ssh.host(), ssh.port());
+    let message = match clipboard::copy(&uri) {
+        Ok(()) => format!("Copied to the clipboard: {}", uri),
+        Err(err) => format!("Couldn't copy {}: {:#}", uri, err),
+    };
+    show_message(app, node.name.clone(), "ssh URI", &message);
+}
+
+/// Open the output pane with a fixed message instead of command output
+fn show_message(app: &mut App, node: String, title: &str, message: &str) {
+    app.mode = Mode::CommandOutput(CommandOutput {
+        node,
+        command: title.to_string(),
+        lines: vec![message.to_string()],
+        status: Some("not run".to_string()),
+        scroll: 0,
+        follow: false,
+        task: None,
+    });
+}
+
+/// Run commands in the background and open the output pane to show their results
+fn start_background_commands(
+    app: &mut App,
+    tx: &mpsc::UnboundedSender<AppEvent>,
+    node: String,
+    title: String,
+    commands: Vec<std::process::Command>,
+) {
+    start_background_commands_with_input(app, tx, node, title, commands, None);
+}
+
+/// Like `start_background_commands`, writing `input` to each command's stdin
+fn start_background_commands_with_input(
+    app: &mut App,
+    tx: &mpsc::UnboundedSender<AppEvent>,
+    node: String,
+    title: String,
+    commands: Vec<std::process::Command>,
+    input: Option<String>,
+) {
+    let task = tokio::spawn(run_background_commands(commands, input, node.clone(), title.clone(), tx.clone()));
+
+    app.mode = Mode::CommandOutput(CommandOutput {
+        node,
+        command: title,
+        lines: Vec::new(),
+        status: None,
+        scroll: 0,
+        follow: true,
+        task: Some(task.abort_handle()),
+    });
+}
+
+/// Handle a key in the default username settings popup
+fn handle_username_settings_key(app: &mut App, key: KeyEvent) {
+    let Mode::UsernameSettings(editor) = &mut app.mode else {
+        return;
+    };
+
+    // The node changed under an edit: decide which version the edit is for first
+    if let Some(fresh) = editor.conflict.clone() {
+        let old_name = editor.node.name.clone();
+        match key.code {
+            KeyCode::Enter => {
+                editor.conflict = None;
+                editor.message = if editor.retarget(fresh) {
+                    Some("The edit now applies to the node as Tailscale describes it".to_string())
+                } else {
+                    editor.input = None;
+                    Some("That scope no longer applies to the node, so the edit was discarded".to_string())
+                };
+            }
+            KeyCode::Char('k') => {
+                editor.conflict = None;
+                editor.message = Some(format!("Keeping the edit for {} as it was", old_name));
+            }
+            KeyCode::Esc => {
+                editor.conflict = None;
+                editor.input = None;
+                editor.retarget(fresh);
+                editor.message = Some("Edit discarded".to_string());
+            }
+            _ => {}
+        }
+        return;
+    }
+
+    // Typing a new value for the highlighted scope
+    if let Some(input) = &mut editor.input {
+        match key.code {
+            KeyCode::Esc => editor.input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Enter => {
+                let username = input.trim().to_string();
+                let scope = editor.selected_scope().clone();
+                editor.input = None;
+                app.config.set_username_at(&scope, Some(username));
+                save_username_settings(app);
+            }
+            _ => {}
+        }
+        return;
+    }
+
+    // Delete and Backspace clear the highlighted scope, unless bound to something else
+    let action = app.keys.action(&key, USERNAME_ACTIONS);
+    let clear = action.is_none() && matches!(key.code, KeyCode::Delete | KeyCode::Backspace);
+    if (action == Some(Action::Select) || clear) && !editor.selected_scope().is_editable() {
+        editor.message = Some("This comes from tailscale status and can't be changed here".to_string());
+        return;
+    }
+
+    match action {
+        Some(Action::Back) => app.mode = Mode::Browse,
+        Some(Action::Up) => editor.cursor = editor.cursor.saturating_sub(1),
+        Some(Action::Down) => editor.cursor = (editor.cursor + 1).min(editor.scopes.len() - 1),
+        Some(Action::Select) => {
+            let current = app.config.username_at(editor.selected_scope()).unwrap_or_default();
+            editor.input = Some(current.to_string());
+        }
+        None if clear => {
+            let scope = editor.selected_scope().clone();
+            app.config.set_username_at(&scope, None);
+            save_username_settings(app);
+        }
+        _ => {}
+    }
+}
+
+/// Persist username changes made in the settings popup and report the outcome there
+fn save_username_settings(app: &mut App) {
+    let message = match save_config(&app.config) {
+        Ok(()) if is_ephemeral() => "Applied for this run only (--ephemeral)".to_string(),
+        Ok(()) => "Saved".to_string(),
+        Err(err) => format!("Failed to save config: {:#}", err),
+    };
+    if let Mode::UsernameSettings(editor) = &mut app.mode {
+        editor.message = Some(message);
+    }
+}
+
+/// Handle a key in the node editor popup
+fn handle_node_editor_key(app: &mut App, key: KeyEvent) {
+    let Mode::EditNode(editor) = &mut app.mode else {
+        return;
+    };
+
+    // Typing a new value for the highlighted field; a rejected value stays to be corrected
+    if let Some(input) = &mut editor.input {
+        match key.code {
+            KeyCode::Esc => editor.input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Enter => match NodeField::ALL[editor.cursor].set(&mut app.config, &editor.node.name, input) {
+                Ok(()) => {
+                    editor.input = None;
+                    save_node_settings(app);
+                }
+                Err(err) => editor.message = Some(format!("{:#}", err)),
+            },
+            _ => {}
+        }
+        return;
+    }
+
+    // Delete and Backspace clear the highlighted field, unless bound to something else
+    let action = app.keys.action(&key, USERNAME_ACTIONS);
+    match action {
+        Some(Action::Back) => app.mode = Mode::Browse,
+        Some(Action::Up) => editor.cursor = editor.cursor.saturating_sub(1),
+        Some(Action::Down) => editor.cursor = (editor.cursor + 1).min(NodeField::ALL.len() - 1),
+        Some(Action::Select) => editor.input = Some(editor.selected_field().value(&app.config, &editor.node.name)),
+        // Clearing can't fail: only typed values are checked
+        None if matches!(key.code, KeyCode::Delete | KeyCode::Backspace)
+            && editor.selected_field().set(&mut app.config, &editor.node.name, "").is_ok() =>
+        {
+            save_node_settings(app);
+        }
+        _ => {}
+    }
+}
+
+/// Persist changes made in the node editor, report the outcome there, and
+/// list the node under its new alias
+fn save_node_settings(app: &mut App) {
+    let message = match save_config(&app.config) {
+        Ok(()) if is_ephemeral() => "Applied for this run only (--ephemeral)".to_string(),
+        Ok(()) => "Saved".to_string(),
+        Err(err) => format!("Failed to save config: {:#}", err),
+    };
+    app.reindex();
+    if let Mode::EditNode(editor) = &mut app.mode {
+        editor.message = Some(message);
+    }
+}
+
+/// Open the help overlay, looking up the config and tailscale locations it shows
+fn open_help(app: &mut App) {
+    let config_path = match get_config_path() {
+        Ok(Some(path)) => path.display().to_string(),
+        Ok(None) if is_ephemeral() => "none (--ephemeral)".to_string(),
+        Ok(None) => "none (no home directory)".to_string(),
+        Err(err) => format!("unavailable: {:#}", err),
+    };
+    let tailscale_program = match tailscale_program(app.config.tailscale_path.as_deref()) {
+        Ok(path) => path.display().to_string(),
+        Err(err) => format!("not found: {:#}", err).lines().next().unwrap_or_default().to_string(),
+    };
+
+    app.mode = Mode::Help(HelpScreen {
+        config_path,
+        tailscale_program,
+        scroll: 0,
+    });
+}
+
+/// Handle a key in the custom action menu: navigate and run the highlighted
+/// action, or run one directly with its own key
+fn handle_action_menu_key(app: &mut App, key: KeyEvent, tx: &mpsc::UnboundedSender<AppEvent>) {
+    let entries = app.menu_entries();
+    let cooling = app.get_selected_node().is_some_and(|node| app.cooldowns.active(&node.name).is_some());
+    let Mode::ActionMenu { cursor, confirm } = &mut app.mode else {
+        return;
+    };
+
+    let chosen = match app.keys.action(&key, ACTION_MENU_ACTIONS) {
+        Some(Action::Back) => {
+            app.mode = Mode::Browse;
+            return;
+        }
+        Some(Action::Up) => {
+            *cursor = cursor.saturating_sub(1);
+            return;
+        }
+        Some(Action::Down) => {
+            *cursor = (*cursor + 1).min(entries.len().saturating_sub(1));
+            return;
+        }
+        Some(Action::Select) => *cursor,
+        _ => match entries.iter().position(|entry| entry.key.is_some_and(|bound| bound.matches(&key))) {
+            Some(position) => position,
+            None => return,
+        },
+    };
+
+    let Some(entry) = entries.get(chosen) else {
+        return;
+    };
+    // A disruptive action on a node still settling from the last one needs choosing twice
+    let cooldown_minutes = match &entry.kind {
+        ActionKind::Custom(action) => action.cooldown_minutes,
+        _ => None,
+    };
+    if cooldown_minutes.is_some() && cooling && *confirm != Some(chosen) {
+        *cursor = chosen;
+        *confirm = Some(chosen);
+        return;
+    }
+    if let Some(minutes) = cooldown_minutes
+        && let Some(node) = app.get_selected_node()
+    {
+        let _ = start_cooldown(&node.name, &entry.name, minutes);
+        if let Ok(cooldowns) = load_cooldowns() {
+            app.cooldowns = cooldowns;
+        }
+    }
+    let Some(node) = app.get_selected_node() else {
+        return;
+    };
+    let (command, input) = command_for(entry, &app.config, node);
+    let title = match &entry.kind {
+        ActionKind::ForwardService(service) => {
+            format!("{} on localhost:{} (Esc stops it)", entry.name, forward_port(service))
+        }
+        _ => entry.name.clone(),
+    };
+    let node_name = node.name.clone();
+    start_background_commands_with_input(app, tx, node_name, title, vec![command], input);
+}
+
+/// Open the selected node's web UI in the browser. A node with several web
+/// services gets the action menu instead, on the first of them.
+fn browse_selected(app: &mut App, tx: &mpsc::UnboundedSender<AppEvent>) {
+    let Some(node) = app.get_selected_node().cloned() else {
+        return;
+    };
+    match browse_action(&app.config, &node) {
+        Some(entry) => {
+            let (command, input) = command_for(&entry, &app.config, &node);
+            start_background_commands_with_input(app, tx, node.name.clone(), entry.name, vec![command], input);
+        }
+        None => {
+            let entries = app.menu_entries();
+            if let Some(cursor) = entries.iter().position(|entry| matches!(entry.kind, ActionKind::OpenService(_))) {
+                app.mode = Mode::ActionMenu { cursor, confirm: None };
+            }
+        }
+    }
+}
+
+/// Handle a key in the tunnel list: choosing a tunnel that ended opens it again
+fn handle_tunnels_key(app: &mut App, key: KeyEvent) {
+    let Mode::Tunnels { cursor } = &mut app.mode else {
+        return;
+    };
+
+    let list = app.tunnels.lock().map(|list| list.clone()).unwrap_or_default();
+    match app.keys.action(&key, TUNNEL_ACTIONS) {
+        Some(Action::Back | Action::Tunnels) => app.mode = Mode::Browse,
+        Some(Action::Up) => *cursor = cursor.saturating_sub(1),
+        Some(Action::Down) => *cursor = (*cursor + 1).min(list.len().saturating_sub(1)),
+        Some(Action::Select) => {
+            let Some(tunnel) = list.get(*cursor).filter(|tunnel| tunnel.state != TunnelState::Running) else {
+                return;
+            };
+            let node = app.nodes.iter().find(|node| node.name == tunnel.node);
+            let services = app.config.node_settings(&tunnel.node).services;
+            if let (Some(node), Some(service)) = (node, services.get(&tunnel.service)) {
+                tunnels::start(&app.tunnels, &app.config, node, &tunnel.service, service);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Open the group list with the group currently shown highlighted
+fn open_groups(app: &mut App) {
+    let cursor = match &app.group {
+        Some(group) => app.config.groups.keys().position(|name| name == group).map_or(0, |position| position + 1),
+        None => 0,
+    };
+    app.mode = Mode::Groups { cursor };
+}
+
+/// Handle a key in the group list: choosing a group lists just its nodes
+fn handle_groups_key(app: &mut App, key: KeyEvent) {
+    let Mode::Groups { cursor } = &mut app.mode else {
+        return;
+    };
+
+    // The first entry is every node
+    let last = app.config.groups.len();
+    match app.keys.action(&key, GROUP_ACTIONS) {
+        Some(Action::Back | Action::Groups) => app.mode = Mode::Browse,
+        Some(Action::Up) => *cursor = cursor.saturating_sub(1),
+        Some(Action::Down) => *cursor = (*cursor + 1).min(last),
+        Some(Action::Top) => *cursor = 0,
+        Some(Action::Bottom) => *cursor = last,
+        Some(Action::Select) => {
+            let group = cursor.checked_sub(1).and_then(|index| app.config.groups.keys().nth(index)).cloned();
+            app.set_group(group);
+            app.mode = Mode::Browse;
+        }
+        _ => {}
+    }
+}
+
+/// Open the sort popup with the sort in use highlighted
+fn open_sorts(app: &mut App) {
+    let cursor = match &app.config.ui.sort {
+        Some(sort) => app.config.sorts.keys().position(|name| name == sort).map_or(0, |position| position + 1),
+        None => 0,
+    };
+    app.mode = Mode::Sorts(Box::new(SortEditor {
+        cursor,
+        ..SortEditor::default()
+    }));
+}
+
+/// Handle a key in the sort popup: choose a sort for the list, or start
+/// composing one with `n` (new) or `e` (edit the highlighted one)
+fn handle_sorts_key(app: &mut App, key: KeyEvent) {
+    let Mode::Sorts(editor) = &mut app.mode else {
+        return;
+    };
+    if editor.draft.is_some() {
+        handle_sort_draft_key(app, key);
+        return;
+    }
+
+    // The first entry is Tailscale's order
+    let last = app.config.sorts.len();
+    let highlighted = editor.cursor.checked_sub(1).and_then(|index| app.config.sorts.keys().nth(index)).cloned();
+    let action = app.keys.action(&key, SORT_ACTIONS);
+    match action {
+        Some(Action::Back | Action::Sort) => app.mode = Mode::Browse,
+        Some(Action::Up) => editor.cursor = editor.cursor.saturating_sub(1),
+        Some(Action::Down) => editor.cursor = (editor.cursor + 1).min(last),
+        Some(Action::Top) => editor.cursor = 0,
+        Some(Action::Bottom) => editor.cursor = last,
+        // Close once the choice is saved, or stay open to say why it wasn't
+        Some(Action::Select) => {
+            app.set_sort(highlighted);
+            match save_config(&app.config) {
+                Ok(()) => app.mode = Mode::Browse,
+                Err(err) => {
+                    if let Mode::Sorts(editor) = &mut app.mode {
+                        editor.message = Some(format!("Failed to save config: {:#}", err));
+                    }
+                }
+            }
+        }
+        Some(_) => {}
+        None => match key.code {
+            KeyCode::Char('n') => {
+                editor.draft = Some(SortDraft {
+                    original: None,
+                    name: String::new(),
+                    keys: vec![SortKey {
+                        field: SortField::Name,
+                        descending: false,
+                    }],
+                    cursor: 0,
+                    naming: false,
+                });
+            }
+            KeyCode::Char('e') => match highlighted {
+                Some(name) => {
+                    editor.draft = Some(SortDraft {
+                        keys: app.config.sorts[&name].clone(),
+                        name: name.clone(),
+                        original: Some(name),
+                        cursor: 0,
+                        naming: false,
+                    });
+                }
+                None => editor.message = Some("Tailscale's order can't be edited; press n for a new sort".to_string()),
+            },
+            KeyCode::Delete | KeyCode::Backspace => {
+                let Some(name) = highlighted else {
+                    return;
+                };
+                app.config.sorts.remove(&name);
+                editor.cursor = editor.cursor.min(last - 1);
+                if app.config.ui.sort.as_ref() == Some(&name) {
+                    app.set_sort(None);
+                }
+                let message = save_sorts(app);
+                if let Mode::Sorts(editor) = &mut app.mode {
+                    editor.message = Some(format!("Deleted {}: {}", name, message));
+                }
+            }
+            _ => {}
+        },
+    }
+}
+
+/// Handle a key while composing a sort: arrows pick a key and its field,
+/// Space flips its direction, Del removes it and `s` saves the sort
+fn handle_sort_draft_key(app: &mut App, key: KeyEvent) {
+    let Mode::Sorts(editor) = &mut app.mode else {
+        return;
+    };
+    let Some(draft) = &mut editor.draft else {
+        return;
+    };
+
+    // Typing the name to save under
+    if draft.naming {
+        match key.code {
+            KeyCode::Esc => draft.naming = false,
+            KeyCode::Backspace => {
+                draft.name.pop();
+            }
+            KeyCode::Char(c) => draft.name.push(c),
+            KeyCode::Enter => save_sort_draft(app),
+            _ => {}
+        }
+        return;
+    }
+
+    // One past the last key is the row that adds a key
+    let adding = draft.cursor == draft.keys.len();
+    match app.keys.action(&key, SORT_ACTIONS) {
+        Some(Action::Back) => {
+            editor.draft = None;
+            editor.message = Some("Discarded".to_string());
+        }
+        Some(Action::Up) => draft.cursor = draft.cursor.saturating_sub(1),
+        Some(Action::Down) => draft.cursor = (draft.cursor + 1).min(draft.keys.len()),
+        Some(Action::Top) => draft.cursor = 0,
+        Some(Action::Bottom) => draft.cursor = draft.keys.len(),
+        Some(Action::Select) if adding => {
+            // Start on the first field the sort doesn't use yet
+            let field = SortField::ALL
+                .into_iter()
+                .find(|field| draft.keys.iter().all(|key| key.field != *field))
+                .unwrap_or_default();
+            draft.keys.push(SortKey { field, descending: false });
+        }
+        Some(_) => {}
+        None => match key.code {
+            KeyCode::Char('s') if draft.keys.is_empty() => editor.message = Some("Add a key first".to_string()),
+            KeyCode::Char('s') => draft.naming = true,
+            _ if adding => {}
+            KeyCode::Left => draft.keys[draft.cursor].field = draft.keys[draft.cursor].field.cycle(-1),
+            KeyCode::Right => draft.keys[draft.cursor].field = draft.keys[draft.cursor].field.cycle(1),
+            KeyCode::Char(' ') => draft.keys[draft.cursor].descending = !draft.keys[draft.cursor].descending,
+            KeyCode::Delete | KeyCode::Backspace => {
+                draft.keys.remove(draft.cursor);
+            }
+            _ => {}
+        },
+    }
+}
+
+/// Save the sort being composed under the typed name and order the list by it
+fn save_sort_draft(app: &mut App) {
+    let Mode::Sorts(editor) = &mut app.mode else {
+        return;
+    };
+    let Some(draft) = editor.draft.take_if(|draft| !draft.name.trim().is_empty()) else {
+        editor.message = Some("Type a name for the sort".to_string());
+        return;
+    };
+
+    let name = draft.name.trim().to_string();
+    if let Some(original) = draft.original.filter(|original| *original != name) {
+        app.config.sorts.remove(&original);
+    }
+    app.config.sorts.insert(name.clone(), draft.keys);
+    app.set_sort(Some(name.clone()));
+    let message = save_sorts(app);
+    if let Mode::Sorts(editor) = &mut app.mode {
+        editor.cursor = app.config.sorts.keys().position(|other| *other == name).map_or(0, |position| position + 1);
+        editor.message = Some(message);
+    }
+}
+
+/// Persist the named sorts and the one in use, describing the outcome
+fn save_sorts(app: &App) -> String {
+    match save_config(&app.config) {
+        Ok(()) if is_ephemeral() => "Applied for this run only (--ephemeral)".to_string(),
+        Ok(()) => "Saved".to_string(),
+        Err(err) => format!("Failed to save config: {:#}", err),
+    }
+}
+
+/// Handle a key in the help overlay
+fn handle_help_key(app: &mut App, key: KeyEvent) {
+    let page = app.pane_page_size();
+    let Mode::Help(help) = &mut app.mode else {
+        return;
+    };
+
+    // Scrolling past the key list still leaves the setup section in view
+    let max_scroll = Action::ALL.len();
+    match app.keys.action(&key, HELP_ACTIONS) {
+        Some(Action::Back | Action::Help) => app.mode = Mode::Browse,
+        Some(Action::Up) => help.scroll = help.scroll.saturating_sub(1),
+        Some(Action::Down) => help.scroll = (help.scroll + 1).min(max_scroll),
+        Some(Action::PageUp) => help.scroll = help.scroll.saturating_sub(page),
+        Some(Action::PageDown) => help.scroll = (help.scroll + page).min(max_scroll),
+        _ => {}
+    }
+}
+
+/// Handle a key while the changes since the last run are shown
+fn handle_changes_key(app: &mut App, key: KeyEvent) {
+    let Mode::Changes { scroll } = &mut app.mode else {
+        return;
+    };
+
+    let max_scroll = app.changes.as_ref().map_or(0, |changes| {
+        changes.sections().iter().map(|(_, names)| names.len() + 2).sum::<usize>()
+    });
+    match app.keys.action(&key, CHANGES_ACTIONS) {
+        Some(Action::Back | Action::Changes) => app.mode = Mode::Browse,
+        Some(Action::Up) => *scroll = scroll.saturating_sub(1),
+        Some(Action::Down) => *scroll = (*scroll + 1).min(max_scroll),
+        _ => {}
+    }
+}
+
+/// Open the diagnostics screen and run `tailscale netcheck` in the background
+fn start_netcheck(app: &mut App, tx: &mpsc::UnboundedSender<AppEvent>) {
+    app.mode = Mode::Diagnostics(Diagnostics { report: None, scroll: 0 });
+    let config = app.config.clone();
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        let report = netcheck::run(&config).await.map_err(|err| format!("{:#}", err));
+        let _ = tx.send(AppEvent::NetcheckFinished(report));
+    });
+}
+
+/// Handle a key on the diagnostics screen: scroll, close, or check again
+fn handle_diagnostics_key(app: &mut App, key: KeyEvent, tx: &mpsc::UnboundedSender<AppEvent>) {
+    let Mode::Diagnostics(diagnostics) = &mut app.mode else {
+        return;
+    };
+
+    // The summary, a blank line and a heading come before the DERP regions
+    let max_scroll = match &diagnostics.report {
+        Some(Ok(report)) => report.findings().len() + 2 + report.derp_latency.len(),
+        _ => 0,
+    };
+    match app.keys.action(&key, DIAGNOSTICS_ACTIONS) {
+        Some(Action::Back | Action::Diagnostics) => app.mode = Mode::Browse,
+        Some(Action::Up) => diagnostics.scroll = diagnostics.scroll.saturating_sub(1),
+        Some(Action::Down) => diagnostics.scroll = (diagnostics.scroll + 1).min(max_scroll),
+        Some(Action::Select) if diagnostics.report.is_some() => start_netcheck(app, tx),
+        _ => {}
+    }
+}
+
+/// Handle a key while the command output pane is open
+fn handle_output_key(app: &mut App, key: KeyEvent) {
+    let page = app.pane_page_size() as isize;
+    let Mode::CommandOutput(output) = &mut app.mode else {
+        return;
+    };
+
+    match app.keys.action(&key, OUTPUT_ACTIONS) {
+        Some(Action::Back) => app.mode = Mode::Browse,
+        Some(Action::Up) => output.scroll_by(-1),
+        Some(Action::Down) => output.scroll_by(1),
+        Some(Action::PageUp) => output.scroll_by(-page),
+        Some(Action::PageDown) => output.scroll_by(page),
+        Some(Action::Top) => {
+            output.scroll = 0;
+            output.follow = false;
+        }
+        Some(Action::Bottom) => output.follow = true,
+        _ => {}
+    }
+}
+
+/// Run commands one after another, stopping at the first failure, and send
+/// their output to the event loop line by line
+async fn run_background_commands(
+    commands: Vec<std::process::Command>,
+    input: Option<String>,
+    node: String,
+    command: String,
+    tx: mpsc::UnboundedSender<AppEvent>,
+) {
+    let send_line = |line: String| {
+        let _ = tx.send(AppEvent::CommandOutputLine {
+            node: node.clone(),
+            command: command.clone(),
+            line,
+        });
+    };
+    let mut status = String::from("exit 0");
+
+    for std_command in commands {
+        let program = std_command.get_program().to_string_lossy().into_owned();
+        match run_streaming(std_command, input.as_deref(), &send_line).await {
+            Ok(exit_status) => {
+                status = match exit_status.code() {
+                    Some(code) => format!("exit {}", code),
+                    None => "terminated by signal".to_string(),
+                };
+                if !exit_status.success() {
+                    break;
+                }
+            }
+            Err(err) => {
+                send_line(format!("Failed to execute {}: {}", program, err));
+                status = "failed".to_string();
+                break;
+            }
+        }
+    }
+
+    let _ = tx.send(AppEvent::CommandFinished { node, command, status });
+}
+
+/// Run a command, writing `input` to its stdin first if there is any, and pass
+/// on each line of its stdout and stderr as it arrives
+async fn run_streaming(
+    command: std::process::Command,
+    input: Option<&str>,
+    send_line: &impl Fn(String),
+) -> io::Result<ExitStatus> {
+    let subprocess = Subprocess::start(&command);
+    let mut child = tokio::process::Command::from(command)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Closing the output pane aborts the task, which must not leave commands like `tail -f` behind
+        .kill_on_drop(true)
+        .spawn()
+        .inspect_err(|err| subprocess.finish(Err(err)))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        // A command that doesn't read its input is fine, so a closed pipe is ignored
+        let _ = stdin.write_all(input.as_bytes()).await;
+    }
+
+    tokio::join!(
+        forward_lines(child.stdout.take(), send_line),
+        forward_lines(child.stderr.take(), send_line)
+    );
+    let status = child.wait().await;
+    subprocess.finish(status.as_ref());
+    status
+}
+
+/// Pass on each line of a stream until it ends
+async fn forward_lines(stream: Option<impl AsyncRead + Unpin>, send_line: &impl Fn(String)) {
+    let Some(stream) = stream else {
+        return;
+    };
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    while matches!(reader.read_until(b'\n', &mut line).await, Ok(read) if read > 0) {
+        send_line(String::from_utf8_lossy(&line).trim_end_matches(['\n', '\r']).to_string());
+        line.clear();
+    }
+}
+
+/// Re-run `tailscale status` on an interval and send the results to the event loop.
+/// The interval grows with the tailnet and with how slow `tailscale status` is,
+/// starting from the `nodes` already shown.
+async fn refresh_nodes_periodically(
+    config: Config,
+    tx: mpsc::UnboundedSender<AppEvent>,
+    nodes: usize,
+    refresh_now: bool,
+) {
+    // A list given with --nodes-from stays as it is
+    if node_list().is_some() {
+        return;
+    }
+    let mut interval = polling::status_interval(&config.polling, nodes, Duration::ZERO);
+    // Refresh right away only when the list shown came from the cache
+    let mut wait = if refresh_now { Duration::ZERO } else { interval };
+    let mut last_enriched: Option<Instant> = None;
+
+    loop {
+        tokio::time::sleep(wait).await;
+        wait = interval;
+
+        // A failed refresh keeps the current list; the next tick will try again
+        let started = Instant::now();
+        let Ok(output) = fetch_tailscale_status(&config).await else {
+            continue;
+        };
+        let latency = started.elapsed();
+        let Ok(status) = parse_tailscale_status(&output) else {
+            continue;
+        };
+        if status.nodes.is_empty() {
+            continue;
+        }
+        interval = polling::status_interval(&config.polling, status.nodes.len(), latency);
+        wait = interval;
+
+        let enrich_interval = polling::enrich_interval(&config.polling, status.nodes.len(), interval);
+        if last_enriched.is_none_or(|enriched| enriched.elapsed() >= enrich_interval) {
+            let _ = record_presence(&status.nodes);
+            let _ = known_hosts::prune(&config, &status.nodes);
+            let _ = cache_status(&output);
+            last_enriched = Some(Instant::now());
+        }
+
+        if tx.send(AppEvent::StatusRefreshed(status)).is_err() {
+            // The event loop has gone away
+            break;
+        }
+    }
+}
+
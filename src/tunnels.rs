@@ -0,0 +1,119 @@
+use crate::{
+    actions::{forward_command, forward_port},
+    config::{Config, Service, Transport},
+    ssh::transport_for,
+    subprocess::Subprocess,
+    tailscale::TailscaleNode,
+};
+use std::{
+    process::Stdio,
+    sync::{Arc, Mutex},
+};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+/// A service forward kept open in the background
+#[derive(Debug, Clone)]
+pub struct Tunnel {
+    pub node: String,
+    /// The service's name in the node's `services`
+    pub service: String,
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub state: TunnelState,
+}
+
+/// Whether a tunnel's ssh is still running
+#[derive(Debug, Clone, PartialEq)]
+pub enum TunnelState {
+    Running,
+    /// ssh exited, with its status and last error line
+    Ended(String),
+}
+
+/// The tunnels of this run, shared with the tasks that keep them open
+pub type Tunnels = Arc<Mutex<Vec<Tunnel>>>;
+
+/// Open the forwards of every `autostart` service on the online nodes. They
+/// stay open for as long as ssh-tailscale runs, including the session picked
+/// afterwards. Nodes reached with `tailscale ssh` are skipped, as it can't forward.
+pub fn autostart(config: &Config, nodes: &[TailscaleNode]) -> Tunnels {
+    let tunnels = Tunnels::default();
+    for node in nodes.iter().filter(|node| node.is_online()) {
+        if transport_for(config, node) != Transport::Ssh {
+            continue;
+        }
+        for (name, service) in &config.node_settings(&node.name).services {
+            if service.autostart {
+                start(&tunnels, config, node, name, service);
+            }
+        }
+    }
+    tunnels
+}
+
+/// Open a service's forward in the background, replacing an ended tunnel for it
+pub fn start(tunnels: &Tunnels, config: &Config, node: &TailscaleNode, name: &str, service: &Service) {
+    let tunnel = Tunnel {
+        node: node.name.clone(),
+        service: name.to_string(),
+        local_port: forward_port(service),
+        remote_port: service.port,
+        state: TunnelState::Running,
+    };
+    let Ok(mut list) = tunnels.lock() else {
+        return;
+    };
+    match list.iter_mut().find(|other| other.node == tunnel.node && other.service == tunnel.service) {
+        Some(other) if other.state == TunnelState::Running => return,
+        Some(other) => *other = tunnel,
+        None => list.push(tunnel),
+    }
+    drop(list);
+
+    let command = forward_command(config, node, service);
+    let (tunnels, node, name) = (tunnels.clone(), node.name.clone(), name.to_string());
+    tokio::spawn(async move {
+        let state = TunnelState::Ended(keep_open(command).await);
+        if let Ok(mut list) = tunnels.lock()
+            && let Some(tunnel) = list.iter_mut().find(|tunnel| tunnel.node == node && tunnel.service == name)
+        {
+            tunnel.state = state;
+        }
+    });
+}
+
+/// Run a forward until ssh exits, and describe how it ended. The child is
+/// killed when the runtime shuts down, so no tunnel outlives the program.
+async fn keep_open(command: std::process::Command) -> String {
+    let subprocess = Subprocess::start(&command);
+    let mut child = match tokio::process::Command::from(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            subprocess.finish(Err(&err));
+            return format!("failed to start: {}", err);
+        }
+    };
+
+    let mut last_line = String::new();
+    if let Some(stderr) = child.stderr.take() {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if !line.trim().is_empty() {
+                last_line = line.trim().to_string();
+            }
+        }
+    }
+    let status = child.wait().await;
+    subprocess.finish(status.as_ref());
+    match status {
+        Ok(status) if last_line.is_empty() => status.to_string(),
+        Ok(status) => format!("{}: {}", status, last_line),
+        Err(err) => err.to_string(),
+    }
+}
@@ -0,0 +1,1435 @@
+use crate::{
+    actions::{self, ActionKind},
+    address,
+    humanize, identities,
+    app::{App, CommandOutput, Diagnostics, Fold, HelpScreen, ListView, Mode, NodeEditor, NodeField, Row, SortEditor, UsernameEditor},
+    config::{ActionTarget, CompactMode, Config, RemoteShell, SshClient, Transport},
+    destinations,
+    keys::{Action, KeyBinding},
+    remote_shell, secrets, sort,
+    ssh::{configured_options, forwarding_for, session_limit_for, tmux_session_for, transport_for},
+    sweep::PortState,
+    tailscale::TailscaleNode,
+    theme::Theme,
+    tint,
+    tunnels::TunnelState,
+    usernames::FALLBACK_USERNAME,
+};
+use ratatui::{
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{
+        Block, Borders, Clear, List, ListItem, ListState, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState,
+        Wrap,
+    },
+};
+use std::time::Duration;
+
+/// Terminal height below which the automatic compact layout kicks in
+const COMPACT_HEIGHT: u16 = 20;
+
+/// Terminal sizes below which rows shrink to a status glyph and the name, and
+/// the detail pane takes the whole list area instead of sharing it
+const MINIMAL_WIDTH: u16 = 60;
+const MINIMAL_HEIGHT: u16 = 10;
+
+/// Room taken by the highlight symbol in front of each row
+const HIGHLIGHT_WIDTH: usize = 2;
+/// Blank space after each column
+const COLUMN_GAP: usize = 2;
+/// Widest the user and tags column gets; longer values are cut short
+const MAX_USER_WIDTH: usize = 14;
+/// Narrowest the hostname is cut to before the user column and Tailscale SSH
+/// marker are dropped to make room
+const SHORT_NAME_WIDTH: usize = 24;
+/// Narrowest the hostname is cut to before the IP column is dropped as well
+const MIN_NAME_WIDTH: usize = 16;
+/// Narrowest the status is cut to before other columns give up room
+const MIN_STATUS_WIDTH: usize = 8;
+/// Follows the name of the machine the picker runs on, when it is listed
+const THIS_MACHINE: &str = " (this machine)";
+
+/// How long the header mentions changes since the last run
+const CHANGES_NOTICE: Duration = Duration::from_secs(30);
+
+/// Render the UI using Ratatui
+pub fn ui(f: &mut ratatui::Frame, app: &mut App) {
+    render(f, app, f.size());
+}
+
+/// Render the picker into part of the frame, for applications that embed it
+pub fn render(f: &mut ratatui::Frame, app: &mut App, size: Rect) {
+    // Set again below if the node list is drawn this frame
+    app.list_view = None;
+
+    // Short terminals get a one-line header and footer so the list keeps some rows
+    let minimal = size.width < MINIMAL_WIDTH || size.height < MINIMAL_HEIGHT;
+    let compact = minimal
+        || match app.config.ui.compact {
+            CompactMode::Auto => size.height < COMPACT_HEIGHT,
+            CompactMode::Always => true,
+            CompactMode::Never => false,
+        };
+    // The input line stays visible whenever there is something typed in it
+    let show_footer = app.config.ui.show_footer || !app.filter.is_empty() || !matches!(app.mode, Mode::Browse);
+    let (header_height, footer_height) = match (compact, show_footer) {
+        (true, _) => (1, u16::from(show_footer)),
+        (false, true) => (3, 3),
+        (false, false) => (3, 0),
+    };
+    // Nothing to show before the first status arrives, or for an embedder that gave none
+    let status_bar = app.config.ui.show_status_bar && !minimal && !app.health.backend_state.is_empty();
+
+    // Create layout
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(if compact { 0 } else { 1 })
+        .constraints(
+            [
+                Constraint::Length(header_height), // Header
+                Constraint::Min(3),                // List
+                Constraint::Length(footer_height), // Footer/Search
+                Constraint::Length(u16::from(status_bar)), // Status bar
+            ]
+            .as_ref(),
+        )
+        .split(size);
+
+    // Header with title and node count
+    let title_style = app.theme.title;
+    if compact {
+        let mut line = health_line(app, chunks[1].height, status_bar);
+        let title = match &app.group {
+            Some(group) => format!("Tailscale SSH [{}]  ", group),
+            None => "Tailscale SSH  ".to_string(),
+        };
+        line.spans.insert(0, Span::styled(title, title_style));
+        f.render_widget(Paragraph::new(line), chunks[0]);
+    } else {
+        let title = match &app.group {
+            Some(group) => format!("Tailscale SSH - Select a Node in {}", group),
+            None => "Tailscale SSH - Select a Node".to_string(),
+        };
+        let header_text = vec![
+            Line::from(vec![Span::styled(title, title_style)]),
+            health_line(app, chunks[1].height, status_bar),
+        ];
+        let header = Paragraph::new(header_text)
+            .block(Block::default().borders(Borders::BOTTOM));
+        f.render_widget(header, chunks[0]);
+    }
+
+    // Give the right side of the list to the detail pane when it is open,
+    // or all of it when there is no room to share
+    let mut list_area = chunks[1];
+    let mut list_hidden = false;
+    if app.show_details
+        && !matches!(app.mode, Mode::CommandOutput(_))
+        && let Some(node) = app.get_selected_node()
+    {
+        if minimal {
+            list_hidden = true;
+            render_node_details(f, app, node, chunks[1]);
+        } else {
+            let columns = Layout::default()
+            .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .split(chunks[1]);
+            list_area = columns[0];
+            render_node_details(f, app, node, columns[1]);
+        }
+    }
+
+    // List of nodes from bottom to top, unless a command's output is being shown
+    if let Mode::CommandOutput(output) = &mut app.mode {
+        app.pane_height = Some(usize::from(chunks[1].height.saturating_sub(2)));
+        render_command_output(f, output, chunks[1]);
+    } else if list_hidden {
+        // The detail pane has the whole area
+    } else if !app.rows.is_empty() {
+        // Long lists give their last column to a scrollbar
+        let total = app.rows.len();
+        let visible = usize::from(list_area.height);
+        let scrollable = total > visible;
+        let rows_area = if scrollable {
+            Rect {
+                width: list_area.width.saturating_sub(1),
+                ..list_area
+            }
+        } else {
+            list_area
+        };
+
+        // Rows are drawn bottom-up, so the selection counts from the end.
+        // Keep the previous scroll position unless the selection leaves the view,
+        // or a page jump asked for the selection to be centered.
+        let display_selection = total - 1 - app.selection;
+        let offset = if std::mem::take(&mut app.center_selection) {
+            display_selection.saturating_sub(visible / 2)
+        } else {
+            app.list_offset
+        };
+        let offset = offset
+            .min(total.saturating_sub(visible))
+            .min(display_selection)
+            .max((display_selection + 1).saturating_sub(visible.max(1)));
+
+        // Only the rows on screen are built, so huge tailnets draw as fast as small ones
+        let columns = Columns::fit(&app.nodes, &app.config, usize::from(rows_area.width));
+        let items: Vec<ListItem> = app
+            .rows
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(visible)
+            .map(|row| match row {
+                &Row::Node(position) => ListItem::new(node_line(app, &app.nodes[position], &columns, minimal)),
+                Row::Folded(fold) => ListItem::new(fold_line(app, fold, &columns, minimal)),
+            })
+            .collect();
+
+        // Display the list with selection
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::NONE)
+            )
+            .highlight_style(app.theme.highlight)
+            .highlight_symbol("> ");
+        let mut state = ListState::default().with_selected(Some(display_selection - offset));
+
+        f.render_stateful_widget(list, rows_area, &mut state);
+        app.list_offset = offset;
+        app.list_view = Some(ListView {
+            area: rows_area,
+            offset,
+        });
+
+        if scrollable {
+            let mut scrollbar_state = ScrollbarState::new(total.saturating_sub(visible))
+                .position(offset)
+                .viewport_content_length(visible);
+            let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                .begin_symbol(None)
+                .end_symbol(None)
+                .thumb_style(app.theme.muted)
+                .track_style(app.theme.faint);
+            f.render_stateful_widget(scrollbar, list_area, &mut scrollbar_state);
+        }
+    } else if !app.filter.is_empty() {
+        // No results for filter
+        let no_results = Paragraph::new("No nodes match your filter")
+            .style(app.theme.warning);
+        f.render_widget(no_results, list_area);
+    }
+
+    // Footer with the current input line and help text, showing the keys actually bound
+    let key = |action| app.keys.label(action);
+    let (input_text, help_text) = match &app.mode {
+        Mode::Browse => (
+            format!("Search: {}", app.filter),
+            app.footer_hints
+                .iter()
+                .map(|&hint| app.keys.hint(hint))
+                .collect::<Vec<_>>()
+                .join("  "),
+        ),
+        Mode::CommandPrompt { input } => (
+            format!(
+                "Run on {}: {}",
+                app.get_selected_node().map(|node| node.name.as_str()).unwrap_or_default(),
+                input
+            ),
+            "Enter: Run  Esc: Cancel".to_string(),
+        ),
+        Mode::SendPrompt { input } => (
+            format!(
+                "Taildrop to {}: {}",
+                app.get_selected_node().map(|node| node.name.as_str()).unwrap_or_default(),
+                input
+            ),
+            "Enter: Send  Esc: Cancel".to_string(),
+        ),
+        Mode::CommandOutput(_) => (
+            format!("Search: {}", app.filter),
+            format!(
+                "{}/{} {}/{}: Scroll  {}: Back to nodes  {}: Exit",
+                key(Action::Up),
+                key(Action::Down),
+                key(Action::PageUp),
+                key(Action::PageDown),
+                key(Action::Back),
+                key(Action::Quit)
+            ),
+        ),
+        Mode::Changes { .. } => (
+            format!("Search: {}", app.filter),
+            format!("{}/{}: Scroll  {}: Close", key(Action::Up), key(Action::Down), key(Action::Back)),
+        ),
+        Mode::Diagnostics(_) => (
+            format!("Search: {}", app.filter),
+            format!(
+                "{}/{}: Scroll  {}: Check again  {}: Close",
+                key(Action::Up),
+                key(Action::Down),
+                key(Action::Select),
+                key(Action::Back)
+            ),
+        ),
+        Mode::Help(_) => (
+            format!("Search: {}", app.filter),
+            format!(
+                "{}/{}: Scroll  {}: Close",
+                key(Action::Up),
+                key(Action::Down),
+                key(Action::Back)
+            ),
+        ),
+        Mode::Groups { .. } => (
+            format!("Search: {}", app.filter),
+            format!(
+                "{}/{}: Choose  {}: Show nodes  {}: Close",
+                key(Action::Up),
+                key(Action::Down),
+                key(Action::Select),
+                key(Action::Back)
+            ),
+        ),
+        Mode::Tunnels { .. } => (
+            format!("Search: {}", app.filter),
+            format!(
+                "{}/{}: Choose  {}: Reopen  {}: Close",
+                key(Action::Up),
+                key(Action::Down),
+                key(Action::Select),
+                key(Action::Back)
+            ),
+        ),
+        Mode::ActionMenu { .. } => (
+            format!("Search: {}", app.filter),
+            format!(
+                "{}/{}: Choose  {}: Run  {}: Close",
+                key(Action::Up),
+                key(Action::Down),
+                key(Action::Select),
+                key(Action::Back)
+            ),
+        ),
+        Mode::Sorts(editor) => (
+            format!("Search: {}", app.filter),
+            match &editor.draft {
+                Some(draft) if draft.naming => "Enter: Save  Esc: Back to the keys".to_string(),
+                Some(_) => format!(
+                    "{}/{}: Choose key  ←/→: Field  Space: Direction  Del: Remove  s: Save  {}: Discard",
+                    key(Action::Up),
+                    key(Action::Down),
+                    key(Action::Back)
+                ),
+                None => format!(
+                    "{}/{}: Choose  {}: Use  n: New  e: Edit  Del: Delete  {}: Close",
+                    key(Action::Up),
+                    key(Action::Down),
+                    key(Action::Select),
+                    key(Action::Back)
+                ),
+            },
+        ),
+        Mode::UsernameSettings(_) => (
+            format!("Search: {}", app.filter),
+            format!(
+                "{}/{}: Choose scope  {}: Edit  Del: Clear  {}: Close",
+                key(Action::Up),
+                key(Action::Down),
+                key(Action::Select),
+                key(Action::Back)
+            ),
+        ),
+        Mode::EditNode(editor) => (
+            format!("Search: {}", app.filter),
+            match editor.input {
+                Some(_) => "Enter: Save  Esc: Cancel".to_string(),
+                None => format!(
+                    "{}/{}: Choose field  {}: Edit  Del: Clear  {}: Close",
+                    key(Action::Up),
+                    key(Action::Down),
+                    key(Action::Select),
+                    key(Action::Back)
+                ),
+            },
+        ),
+    };
+    if !show_footer {
+        // Nothing typed and the footer is turned off
+    } else if compact {
+        let line = Line::from(vec![
+            Span::raw(input_text),
+            Span::raw("  "),
+            Span::styled(help_text, app.theme.muted),
+        ]);
+        f.render_widget(Paragraph::new(line), chunks[2]);
+    } else {
+        let search = Paragraph::new(input_text)
+            .style(Style::default())
+            .block(
+                Block::default()
+                    .borders(Borders::TOP)
+                    .title(help_text),
+            );
+        f.render_widget(search, chunks[2]);
+    }
+
+    if status_bar {
+        f.render_widget(Paragraph::new(status_bar_line(app)), chunks[3]);
+    }
+
+    // Popups are drawn last so they sit on top of everything else
+    if matches!(app.mode, Mode::Help(_)) {
+        app.pane_height = Some(usize::from(centered_rect(80, 80, size).height.saturating_sub(2)));
+    }
+    match &app.mode {
+        Mode::UsernameSettings(editor) => render_username_settings(f, app, editor, size),
+        Mode::EditNode(editor) => render_node_editor(f, app, editor, size),
+        Mode::Help(help) => render_help(f, app, help, size),
+        Mode::ActionMenu { cursor, confirm } => render_action_menu(f, app, *cursor, *confirm, size),
+        Mode::Groups { cursor } => render_groups(f, app, *cursor, size),
+        Mode::Tunnels { cursor } => render_tunnels(f, app, *cursor, size),
+        Mode::Changes { scroll } => render_changes(f, app, *scroll, size),
+        Mode::Diagnostics(diagnostics) => render_diagnostics(f, app, diagnostics, size),
+        Mode::Sorts(editor) => render_sorts(f, app, editor, size),
+        _ => {}
+    }
+}
+
+/// Tags as colored chips filling exactly `width` columns, with a `+N` count
+/// for those that don't fit
+fn tag_chips(theme: &Theme, tags: &[String], width: usize) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut used = 0;
+
+    for (index, tag) in tags.iter().enumerate() {
+        let chip = format!(" {} ", tag.trim_start_matches("tag:"));
+        let remaining = tags.len() - index - 1;
+        // Keep room for the overflow count unless this is the last tag
+        let reserve = if remaining > 0 { format!("+{}", remaining).len() + 1 } else { 0 };
+        if used + chip.chars().count() + 1 + reserve > width {
+            let more = format!("+{}", tags.len() - index);
+            used += more.len();
+            spans.push(Span::styled(more, theme.accent));
+            break;
+        }
+        used += chip.chars().count() + 1;
+        spans.push(Span::styled(chip, theme.chip));
+        spans.push(Span::raw(" "));
+    }
+
+    spans.push(Span::raw(" ".repeat(width.saturating_sub(used))));
+    spans
+}
+
+/// Widths of the node list columns, sized to the longest values and the room
+/// available. A width of 0 means the column is left out.
+struct Columns {
+    name: usize,
+    /// The Tailscale SSH marker
+    ssh: usize,
+    ip: usize,
+    /// Short OS label, like `lnx` or `win`
+    os: usize,
+    /// Owner, or tag chips for tagged nodes
+    user: usize,
+    status: usize,
+}
+
+impl Columns {
+    /// Fit the columns into a list `width` columns wide. Long hostnames are cut
+    /// first; narrow lists then lose the user column, then the OS and the
+    /// Tailscale SSH marker, and the IP once the hostname can't be cut any shorter.
+    fn fit(nodes: &[TailscaleNode], config: &Config, width: usize) -> Self {
+        let longest = |width_of: &dyn Fn(&TailscaleNode) -> usize| nodes.iter().map(width_of).max().unwrap_or(0);
+        let longest_name = longest(&|node| name_label(config.alias(&node.name), node).chars().count());
+        let mut columns = Self {
+            name: longest_name,
+            ssh: if nodes.iter().any(|node| node.tailscale_ssh) { 2 } else { 0 },
+            ip: longest(&|node| node.ip.chars().count()),
+            os: longest(&|node| node.platform().abbreviation().len()),
+            user: longest(&|node| {
+                if node.tags.is_empty() {
+                    node.suggested_user.chars().count()
+                } else {
+                    node.tags.iter().map(|tag| tag.trim_start_matches("tag:").chars().count() + 3).sum()
+                }
+            })
+            .min(MAX_USER_WIDTH),
+            status: 0,
+        };
+
+        let available = width.saturating_sub(HIGHLIGHT_WIDTH);
+        let others = |columns: &Self| {
+            [columns.ssh, columns.ip, columns.os, columns.user]
+                .into_iter()
+                .filter(|&width| width > 0)
+                .map(|width| width + COLUMN_GAP)
+                .sum::<usize>()
+        };
+        // The hostname gets what the other columns leave, but not less than `floor` if it is that long
+        let fit_name = |columns: &mut Self, floor: usize| {
+            let room = available.saturating_sub(COLUMN_GAP + others(columns) + MIN_STATUS_WIDTH);
+            columns.name = longest_name.min(room.max(floor));
+            columns.name + COLUMN_GAP + others(columns) + MIN_STATUS_WIDTH <= available
+        };
+
+        if !fit_name(&mut columns, SHORT_NAME_WIDTH) {
+            columns.user = 0;
+        }
+        if !fit_name(&mut columns, SHORT_NAME_WIDTH) {
+            columns.os = 0;
+        }
+        if !fit_name(&mut columns, SHORT_NAME_WIDTH) {
+            columns.ssh = 0;
+        }
+        if !fit_name(&mut columns, MIN_NAME_WIDTH) {
+            columns.ip = 0;
+        }
+        fit_name(&mut columns, 0);
+        columns.status = available.saturating_sub(columns.name + COLUMN_GAP + others(&columns));
+        columns
+    }
+}
+
+/// A node's status, with when it was last seen if it is offline
+fn status_label(node: &TailscaleNode, absolute_times: bool) -> String {
+    match node.last_seen {
+        Some(last_seen) if !node.is_online() => {
+            format!("{}, seen {}", node.status, humanize::timestamp(last_seen, absolute_times))
+        }
+        _ => node.status.clone(),
+    }
+}
+
+/// Text cut to `width` characters, ending in an ellipsis if anything was cut
+fn ellipsize(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    if width > 0 {
+        cut.push('…');
+    }
+    cut
+}
+
+/// What the name column says for a node: its alias followed by the hostname,
+/// if it has one, and a note when it is this machine
+fn name_label(alias: Option<&str>, node: &TailscaleNode) -> String {
+    let mut label = match alias {
+        Some(alias) => format!("{} ({})", alias, node.name),
+        None => node.name.clone(),
+    };
+    if node.is_self {
+        label.push_str(THIS_MACHINE);
+    }
+    label
+}
+
+/// The name column for a node, with an alias in bold and what follows it muted
+fn name_cell(theme: &Theme, alias: Option<&str>, node: &TailscaleNode, width: usize) -> Vec<Span<'static>> {
+    let text = cell(&name_label(alias, node), width);
+    let (lead, bold) = match alias {
+        Some(alias) => (alias, Style::default().add_modifier(Modifier::BOLD)),
+        None if node.is_self => (node.name.as_str(), Style::default()),
+        None => return vec![Span::raw(text)],
+    };
+    let split = text.char_indices().nth(lead.chars().count()).map_or(text.len(), |(index, _)| index);
+    vec![
+        Span::styled(text[..split].to_string(), bold),
+        Span::styled(text[split..].to_string(), theme.muted),
+    ]
+}
+
+/// A node's row in the list
+fn node_line<'a>(app: &'a App, node: &'a TailscaleNode, columns: &Columns, minimal: bool) -> Line<'a> {
+    // Color status based on online/offline, unless the health sweep found nothing on the ssh port
+    let ssh_down = node.is_online() && app.ports.get(&node.name) == Some(&PortState::Closed);
+    let status_style = if ssh_down {
+        app.theme.warning
+    } else if node.status.contains("active") {
+        app.theme.active
+    } else {
+        app.theme.inactive
+    };
+
+    // Tiny terminals only get the name, after a glyph telling the status apart without color
+    if minimal {
+        let glyph = if ssh_down {
+            "×"
+        } else if node.status.contains("active") {
+            "●"
+        } else if node.is_online() {
+            "○"
+        } else {
+            "·"
+        };
+        let name = app.config.alias(&node.name).unwrap_or(&node.name);
+        return Line::from(vec![Span::styled(format!("{} ", glyph), status_style), Span::raw(name)]);
+    }
+
+    let mut spans = name_cell(&app.theme, app.config.alias(&node.name), node, columns.name);
+    if columns.ssh > 0 {
+        spans.push(Span::styled(cell(if node.tailscale_ssh { "TS" } else { "" }, columns.ssh), app.theme.accent));
+    }
+    if columns.ip > 0 {
+        spans.push(Span::raw(cell(&node.ip, columns.ip)));
+    }
+    if columns.os > 0 {
+        spans.push(Span::styled(cell(node.platform().abbreviation(), columns.os), app.theme.faint));
+    }
+    // Tagged nodes have no owner, so their tags take the user column as chips
+    if columns.user > 0 {
+        if node.tags.is_empty() {
+            spans.push(Span::styled(cell(&node.suggested_user, columns.user), app.theme.muted));
+        } else {
+            spans.extend(tag_chips(&app.theme, &node.tags, columns.user + COLUMN_GAP));
+        }
+    }
+    // A node still settling from a disruptive action gets a badge with the time left, when there is room
+    let mut status_width = columns.status;
+    if let Some(cooldown) = app.cooldowns.active(&node.name) {
+        let badge = format!(" {} {} ", cooldown.action, humanize::duration(cooldown.remaining()));
+        if badge.chars().count() + MIN_STATUS_WIDTH < status_width {
+            status_width -= badge.chars().count() + 1;
+            spans.push(Span::styled(badge, app.theme.warning.add_modifier(Modifier::REVERSED)));
+            spans.push(Span::raw(" "));
+        }
+    }
+    let mut status = status_label(node, app.config.ui.absolute_times);
+    if ssh_down {
+        status = format!("ssh down, {}", status);
+    }
+    spans.push(Span::styled(ellipsize(&status, status_width), status_style));
+    Line::from(spans)
+}
+
+/// The row standing in for the nodes of a `ui.collapse` entry: the entry in
+/// the name column, then how many nodes it folds and how many are online
+fn fold_line<'a>(app: &'a App, fold: &Fold, columns: &Columns, minimal: bool) -> Line<'a> {
+    let label = format!("▸ {}", fold.pattern);
+    let count = format!(
+        "{} nodes, {} online ({} expands)",
+        fold.nodes.len(),
+        fold.online,
+        app.keys.label(Action::Fold)
+    );
+    if minimal {
+        return Line::from(vec![
+            Span::styled(label, app.theme.accent),
+            Span::styled(format!(" {}", fold.nodes.len()), app.theme.muted),
+        ]);
+    }
+    let rest = [columns.ssh, columns.ip, columns.os, columns.user]
+        .into_iter()
+        .filter(|&width| width > 0)
+        .map(|width| width + COLUMN_GAP)
+        .sum::<usize>()
+        + columns.status;
+    Line::from(vec![
+        Span::styled(cell(&label, columns.name), app.theme.accent),
+        Span::styled(ellipsize(&count, rest), app.theme.muted),
+    ])
+}
+
+/// A column's text, cut to fit and padded out to the next column
+fn cell(text: &str, width: usize) -> String {
+    format!("{:<1$}", ellipsize(text, width), width + COLUMN_GAP)
+}
+
+/// The node count followed by a compact summary of this machine's Tailscale
+/// client. The exit node and warnings are left to the status bar when it is shown.
+fn health_line(app: &App, list_rows: u16, status_bar: bool) -> Line<'_> {
+    let gray = app.theme.muted;
+    let health = &app.health;
+    let mut spans = vec![Span::styled(format!("Found {} nodes", app.nodes.len()), gray)];
+    // Where the selection is, once the list is filtered or too long to see at once
+    let shown = app.rows.len();
+    if shown > 0 && (!app.filter.is_empty() || app.group.is_some() || shown > usize::from(list_rows)) {
+        spans.push(Span::styled(format!(", {} of {}", app.selection + 1, shown), gray));
+    }
+    let hidden = app.hidden_count();
+    if hidden > 0 {
+        spans.push(Span::styled(
+            format!(" ({} hidden, {} shows them)", hidden, app.keys.label(Action::ShowHidden)),
+            app.theme.faint,
+        ));
+    }
+    if app.cached {
+        spans.push(Span::styled(" (from last run, refreshing...)", app.theme.faint));
+    }
+    if let Some(changes) = &app.changes
+        && changes.found.elapsed() < CHANGES_NOTICE
+    {
+        let style = if changes.has_losses() { app.theme.warning } else { app.theme.accent };
+        spans.push(Span::styled(
+            format!(
+                " (since last run: {}, {} lists them)",
+                changes.summary(),
+                app.keys.label(Action::Changes)
+            ),
+            style,
+        ));
+    }
+
+    // Older clients or a failed parse leave everything empty; show just the count then
+    if health.backend_state.is_empty() {
+        return Line::from(spans);
+    }
+
+    let state_style = if health.backend_state == "Running" { app.theme.active } else { app.theme.warning };
+    spans.push(Span::styled(format!("  |  Tailscale {}  ", health.version), gray));
+    spans.push(Span::styled(health.backend_state.as_str(), state_style));
+    if !health.derp_home.is_empty() {
+        spans.push(Span::styled(format!("  DERP {}", health.derp_home), gray));
+    }
+    spans.push(Span::styled(
+        format!("  MagicDNS {}", if health.magic_dns { "on" } else { "off" }),
+        gray,
+    ));
+    if status_bar {
+        return Line::from(spans);
+    }
+    if let Some(exit_node) = &health.exit_node {
+        spans.push(Span::styled(format!("  Exit node {}", exit_node), app.theme.accent));
+    }
+    if !health.warnings.is_empty() {
+        // The full messages are long; the count is enough to prompt a `tailscale status`
+        spans.push(Span::styled(
+            format!("  {} health warning(s)", health.warnings.len()),
+            app.theme.warning,
+        ));
+    }
+
+    Line::from(spans)
+}
+
+/// This machine's name and IP, the tailnet, the exit node in use and the
+/// client's health warnings in full, as context before connecting anywhere
+fn status_bar_line(app: &App) -> Line<'_> {
+    let health = &app.health;
+    let gray = app.theme.muted;
+    let mut spans = Vec::new();
+    if !health.self_name.is_empty() {
+        spans.push(Span::styled(health.self_name.as_str(), app.theme.accent));
+        spans.push(Span::styled(format!(" {}", health.self_ip), gray));
+    }
+    if !health.tailnet.is_empty() {
+        spans.push(Span::styled(format!("  |  tailnet {}", health.tailnet), gray));
+    }
+    match &health.exit_node {
+        Some(exit_node) => spans.push(Span::styled(format!("  |  exit node {}", exit_node), app.theme.accent)),
+        None => spans.push(Span::styled("  |  no exit node", app.theme.faint)),
+    }
+    if health.warnings.is_empty() {
+        spans.push(Span::styled("  |  healthy", app.theme.active));
+    } else {
+        spans.push(Span::styled(format!("  |  {}", health.warnings.join("; ")), app.theme.warning));
+    }
+    Line::from(spans)
+}
+
+/// Render the selected node's details and the connection settings that will
+/// actually be used for it, with the config layer each one came from
+fn render_node_details(f: &mut ratatui::Frame, app: &App, node: &TailscaleNode, area: Rect) {
+    let label_style = app.theme.muted;
+    let source_style = app.theme.faint;
+    let row = |label: &str, value: String, source: String| {
+        Line::from(vec![
+            Span::styled(format!("{:<22}", label), label_style),
+            Span::raw(value),
+            Span::styled(format!("  ({})", source), source_style),
+        ])
+    };
+
+    let tags = if node.tags.is_empty() { "-".to_string() } else { node.tags.join(", ") };
+    let mut lines = vec![
+        Line::from(vec![Span::styled("IP ", label_style), Span::raw(node.ip.as_str())]),
+        Line::from(vec![Span::styled("OS ", label_style), Span::raw(node.os.as_str())]),
+        Line::from(vec![Span::styled("Tags ", label_style), Span::raw(tags)]),
+        Line::from(vec![
+            Span::styled("Status ", label_style),
+            Span::raw(status_label(node, app.config.ui.absolute_times)),
+        ]),
+    ];
+    if node.rx_bytes > 0 || node.tx_bytes > 0 {
+        lines.push(Line::from(vec![
+            Span::styled("Traffic ", label_style),
+            Span::raw(format!(
+                "{} received, {} sent",
+                humanize::bytes(node.rx_bytes),
+                humanize::bytes(node.tx_bytes)
+            )),
+        ]));
+    }
+    let notes = app.config.node_settings(&node.name).notes;
+    for (index, note) in notes.lines().enumerate() {
+        let label = if index == 0 { "Notes " } else { "      " };
+        lines.push(Line::from(vec![Span::styled(label, label_style), Span::raw(note.to_string())]));
+    }
+    lines.push(Line::raw(""));
+    lines.push(Line::from(Span::styled(
+        "Effective connection",
+        Style::default().add_modifier(Modifier::BOLD),
+    )));
+
+    let (username, username_scope) = app.config.resolve_username(node);
+    let username_source = match username_scope {
+        Some(scope) => scope.label(),
+        None => format!("Built-in fallback ({})", FALLBACK_USERNAME),
+    };
+    match secrets::username_group(&app.config, node) {
+        // Fetched when connecting, never for display
+        Some(group) => lines.push(row(
+            "User",
+            "(secret manager)".to_string(),
+            format!("config secrets, group {}", group),
+        )),
+        None => lines.push(row("User", username, username_source)),
+    }
+    let destination = address::destination(&app.config, node);
+    let remembered = app.config.node_settings(&node.name).preferred_address == Some(destination.kind);
+    let address_source = match destination.fallback_reason {
+        Some(reason) => format!("IP fallback: {}", reason),
+        None if remembered => format!("{}, worked when others failed", destination.kind.label()),
+        None if app.config.ssh.use_magic_dns => "MagicDNS, config ssh.use_magic_dns".to_string(),
+        None => "tailscale status".to_string(),
+    };
+    lines.push(row("Address", destination.host, address_source));
+    // Anything not set here is left to ssh, including ~/.ssh/config
+    lines.push(row("Port", "22".to_string(), "ssh default".to_string()));
+    let port_state = match app.ports.get(&node.name) {
+        Some(PortState::Checking) => Some("checking..."),
+        Some(PortState::Open) => Some("answering"),
+        Some(PortState::Closed) => Some("not answering"),
+        None => None,
+    };
+    if let Some(state) = port_state {
+        lines.push(row("SSH port", state.to_string(), "health sweep".to_string()));
+    }
+    let (identity, identity_source) = identities::identity_for(&app.config, node)
+        .unwrap_or_else(|| ("-".to_string(), "ssh default / agent".to_string()));
+    lines.push(row("Identity", identity, identity_source));
+    if let Some(limit) = session_limit_for(&app.config, node)
+        && let Some(minutes) = limit.max_session_minutes
+    {
+        lines.push(row(
+            "Session limit",
+            humanize::duration(Duration::from_secs(minutes * 60)),
+            "config session_limits".to_string(),
+        ));
+    }
+    if let Some(color) = tint::color_for(&app.config, node) {
+        let source = if app.config.node_settings(&node.name).session_color.is_some() {
+            "config nodes"
+        } else {
+            "config session_colors"
+        };
+        let shown = [color.marker, color.background].into_iter().flatten().collect::<Vec<_>>().join(", ");
+        lines.push(row("Session color", shown, source.to_string()));
+    }
+    let client = app.config.ssh.client;
+    let (transport, transport_source) = match transport_for(&app.config, node) {
+        Transport::Tailscale => ("Tailscale SSH (tailscale ssh)", "config ssh.transports"),
+        Transport::Ssh if client == SshClient::default() => (client.label(), "built-in default"),
+        Transport::Ssh => (client.label(), "config ssh.client"),
+    };
+    lines.push(row("Transport", transport.to_string(), transport_source.to_string()));
+    let shell_source = if app.config.node_settings(&node.name).remote_shell.is_some() {
+        "config nodes"
+    } else if app.config.ssh.remote_shell != RemoteShell::Auto {
+        "config ssh.remote_shell"
+    } else {
+        "node OS"
+    };
+    lines.push(row(
+        "Remote shell",
+        remote_shell::shell_for(&app.config, node).label().to_string(),
+        shell_source.to_string(),
+    ));
+
+    // `tailscale ssh` takes no flags, so forwarding only applies to the ssh client
+    if transport_for(&app.config, node) == Transport::Ssh {
+        let own = app.config.node_settings(&node.name);
+        let forwarding = app.forwarding.apply(forwarding_for(&app.config, node));
+        let source = |toggled: bool, configured: bool, name: &str, action: Action| {
+            let origin = match (toggled, configured) {
+                (true, _) => "toggled for this session".to_string(),
+                (false, true) => "config nodes".to_string(),
+                (false, false) => format!("config ssh.{}", name),
+            };
+            format!("{}; {} toggles", origin, app.keys.label(action))
+        };
+        let on_off = |on: bool| if on { "on" } else { "off" }.to_string();
+        lines.push(row(
+            "Agent forwarding",
+            on_off(forwarding.agent),
+            source(app.forwarding.agent.is_some(), own.forward_agent.is_some(), "forward_agent", Action::ToggleAgent),
+        ));
+        lines.push(row(
+            "X11 forwarding",
+            forwarding.x11.label().to_string(),
+            source(app.forwarding.x11.is_some(), own.forward_x11.is_some(), "forward_x11", Action::ToggleX11),
+        ));
+        lines.push(row(
+            "Force TTY",
+            on_off(forwarding.tty),
+            source(app.forwarding.tty.is_some(), own.force_tty.is_some(), "force_tty", Action::ToggleTty),
+        ));
+        if let Some(session) = tmux_session_for(&app.config, node, None)
+            && remote_shell::shell_for(&app.config, node) == RemoteShell::Posix
+        {
+            let source = if own.tmux_session.is_some() { "config nodes" } else { "config ssh.tmux_session" };
+            lines.push(row("tmux session", session, source.to_string()));
+        }
+    }
+    for destination in &app.config.node_settings(&node.name).destinations {
+        lines.push(row("Also offered", destinations::label(destination, node), "config node destinations".to_string()));
+    }
+    for option in configured_options(&app.config, node) {
+        lines.push(row(&format!("-o {}", option.key), option.value, option.source.to_string()));
+    }
+    for (name, value) in &app.config.node_settings(&node.name).env {
+        lines.push(row(&format!("env {}", name), value.clone(), "config node env".to_string()));
+    }
+
+    // Long values wrap rather than disappear off the edge of narrow panes
+    let pane = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(name_label(None, node)))
+        .wrap(Wrap { trim: false });
+    f.render_widget(pane, area);
+}
+
+/// Render the default username settings popup with its precedence preview
+fn render_username_settings(f: &mut ratatui::Frame, app: &App, editor: &UsernameEditor, size: Rect) {
+    let area = centered_rect(70, 60, size);
+    let (_, used_scope) = app.config.resolve_username(&editor.node);
+
+    let mut lines = vec![
+        Line::from(Span::styled(
+            "Most specific scope wins. The highlighted value is what will be used.",
+            app.theme.muted,
+        )),
+        Line::raw(""),
+    ];
+
+    for (index, scope) in editor.scopes.iter().enumerate() {
+        let is_cursor = index == editor.cursor;
+        let value = match (&editor.input, is_cursor) {
+            (Some(input), true) => format!("{}_", input),
+            _ => app.config.username_at(scope).unwrap_or("-").to_string(),
+        };
+        let is_used = used_scope.as_ref() == Some(scope);
+
+        let mut value_style = Style::default();
+        if is_used {
+            value_style = app.theme.active.add_modifier(Modifier::BOLD);
+        }
+        let row_style = if is_cursor {
+            app.theme.highlight
+        } else {
+            Style::default()
+        };
+
+        lines.push(Line::from(vec![
+            Span::styled(if is_cursor { "> " } else { "  " }, row_style),
+            Span::styled(format!("{:<30}", scope.label()), row_style),
+            Span::styled(format!("{:<20}", value), value_style.patch(row_style)),
+            Span::styled(if is_used { "<- used" } else { "" }, value_style),
+        ]));
+    }
+
+    // The fallback is not editable but completes the picture
+    let fallback_style = if used_scope.is_none() {
+        app.theme.active.add_modifier(Modifier::BOLD)
+    } else {
+        app.theme.muted
+    };
+    lines.push(Line::from(vec![
+        Span::raw("  "),
+        Span::styled(format!("{:<30}", "Built-in fallback"), app.theme.muted),
+        Span::styled(format!("{:<20}", FALLBACK_USERNAME), fallback_style),
+        Span::styled(if used_scope.is_none() { "<- used" } else { "" }, fallback_style),
+    ]));
+
+    if let Some(fresh) = &editor.conflict {
+        lines.push(Line::raw(""));
+        lines.push(Line::from(Span::styled(
+            format!("{} changed while you were editing:", editor.node.name),
+            app.theme.warning.add_modifier(Modifier::BOLD),
+        )));
+        for change in fresh.identity_changes(&editor.node) {
+            lines.push(Line::from(Span::styled(format!("  {}", change), app.theme.warning)));
+        }
+        lines.push(Line::from(vec![
+            Span::styled("Enter", app.theme.accent),
+            Span::raw(format!(": apply the edit to {} as it is now  ", fresh.name)),
+            Span::styled("k", app.theme.accent),
+            Span::raw(format!(": keep it for {}  ", editor.node.name)),
+            Span::styled("Esc", app.theme.accent),
+            Span::raw(": discard it"),
+        ]));
+    } else if let Some(message) = &editor.message {
+        lines.push(Line::raw(""));
+        lines.push(Line::from(Span::styled(message.as_str(), app.theme.warning)));
+    }
+
+    let popup = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("Default usernames for {}", editor.node.name)),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Render the node editor popup: each field with its value, or the text being typed
+fn render_node_editor(f: &mut ratatui::Frame, app: &App, editor: &NodeEditor, size: Rect) {
+    let area = centered_rect(70, 50, size);
+    let mut lines = vec![
+        Line::from(Span::styled(
+            "Kept in the config for this node. An empty value clears the setting.",
+            app.theme.muted,
+        )),
+        Line::raw(""),
+    ];
+
+    for (index, field) in NodeField::ALL.into_iter().enumerate() {
+        let is_cursor = index == editor.cursor;
+        let row_style = if is_cursor { app.theme.highlight } else { Style::default() };
+        let value = match (&editor.input, is_cursor) {
+            (Some(input), true) => Span::styled(format!("{}_", input), row_style),
+            _ => match field.value(&app.config, &editor.node.name) {
+                value if value.is_empty() => Span::styled("-", app.theme.muted.patch(row_style)),
+                value => Span::styled(value, row_style),
+            },
+        };
+        lines.push(Line::from(vec![
+            Span::styled(if is_cursor { "> " } else { "  " }, row_style),
+            Span::styled(format!("{:<12}", field.label()), row_style),
+            value,
+        ]));
+    }
+
+    if let Some(message) = &editor.message {
+        lines.push(Line::raw(""));
+        lines.push(Line::from(Span::styled(message.as_str(), app.theme.warning)));
+    }
+
+    let popup = Paragraph::new(lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::default().borders(Borders::ALL).title(format!("Edit {}", editor.node.name)));
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Render the group list, with how many nodes each group has and how many are online
+fn render_groups(f: &mut ratatui::Frame, app: &App, cursor: usize, size: Rect) {
+    let area = centered_rect(60, 60, size);
+    let entries = app.group_entries();
+    let name_width = entries
+        .iter()
+        .filter_map(|entry| entry.name.as_ref().map(|name| name.chars().count()))
+        .max()
+        .unwrap_or(0)
+        .max("All nodes".len());
+
+    let lines: Vec<Line> = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let row_style = if index == cursor { app.theme.highlight } else { Style::default() };
+            let name = entry.name.as_deref().unwrap_or("All nodes");
+            Line::from(vec![
+                Span::styled(if index == cursor { "> " } else { "  " }, row_style),
+                Span::styled(format!("{:<width$}", name, width = name_width), row_style),
+                Span::styled(
+                    format!("  {:>4} {}", entry.nodes, if entry.nodes == 1 { "node " } else { "nodes" }),
+                    app.theme.muted,
+                ),
+                Span::styled(format!("  {} online", entry.online), app.theme.faint),
+            ])
+        })
+        .collect();
+
+    // Keep the highlighted group in view on long lists
+    let visible = usize::from(area.height.saturating_sub(2));
+    let scroll = cursor.saturating_sub(visible.saturating_sub(1));
+    let popup = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title("Groups"))
+        .scroll((scroll.min(u16::MAX as usize) as u16, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Render the tunnel list: each forward's node, service, ports and whether it is still open
+fn render_tunnels(f: &mut ratatui::Frame, app: &App, cursor: usize, size: Rect) {
+    let area = centered_rect(70, 60, size);
+    let list = app.tunnels.lock().map(|list| list.clone()).unwrap_or_default();
+
+    let mut lines = Vec::new();
+    if list.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "No tunnels. Set `\"autostart\": true` on a node's service to forward it whenever the picker starts.",
+            app.theme.muted,
+        )));
+    }
+    for (index, tunnel) in list.iter().enumerate() {
+        let row_style = if index == cursor { app.theme.highlight } else { Style::default() };
+        let (state, state_style) = match &tunnel.state {
+            TunnelState::Running => ("open".to_string(), app.theme.active),
+            TunnelState::Ended(reason) => (format!("ended ({})", reason), app.theme.warning),
+        };
+        lines.push(Line::from(vec![
+            Span::styled(if index == cursor { "> " } else { "  " }, row_style),
+            Span::styled(format!("{:<20}", tunnel.node), row_style),
+            Span::styled(format!("{:<14}", tunnel.service), row_style),
+            Span::styled(
+                format!("localhost:{:<6} -> port {:<6}", tunnel.local_port, tunnel.remote_port),
+                app.theme.muted,
+            ),
+            Span::styled(state, state_style),
+        ]));
+    }
+
+    let popup = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Tunnels"));
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Render the nodes added, removed, or gone on- or offline since the last run
+fn render_changes(f: &mut ratatui::Frame, app: &App, scroll: usize, size: Rect) {
+    let area = centered_rect(60, 60, size);
+    let mut lines = Vec::new();
+    match &app.changes {
+        Some(changes) => {
+            for (label, names) in changes.sections() {
+                if names.is_empty() {
+                    continue;
+                }
+                let style = if matches!(label, "went offline" | "removed") { app.theme.warning } else { app.theme.accent };
+                lines.push(Line::from(Span::styled(
+                    format!("{} {}", names.len(), label),
+                    style.add_modifier(Modifier::BOLD),
+                )));
+                for name in names {
+                    lines.push(match app.config.alias(name) {
+                        Some(alias) => Line::from(vec![
+                            Span::raw(format!("  {} ", alias)),
+                            Span::styled(format!("({})", name), app.theme.muted),
+                        ]),
+                        None => Line::raw(format!("  {}", name)),
+                    });
+                }
+                lines.push(Line::raw(""));
+            }
+        }
+        None if app.cached => lines.push(Line::from(Span::styled(
+            "Waiting for tailscale status to compare with the list from the last run...",
+            app.theme.muted,
+        ))),
+        None => lines.push(Line::from(Span::styled(
+            "Nothing changed since the last run, or there was no saved list to compare with.",
+            app.theme.muted,
+        ))),
+    }
+
+    let popup = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title("Changes since last run"))
+        .scroll((scroll.min(u16::MAX as usize) as u16, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Render the diagnostics screen: what netcheck found that affects connections,
+/// with likely problems highlighted, then the round trip to every DERP region
+fn render_diagnostics(f: &mut ratatui::Frame, app: &App, diagnostics: &Diagnostics, size: Rect) {
+    let area = centered_rect(70, 70, size);
+    let mut lines = Vec::new();
+    match &diagnostics.report {
+        None => lines.push(Line::from(Span::styled(
+            "Running tailscale netcheck, which takes a few seconds...",
+            app.theme.muted,
+        ))),
+        Some(Err(err)) => lines.push(Line::from(Span::styled(err.as_str(), app.theme.warning))),
+        Some(Ok(report)) => {
+            for finding in report.findings() {
+                let style = if finding.problem { app.theme.warning } else { Style::default() };
+                lines.push(Line::from(vec![
+                    Span::styled(format!("{:<16}", finding.label), app.theme.muted),
+                    Span::styled(finding.value, style),
+                ]));
+            }
+            if !report.derp_latency.is_empty() {
+                lines.push(Line::raw(""));
+                lines.push(Line::from(Span::styled(
+                    "DERP latency",
+                    Style::default().add_modifier(Modifier::BOLD),
+                )));
+            }
+            for derp in &report.derp_latency {
+                lines.push(Line::from(vec![
+                    Span::raw(format!("  {:<6}{:>10}  ", derp.region, derp.latency)),
+                    Span::styled(derp.name.clone(), app.theme.muted),
+                ]));
+            }
+        }
+    }
+
+    let popup = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title("Network diagnostics"))
+        .wrap(Wrap { trim: false })
+        .scroll((diagnostics.scroll.min(u16::MAX as usize) as u16, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Render the sort popup: the named sorts with their keys, or the keys of the
+/// one being composed
+fn render_sorts(f: &mut ratatui::Frame, app: &App, editor: &SortEditor, size: Rect) {
+    let area = centered_rect(60, 60, size);
+    let marker = |selected: bool| if selected { "> " } else { "  " };
+    let row_style = |selected: bool| if selected { app.theme.highlight } else { Style::default() };
+
+    let mut lines = Vec::new();
+    let title = match &editor.draft {
+        None => {
+            let name_width = app.config.sorts.keys().map(|name| name.chars().count()).max().unwrap_or(0).max(16);
+            let entries = std::iter::once((None, "Tailscale's order".to_string()))
+                .chain(app.config.sorts.iter().map(|(name, keys)| (Some(name), sort::describe(keys))));
+            for (index, (name, keys)) in entries.enumerate() {
+                let selected = index == editor.cursor;
+                let in_use = name == app.config.ui.sort.as_ref();
+                lines.push(Line::from(vec![
+                    Span::styled(marker(selected), row_style(selected)),
+                    Span::styled(
+                        format!("{:<width$}", name.map_or("Default", String::as_str), width = name_width),
+                        row_style(selected),
+                    ),
+                    Span::styled(format!("  {}", keys), app.theme.muted),
+                    Span::styled(if in_use { "  <- in use" } else { "" }, app.theme.active),
+                ]));
+            }
+            "Sort".to_string()
+        }
+        Some(draft) => {
+            lines.push(Line::from(Span::styled(
+                "Keys are tried in turn; nodes that tie on one are ordered by the next.",
+                app.theme.muted,
+            )));
+            lines.push(Line::raw(""));
+            for (index, key) in draft.keys.iter().enumerate() {
+                let selected = index == draft.cursor;
+                let direction = if key.descending { "descending" } else { "ascending" };
+                lines.push(Line::from(vec![
+                    Span::styled(marker(selected), row_style(selected)),
+                    Span::styled(format!("{}. {:<10}", index + 1, key.field.name()), row_style(selected)),
+                    Span::styled(format!("  {}", direction), app.theme.muted),
+                ]));
+            }
+            let selected = draft.cursor == draft.keys.len();
+            lines.push(Line::from(vec![
+                Span::styled(marker(selected), row_style(selected)),
+                Span::styled(
+                    format!("+ Add a key ({})", app.keys.label(Action::Select)),
+                    app.theme.faint.patch(row_style(selected)),
+                ),
+            ]));
+            if draft.naming {
+                lines.push(Line::raw(""));
+                lines.push(Line::from(vec![
+                    Span::styled("Save as: ", app.theme.accent),
+                    Span::raw(format!("{}_", draft.name)),
+                ]));
+            }
+            match &draft.original {
+                Some(name) => format!("Edit sort {}", name),
+                None => "New sort".to_string(),
+            }
+        }
+    };
+    if let Some(message) = &editor.message {
+        lines.push(Line::raw(""));
+        lines.push(Line::from(Span::styled(message.as_str(), app.theme.warning)));
+    }
+
+    let popup = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Render the custom action menu: each action's key, name and where it runs
+fn render_action_menu(f: &mut ratatui::Frame, app: &App, cursor: usize, confirm: Option<usize>, size: Rect) {
+    let area = centered_rect(70, 60, size);
+    let node = app.get_selected_node().map(|node| node.name.as_str()).unwrap_or_default();
+
+    let mut lines = Vec::new();
+    let entries = app.menu_entries();
+    if let Some(cooldown) = app.cooldowns.active(node) {
+        lines.push(Line::from(Span::styled(
+            format!(
+                "{} was disturbed by {} {}; it settles in {}.",
+                node,
+                cooldown.action,
+                humanize::timestamp(cooldown.started, false),
+                humanize::duration(cooldown.remaining())
+            ),
+            app.theme.warning,
+        )));
+        if let Some(entry) = confirm.and_then(|confirm| entries.get(confirm)) {
+            lines.push(Line::from(Span::styled(
+                format!("Choose {} again to run it anyway.", entry.name),
+                app.theme.warning.add_modifier(Modifier::BOLD),
+            )));
+        }
+        lines.push(Line::raw(""));
+    }
+    if entries.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "No actions yet. Add them to the `actions` list in the config file, add `services` to the node, \
+             or put ssh-tailscale-* plugins on PATH.",
+            app.theme.muted,
+        )));
+    }
+    for (index, entry) in entries.iter().enumerate() {
+        let row_style = if index == cursor { app.theme.highlight } else { Style::default() };
+        let key = entry.key.as_ref().map(KeyBinding::label).unwrap_or_default();
+        let (target, detail) = match &entry.kind {
+            ActionKind::Custom(action) if action.run == ActionTarget::Remote => ("on node", action.command.clone()),
+            ActionKind::Custom(action) => ("locally", action.command.clone()),
+            ActionKind::Plugin(plugin) => ("plugin", plugin.path.display().to_string()),
+            ActionKind::OpenService(service) => (
+                "browser",
+                app.get_selected_node()
+                    .and_then(|node| actions::service_url(&app.config, node, service))
+                    .unwrap_or_default(),
+            ),
+            ActionKind::ForwardService(service) => (
+                "forward",
+                format!("localhost:{} -> port {}", actions::forward_port(service), service.port),
+            ),
+            ActionKind::OpenUrl(url) => ("browser", url.clone()),
+        };
+        lines.push(Line::from(vec![
+            Span::styled(if index == cursor { "> " } else { "  " }, row_style),
+            Span::styled(format!("{:<8}", key), row_style),
+            Span::styled(format!("{:<24}", entry.name), row_style),
+            Span::styled(format!("{:<9}", target), app.theme.muted),
+            Span::styled(detail, app.theme.faint),
+        ]));
+    }
+
+    let popup = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!("Actions for {}", node)),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Render the help overlay: every key binding, then where settings and the
+/// tailscale CLI come from
+fn render_help(f: &mut ratatui::Frame, app: &App, help: &HelpScreen, size: Rect) {
+    let area = centered_rect(80, 80, size);
+    let heading = Style::default().add_modifier(Modifier::BOLD);
+    let row = |label: String, value: String, note: &str| {
+        Line::from(vec![
+            Span::raw(format!("  {:<22}", label)),
+            Span::raw(value),
+            Span::styled(note.to_string(), app.theme.faint),
+        ])
+    };
+
+    let mut lines = vec![
+        Line::from(Span::styled(format!("ssh-tailscale {}", env!("CARGO_PKG_VERSION")), app.theme.title)),
+        Line::raw(""),
+        Line::from(Span::styled("Keys", heading)),
+    ];
+    for action in Action::ALL {
+        lines.push(row(
+            app.keys.labels(action),
+            format!("{:<16}", action.description()),
+            &format!("  keys.{}", action.name()),
+        ));
+    }
+    lines.push(row("Other characters".to_string(), "Filter nodes".to_string(), ""));
+    lines.push(row("Mouse".to_string(), "Wheel to move, click to select, double-click to connect".to_string(), ""));
+
+    let health = &app.health;
+    let tailscale = if health.backend_state.is_empty() {
+        "unknown".to_string()
+    } else {
+        format!("{} ({})", health.version, health.backend_state)
+    };
+    let transports: Vec<&str> = app
+        .config
+        .ssh
+        .transports
+        .iter()
+        .map(|transport| match transport {
+            Transport::Tailscale => "tailscale ssh",
+            Transport::Ssh => "ssh client",
+        })
+        .collect();
+    lines.extend([
+        Line::raw(""),
+        Line::from(Span::styled("Setup", heading)),
+        row("Config file".to_string(), help.config_path.clone(), ""),
+        row("Tailscale CLI".to_string(), help.tailscale_program.clone(), ""),
+        row("Tailscale client".to_string(), tailscale, ""),
+        row("SSH client".to_string(), app.config.ssh.client.label().to_string(), ""),
+        row("Transports".to_string(), transports.join(", "), "  in order of preference"),
+    ]);
+
+    let popup = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title("Help"))
+        .scroll((help.scroll.min(u16::MAX as usize) as u16, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// A rectangle centered in `area`, sized as a percentage of it
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ])
+        .split(area);
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ])
+        .split(vertical[1])[1]
+}
+
+/// Render the scrollable output of a one-off remote command
+fn render_command_output(f: &mut ratatui::Frame, output: &mut CommandOutput, area: Rect) {
+    // Following output keeps the last lines at the bottom of the pane
+    if output.follow {
+        output.scroll = output.lines.len().saturating_sub(usize::from(area.height.saturating_sub(2)));
+    }
+
+    let title = match &output.status {
+        Some(status) => format!("{} on {} ({})", output.command, output.node, status),
+        None => format!("{} on {} (running...)", output.command, output.node),
+    };
+
+    let lines: Vec<Line> = output.lines.iter().map(|line| Line::raw(line.as_str())).collect();
+    let pane = Paragraph::new(lines)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .scroll((output.scroll.min(u16::MAX as usize) as u16, 0));
+    f.render_widget(pane, area);
+}
@@ -0,0 +1,112 @@
+use crate::{
+    config::Config,
+    tailscale::{Platform, TailscaleNode},
+};
+
+/// Username used when no scope provides one
+pub const FALLBACK_USERNAME: &str = "ubuntu";
+
+/// A layer of the config that can provide a default username, most specific first
+#[derive(Clone, Debug, PartialEq)]
+pub enum UsernameScope {
+    /// Set for one node
+    Node(String),
+    /// Set for every node carrying an ACL tag
+    Tag(String),
+    /// Set for every node running an operating system
+    Os(String),
+    /// The node owner's Tailscale login, from `tailscale status`; not editable
+    Suggested(String),
+    /// The usual username on the node's platform, like `Administrator` on Windows; not editable
+    Platform(String),
+    /// The global default, also updated with the last username typed
+    Global,
+}
+
+impl UsernameScope {
+    /// Short description of the scope for display
+    pub fn label(&self) -> String {
+        match self {
+            UsernameScope::Node(name) => format!("Node {}", name),
+            UsernameScope::Tag(tag) => format!("Tag {}", tag),
+            UsernameScope::Os(os) => format!("OS {}", os),
+            UsernameScope::Suggested(_) => "Tailscale login".to_string(),
+            UsernameScope::Platform(os) => format!("Usual for {}", os),
+            UsernameScope::Global => "Global default".to_string(),
+        }
+    }
+
+    /// Whether the scope's username can be changed in the config
+    pub fn is_editable(&self) -> bool {
+        !matches!(self, UsernameScope::Suggested(_) | UsernameScope::Platform(_))
+    }
+}
+
+/// The scopes that apply to a node, in precedence order
+pub fn scopes_for(node: &TailscaleNode) -> Vec<UsernameScope> {
+    let mut scopes = vec![UsernameScope::Node(node.name.clone())];
+    scopes.extend(node.tags.iter().cloned().map(UsernameScope::Tag));
+    if !node.os.is_empty() {
+        scopes.push(UsernameScope::Os(node.os.clone()));
+    }
+    // Anything configured explicitly wins over the login, but the login beats the global default
+    if !node.suggested_user.is_empty() {
+        scopes.push(UsernameScope::Suggested(node.suggested_user.clone()));
+    }
+    // Tagged Windows servers have no owner, and rarely the same account as Linux nodes
+    if node.platform().default_username().is_some() {
+        scopes.push(UsernameScope::Platform(node.os.clone()));
+    }
+    scopes.push(UsernameScope::Global);
+    scopes
+}
+
+impl Config {
+    /// The username configured at a scope, if any
+    pub fn username_at<'a>(&'a self, scope: &'a UsernameScope) -> Option<&'a str> {
+        let username = match scope {
+            UsernameScope::Node(name) => self.nodes.get(name).and_then(|settings| settings.username.as_deref()),
+            UsernameScope::Tag(tag) => self.usernames.tags.get(tag).map(String::as_str),
+            UsernameScope::Os(os) => self.usernames.os.get(&os.to_lowercase()).map(String::as_str),
+            UsernameScope::Suggested(username) => Some(username.as_str()),
+            UsernameScope::Platform(os) => Platform::from_os(os).default_username(),
+            UsernameScope::Global => Some(self.default_username.as_str()),
+        };
+        username.filter(|username| !username.is_empty())
+    }
+
+    /// Set or clear the username at a scope; scopes that aren't editable are left alone
+    pub fn set_username_at(&mut self, scope: &UsernameScope, username: Option<String>) {
+        let username = username.filter(|username| !username.is_empty());
+        match scope {
+            UsernameScope::Node(name) => self.nodes.entry(name.clone()).or_default().username = username,
+            UsernameScope::Tag(tag) => set_or_remove(&mut self.usernames.tags, tag.clone(), username),
+            UsernameScope::Os(os) => set_or_remove(&mut self.usernames.os, os.to_lowercase(), username),
+            UsernameScope::Suggested(_) | UsernameScope::Platform(_) => {}
+            UsernameScope::Global => self.default_username = username.unwrap_or_default(),
+        }
+    }
+
+    /// The username to use for a node and the scope it came from; `None` means the built-in fallback
+    pub fn resolve_username(&self, node: &TailscaleNode) -> (String, Option<UsernameScope>) {
+        scopes_for(node)
+            .into_iter()
+            .find_map(|scope| {
+                let username = self.username_at(&scope)?.to_string();
+                Some((username, Some(scope)))
+            })
+            .unwrap_or_else(|| (FALLBACK_USERNAME.to_string(), None))
+    }
+}
+
+/// Insert a value into a map, or remove the key when there is no value
+fn set_or_remove(map: &mut std::collections::BTreeMap<String, String>, key: String, value: Option<String>) {
+    match value {
+        Some(value) => {
+            map.insert(key, value);
+        }
+        None => {
+            map.remove(&key);
+        }
+    }
+}
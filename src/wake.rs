@@ -0,0 +1,139 @@
+use crate::{
+    config::{Config, WakeOnLan},
+    index::find_node,
+    preflight::spin,
+    ssh::SshCommand,
+    subprocess,
+    tailscale::{get_tailscale_nodes, TailscaleNode},
+};
+use anyhow::{anyhow, Context, Result};
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use std::{
+    io::{self, IsTerminal},
+    process::Stdio,
+    time::Duration,
+};
+
+/// How long to wait for a woken node when `timeout_secs` isn't set
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(180);
+
+/// How often `tailscale status` is asked whether the node is back
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The node's Wake-on-LAN settings, if it is offline and has them
+pub fn wakeable(config: &Config, node: &TailscaleNode) -> Option<WakeOnLan> {
+    if node.is_online() {
+        return None;
+    }
+    config.node_settings(&node.name).wake
+}
+
+/// Offer to wake an offline node before connecting to it. Returns the node as
+/// it is now: woken and online, or unchanged if there is nothing to wake or the
+/// offer was declined. Without a terminal to ask on, the node is woken.
+pub async fn offer(config: &Config, node: TailscaleNode) -> Result<TailscaleNode> {
+    let Some(wake) = wakeable(config, &node) else {
+        return Ok(node);
+    };
+    if io::stdin().is_terminal() {
+        let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("{} is offline. Wake it through {}?", node.name, wake.via))
+            .default(true)
+            .interact()?;
+        if !confirmed {
+            return Ok(node);
+        }
+    }
+    wake_node(config, &node, &wake).await
+}
+
+/// Send the magic packet from the `via` node, then wait for the node to come
+/// online and return it as `tailscale status` now reports it
+pub async fn wake_node(config: &Config, node: &TailscaleNode, wake: &WakeOnLan) -> Result<TailscaleNode> {
+    let mac = parse_mac(&wake.mac).with_context(|| format!("Invalid `wake.mac` for {}", node.name))?;
+    let nodes = get_tailscale_nodes(config).await.context("Failed to get Tailscale nodes")?;
+    let waker = find_node(&nodes, config, &wake.via)
+        .with_context(|| format!("Can't find {}, which wakes {}", wake.via, node.name))?;
+    if !waker.is_online() {
+        return Err(anyhow!("{} is offline too, so it can't wake {}", waker.name, node.name));
+    }
+
+    let command_line = match &wake.command {
+        Some(command) => command.replace("{mac}", &wake.mac),
+        None => magic_packet_command(&mac),
+    };
+    let (username, _) = config.resolve_username(waker);
+    let command = SshCommand::for_node(config, &username, waker)
+        .option("BatchMode", "yes")
+        .remote_command(vec![command_line])
+        .command();
+    let output = subprocess::output_async(tokio::process::Command::from(command).stdin(Stdio::null()))
+        .await
+        .context("Failed to execute ssh")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!(
+            "Sending the magic packet from {} failed with {}: {}",
+            waker.name,
+            output.status,
+            stderr.trim()
+        ));
+    }
+    println!("Sent a magic packet to {} from {}", wake.mac, waker.name);
+
+    let timeout = wake.timeout_secs.map(Duration::from_secs).unwrap_or(DEFAULT_TIMEOUT);
+    let label = format!("Waiting for {} to come online", node.name);
+    match spin(&label, tokio::time::timeout(timeout, wait_online(config, node))).await {
+        Ok(woken) => {
+            println!("{} is online", woken.name);
+            Ok(woken)
+        }
+        Err(_) => Err(anyhow!(
+            "{} didn't come online within {} seconds of the magic packet.\n\
+             - Check that Wake-on-LAN is enabled in its firmware and network settings\n\
+             - Check that {} is on the same LAN and `wake.mac` is the right card",
+            node.name,
+            timeout.as_secs(),
+            waker.name
+        )),
+    }
+}
+
+/// Poll `tailscale status` until the node is online. Errors in between are
+/// ignored, as the tailnet can be briefly unsettled while a machine boots.
+async fn wait_online(config: &Config, node: &TailscaleNode) -> TailscaleNode {
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        if let Ok(nodes) = get_tailscale_nodes(config).await
+            && let Some(woken) = nodes.into_iter().find(|other| other.same_node(node))
+            && woken.is_online()
+        {
+            return woken;
+        }
+    }
+}
+
+/// Parse a MAC address written with `:` or `-` between the bytes, or none
+fn parse_mac(mac: &str) -> Result<[u8; 6]> {
+    let digits: String = mac.chars().filter(|c| !matches!(c, ':' | '-')).collect();
+    if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("'{}' isn't a MAC address like aa:bb:cc:dd:ee:ff", mac));
+    }
+    let mut bytes = [0; 6];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[index * 2..index * 2 + 2], 16)?;
+    }
+    Ok(bytes)
+}
+
+/// A command that broadcasts the magic packet (six `ff` bytes, then the MAC
+/// sixteen times) to UDP port 9. It needs only python3 on the sending node.
+fn magic_packet_command(mac: &[u8; 6]) -> String {
+    let hex: String = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "python3 -c 'import socket; s = socket.socket(socket.AF_INET, socket.SOCK_DGRAM); \
+         s.setsockopt(socket.SOL_SOCKET, socket.SO_BROADCAST, 1); \
+         s.sendto(bytes.fromhex(\"ff\" * 6 + \"{}\" * 16), (\"255.255.255.255\", 9))'",
+        hex
+    )
+}
@@ -0,0 +1,31 @@
+use std::{fs, path::PathBuf, process::Command};
+
+/// A state directory of its own, with a config file and a node list
+fn state_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ssh-tailscale-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("config.json"), "{\n  \"default_username\": \"root\"\n}\n").unwrap();
+    fs::write(dir.join("nodes.csv"), "name,ip,os\nweb-1,100.64.0.2,linux\n").unwrap();
+    dir
+}
+
+#[test]
+fn a_dry_run_leaves_the_config_unchanged() {
+    let dir = state_dir("dry-run");
+    let before = fs::read_to_string(dir.join("config.json")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ssh-tailscale"))
+        .env("SSH_TAILSCALE_STATE_DIR", &dir)
+        .arg("--nodes-from")
+        .arg(dir.join("nodes.csv"))
+        .args(["--dry-run", "--user", "alice", "web-1"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("alice@100.64.0.2"));
+    assert_eq!(fs::read_to_string(dir.join("config.json")).unwrap(), before);
+
+    fs::remove_dir_all(&dir).unwrap();
+}